@@ -0,0 +1,97 @@
+use std::ffi::CStr;
+use std::fmt;
+use std::path::Path;
+
+use crate::fs::{group_name, user_name};
+use crate::provider;
+
+pub const ACCESS_ACL_ATTR: &CStr = c"system.posix_acl_access";
+pub const DEFAULT_ACL_ATTR: &CStr = c"system.posix_acl_default";
+
+/// The version in the header of the xattrs, from linux/posix_acl_xattr.h
+const ACL_VERSION: u32 = 2;
+
+const ACL_USER_OBJ: u16 = 0x01;
+const ACL_USER: u16 = 0x02;
+const ACL_GROUP_OBJ: u16 = 0x04;
+const ACL_GROUP: u16 = 0x08;
+const ACL_MASK: u16 = 0x10;
+const ACL_OTHER: u16 = 0x20;
+
+/// One entry of a POSIX ACL, e.g. "user:alice:rwx"
+#[derive(Debug, Clone, Copy)]
+pub struct AclEntry {
+    pub tag: u16,
+    pub perm: u16,
+    pub id: u32,
+}
+
+impl fmt::Display for AclEntry {
+    /// Like getfacl prints it, with names for the ids
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (tag, qualifier) = match self.tag {
+            ACL_USER_OBJ => ("user", String::new()),
+            ACL_USER => ("user", user_name(self.id).to_string()),
+            ACL_GROUP_OBJ => ("group", String::new()),
+            ACL_GROUP => ("group", group_name(self.id).to_string()),
+            ACL_MASK => ("mask", String::new()),
+            ACL_OTHER => ("other", String::new()),
+            _ => ("unknown", self.id.to_string()),
+        };
+        let perm: String = [(4, 'r'), (2, 'w'), (1, 'x')]
+            .iter()
+            .map(|&(bit, c)| if self.perm & bit != 0 { c } else { '-' })
+            .collect();
+        write!(f, "{}:{}:{}", tag, qualifier, perm)
+    }
+}
+
+/// Parse the value of one of the ACL xattrs: a version, then 8 bytes per
+/// entry, all little-endian
+pub fn parse(value: &[u8]) -> Option<Vec<AclEntry>> {
+    let (header, entries) = value.split_first_chunk::<4>()?;
+    if u32::from_le_bytes(*header) != ACL_VERSION || entries.len() % 8 != 0 {
+        return None;
+    }
+    Some(
+        entries
+            .chunks_exact(8)
+            .map(|e| AclEntry {
+                tag: u16::from_le_bytes([e[0], e[1]]),
+                perm: u16::from_le_bytes([e[2], e[3]]),
+                id: u32::from_le_bytes([e[4], e[5], e[6], e[7]]),
+            })
+            .collect(),
+    )
+}
+
+/// The inverse of `parse()`
+pub fn encode(entries: &[AclEntry]) -> Vec<u8> {
+    let mut value = ACL_VERSION.to_le_bytes().to_vec();
+    for entry in entries {
+        value.extend(entry.tag.to_le_bytes());
+        value.extend(entry.perm.to_le_bytes());
+        value.extend(entry.id.to_le_bytes());
+    }
+    value
+}
+
+/// The ACL of `path`, and for directories, the default ACL that new entries
+/// inherit. Either is empty if there isn't one.
+pub fn get(path: &Path) -> (Vec<AclEntry>, Vec<AclEntry>) {
+    let read = |name| {
+        provider::get()
+            .xattr(path, name)
+            .and_then(|value| parse(&value))
+            .unwrap_or_default()
+    };
+    (read(ACCESS_ACL_ATTR), read(DEFAULT_ACL_ATTR))
+}
+
+/// Whether `path` has an ACL beyond its permission bits, which `ls -l`
+/// marks with a `+`
+pub fn has_acl(path: &Path) -> bool {
+    let (access, default) = get(path);
+    // The permission bits alone are user::, group::, and other::
+    access.len() > 3 || !default.is_empty()
+}
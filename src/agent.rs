@@ -0,0 +1,164 @@
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+
+use clap::Args;
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::control;
+use crate::fs::has_ceph_stats;
+use crate::loader::{LoadOptions, Loader, ls};
+use crate::provider;
+use crate::report::entry_record;
+use crate::schema::{DirRecord, SCHEMA_VERSION};
+
+/// Refuse messages bigger than this, rather than allocating whatever a peer asks for
+const MAX_MESSAGE_SIZE: u32 = 1 << 20;
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Answer listing requests from another process, e.g. a frontend on another host over SSH.
+///
+/// Each message, in either direction, is a 4-byte big-endian length followed
+/// by that many bytes of JSON. Requests are {"op": "list", "path": "/dir"}
+/// or {"op": "ping"}; every response has "ok" and either the result or "error".
+#[derive(Args)]
+pub struct AgentArgs {
+    /// Listen on this Unix socket instead of stdin/stdout
+    #[arg(long, value_name = "PATH")]
+    pub socket: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Request {
+    Ping,
+    List { path: PathBuf },
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Response {
+    Pong {
+        ok: bool,
+        version: u32,
+        schema_version: u32,
+    },
+    Listing {
+        ok: bool,
+        schema_version: u32,
+        path: PathBuf,
+        ceph: bool,
+        /// The directory itself, at depth 0, and its entries, at depth 1
+        total: DirRecord,
+        entries: Vec<DirRecord>,
+    },
+    Error {
+        ok: bool,
+        schema_version: u32,
+        error: String,
+    },
+}
+
+pub fn run(args: &AgentArgs, config: &Config) -> Result<()> {
+    let mut options = Loader::new(config).options();
+    options.lazy_threshold = usize::MAX;
+    options.page_size = usize::MAX;
+
+    let Some(socket) = &args.socket else {
+        return serve(std::io::stdin().lock(), std::io::stdout().lock(), options);
+    };
+
+    let listener = control::bind_private(socket)?;
+    eprintln!("Listening on {:?}", socket);
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        if !control::peer_is_owner(&stream) {
+            continue;
+        }
+        provider::spawn(move || {
+            if let Ok(reader) = stream.try_clone() {
+                let _ = serve(reader, stream, options);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Answer requests until the peer hangs up
+fn serve(mut reader: impl Read, mut writer: impl Write, options: LoadOptions) -> Result<()> {
+    while let Some(message) = read_message(&mut reader)? {
+        let response = match serde_json::from_slice::<Request>(&message) {
+            Ok(request) => handle(request, options),
+            Err(e) => Response::Error {
+                ok: false,
+                schema_version: SCHEMA_VERSION,
+                error: format!("Bad request: {}", e),
+            },
+        };
+        write_message(&mut writer, &serde_json::to_vec(&response)?)?;
+    }
+    Ok(())
+}
+
+fn handle(request: Request, options: LoadOptions) -> Response {
+    match request {
+        Request::Ping => Response::Pong {
+            ok: true,
+            version: PROTOCOL_VERSION,
+            schema_version: SCHEMA_VERSION,
+        },
+        Request::List { path } => {
+            let listing = provider::get()
+                .canonicalize(&path)
+                .and_then(|path| ls(&path, options, &AtomicBool::new(false)).map(|l| (path, l)));
+            match listing {
+                Ok((path, (entry_cwd, entries, _, _))) => Response::Listing {
+                    ok: true,
+                    schema_version: SCHEMA_VERSION,
+                    ceph: has_ceph_stats(&path),
+                    total: entry_record(path.clone(), 0, &entry_cwd),
+                    entries: entries
+                        .iter()
+                        .map(|entry| {
+                            entry_record(path.join(entry.name.trim_end_matches('/')), 1, entry)
+                        })
+                        .collect(),
+                    path,
+                },
+                Err(e) => Response::Error {
+                    ok: false,
+                    schema_version: SCHEMA_VERSION,
+                    error: e.to_string(),
+                },
+            }
+        }
+    }
+}
+
+/// Read one length-prefixed message, or None at end of stream
+fn read_message(reader: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_be_bytes(len);
+    if len > MAX_MESSAGE_SIZE {
+        return Err(color_eyre::eyre::eyre!("Message too big: {} bytes", len));
+    }
+    let mut message = vec![0u8; len as usize];
+    reader.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+fn write_message(writer: &mut impl Write, message: &[u8]) -> Result<()> {
+    writer.write_all(&(message.len() as u32).to_be_bytes())?;
+    writer.write_all(message)?;
+    writer.flush()?;
+    Ok(())
+}
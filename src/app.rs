@@ -1,39 +1,303 @@
-use std::collections::HashMap;
-use std::fs::Metadata;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
-use std::{fs, os::unix::fs::MetadataExt};
-
-use crossterm::event::{self, Event, KeyCode, KeyModifiers, poll};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
+use clap::ValueEnum;
 use ratatui::widgets::ListState;
+use rhai::Dynamic;
+use serde::{Deserialize, Serialize};
+
+use compact_str::{CompactString, format_compact};
 
-use crate::fs::{FSType, get_fs, get_rbytes, get_rctime, get_rentries, id_to_name};
-use crate::navigation;
-use crate::popup::Popup;
+use crate::acl;
+use crate::cache::ListingCache;
+use crate::cleanup::{CleanupScan, Deletion, Reason};
+use crate::config::{
+    AlertRule, CleanupConfig, Column, Config, CostConfig, Hook, Overrides, PluginConfig,
+    RetentionPolicy, SearchConfig, SubvolumeConfig, ThemeConfig,
+};
+use crate::control::ControlSocket;
+use crate::deepsize::{DeepSize, DeepSizer};
+use crate::fs::{
+    FSType, Quota, Share, all_xattrs, get_effective_pool, get_fs, get_pool, group_name, is_dir,
+    logical_current_dir, normalize_lexically, set_name_cache_limits, user_name,
+};
+use crate::glob;
+use crate::history::{History, TREND_WEEKS, Trend};
+use crate::hooks::HookRunner;
+use crate::input;
+use crate::jobs::{BAR_WIDTH, JobState, Jobs};
+use crate::keymap::{Action, Keymap};
+use crate::loader::{
+    LoadOptions, LoadReport, Loader, MoreEntries, Timings, load_entry, ls, read_names,
+    unloaded_entries,
+};
+use crate::marks;
+use crate::mounts::{self, CephFs, Mount};
+use crate::plugins::{Finished, PluginRunner};
+use crate::pools::{self, PoolScan, PoolUsage};
+use crate::popup::{self, Popup};
+use crate::prefetch::{PREFETCH_DELAY, Prefetcher};
+use crate::projects::ProjectMap;
+use crate::provider::{self, Space, Stat};
+use crate::purge::{RETENTION_STEPS, RetentionScan, cutoff, date_str};
+use crate::report;
+use crate::rstats::{self, RStats};
+use crate::schema::{CwdRecord, DirRecord, ListingRecord, SCHEMA_VERSION};
+use crate::script;
+use crate::search::{MAX_MATCHES, Search};
+use crate::subvolume::{Subvolume, SubvolumeLookups};
+use crate::terminal;
+use crate::timeout::timed_out_error;
+use crate::ui::{
+    POPUP_TEXT_HEIGHT, age_str, cost_str, count_str, exact_sizes, mode_str, rentries_str,
+    set_exact_sizes, set_units, signed_str, size_str, xattr_value_str,
+};
+use crate::uring;
+use crate::userquota::{self, UserQuota};
+use crate::views::{self, View};
+use crate::watch::{WATCH_DEBOUNCE, WATCH_MAX_DELAY, Watcher};
 
 const DEFAULT_SORT_MODE: SortMode = SortMode::Reversed(SortField::Size);
+/// How wide the search results are, at least
+const SEARCH_WIDTH: usize = 60;
+/// How wide the cleanup wizard is
+const CLEANUP_WIDTH: usize = 76;
+/// How many rows beyond the visible ones to load in a lazy listing
+const LAZY_MARGIN: usize = 50;
+/// Listings with more entries than this are sorted in two steps: first the
+/// entries displayed first, so that they're on screen right away, and then
+/// the rest, on the next tick
+const PARTIAL_SORT_MIN: usize = 50_000;
+/// How many entries the first step puts in order
+const PARTIAL_SORT_TOP: usize = 1_000;
+/// How long rows that changed in a refresh stay highlighted
+const CHANGE_HIGHLIGHT: Duration = Duration::from_secs(2);
+/// How long they keep showing how much they changed by
+const CHANGE_ARROWS: Duration = Duration::from_secs(10);
+/// Recursive stats can take this long to propagate up the tree after a change
+const RSTAT_PROPAGATION_SECS: usize = 30;
+/// Longer xattr values are cut off in the details popup
+const XATTR_VALUE_MAX_CHARS: usize = 48;
+/// How many entries the peek popup shows
+const PEEK_ENTRIES: usize = 10;
+/// How far `drill_to_biggest` goes at once, in case of a symlink loop with -L
+const MAX_DRILL_LEVELS: usize = 64;
 
 pub struct App {
     pub should_exit: bool,
     pub cwd: PathBuf,
     pub dir_listing: DirListing,
     pub original_cwd: PathBuf,
+    /// The directory we were in before it went away, e.g. deleted or on a
+    /// stale mount, to go back to on a refresh if it comes back
+    pub lost_cwd: Option<PathBuf>,
     pub popup: Option<Popup>,
     pub show_owner: bool,
+    pub show_group: bool,
+    /// Show uids and gids instead of names
+    pub numeric_ids: bool,
+    /// Show the permissions, and flag risky ones
+    pub show_permissions: bool,
     pub show_ctime: bool,
+    /// Show the average size of the files in directories, to find trees of tiny files
+    pub show_average: bool,
+    /// Show what the entries cost a month, if there are rates in the config
+    pub show_cost: bool,
+    pub cost: CostConfig,
+    /// How many columns the rows are scrolled left by, past the gauges
+    pub pan: usize,
+    /// How far they can be before the longest row on screen ends, as of the last render
+    pub pan_limit: usize,
+    pub show_debug: bool,
+    /// Show how much of the directory's totals the entries don't add up to
+    pub show_unaccounted: bool,
+    /// Keep `cwd` as navigated instead of resolving symlinks, so ".." goes
+    /// back the way we came
+    pub logical_paths: bool,
+    /// Draw plain text for screen readers
+    pub plain: bool,
+    pub keymap: Keymap,
+    pub theme: ThemeConfig,
+    pub alerts: Vec<AlertRule>,
+    /// Show the key hint bar
+    pub show_hints: bool,
+    /// Entries to keep visible above and below the cursor
+    pub scroll_off: usize,
+    /// The start of a name being typed to jump to, and when the last key was typed
+    pub jump: Option<(String, Instant)>,
+    /// A count typed before a motion, like the 15 of `15j`
+    pub count: Option<usize>,
+    /// Directories marked with `m`, by letter
+    pub marks: HashMap<char, PathBuf>,
+    /// Save marks and views for later sessions
+    pub persist: bool,
+    /// Views saved with `SaveView`, by directory
+    pub views: BTreeMap<PathBuf, View>,
+    /// The directory whose saved view is in effect, if any
+    view_dir: Option<PathBuf>,
+    /// The view to go back to when leaving the directories with saved views
+    default_view: View,
+    /// `SetMark` or `GoToMark`, waiting for the letter of the mark
+    pub pending_mark: Option<Action>,
+    /// A line of text being typed, e.g. the pattern for `MarkMatching`
+    pub prompt: Option<Prompt>,
+    /// The hostname to put in file:// hyperlinks, if hyperlinks are enabled
+    pub hyperlinks: Option<String>,
+    /// Where the config came from, to read it again with `reload_config()`
+    pub overrides: Overrides,
     pub message: Option<Message>,
+    pub loader: Loader,
+    /// Directories we've recently left
+    pub listing_cache: ListingCache,
+    pub prefetcher: Prefetcher,
+    /// Recursive sizes of non-Ceph directories, computed on request
+    pub deep_sizer: DeepSizer,
+    /// What's running in the background, and what ran, for the jobs panel
+    pub jobs: Jobs,
+    /// The highlighted job, while the jobs panel is open
+    pub jobs_panel: Option<usize>,
+    /// The job that last finished while we were somewhere else, to go to its results
+    pub finished_job: Option<usize>,
+    /// Ring the bell when jobs finish elsewhere
+    pub notify: bool,
+    /// Notifications for the terminal, which the event loop sends, since
+    /// it knows whether there is one
+    pub notifications: Vec<String>,
+    /// Set to have the event loop pass the next frame drawn to `save_screen`
+    pub export_screen: bool,
+    /// Text for the terminal to copy, which the event loop sends too
+    pub clipboard: Option<String>,
+    /// Where the sizes of visited directories are recorded, if enabled
+    pub history: Option<History>,
+    /// Watches the cwd for changes, if live updates are enabled
+    watcher: Option<Watcher>,
+    /// When the first and the latest change we haven't refreshed for were seen
+    pending_refresh: Option<(Instant, Instant)>,
+    /// A refresh that was asked for too soon after the last one, and
+    /// happens once `refresh_interval` has passed
+    refresh_requested: bool,
+    /// When the cwd was last listed, to keep refreshes `refresh_interval` apart
+    listed_at: Instant,
+    refresh_interval: Duration,
+    /// The highlighted directory, and since when
+    prefetch_candidate: Option<(PathBuf, Instant)>,
+    /// The directory being listed for the peek popup
+    peeking: Option<PathBuf>,
+    pub subvolume_config: SubvolumeConfig,
+    /// Subvolumes looked up for the details popup
+    pub subvolumes: SubvolumeLookups,
+    /// Commands to run when entering matching directories
+    pub hooks: Vec<Hook>,
+    hook_runner: HookRunner,
+    /// Actions and columns added by the site
+    pub plugins: PluginConfig,
+    pub plugin_runner: PluginRunner,
+    pub search_config: SearchConfig,
+    /// Names that background walks, i.e. deep sizes and searches, leave out
+    pub exclude: Vec<String>,
+    /// The last search for names under a directory, and its results
+    pub search: Option<Search>,
+    /// The highlighted result, while they're shown
+    pub search_panel: Option<usize>,
+    /// The last walk adding up a tree by data pool
+    pub pool_scan: Option<PoolScan>,
+    /// Whether the popup is the pool usage of the current directory
+    pub pool_panel: bool,
+    /// The last count of what's older than some ages, for the retention simulator
+    pub retention_scan: Option<RetentionScan>,
+    /// The age picked in the retention simulator, in days, while it's shown
+    pub retention_panel: Option<u64>,
+    /// From the config, for the age that the retention simulator starts at
+    pub retention: Vec<RetentionPolicy>,
+    /// The cleanup wizard's last search, with what was picked from it
+    pub cleanup: Option<CleanupScan>,
+    /// The highlighted candidate, while the cleanup wizard is shown
+    pub cleanup_panel: Option<usize>,
+    /// Whether the next key says whether to delete what's picked
+    pub confirm_cleanup: bool,
+    /// The last deletion that the cleanup wizard started
+    pub deletion: Option<Deletion>,
+    pub cleanup_config: CleanupConfig,
+    /// Where scripts send commands, with --control-socket
+    pub control: Option<ControlSocket>,
+    /// The subvolume being looked up for the details popup
+    detailing: Option<PathBuf>,
+    /// Which projects users and groups belong to
+    pub projects: ProjectMap,
     highlighted: HashMap<PathBuf, (String, usize)>,
 }
 
 /// An encapsulation of a list of all files/dirs in a directory.
 pub struct DirListing {
+    path: PathBuf,
     dotdot: Option<DirEntry>,
     entries: Vec<DirEntry>,
+    /// The dotfiles left out of `entries`, while they're hidden
+    hidden: Vec<DirEntry>,
+    hide_dotfiles: bool,
     state: ListState,
     sort_mode: SortMode,
+    /// Whether only the first PARTIAL_SORT_TOP entries displayed are in
+    /// order yet; see `finish_sort()`
+    partially_sorted: bool,
+    size_source: SizeSource,
     pub stats: ListingStats,
     pub fs: Option<FSType>,
+    /// What the directory is mounted from
+    pub mount: Option<Mount>,
+    /// Other filesystems mounted on subdirectories, by the entries' names
+    mounted: HashMap<CompactString, Mount>,
+    pub share: Option<Share>,
+    /// Where some directory sizes come from elsewhere than CephFS, e.g.
+    /// "Lustre project quotas"
+    pub rstats: Option<&'static str>,
+    /// How full the filesystem is
+    pub space: Option<Space>,
+    /// How much of their quota on the filesystem the user has used, off CephFS
+    pub user_quota: Option<UserQuota>,
+    pub load_report: LoadReport,
+    /// How long to wait for the metadata of entries loaded later
+    timeout: Option<Duration>,
+    /// The number of rows on screen, as of the last render
+    pub viewport_height: usize,
+    /// Widths of the user and group columns. These only grow as names are
+    /// resolved for the visible rows, so the columns don't jitter while scrolling.
+    pub owner_widths: (usize, usize),
+    /// Entries that were added or resized since the previous listing, and when
+    changed: HashMap<CompactString, Change>,
+    /// Names of the entries marked for bulk operations
+    marked: HashSet<CompactString>,
+    changed_at: Option<Instant>,
+    /// Whether the changed rows are still highlighted
+    changes_highlighted: bool,
+    /// The rest of a directory that's listed one page at a time
+    more: Option<MoreEntries>,
+    /// How the subdirectories have grown, by name, from the history database
+    pub trends: HashMap<CompactString, Trend>,
+    /// When the directory was visited before, from the history database
+    pub visited: Option<i64>,
+    /// How the subdirectories changed since then, by name
+    since_visit: HashMap<CompactString, Change>,
+    /// The data pool of the directory, once looked up for the costs
+    pool: Option<Option<String>>,
+    /// The pools of the entries that have layouts of their own, by name
+    pools: HashMap<CompactString, Option<String>>,
+}
+
+/// How an entry changed since the previous listing of its directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    /// By this many bytes and files
+    Resized {
+        size: isize,
+        rentries: isize,
+    },
 }
 
 /// The size/rentries stats for a directory listing
@@ -42,71 +306,205 @@ pub struct ListingStats {
     pub total_rentries: usize,
     pub max_size: usize,
     pub total_size: usize,
+    /// The total size according to the other `SizeSource`
+    pub alt_total_size: usize,
+    /// The sums over the listed entries, which should add up to the totals
+    pub child_size: usize,
+    pub child_rentries: usize,
+    /// The recursive ctime of the directory itself
+    pub rctime: Option<usize>,
+    /// The newest (recursive) ctime among the entries
+    pub newest_ctime: usize,
+    /// How many of the listed entries are of each kind
+    pub kinds: KindCounts,
+}
+
+/// The number of directories, files, and symlinks in a listing
+#[derive(Debug, Default, Clone, Copy)]
+pub struct KindCounts {
+    pub dirs: usize,
+    pub files: usize,
+    pub symlinks: usize,
+}
+
+impl KindCounts {
+    pub fn of<'a>(entries: impl IntoIterator<Item = &'a DirEntry>) -> Self {
+        let mut counts = KindCounts::default();
+        counts.add(entries);
+        counts
+    }
+
+    pub fn add<'a>(&mut self, entries: impl IntoIterator<Item = &'a DirEntry>) {
+        for entry in entries {
+            match entry.kind {
+                EntryKind::Dir => self.dirs += 1,
+                EntryKind::File => self.files += 1,
+                EntryKind::Symlink => self.symlinks += 1,
+            }
+        }
+    }
 }
 
 /// A single file/dir in the current directory.
+/// There can be millions of these, so keep them small: most names fit inline
+/// in a CompactString, and owners are kept as ids until they're displayed.
 #[derive(Debug, Clone)]
 pub struct DirEntry {
-    pub name: String,
+    pub name: CompactString,
     pub kind: EntryKind,
+    /// False until the metadata has been fetched
+    pub loaded: bool,
     pub size: Option<usize>,
+    /// For directories, the size according to the other `SizeSource`
+    pub alt_size: Option<usize>,
     pub rentries: Option<usize>,
     pub ctime: Option<usize>,
-    pub user: Option<String>,
-    pub group: Option<String>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// The permission bits, like `Stat::mode`
+    pub mode: Option<u32>,
+    /// Whether there's an ACL, once that's been checked
+    pub acl: Option<bool>,
+    /// The directory's own quota. Boxed, since few directories have one.
+    pub quota: Option<Box<Quota>>,
+    /// For sparse files, the bytes allocated on disk, which are far fewer
+    /// than their size
+    pub allocated: Option<usize>,
+    /// Whether fetching the metadata took too long, e.g. from a hung MDS
+    pub timed_out: bool,
 }
 
 impl DirEntry {
-    fn from(path: PathBuf, stat: Metadata) -> Self {
-        let kind = if stat.is_dir() {
-            EntryKind::Dir
-        } else if stat.is_symlink() {
-            EntryKind::Symlink
-        } else {
-            EntryKind::File
-        };
+    pub fn from(path: PathBuf, stat: Stat, timings: &mut Timings) -> Self {
+        let mut entry = DirEntry::unloaded(&path, stat.kind);
+        entry.load(&path, &stat, timings);
+        entry
+    }
 
-        // we want to do our xattr calls asap to try and take advantage of MDS caching
-        let rentries: Option<usize> = if kind == EntryKind::Dir {
-            // rentries seems to include the self-count, which is confusing when there are
-            // only N files but N+1 rentries.
-            get_rentries(&path).map(|r| r.saturating_sub(1))
+    /// An entry with only a name, for when fetching the metadata is deferred.
+    pub fn unloaded(path: &Path, kind: EntryKind) -> Self {
+        let name_str = path.file_name().unwrap_or_default().to_string_lossy();
+        let name = if kind == EntryKind::Dir {
+            format_compact!("{}/", name_str)
         } else {
-            None
+            CompactString::from(name_str)
         };
 
-        let size: Option<usize> = if kind == EntryKind::Dir {
-            get_rbytes(&path)
-        } else {
-            Some(stat.len() as usize)
-        };
+        DirEntry {
+            name,
+            kind,
+            loaded: false,
+            size: None,
+            alt_size: None,
+            rentries: None,
+            ctime: None,
+            uid: None,
+            gid: None,
+            mode: None,
+            acl: None,
+            quota: None,
+            allocated: None,
+            timed_out: false,
+        }
+    }
+
+    /// An entry whose metadata didn't come in time, so that it isn't
+    /// asked for again until the listing is refreshed
+    pub fn timed_out(path: &Path, kind: EntryKind) -> Self {
+        DirEntry {
+            loaded: true,
+            timed_out: true,
+            ..DirEntry::unloaded(path, kind)
+        }
+    }
 
-        let ctime: Option<usize> = if kind == EntryKind::Dir {
-            get_rctime(&path)
+    /// Fill in the sizes, counts, times, and owners.
+    pub fn load(&mut self, path: &Path, stat: &Stat, timings: &mut Timings) {
+        let rstats = if self.kind == EntryKind::Dir {
+            rstats::for_dir(path, stat.dev).load(path, stat, timings)
         } else {
-            Some(stat.ctime() as usize)
+            RStats::default()
         };
+        self.load_with(stat, rstats);
+    }
 
-        let name_str = path.file_name().unwrap_or_default().to_string_lossy();
-        let name = if kind == EntryKind::Dir {
-            format!("{}/", name_str)
+    /// Fill in the metadata from `stat` and, for a directory, its recursive
+    /// stats, which have been fetched already
+    pub fn load_with(&mut self, stat: &Stat, rstats: RStats) {
+        if self.kind == EntryKind::Dir {
+            // rentries seems to include the self-count, which is confusing when there are
+            // only N files but N+1 rentries.
+            self.rentries = rstats.entries.map(|r| r.saturating_sub(1));
+            // Directory sizes start out as rbytes; see `set_size_source()`
+            self.size = rstats.bytes;
+            self.alt_size = Some(stat.size);
+            self.ctime = rstats.ctime;
+            self.quota = rstats.quota.map(Box::new);
         } else {
-            name_str.to_string()
-        };
+            self.rentries = None;
+            self.size = Some(stat.size);
+            self.alt_size = None;
+            self.ctime = Some(stat.ctime);
+            self.allocated = stat.is_sparse().then(|| stat.allocated());
+        }
+
+        self.uid = Some(stat.uid);
+        self.gid = Some(stat.gid);
+        self.mode = Some(stat.mode);
+        self.loaded = true;
+    }
 
-        let name_or_id = |id: u32| id_to_name(id).unwrap_or_else(|| format!("{}", id));
+    /// The average size of the files in the directory, all the way down.
+    /// Subdirectories count as files, like they do in rentries.
+    pub fn average_size(&self) -> Option<usize> {
+        if self.kind != EntryKind::Dir {
+            return None;
+        }
+        let rentries = self.rentries.filter(|&r| r > 0)?;
+        Some(self.size? / rentries)
+    }
 
-        let user = Some(name_or_id(stat.uid()));
-        let group = Some(name_or_id(stat.gid()));
+    /// How much of its quota the directory uses, if it has one
+    pub fn quota_used(&self) -> Option<f64> {
+        let quota = self.quota.as_ref()?;
+        Some(quota.fraction_used(self.size.unwrap_or(0), self.rentries.unwrap_or(0)))
+    }
 
-        DirEntry {
-            name,
-            kind,
-            size,
-            rentries,
-            ctime,
-            user,
-            group,
+    /// What's risky about the permissions, if anything: setuid or setgid
+    /// programs, and things anyone can write to. Symlinks' own permissions
+    /// don't matter.
+    pub fn permission_warning(&self) -> Option<&'static str> {
+        let mode = self.mode?;
+        let world_writable = mode & 0o002 != 0;
+        match self.kind {
+            EntryKind::File if mode & 0o4000 != 0 => Some("setuid"),
+            EntryKind::File if mode & 0o2000 != 0 => Some("setgid"),
+            EntryKind::File if world_writable => Some("world-writable"),
+            EntryKind::Dir if world_writable && mode & 0o1000 == 0 => {
+                Some("world-writable without sticky bit")
+            }
+            _ => None,
+        }
+    }
+
+    pub fn is_dotfile(&self) -> bool {
+        self.name.starts_with('.')
+    }
+
+    /// The owner's name, resolved on demand (and cached), or with `numeric`,
+    /// the uid (like `ls -n`)
+    pub fn user(&self, numeric: bool) -> Option<Arc<str>> {
+        match numeric {
+            true => self.uid.map(|uid| uid.to_string().into()),
+            false => self.uid.map(user_name),
+        }
+    }
+
+    /// The group's name, or with `numeric`, the gid
+    pub fn group(&self, numeric: bool) -> Option<Arc<str>> {
+        match numeric {
+            true => self.gid.map(|gid| gid.to_string().into()),
+            false => self.gid.map(group_name),
         }
     }
 }
@@ -118,19 +516,91 @@ pub enum EntryKind {
     Symlink,
 }
 
+impl EntryKind {
+    /// "file", "dir", or "symlink", for machine-readable output
+    pub fn name(self) -> &'static str {
+        match self {
+            EntryKind::File => "file",
+            EntryKind::Dir => "dir",
+            EntryKind::Symlink => "symlink",
+        }
+    }
+}
+
+impl From<std::fs::FileType> for EntryKind {
+    fn from(file_type: std::fs::FileType) -> Self {
+        if file_type.is_dir() {
+            EntryKind::Dir
+        } else if file_type.is_symlink() {
+            EntryKind::Symlink
+        } else {
+            EntryKind::File
+        }
+    }
+}
+
+/// Where directory sizes come from. These usually agree on CephFS, but
+/// `stat` only reports recursive sizes if the mount has the `rbytes` option.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SizeSource {
+    /// The `ceph.dir.rbytes` xattr
+    #[default]
+    Rbytes,
+    /// `st_size` from stat()
+    Stat,
+}
+
+impl SizeSource {
+    pub fn other(self) -> SizeSource {
+        match self {
+            SizeSource::Rbytes => SizeSource::Stat,
+            SizeSource::Stat => SizeSource::Rbytes,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SizeSource::Rbytes => "rbytes",
+            SizeSource::Stat => "stat",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SortMode {
     Normal(SortField),
     Reversed(SortField),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+#[value(rename_all = "lowercase")]
 pub enum SortField {
     Name,
     Size,
+    #[serde(rename = "count")]
+    #[value(name = "count")]
     Rentries,
     Owner,
+    Group,
     CTime,
+    /// The average size of the files in directories
+    Average,
+}
+
+impl SortField {
+    /// Which way sorting by this starts out: biggest and newest first, names
+    /// in alphabetical order, and the smallest files first, since those are
+    /// the trees the MDS suffers from
+    pub fn initial_mode(self) -> SortMode {
+        match self {
+            SortField::Size | SortField::Rentries | SortField::CTime => SortMode::Reversed(self),
+            SortField::Name | SortField::Owner | SortField::Group | SortField::Average => {
+                SortMode::Normal(self)
+            }
+        }
+    }
 }
 
 impl SortMode {
@@ -163,6 +633,24 @@ pub struct Message {
     pub kind: MessageKind,
 }
 
+/// A line of text typed into the message line, for `kind` to use once
+/// Enter is pressed
+#[derive(Debug, Clone)]
+pub struct Prompt {
+    pub label: String,
+    pub text: String,
+    pub kind: PromptKind,
+}
+
+/// What a prompt's text is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptKind {
+    /// What a key's action works on, like what to search for
+    Action(Action),
+    /// What has to be typed to delete what's marked in the cleanup wizard
+    ConfirmDelete,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum MessageKind {
     Error,
@@ -171,27 +659,147 @@ pub enum MessageKind {
 }
 
 impl App {
-    pub fn new(cwd: Option<&PathBuf>) -> Result<App, std::io::Error> {
+    pub fn new(cwd: Option<&PathBuf>, config: &Config) -> Result<App, std::io::Error> {
         let cwd: PathBuf = if let Some(cwd) = cwd {
             cwd.clone()
         } else {
             std::env::current_dir()?
         };
 
-        let dir_listing = DirListing::default();
+        let mut dir_listing = DirListing::default();
+        dir_listing.sort_mode = config.sort.initial_mode();
+        let column = |column| config.columns.contains(&column);
         let original_cwd = cwd.clone();
         let mut app = App {
             should_exit: false,
             cwd: PathBuf::new(),
             dir_listing,
             original_cwd,
+            lost_cwd: None,
             popup: None,
-            show_owner: false,
-            show_ctime: false,
+            show_owner: column(Column::Owner),
+            show_group: column(Column::Group),
+            numeric_ids: false,
+            show_permissions: column(Column::Permissions),
+            show_ctime: column(Column::Ctime),
+            show_average: column(Column::Average),
+            show_cost: column(Column::Cost) && config.cost.is_set(),
+            cost: config.cost.clone(),
+            pan: 0,
+            pan_limit: 0,
+            show_debug: false,
+            show_unaccounted: config.show_unaccounted,
+            logical_paths: config.logical_paths,
+            plain: config.plain,
+            keymap: config.keys.clone(),
+            theme: config.theme.clone(),
+            alerts: config.alerts.clone(),
+            show_hints: config.hint_bar,
+            scroll_off: config.scroll_off,
+            jump: None,
+            count: None,
+            marks: if config.persist {
+                marks::load()
+            } else {
+                HashMap::new()
+            },
+            persist: config.persist,
+            views: if config.persist {
+                views::load()
+            } else {
+                BTreeMap::new()
+            },
+            view_dir: None,
+            default_view: View {
+                owner: column(Column::Owner),
+                group: column(Column::Group),
+                permissions: column(Column::Permissions),
+                ctime: column(Column::Ctime),
+                average: column(Column::Average),
+                cost: column(Column::Cost),
+                hide_dotfiles: config.hide_dotfiles,
+                ..View::new(config.sort.initial_mode())
+            },
+            pending_mark: None,
+            prompt: None,
+            hyperlinks: None,
+            overrides: Overrides::default(),
             message: None,
+            loader: Loader::new(config),
+            listing_cache: ListingCache::new(config.cache.listings),
+            prefetcher: Prefetcher::new(config.cache.prefetch),
+            deep_sizer: DeepSizer::new(),
+            jobs: Jobs::default(),
+            jobs_panel: None,
+            finished_job: None,
+            notify: config.notify,
+            notifications: Vec::new(),
+            export_screen: false,
+            clipboard: None,
+            history: None,
+            watcher: config.live_update.then(|| Watcher::new().ok()).flatten(),
+            pending_refresh: None,
+            refresh_requested: false,
+            listed_at: Instant::now(),
+            refresh_interval: Duration::from_secs(config.refresh_interval_secs),
+            prefetch_candidate: None,
+            peeking: None,
+            subvolume_config: config.subvolumes.clone(),
+            subvolumes: SubvolumeLookups::new(),
+            hooks: config.hooks.clone(),
+            hook_runner: HookRunner::new(),
+            plugins: config.plugins.clone(),
+            plugin_runner: PluginRunner::new(),
+            search_config: config.search.clone(),
+            exclude: config.exclude.clone(),
+            search: None,
+            search_panel: None,
+            pool_scan: None,
+            pool_panel: false,
+            retention_scan: None,
+            retention_panel: None,
+            retention: config.retention.clone(),
+            cleanup: None,
+            cleanup_panel: None,
+            confirm_cleanup: false,
+            deletion: None,
+            cleanup_config: config.cleanup.clone(),
+            control: None,
+            detailing: None,
+            projects: ProjectMap::default(),
             highlighted: HashMap::new(),
         };
+        let projects_error = match config.project_map() {
+            Ok(projects) => {
+                app.projects = projects;
+                None
+            }
+            Err(e) => Some(e),
+        };
+        let history_error = if config.history.enabled {
+            match History::open(&config.history.path()) {
+                Ok(history) => {
+                    app.history = Some(history);
+                    None
+                }
+                Err(e) => Some(e),
+            }
+        } else {
+            None
+        };
         app.try_cd(&cwd)?;
+        if let Some(e) = history_error {
+            app.message(Some(Message {
+                text: format!("Error opening history {:?}: {}", config.history.path(), e),
+                kind: MessageKind::Error,
+            }));
+        }
+        if let Some(e) = projects_error {
+            app.message(Some(Message {
+                text: e,
+                kind: MessageKind::Error,
+            }));
+        }
 
         // Save the original (resolved) dir
         app.original_cwd = app.cwd.clone();
@@ -199,76 +807,2407 @@ impl App {
         Ok(app)
     }
 
-    pub fn cd(&mut self, path: &PathBuf) {
-        let res = self.try_cd(path);
-        if let Err(e) = res {
-            self.message(Some(Message {
+    pub fn cd(&mut self, path: &Path) {
+        let before = self.cwd.clone();
+        match self.try_cd(path) {
+            Ok(()) if self.cwd != before => self.lost_cwd = None,
+            Ok(()) => {}
+            // Everything else would fail the same way
+            Err(e)
+                if is_gone(&e)
+                    && !self.cwd.as_os_str().is_empty()
+                    && provider::get().stat(&self.cwd).is_err() =>
+            {
+                self.recover_cwd(&e)
+            }
+            Err(e) => self.message(Some(Message {
                 text: format!("Error changing directory: {}", e),
                 kind: MessageKind::Error,
+            })),
+        }
+    }
+
+    /// Refresh the listing, or go back to the directory that went away, if
+    /// it's there again
+    pub fn refresh(&mut self) {
+        self.prefetcher.forget_failure();
+        let Some(lost) = self.lost_cwd.take() else {
+            // Pressed again and again, or while changes are coming in, it
+            // lists once per `refresh_interval`
+            if self.listed_at.elapsed() < self.refresh_interval {
+                self.refresh_requested = true;
+            } else {
+                self.cd(&self.cwd.clone());
+            }
+            return;
+        };
+        if let Err(e) = self.try_cd(&lost) {
+            self.message(Some(Message {
+                text: format!("{} is still gone: {}", lost.display(), e),
+                kind: MessageKind::Error,
             }));
+            self.lost_cwd = Some(lost);
         }
     }
 
-    fn try_cd(&mut self, path: &PathBuf) -> Result<(), std::io::Error> {
+    /// Move up from the current directory, which is gone, to the closest
+    /// directory above it that can still be listed, and say what happened
+    fn recover_cwd(&mut self, e: &io::Error) {
+        let lost = self.cwd.clone();
+        let moved = lost.ancestors().skip(1).any(|dir| self.try_cd(dir).is_ok());
+        let retry = match self.keymap.first_key_for(Action::Refresh) {
+            Some(key) => format!(". Press {} to try going back", key),
+            None => String::new(),
+        };
+        let text = if moved {
+            format!("{} is gone ({}), so moved up{}", lost.display(), e, retry)
+        } else {
+            format!(
+                "{} is gone ({}), and so is everything above it",
+                lost.display(),
+                e
+            )
+        };
+        self.message(Some(Message {
+            text,
+            kind: MessageKind::Warning,
+        }));
+        self.lost_cwd = Some(lost);
+    }
+
+    fn try_cd(&mut self, path: &Path) -> Result<(), std::io::Error> {
         // Record which entry was highlighted in case we navigate back
         self.save_selected();
 
-        let new = if path.is_absolute() {
-            path.canonicalize()?
+        let new = self.resolve(path)?;
+        let current_view = self.current_view();
+        let (view_dir, view) = self.view_for(&new);
+        let sort_mode = view.sort_mode();
+        // A refresh should always hit the filesystem
+        let cached = (new != self.cwd)
+            .then(|| {
+                self.listing_cache
+                    .take(&new)
+                    .or_else(|| self.prefetcher.take(&new))
+            })
+            .flatten();
+        let fresh = cached.is_none();
+        let mut listing = if let Some(mut listing) = cached {
+            listing.sort(sort_mode);
+            listing.set_size_source(self.loader.size_source);
+            listing.set_hide_dotfiles(view.hide_dotfiles);
+            listing
         } else {
-            self.cwd.join(path).canonicalize()?
+            input::clear_interrupt();
+            let mut options = self.loader.options();
+            options.hide_dotfiles = view.hide_dotfiles;
+            DirListing::from(&new, sort_mode, options, &input::INTERRUPTED)?
         };
-        self.dir_listing = DirListing::from(&new, self.dir_listing.sort_mode)?;
-        self.cwd = new;
-        if !self.dir_listing.is_ceph() {
+        listing.viewport_height = self.dir_listing.viewport_height;
+        self.loader.record(listing.load_report);
+
+        let old = std::mem::replace(&mut self.dir_listing, listing);
+        let old_cwd = std::mem::replace(&mut self.cwd, new);
+        let refreshed = old_cwd == self.cwd;
+        if refreshed {
+            self.dir_listing.carry_over(&old);
+        } else if !old_cwd.as_os_str().is_empty() {
+            self.listing_cache.insert(old_cwd, old);
+        }
+        if view_dir != self.view_dir {
+            if self.view_dir.is_none() {
+                self.default_view = current_view;
+            }
+            self.view_dir = view_dir;
+            self.apply_view(view);
+        }
+        self.pending_refresh = None;
+        self.refresh_requested = false;
+        self.listed_at = Instant::now();
+        provider::get().pin_dir(&self.cwd);
+        if let Some(watcher) = &mut self.watcher {
+            // Re-listing a huge directory on every change would be too slow
+            let watch = (!self.dir_listing.load_report.lazy).then_some(self.cwd.as_path());
+            let _ = watcher.watch(watch);
+        }
+
+        self.message(self.listing_message());
+        if fresh {
+            self.load_since_visit(refreshed);
+            self.record_history();
+            self.load_trends();
+        }
+
+        self.apply_deep_sizes();
+
+        // Restore the highlighted entry if we have one
+        if !refreshed {
+            self.restore_selected();
+            self.hook_runner.entered(&self.hooks, &self.cwd);
+        }
+        if !self.plugins.columns.is_empty() {
+            let listing = script::listing(&self.cwd, self.dir_listing.iter_entries());
+            self.plugin_runner
+                .list(&self.plugins.columns, &self.cwd, &listing);
+        }
+        Ok(())
+    }
+
+    /// Record the sizes of the current directory and its subdirectories
+    fn record_history(&mut self) {
+        let Some(history) = &mut self.history else {
+            return;
+        };
+        let listing = &self.dir_listing;
+        let path = listing.path.as_path();
+        if !listing.is_ceph() || listing.load_report.lazy {
+            return;
+        }
+        let subdirs: Vec<(PathBuf, usize, usize)> = listing
+            .entries
+            .iter()
+            .chain(&listing.hidden)
+            .filter(|e| e.kind == EntryKind::Dir)
+            .map(|e| {
+                (
+                    path.join(e.name.trim_end_matches('/')),
+                    e.size.unwrap_or(0),
+                    e.rentries.unwrap_or(0),
+                )
+            })
+            .collect();
+        let dirs = std::iter::once((path, listing.stats.total_size, listing.stats.total_rentries))
+            .chain(subdirs.iter().map(|(p, s, r)| (p.as_path(), *s, *r)));
+        if let Err(e) = history.record(chrono::Utc::now().timestamp(), dirs) {
             self.message(Some(Message {
+                text: format!("Error recording history: {}", e),
+                kind: MessageKind::Error,
+            }));
+        }
+    }
+
+    /// Look up how the subdirectories have grown, if we have history for them
+    fn load_trends(&mut self) {
+        let Some(history) = &self.history else {
+            return;
+        };
+        let now = chrono::Utc::now().timestamp();
+        let since = now - TREND_WEEKS as i64 * 7 * 24 * 3600;
+        let Ok(children) = history.child_samples(&self.dir_listing.path, since) else {
+            return;
+        };
+        self.dir_listing.trends = children
+            .into_iter()
+            .filter_map(|(name, samples)| {
+                let trend = Trend::from_samples(&samples, now)?;
+                Some((format_compact!("{}/", name), trend))
+            })
+            .collect();
+    }
+
+    /// See how the subdirectories changed since the cwd was visited before,
+    /// and record this visit. A refresh compares with the same visit again.
+    fn load_since_visit(&mut self, refreshed: bool) {
+        let Some(history) = &mut self.history else {
+            return;
+        };
+        let listing = &mut self.dir_listing;
+        // Like the history, which it's compared with
+        if !listing.is_ceph() || listing.load_report.lazy {
+            return;
+        }
+        if !refreshed {
+            let now = chrono::Utc::now().timestamp();
+            listing.visited = history.visit(&listing.path, now).unwrap_or(None);
+        }
+        let Some(visited) = listing.visited else {
+            return;
+        };
+        let Ok(before) = history.child_samples_at(&listing.path, visited) else {
+            return;
+        };
+        // Without anything to compare with, everything would look new
+        if before.is_empty() {
+            return;
+        }
+        let delta = |new: Option<usize>, old: usize| new.unwrap_or(0) as isize - old as isize;
+        listing.since_visit = listing
+            .entries
+            .iter()
+            .chain(&listing.hidden)
+            .filter(|e| e.kind == EntryKind::Dir && e.name != "..")
+            .filter_map(|e| {
+                let change = match before.get(e.name.trim_end_matches('/')) {
+                    None => Change::Added,
+                    Some(sample)
+                        if (Some(sample.bytes), Some(sample.rentries)) == (e.size, e.rentries) =>
+                    {
+                        return None;
+                    }
+                    Some(sample) => Change::Resized {
+                        size: delta(e.size, sample.bytes),
+                        rentries: delta(e.rentries, sample.rentries),
+                    },
+                };
+                Some((e.name.clone(), change))
+            })
+            .collect();
+
+        if refreshed || self.message.is_some() || listing.since_visit.is_empty() {
+            return;
+        }
+        let total: isize = listing
+            .entries
+            .iter()
+            .chain(&listing.hidden)
+            .filter(|e| listing.since_visit.contains_key(&e.name))
+            .map(|e| {
+                let before = before.get(e.name.trim_end_matches('/'));
+                delta(e.size, before.map_or(0, |sample| sample.bytes))
+            })
+            .sum();
+        let changed = listing.since_visit.len();
+        let plus = if total > 0 { "+" } else { "" };
+        self.message = Some(Message {
+            text: format!(
+                "Since the last visit {}: {} {} changed, {}{} in all",
+                age_str(visited as usize),
+                count_str(changed),
+                if changed == 1 {
+                    "directory"
+                } else {
+                    "directories"
+                },
+                plus,
+                signed_str(total, |s| size_str(Some(s), false)),
+            ),
+            kind: MessageKind::Info,
+        });
+    }
+
+    /// What to tell the user about the listing they're looking at
+    fn listing_message(&self) -> Option<Message> {
+        if self.dir_listing.load_report.timed_out > 0 {
+            Some(Message {
+                text: format!(
+                    "{} entries timed out: the metadata server may be hung. \
+                     Press r to try again",
+                    count_str(self.dir_listing.load_report.timed_out)
+                ),
+                kind: MessageKind::Warning,
+            })
+        } else if self.dir_listing.has_more() {
+            Some(Message {
+                text: format!(
+                    "Gigantic directory: showing the first {} entries. \
+                     Press + to list more, or * to list all",
+                    self.dir_listing.entries.len()
+                ),
+                kind: MessageKind::Info,
+            })
+        } else if let Some(share) = self.dir_listing.share {
+            let text = match share {
+                Share::Nfs { ceph_xattrs: true } => {
+                    "NFS export of CephFS: recursive sizes are Ceph's, \
+                     but may be a little behind because NFS caches them"
+                }
+                Share::Nfs { ceph_xattrs: false } => {
+                    "NFS share without Ceph's xattrs: directory sizes and file \
+                     counts aren't known, file sizes are. Press d to add them up"
+                }
+                Share::Smb => {
+                    "SMB share: directory sizes and file counts don't come through SMB, \
+                     file sizes do. Press d to add them up"
+                }
+            };
+            Some(Message {
+                text: text.to_string(),
+                kind: MessageKind::Warning,
+            })
+        } else if let Some(rstats) = self.dir_listing.rstats {
+            Some(Message {
+                text: format!(
+                    "Not CephFS: directory sizes are only known where there are {}. \
+                     Press d to add up the others",
+                    rstats
+                ),
+                kind: MessageKind::Warning,
+            })
+        } else if !self.dir_listing.is_ceph() {
+            Some(Message {
                 text: "Warning: not a Ceph directory".to_string(),
                 kind: MessageKind::Warning,
-            }));
+            })
+        } else if self.dir_listing.load_report.lazy {
+            Some(Message {
+                text: format!(
+                    "Large directory ({} entries): sizes are loaded as you scroll",
+                    self.dir_listing.entries.len()
+                ),
+                kind: MessageKind::Info,
+            })
         } else {
-            self.message(None);
+            None
         }
-
-        // Restore the highlighted entry if we have one
-        self.restore_selected();
-        Ok(())
     }
 
-    pub fn popup(&mut self, title: Option<&str>, bottom_title: Option<&str>, text: Option<&str>) {
-        self.popup = text.map(|x| Popup::new(title.unwrap_or(""), bottom_title.unwrap_or(""), x));
+    /// List the next page of a directory that was listed one page at a time,
+    /// or all the rest of it.
+    pub fn load_more(&mut self, all: bool) {
+        if !self.dir_listing.has_more() {
+            return;
+        }
+        let limit = if all {
+            usize::MAX
+        } else {
+            self.loader.page_size
+        };
+        input::clear_interrupt();
+        let message = match self.dir_listing.load_more(limit, &input::INTERRUPTED) {
+            Ok(_) if input::INTERRUPTED.load(std::sync::atomic::Ordering::Relaxed) => {
+                Some(Message {
+                    text: format!(
+                        "Interrupted: {} entries listed so far",
+                        self.dir_listing.entries.len()
+                    ),
+                    kind: MessageKind::Warning,
+                })
+            }
+            Ok(_) => self.listing_message(),
+            Err(e) => Some(Message {
+                text: format!("Error listing directory: {}", e),
+                kind: MessageKind::Error,
+            }),
+        };
+        self.message(message);
     }
 
-    pub fn message(&mut self, message: Option<Message>) {
-        self.message = message;
+    /// Make `path` absolute. In logical mode, ".." drops the last component
+    /// of the path as navigated, like `cd -L`; symlinks are only resolved if
+    /// that doesn't lead to a directory.
+    fn resolve(&self, path: &Path) -> Result<PathBuf, io::Error> {
+        if self.logical_paths {
+            let base = if self.cwd.as_os_str().is_empty() {
+                logical_current_dir()?
+            } else {
+                self.cwd.clone()
+            };
+            let logical = normalize_lexically(&base.join(path));
+            if is_dir(&logical) {
+                return Ok(logical);
+            }
+        }
+        provider::get().canonicalize(&self.cwd.join(path))
     }
 
-    pub fn help(&mut self) {
-        let lhs_width = navigation::HELP
+    /// Whether Enter should open this entry
+    /// Open the biggest subdirectory, and then its biggest one, and so on,
+    /// for as long as it holds at least half of the space: stop where the
+    /// space is in files, or spread out over several directories
+    pub fn drill_to_biggest(&mut self) {
+        let start = self.cwd.clone();
+        let mut levels = 0;
+        let reason = loop {
+            if levels == MAX_DRILL_LEVELS {
+                break "stopped, in case that's a loop";
+            }
+            let listing = &self.dir_listing;
+            if !listing.is_ceph() || listing.load_report.lazy || listing.has_more() {
+                break "the sizes here aren't known";
+            }
+            let total = listing.stats.total_size;
+            let biggest = listing
+                .entries
+                .iter()
+                .filter(|e| self.can_enter(e))
+                .max_by_key(|e| e.size.unwrap_or(0));
+            let Some(biggest) = biggest else {
+                break "there are no subdirectories here";
+            };
+            if biggest.size.unwrap_or(0) * 2 < total || total == 0 {
+                break "no subdirectory holds most of the space";
+            }
+            let name = biggest.name.clone();
+            self.dir_listing.select_by_name(&name);
+            if let Err(e) = self.try_cd(Path::new(name.as_str())) {
+                self.message(Some(Message {
+                    text: format!("Error changing directory: {}", e),
+                    kind: MessageKind::Error,
+                }));
+                return;
+            }
+            levels += 1;
+        };
+        // Start on what takes the space here
+        let biggest = self
+            .dir_listing
+            .entries
             .iter()
-            .map(|h| h[0].len())
-            .max()
-            .unwrap_or(0);
-        let rhs_width = navigation::HELP
+            .max_by_key(|e| e.size.unwrap_or(0))
+            .map(|e| e.name.clone());
+        if levels > 0
+            && let Some(name) = biggest
+        {
+            self.dir_listing.select_by_name(&name);
+        }
+        let text = match levels {
+            0 => format!("Nothing to drill into: {}", reason),
+            _ => format!(
+                "Drilled {} level{} down from {}: {}",
+                levels,
+                if levels == 1 { "" } else { "s" },
+                start.display(),
+                reason
+            ),
+        };
+        self.message(Some(Message {
+            text,
+            kind: MessageKind::Info,
+        }));
+    }
+
+    pub fn can_enter(&self, entry: &DirEntry) -> bool {
+        match entry.kind {
+            EntryKind::Dir => true,
+            EntryKind::Symlink => self.logical_paths && is_dir(&self.cwd.join(&entry.name)),
+            EntryKind::File => false,
+        }
+    }
+
+    /// Whether `entry` is big enough to match one of the alert rules
+    pub fn is_alert(&self, entry: &DirEntry) -> bool {
+        self.alerts
             .iter()
-            .map(|h| h[1].len())
-            .max()
-            .unwrap_or(0);
+            .any(|rule| rule.matches(entry.size, entry.rentries))
+    }
+
+    /// How many of the entries match an alert rule
+    pub fn alert_count(&self) -> usize {
+        if self.alerts.is_empty() {
+            return 0;
+        }
+        self.dir_listing
+            .iter_entries()
+            .filter(|entry| self.is_alert(entry))
+            .count()
+    }
+
+    /// The sort and columns as they are now
+    pub fn current_view(&self) -> View {
+        let sort_mode = self.dir_listing.sort_mode();
+        View {
+            sort: *sort_mode.field(),
+            reversed: sort_mode.is_reversed(),
+            owner: self.show_owner,
+            group: self.show_group,
+            numeric_ids: self.numeric_ids,
+            permissions: self.show_permissions,
+            ctime: self.show_ctime,
+            average: self.show_average,
+            cost: self.show_cost,
+            hide_dotfiles: self.loader.hide_dotfiles,
+        }
+    }
+
+    fn apply_view(&mut self, view: View) {
+        self.show_owner = view.owner;
+        self.show_group = view.group;
+        if view.numeric_ids != self.numeric_ids {
+            self.numeric_ids = view.numeric_ids;
+            self.dir_listing.owner_widths = (0, 0);
+        }
+        self.show_permissions = view.permissions;
+        self.show_ctime = view.ctime;
+        self.show_average = view.average;
+        self.show_cost = view.cost && self.cost.is_set();
+        self.loader.hide_dotfiles = view.hide_dotfiles;
+        self.dir_listing.sort(view.sort_mode());
+        self.dir_listing.set_hide_dotfiles(view.hide_dotfiles);
+    }
+
+    /// The nearest directory at or above `dir` with a saved view, and the
+    /// view to show `dir` with: the saved one when entering its directory,
+    /// the default one when leaving, and otherwise the current one.
+    fn view_for(&self, dir: &Path) -> (Option<PathBuf>, View) {
+        let saved = dir
+            .ancestors()
+            .find(|d| self.views.contains_key(*d))
+            .map(Path::to_path_buf);
+        let view = if saved == self.view_dir {
+            self.current_view()
+        } else {
+            saved.as_ref().map_or(self.default_view, |d| self.views[d])
+        };
+        (saved, view)
+    }
+
+    /// Save the view for the current directory and everything below it, or
+    /// forget it if it's already saved just like this.
+    pub fn save_view(&mut self) {
+        let view = self.current_view();
+        let forget = self.views.get(&self.cwd) == Some(&view);
+        let (mut text, mut kind) = if forget {
+            self.views.remove(&self.cwd);
+            (
+                format!("Forgot the view for {}", self.cwd.display()),
+                MessageKind::Info,
+            )
+        } else {
+            self.views.insert(self.cwd.clone(), view);
+            (
+                format!("Saved the view for {} and below", self.cwd.display()),
+                MessageKind::Info,
+            )
+        };
+        self.view_dir = self
+            .cwd
+            .ancestors()
+            .find(|d| self.views.contains_key(*d))
+            .map(Path::to_path_buf);
+        if self.persist
+            && let Err(e) = views::save(&self.cwd, (!forget).then_some(view))
+        {
+            text = format!("{}, but couldn't save it: {}", text, e);
+            kind = MessageKind::Warning;
+        }
+        self.message(Some(Message { text, kind }));
+    }
+
+    /// Switch where directory sizes come from, for this and later listings.
+    pub fn toggle_size_source(&mut self) {
+        let source = self.loader.size_source.other();
+        self.loader.size_source = source;
+        self.dir_listing.set_size_source(source);
+        self.message(Some(Message {
+            text: format!("Directory sizes from {}", source.name()),
+            kind: MessageKind::Info,
+        }));
+    }
+
+    /// Show what the entries cost a month, if the config has rates
+    pub fn toggle_cost(&mut self) {
+        if !self.cost.is_set() {
+            self.message(Some(Message {
+                text: "No storage costs configured; set cost.per_tb_month".to_string(),
+                kind: MessageKind::Warning,
+            }));
+            return;
+        }
+        self.show_cost = !self.show_cost;
+    }
+
+    /// Show sizes and counts in full, or rounded again
+    pub fn toggle_exact_sizes(&mut self) {
+        let exact = !exact_sizes();
+        set_exact_sizes(exact);
+        self.message(Some(Message {
+            text: if exact {
+                "Showing exact sizes"
+            } else {
+                "Showing rounded sizes"
+            }
+            .to_string(),
+            kind: MessageKind::Info,
+        }));
+    }
+
+    /// Show or hide dotfiles, in this and later listings.
+    pub fn toggle_dotfiles(&mut self) {
+        let hide = !self.loader.hide_dotfiles;
+        self.loader.hide_dotfiles = hide;
+        self.dir_listing.set_hide_dotfiles(hide);
+        self.message(Some(Message {
+            text: if hide {
+                "Hiding dotfiles"
+            } else {
+                "Showing dotfiles"
+            }
+            .to_string(),
+            kind: MessageKind::Info,
+        }));
+    }
+
+    /// Read the config file again and apply it, keeping the caches warm.
+    /// The history database is only opened at startup.
+    pub fn reload_config(&mut self) {
+        let config = match self.overrides.load() {
+            Ok(config) => config,
+            Err(e) => {
+                // Only one line fits: leave out the snippet that points at the error
+                let lines: Vec<&str> = e.lines().filter(|l| !l.trim().is_empty()).collect();
+                let text = match lines[..] {
+                    [first, .., last] => format!("{} {}", first, last),
+                    _ => e.clone(),
+                };
+                self.message(Some(Message {
+                    text,
+                    kind: MessageKind::Error,
+                }));
+                return;
+            }
+        };
+
+        match config.project_map() {
+            Ok(projects) => self.projects = projects,
+            Err(e) => self.message(Some(Message {
+                text: e,
+                kind: MessageKind::Error,
+            })),
+        }
+        self.loader.timeout = config.metadata_timeout();
+        self.show_unaccounted = config.show_unaccounted;
+        self.logical_paths = config.logical_paths;
+        self.plain = config.plain;
+        self.keymap = config.keys;
+        self.theme = config.theme;
+        self.alerts = config.alerts;
+        self.show_hints = config.hint_bar;
+        self.notify = config.notify;
+        set_exact_sizes(config.exact_sizes);
+        set_units(config.units);
+
+        self.show_cost &= config.cost.is_set();
+        self.cost = config.cost.clone();
+        self.scroll_off = config.scroll_off;
+        self.refresh_interval = Duration::from_secs(config.refresh_interval_secs);
+        self.persist = config.persist;
+        self.subvolume_config = config.subvolumes;
+        self.hooks = config.hooks;
+        self.plugins = config.plugins;
+        self.search_config = config.search;
+        self.exclude = config.exclude;
+        self.retention = config.retention;
+        self.cleanup_config = config.cleanup;
+        self.loader.lazy_threshold = config.lazy_threshold;
+        self.loader.page_size = config.page_size;
+        self.loader.size_source = config.dir_sizes;
+        self.loader.hide_dotfiles = config.hide_dotfiles;
+        self.dir_listing.set_size_source(config.dir_sizes);
+        self.dir_listing.set_hide_dotfiles(config.hide_dotfiles);
+        self.listing_cache.set_limits(config.cache.listings);
+        self.prefetcher.set_limits(config.cache.prefetch);
+        let names = config.cache.names;
+        set_name_cache_limits(names.max_entries, Duration::from_secs(names.ttl_secs));
+        rstats::configure(&config.recursive_stats);
+        uring::configure(config.io_uring);
+        if config.live_update != self.watcher.is_some() {
+            self.watcher = config.live_update.then(|| Watcher::new().ok()).flatten();
+            if let Some(watcher) = &mut self.watcher
+                && !self.dir_listing.load_report.lazy
+            {
+                let _ = watcher.watch(Some(&self.cwd));
+            }
+        }
+
+        self.message(Some(Message {
+            text: match &self.overrides.path {
+                Some(path) => format!("Reloaded {:?}", path),
+                None => "Reloaded the config".to_string(),
+            },
+            kind: MessageKind::Info,
+        }));
+    }
+
+    /// Throw away all cached listings and names, and reload the current directory.
+    pub fn flush_caches(&mut self) {
+        self.listing_cache.clear();
+        self.prefetcher.clear();
+        self.deep_sizer.clear();
+        self.subvolumes.clear();
+        crate::fs::clear_name_caches();
+        let message = match self.try_cd(&self.cwd.clone()) {
+            Ok(()) => Message {
+                text: "Flushed all caches".to_string(),
+                kind: MessageKind::Info,
+            },
+            Err(e) => Message {
+                text: format!("Error changing directory: {}", e),
+                kind: MessageKind::Error,
+            },
+        };
+        self.message(Some(message));
+    }
+
+    /// Called periodically from the event loop. Returns true if the screen needs redrawing.
+    pub fn tick(&mut self) -> bool {
+        self.prefetcher.poll();
+        let sorted = self.dir_listing.finish_sort();
+        let refreshed = self.poll_watcher();
+        let sized = self.poll_deep_sizer();
+        let timed_out = self.dir_listing.load_report.timed_out;
+        let loaded = self.dir_listing.load_visible();
+        if self.dir_listing.load_report.timed_out > timed_out {
+            self.message(self.listing_message());
+        }
+        let unhighlighted = self.dir_listing.expire_changed();
+        let jumped = self.expire_jump();
+        let reloaded = terminal::take_reload_request();
+        if reloaded {
+            self.reload_config();
+        }
+
+        let highlighted = self
+            .dir_listing
+            .selected()
+            .map(|i| self.dir_listing.get(i))
+            .filter(|e| e.kind == EntryKind::Dir && e.name != "..")
+            .map(|e| self.cwd.join(&e.name));
+
+        match (&self.prefetch_candidate, highlighted) {
+            (Some((path, since)), Some(highlighted)) if *path == highlighted => {
+                if since.elapsed() >= PREFETCH_DELAY {
+                    self.prefetcher.request(
+                        path,
+                        self.dir_listing.sort_mode,
+                        self.loader.options(),
+                    );
+                }
+            }
+            (_, highlighted) => {
+                self.prefetcher.forget_failure();
+                self.prefetch_candidate = highlighted.map(|p| (p, Instant::now()));
+            }
+        }
+
+        let peeked = self.poll_peek();
+        let looked_up = self.poll_subvolumes();
+        let hooked = self.poll_hooks();
+        let plugged = self.poll_plugins();
+        let controlled = self.poll_control();
+        let searched = self.poll_search();
+        let pooled = self.poll_pool_scan();
+        let simulated = self.poll_retention_scan();
+        let cleaned = self.poll_cleanup();
+        // Running jobs' progress changes on its own
+        let jobs_changed = self.jobs_panel.is_some() && (sized || self.jobs.running() > 0);
+        if jobs_changed {
+            self.update_jobs_panel();
+        }
+
+        // Otherwise, only the debug overlay shows anything that changes on its own
+        peeked
+            || looked_up
+            || hooked
+            || plugged
+            || controlled
+            || searched
+            || pooled
+            || simulated
+            || cleaned
+            || sorted
+            || jobs_changed
+            || refreshed
+            || sized
+            || loaded
+            || unhighlighted
+            || jumped
+            || reloaded
+            || self.show_debug
+    }
+
+    /// Show the biggest entries of the highlighted directory in a popup,
+    /// once the prefetcher has listed it, without opening it
+    pub fn peek(&mut self) {
+        let Some(selected) = self.dir_listing.selected() else {
+            return;
+        };
+        let entry = self.dir_listing.get(selected);
+        if !self.can_enter(entry) || entry.name == ".." {
+            return;
+        }
+        let path = self.cwd.join(&entry.name);
+        let title = format!("Peek: {}", entry.name);
+        self.prefetcher
+            .request(&path, self.dir_listing.sort_mode, self.loader.options());
+        self.popup(Some(&title), None, Some("Listing..."));
+        self.peeking = Some(path);
+        self.poll_peek();
+    }
+
+    /// Add up the entries of the directory by project, or by owner if no
+    /// projects are configured, to see whose allocation the space counts against
+    pub fn project_usage(&mut self) {
+        let entries: Vec<&DirEntry> = self
+            .dir_listing
+            .entries
+            .iter()
+            .chain(&self.dir_listing.hidden)
+            .filter(|e| self.dir_listing.mounted_on(e).is_none())
+            .collect();
+        let usage = self.projects.usage(entries);
+        let title = if self.projects.is_empty() {
+            "Usage by owner (no projects file)"
+        } else {
+            "Usage by project"
+        };
+        let text = if usage.is_empty() {
+            "Nothing here".to_string()
+        } else {
+            let total = self.dir_listing.stats.child_size.max(1);
+            usage
+                .iter()
+                .map(|(project, size, files)| {
+                    format!(
+                        "{:>8}  {:>5.1}%  {:>7} files  {}",
+                        size_str(Some(*size), false),
+                        100.0 * *size as f64 / total as f64,
+                        rentries_str(Some(*files), false),
+                        project
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        self.popup(Some(title), None, Some(&text));
+    }
+
+    /// Add up the entries of the directory by the data pool they're on, e.g.
+    /// before moving data to an erasure-coded pool. Again in the popup, add
+    /// up every file under the directory in the background, which is exact.
+    pub fn pool_usage(&mut self) {
+        if !self.dir_listing.is_ceph() {
+            self.message(Some(Message {
+                text: "Data pools are only known on CephFS".to_string(),
+                kind: MessageKind::Info,
+            }));
+            return;
+        }
+        let key = self.keymap.first_key_for(Action::PoolUsage);
+        let scan = self.pool_scan.as_ref().filter(|scan| scan.root == self.cwd);
+        let (title, bottom, text) = match scan.map(|scan| &scan.result) {
+            Some(Some(Ok(usage))) if !self.pool_panel => (
+                "Usage by data pool, of every file",
+                key.map(|key| format!("{} Count again", key)),
+                self.pool_text(usage),
+            ),
+            Some(None) => (
+                "Usage by data pool",
+                None,
+                "Counting every file in the background (J)...".to_string(),
+            ),
+            // Asked again, with the popup open, or after the walk failed
+            _ if self.pool_panel || scan.is_some() => {
+                let description = format!("Adding up {} by data pool", self.cwd.display());
+                let (job, progress) = self.jobs.start(description, self.cwd.clone());
+                self.pool_scan = Some(PoolScan::start(&self.cwd, &self.exclude, job, progress));
+                (
+                    "Usage by data pool",
+                    None,
+                    "Counting every file in the background (J)...".to_string(),
+                )
+            }
+            _ => {
+                let entries = self
+                    .dir_listing
+                    .entries
+                    .iter()
+                    .chain(&self.dir_listing.hidden)
+                    .filter(|e| e.name != ".." && self.dir_listing.mounted_on(e).is_none());
+                let usage =
+                    pools::usage(&self.cwd, get_effective_pool(&self.cwd).as_deref(), entries);
+                let bottom = key.map(|key| format!("{} Count every file", key));
+                (
+                    "Usage by data pool, of directories as a whole",
+                    bottom,
+                    self.pool_text(&usage),
+                )
+            }
+        };
+        self.popup(Some(title), bottom.as_deref(), Some(&text));
+        self.pool_panel = true;
+    }
+
+    /// A line per pool, with its share of the total and what it costs
+    fn pool_text(&self, usage: &[PoolUsage]) -> String {
+        if usage.is_empty() {
+            return "Nothing here".to_string();
+        }
+        let total = usage.iter().map(|u| u.bytes).sum::<usize>().max(1);
+        let lines: Vec<String> = usage
+            .iter()
+            .map(|u| {
+                let cost = self
+                    .cost
+                    .monthly(u.bytes, Some(&u.pool))
+                    .map(|cost| format!("  {:>9}/mo", cost_str(cost, &self.cost.currency)))
+                    .unwrap_or_default();
+                format!(
+                    "{:>8}  {:>5.1}%  {:>7} files{}  {}",
+                    size_str(Some(u.bytes), false),
+                    100.0 * u.bytes as f64 / total as f64,
+                    rentries_str(Some(u.files), false),
+                    cost,
+                    u.pool
+                )
+            })
+            .collect();
+        let width = lines
+            .iter()
+            .map(|line| line.chars().count())
+            .max()
+            .unwrap_or(0);
+        lines
+            .iter()
+            .map(|line| format!("{:width$}", line))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Finish the job of the pool walk once it's done, and show its result
+    /// if its popup is still open. Returns true if it finished.
+    fn poll_pool_scan(&mut self) -> bool {
+        let Some(scan) = &mut self.pool_scan else {
+            return false;
+        };
+        if !scan.poll() {
+            return false;
+        }
+        let summary = match &scan.result {
+            Some(Ok(usage)) => Ok(format!("{} pools", usage.len())),
+            Some(Err(e)) => Err(e.clone()),
+            None => return false,
+        };
+        let (job, root) = (scan.job, scan.root.clone());
+        self.jobs.finish(job, summary);
+        if self.pool_panel && root == self.cwd {
+            // Showing the result, rather than asking again
+            self.pool_panel = false;
+            self.pool_usage();
+        }
+        true
+    }
+
+    /// The age that the retention simulator starts at: the longest one
+    /// counted last time here, or what the retention policy says
+    pub fn retention_days(&self) -> u64 {
+        self.retention_scan
+            .as_ref()
+            .filter(|scan| scan.root == self.cwd)
+            .and_then(|scan| scan.days.last().copied())
+            .or_else(|| RetentionPolicy::covering(&self.retention, &self.cwd).map(|p| p.days))
+            .unwrap_or(90)
+    }
+
+    /// Show what deleting everything here that's older than `days` would
+    /// free, first counting it in the background unless the last count did
+    pub fn simulate_retention(&mut self, days: u64) {
+        let last = self
+            .retention_scan
+            .as_ref()
+            .filter(|scan| scan.root == self.cwd);
+        let counted = last
+            .is_some_and(|scan| scan.days.contains(&days) && !matches!(scan.result, Some(Err(_))));
+        if !counted {
+            // The shorter ages come with it, including ones typed in before
+            let typed = last.map(|scan| scan.days.clone()).unwrap_or_default();
+            let ages: Vec<u64> = RETENTION_STEPS
+                .into_iter()
+                .chain(typed)
+                .filter(|&d| d < days)
+                .chain([days])
+                .collect();
+            // One count at a time
+            let running = self
+                .retention_scan
+                .take()
+                .filter(|scan| scan.result.is_none());
+            let index = running.and_then(|scan| {
+                let i = self.jobs.iter().position(|j| j.id == scan.job)?;
+                Some((i, scan.job))
+            });
+            if let Some((i, job)) = index {
+                self.jobs.cancel(i);
+                self.jobs.finish(job, Err("cancelled".to_string()));
+            }
+            let description = format!(
+                "Counting what under {} is older than {} days",
+                self.cwd.display(),
+                days
+            );
+            let (job, progress) = self.jobs.start(description, self.cwd.clone());
+            self.retention_scan = Some(RetentionScan::start(
+                &self.cwd,
+                ages,
+                self.loader.options(),
+                &self.exclude,
+                job,
+                progress,
+            ));
+        }
+        self.show_retention(days);
+    }
+
+    /// Open the retention simulator at `days`, or update it
+    fn show_retention(&mut self, days: u64) {
+        let text = self.retention_text(days);
+        let bottom = match self.keymap.first_key_for(Action::Retention) {
+            Some(key) => format!("\u{2190}\u{2192} Age  {} Type one  Esc Close", key),
+            None => "\u{2190}\u{2192} Age  Esc Close".to_string(),
+        };
+        let scroll = self
+            .retention_panel
+            .and(self.popup.as_ref().map(|popup| popup.scroll()));
+        // The line of `days`, after the summary and the header
+        let selected = self
+            .retention_scan
+            .as_ref()
+            .and_then(|scan| scan.ages().iter().position(|&age| age == days))
+            .map_or(0, |i| i + 2);
+        self.popup(Some("Retention simulator"), Some(&bottom), Some(&text));
+        if let Some(popup) = &mut self.popup {
+            let scroll = scroll.unwrap_or(0);
+            popup.scroll_to(
+                scroll.clamp((selected + 1).saturating_sub(POPUP_TEXT_HEIGHT), selected),
+            );
+        }
+        self.retention_panel = Some(days);
+    }
+
+    /// What deleting what's older than `days` would free, and a line per age
+    /// to step through, with `days` marked
+    fn retention_text(&self, days: u64) -> String {
+        let Some(scan) = self
+            .retention_scan
+            .as_ref()
+            .filter(|scan| scan.root == self.cwd)
+        else {
+            return String::new();
+        };
+        let all = match &scan.result {
+            Some(Ok((_, all))) => Some(*all),
+            Some(Err(e)) => return format!("Couldn't count what's here: {}", e),
+            None => None,
+        };
+        let share = |bytes: usize| match all {
+            Some(all) => format!("{:.0}%", bytes as f64 / all.bytes.max(1) as f64 * 100.0),
+            None => String::new(),
+        };
+        let summary = match (scan.older_than(days), all) {
+            (Some(older), Some(all)) => format!(
+                "Unchanged for {} days: {} of {} ({}), {} files",
+                days,
+                size_str(Some(older.bytes), false),
+                size_str(Some(all.bytes), false),
+                share(older.bytes),
+                rentries_str(Some(older.files), false)
+            ),
+            _ => format!(
+                "Counting what's older than {} days in the background (J)...",
+                days
+            ),
+        };
+        let mut lines = vec![format!(
+            "  {:>10}  {:>10}  {:>8}  {:>5}  {:>7}",
+            "Older than", "Since", "Frees", "Share", "Files"
+        )];
+        for age in scan.ages() {
+            let counts = match scan.older_than(age) {
+                Some(older) => format!(
+                    "{:>8}  {:>5}  {:>7}",
+                    size_str(Some(older.bytes), false),
+                    share(older.bytes),
+                    rentries_str(Some(older.files), false)
+                ),
+                None => String::new(),
+            };
+            lines.push(format!(
+                "{} {:>5} days  {:>10}  {}",
+                if age == days { '>' } else { ' ' },
+                age,
+                date_str(cutoff(age)),
+                counts
+            ));
+        }
+        let width = lines
+            .iter()
+            .map(|line| line.chars().count())
+            .max()
+            .unwrap_or(0);
+        let table: Vec<String> = lines
+            .iter()
+            .map(|line| format!("{:width$}", line))
+            .collect();
+        format!("{}\n{}", summary, table.join("\n"))
+    }
+
+    /// Finish the job of the retention count once it's done, and show its
+    /// result if the simulator is still open. Returns true if it finished.
+    fn poll_retention_scan(&mut self) -> bool {
+        let Some(scan) = &mut self.retention_scan else {
+            return false;
+        };
+        if !scan.poll() {
+            return false;
+        }
+        let days = scan.days.last().copied().unwrap_or(0);
+        let summary = match &scan.result {
+            Some(Ok(_)) => Ok(format!(
+                "{} older than {} days",
+                size_str(scan.older_than(days).map(|older| older.bytes), false),
+                days
+            )),
+            Some(Err(e)) => Err(e.clone()),
+            None => return false,
+        };
+        let (job, root) = (scan.job, scan.root.clone());
+        self.jobs.finish(job, summary);
+        if let Some(days) = self.retention_panel
+            && root == self.cwd
+        {
+            self.show_retention(days);
+        }
+        true
+    }
+
+    /// Open the cleanup wizard with what its last search here found, or
+    /// search anew if it's asked for again while open, or the last one failed
+    pub fn cleanup(&mut self) {
+        if !self.dir_listing.is_ceph() {
+            self.message(Some(Message {
+                text: "The cleanup wizard goes by the recursive stats of CephFS".to_string(),
+                kind: MessageKind::Info,
+            }));
+            return;
+        }
+        let last = self.cleanup.as_ref().filter(|scan| scan.root == self.cwd);
+        let again = self.cleanup_panel.is_some() && self.popup.is_some();
+        if last.is_none_or(|scan| again || matches!(scan.result, Some(Err(_)))) {
+            // One search at a time
+            let running = self.cleanup.take().filter(|scan| scan.result.is_none());
+            let index = running.and_then(|scan| {
+                let i = self.jobs.iter().position(|j| j.id == scan.job)?;
+                Some((i, scan.job))
+            });
+            if let Some((i, job)) = index {
+                self.jobs.cancel(i);
+                self.jobs.finish(job, Err("cancelled".to_string()));
+            }
+            let description = format!("Looking for what to clean up under {}", self.cwd.display());
+            let (job, progress) = self.jobs.start(description, self.cwd.clone());
+            self.cleanup = Some(CleanupScan::start(
+                &self.cwd,
+                self.retention_days(),
+                self.loader.options(),
+                &self.exclude,
+                &self.cleanup_config,
+                job,
+                progress,
+            ));
+            self.cleanup_panel = None;
+        }
+        let selected = self.cleanup_panel.unwrap_or(0);
+        let (title, text) = self.cleanup_text(selected);
+        let bottom = "x Mark  Enter Delete marked  \u{2192} Go to  Esc Close";
+        self.popup(Some(&title), Some(bottom), Some(&text));
+        self.cleanup_panel = Some(selected);
+        self.update_cleanup_panel();
+    }
+
+    pub fn update_cleanup_panel(&mut self) {
+        let (Some(selected), Some(scan)) = (self.cleanup_panel, &self.cleanup) else {
+            return;
+        };
+        let selected = selected.min(scan.candidates().len().saturating_sub(1));
+        self.cleanup_panel = Some(selected);
+        let (title, text) = self.cleanup_text(selected);
+        if let Some(popup) = &mut self.popup {
+            let scroll = popup.scroll();
+            popup.title = title;
+            popup.set_text(&text);
+            popup.scroll_to(
+                scroll.clamp((selected + 1).saturating_sub(POPUP_TEXT_HEIGHT), selected),
+            );
+        }
+    }
+
+    /// The title of the cleanup wizard, with how its search is going and
+    /// what's marked, and a line per candidate, with the one at `selected`
+    /// highlighted
+    fn cleanup_text(&self, selected: usize) -> (String, String) {
+        let Some(scan) = &self.cleanup else {
+            return (String::new(), String::new());
+        };
+        let candidates = scan.candidates();
+        let status = match &scan.result {
+            None => "looking in the background (J)".to_string(),
+            Some(Ok(_)) => format!(
+                "{} found, {} marked ({})",
+                count_str(candidates.len()),
+                count_str(scan.marked().count()),
+                size_str(Some(scan.marked().map(|c| c.bytes).sum()), false)
+            ),
+            Some(Err(e)) => e.clone(),
+        };
+        let title = format!("Cleanup of {}: {}", scan.root.display(), status);
+        let relative = |path: &Path| {
+            let path = path.strip_prefix(&scan.root).unwrap_or(path);
+            path.display().to_string()
+        };
+        let mut lines: Vec<String> = candidates
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let marker = if i == selected { '>' } else { ' ' };
+                let mark = if c.marked { 'x' } else { ' ' };
+                let prefix = format!(
+                    "{} [{}] {:>8} {:>7}  ",
+                    marker,
+                    mark,
+                    size_str(Some(c.bytes), false),
+                    rentries_str(Some(c.files), false)
+                );
+                let reason = match &c.reason {
+                    Reason::Old => format!("unchanged in {} days", scan.days),
+                    Reason::Matches(pattern) => format!("like {}", pattern),
+                    Reason::CopyOf(kept) => format!("copy of {}", relative(kept)),
+                };
+                let reason = elide_start(&reason, CLEANUP_WIDTH / 3);
+                let slash = if c.kind == EntryKind::Dir { "/" } else { "" };
+                let path = format!("{}{}", relative(&c.path), slash);
+                let room = CLEANUP_WIDTH
+                    .saturating_sub(prefix.chars().count() + reason.chars().count() + 2);
+                format!("{}{:room$}  {}", prefix, elide_start(&path, room), reason)
+            })
+            .collect();
+        if lines.is_empty() {
+            lines.push(match scan.result {
+                None => "  looking…".to_string(),
+                Some(_) => "  nothing found".to_string(),
+            });
+        }
+        let width = CLEANUP_WIDTH.max(title.chars().count());
+        let text = lines
+            .iter()
+            .map(|line| format!("{:w$}\n", line, w = width))
+            .collect();
+        (title, text)
+    }
+
+    /// Ask whether to go ahead with deleting what's marked in the cleanup
+    /// wizard: with y, or above the thresholds in the config, by typing the
+    /// name of what's marked, or how many files there are in all
+    pub fn ask_to_delete(&mut self) {
+        let Some(scan) = &self.cleanup else {
+            return;
+        };
+        let (count, bytes, files) = scan
+            .marked()
+            .fold((0, 0, 0), |(n, b, f), c| (n + 1, b + c.bytes, f + c.files));
+        let what = format!(
+            "{} marked, {} in {} files",
+            count_str(count),
+            size_str(Some(bytes), false),
+            rentries_str(Some(files), false)
+        );
+        let (text, kind) = if self.deletion.as_ref().is_some_and(|d| d.result.is_none()) {
+            (
+                "Still deleting what was marked before".to_string(),
+                MessageKind::Info,
+            )
+        } else if count == 0 {
+            (
+                "Nothing is marked; x marks what's highlighted".to_string(),
+                MessageKind::Info,
+            )
+        } else if let Some(typed) = self.deletion_confirmation() {
+            self.prompt = Some(Prompt {
+                label: format!("Delete {}? Type \"{}\" to go ahead", what, typed),
+                text: String::new(),
+                kind: PromptKind::ConfirmDelete,
+            });
+            self.show_prompt();
+            return;
+        } else {
+            self.confirm_cleanup = true;
+            (
+                format!("Delete {}? y to go ahead, anything else to keep them", what),
+                MessageKind::Warning,
+            )
+        };
+        self.message(Some(Message { text, kind }));
+    }
+
+    /// What has to be typed to delete what's marked, if it's more than the
+    /// config lets go with a keypress: its name, if it's one thing, or how
+    /// many files there are
+    fn deletion_confirmation(&self) -> Option<String> {
+        let scan = self.cleanup.as_ref()?;
+        let bytes: usize = scan.marked().map(|c| c.bytes).sum();
+        let files: usize = scan.marked().map(|c| c.files).sum();
+        if bytes <= self.cleanup_config.typed_confirm_bytes
+            && files <= self.cleanup_config.typed_confirm_files
+        {
+            return None;
+        }
+        let name = match scan.marked().collect::<Vec<_>>()[..] {
+            [one] => one
+                .path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned()),
+            _ => None,
+        };
+        name.or_else(|| Some(format!("yes, {} files", files)))
+    }
+
+    /// Take the answer to `ask_to_delete()`: the key that was pressed, if it
+    /// asked for y, or else what was typed into its prompt
+    pub fn answer_deletion(&mut self, answer: &str) {
+        if std::mem::take(&mut self.confirm_cleanup) {
+            if answer == "y" && self.deletion_confirmation().is_none() {
+                self.delete_marked();
+            } else {
+                self.keep_marked();
+            }
+            return;
+        }
+        self.confirm_deletion(answer);
+    }
+
+    /// Delete what's marked if `typed` is what `deletion_confirmation()` asked for
+    fn confirm_deletion(&mut self, typed: &str) {
+        match self.deletion_confirmation() {
+            Some(confirmation) if confirmation == typed.trim() => self.delete_marked(),
+            _ => self.keep_marked(),
+        }
+    }
+
+    fn keep_marked(&mut self) {
+        self.message(Some(Message {
+            text: "Nothing was deleted".to_string(),
+            kind: MessageKind::Info,
+        }));
+    }
+
+    /// Delete what's marked in the cleanup wizard, in the background, unless
+    /// what was marked before is still being deleted
+    fn delete_marked(&mut self) {
+        let Some(scan) = &self.cleanup else {
+            return;
+        };
+        if self.deletion.as_ref().is_some_and(|d| d.result.is_none()) {
+            return;
+        }
+        let marked: Vec<_> = scan.marked().cloned().collect();
+        let root = scan.root.clone();
+        let count = marked.len();
+        let description = format!("Deleting {} under {}", count_str(count), root.display());
+        let (job, progress) = self.jobs.start(description, root.clone());
+        self.deletion = Some(Deletion::start(&root, marked, scan.days, job, progress));
+        self.popup(None, None, None);
+        self.message(Some(Message {
+            text: format!("Deleting {} in the background (J)...", count_str(count)),
+            kind: MessageKind::Info,
+        }));
+    }
+
+    /// Finish the jobs of the cleanup wizard's search and deletion once
+    /// they're done, and say how the deletion went. Returns true if either
+    /// finished.
+    fn poll_cleanup(&mut self) -> bool {
+        let mut changed = false;
+        if let Some(scan) = &mut self.cleanup
+            && scan.poll()
+        {
+            let summary = match &scan.result {
+                Some(Ok(candidates)) => Ok(format!("{} found", count_str(candidates.len()))),
+                Some(Err(e)) => Err(e.clone()),
+                None => Err("the search died".to_string()),
+            };
+            self.jobs.finish(scan.job, summary);
+            self.update_cleanup_panel();
+            changed = true;
+        }
+        if let Some(deletion) = &mut self.deletion
+            && deletion.poll()
+            && let Some(deleted) = &deletion.result
+        {
+            let freed = format!(
+                "Deleted {}, freeing {}",
+                count_str(deleted.paths.len()),
+                size_str(Some(deleted.bytes), false)
+            );
+            let (summary, message) = match &deleted.failed[..] {
+                [] => (Ok(freed.clone()), freed),
+                [(path, e), rest @ ..] => {
+                    let more = match rest.len() {
+                        0 => String::new(),
+                        n => format!(", and {} more", count_str(n)),
+                    };
+                    let failed = format!("delete {}: {}{}", path.display(), e, more);
+                    let message = match deleted.paths.len() {
+                        0 => format!("Couldn't {}", failed),
+                        _ => format!("{}; couldn't {}", freed, failed),
+                    };
+                    (Err(format!("couldn't {}", failed)), message)
+                }
+            };
+            let kind = match summary {
+                Ok(_) => MessageKind::Info,
+                Err(_) => MessageKind::Warning,
+            };
+            // What's gone isn't offered again
+            if let Some(scan) = &mut self.cleanup {
+                scan.forget(&deleted.paths);
+            }
+            self.jobs.finish(deletion.job, summary);
+            // Listing again says nothing, unless something's wrong
+            self.refresh();
+            self.message(Some(Message {
+                text: message,
+                kind,
+            }));
+            changed = true;
+        }
+        changed
+    }
+
+    /// Fill in the peek popup if the listing is done. Returns true if it changed.
+    fn poll_peek(&mut self) -> bool {
+        let Some(path) = &self.peeking else {
+            return false;
+        };
+        let Some(popup) = &self.popup else {
+            // Closed before the listing was done
+            self.peeking = None;
+            return false;
+        };
+        let title = popup.title.clone();
+        let text = match self.prefetcher.get(path).map(peek_text) {
+            Some(text) => text,
+            None if self.prefetcher.in_flight() == Some(path.as_path()) => return false,
+            None => "Couldn't list it; open it to see why".to_string(),
+        };
+        self.popup(Some(&title), None, Some(&text));
+        self.peeking = None;
+        true
+    }
+
+    /// Show what was looked up about a subvolume, if the details popup is
+    /// still waiting for it. Returns true if it changed.
+    fn poll_subvolumes(&mut self) -> bool {
+        let done = self.subvolumes.poll();
+        let Some(path) = &self.detailing else {
+            return false;
+        };
+        if !done.contains(path) {
+            return false;
+        }
+        self.detailing = None;
+        // Unless it was closed in the meantime
+        if self.popup.is_none() {
+            return false;
+        }
+        self.details();
+        true
+    }
+
+    /// Show what the hooks of the current directory said, once they're done.
+    /// Returns true if there was anything to show.
+    fn poll_hooks(&mut self) -> bool {
+        let mut shown = false;
+        for (dir, result) in self.hook_runner.poll() {
+            // We've moved on
+            if dir != self.cwd {
+                continue;
+            }
+            let message = match result {
+                Ok(line) if line.is_empty() => continue,
+                Ok(line) => Message {
+                    text: line,
+                    kind: MessageKind::Info,
+                },
+                Err(e) => Message {
+                    text: format!("Hook failed: {}", e),
+                    kind: MessageKind::Warning,
+                },
+            };
+            self.message(Some(message));
+            shown = true;
+        }
+        shown
+    }
+
+    /// Run the plugin action at `index` on the highlighted entry, in the background
+    pub fn run_plugin_action(&mut self, index: usize) {
+        let Some(action) = self.plugins.actions.get(index) else {
+            return;
+        };
+        let (path, selected) = match self.dir_listing.selected().map(|i| self.dir_listing.get(i)) {
+            Some(entry) if entry.name != ".." => (
+                self.cwd.join(entry.name.trim_end_matches('/')),
+                script::entry_map(&self.cwd, entry),
+            ),
+            _ => (self.cwd.clone(), Dynamic::UNIT),
+        };
+        let listing = script::listing(&self.cwd, self.dir_listing.iter_entries());
+        let description = format!("{} on {}", action.name, path.display());
+        let (job, progress) = self.jobs.start(description, path.clone());
+        self.plugin_runner
+            .run_action(action, &path, &self.cwd, listing, selected, job, progress);
+        self.message(Some(Message {
+            text: format!("Running {} (J)", action.name),
+            kind: MessageKind::Info,
+        }));
+    }
+
+    /// Show what the plugin actions that have finished printed, and fill in
+    /// the plugin columns. Returns true if anything changed.
+    fn poll_plugins(&mut self) -> bool {
+        let mut changed = false;
+        for Finished {
+            job,
+            name,
+            popup,
+            result,
+        } in self.plugin_runner.poll_actions()
+        {
+            let summary = match &result {
+                Ok(output) => Ok(output.lines().next().unwrap_or("no output").to_string()),
+                Err(e) => Err(e.clone()),
+            };
+            self.jobs.finish(job, summary);
+            let cancelled = self
+                .jobs
+                .iter()
+                .any(|j| j.id == job && j.state == JobState::Cancelled);
+            match result {
+                _ if cancelled => {}
+                // A long answer gets a popup, unless one is already open
+                Ok(output) if (popup || output.lines().count() > 1) && self.popup.is_none() => {
+                    let output = match output.as_str() {
+                        "" => "(no output)".to_string(),
+                        _ => popup::left_aligned(&output, name.chars().count()),
+                    };
+                    self.popup(Some(&name), None, Some(&output));
+                }
+                Ok(output) => self.message(Some(Message {
+                    text: format!("{}: {}", name, output.lines().next().unwrap_or("done")),
+                    kind: MessageKind::Info,
+                })),
+                Err(e) => self.message(Some(Message {
+                    text: format!("{} failed: {}", name, e),
+                    kind: MessageKind::Error,
+                })),
+            }
+            changed = true;
+        }
+        let (columns_changed, errors) = self.plugin_runner.poll_columns(&self.plugins.columns);
+        if let Some(e) = errors.into_iter().next() {
+            self.message(Some(Message {
+                text: e,
+                kind: MessageKind::Warning,
+            }));
+            changed = true;
+        }
+        changed || columns_changed
+    }
+
+    /// Answer the commands that came in on the control socket. Returns true
+    /// if there were any, since they can change anything.
+    fn poll_control(&mut self) -> bool {
+        let Some(control) = &self.control else {
+            return false;
+        };
+        let requests = control.requests();
+        for (command, reply) in &requests {
+            let _ = reply.send(self.control_command(command));
+        }
+        !requests.is_empty()
+    }
+
+    /// Carry out a command from the control socket, and say how it went:
+    /// "cd PATH", "keys KEYS", "query cwd", "query selected", or "export json"
+    fn control_command(&mut self, command: &str) -> String {
+        let (verb, arg) = command
+            .trim()
+            .split_once(' ')
+            .unwrap_or((command.trim(), ""));
+        let arg = arg.trim();
+        match (verb, arg) {
+            ("cd", "") => "error: cd needs a path".to_string(),
+            ("cd", path) => match self.try_cd(&self.cwd.join(path)) {
+                Ok(()) => "ok".to_string(),
+                Err(e) => format!("error: {}", e),
+            },
+            ("keys", script) => match input::parse_keys(script) {
+                Ok(steps) => {
+                    for step in steps {
+                        if let input::Scripted::Key(key) = step {
+                            self.handle_key(key);
+                        }
+                    }
+                    "ok".to_string()
+                }
+                Err(e) => format!("error: {}", e),
+            },
+            ("query", "cwd") => CwdRecord {
+                schema_version: SCHEMA_VERSION,
+                path: self.cwd.clone(),
+            }
+            .to_json(),
+            ("query", "selected") => {
+                let entry = self.dir_listing.selected().map(|i| self.dir_listing.get(i));
+                match entry {
+                    Some(entry) => self.control_record(entry).to_json(),
+                    None => "error: nothing is selected".to_string(),
+                }
+            }
+            ("export", "json") => {
+                let stats = &self.dir_listing.stats;
+                ListingRecord {
+                    schema_version: SCHEMA_VERSION,
+                    path: self.cwd.clone(),
+                    bytes: stats.total_size,
+                    rentries: stats.total_rentries,
+                    entries: self
+                        .dir_listing
+                        .iter_entries()
+                        .filter(|entry| entry.name != "..")
+                        .map(|entry| self.control_record(entry))
+                        .collect(),
+                }
+                .to_json()
+            }
+            _ => format!("error: unknown command {:?}", command.trim()),
+        }
+    }
+
+    /// An entry of the current directory, as the control socket replies with it
+    fn control_record(&self, entry: &DirEntry) -> DirRecord {
+        let path = self.cwd.join(entry.name.trim_end_matches('/'));
+        report::entry_record(path, 1, entry)
+    }
+
+    /// Feed the marked entries' paths, or else the listing, to the shell
+    /// command `command_line`, in the background, and show what it prints
+    pub fn pipe_through(&mut self, command_line: &str) {
+        let input: String = if self.dir_listing.marked_count() > 0 {
+            self.dir_listing
+                .iter_entries()
+                .filter(|entry| self.dir_listing.is_marked(entry))
+                .map(|entry| {
+                    let path = self.cwd.join(entry.name.trim_end_matches('/'));
+                    format!("{}\n", path.display())
+                })
+                .collect()
+        } else {
+            // Exact numbers, for sort -n and awk
+            self.dir_listing
+                .iter_entries()
+                .filter(|entry| entry.name != "..")
+                .map(|entry| {
+                    let number = |n: Option<usize>| n.map(|n| n.to_string()).unwrap_or_default();
+                    format!(
+                        "{}\t{}\t{}\n",
+                        number(entry.size),
+                        number(entry.rentries),
+                        entry.name
+                    )
+                })
+                .collect()
+        };
+        let description = format!("{} in {}", command_line, self.cwd.display());
+        let (job, progress) = self.jobs.start(description, self.cwd.clone());
+        self.plugin_runner
+            .run_pipe(command_line, input.into_bytes(), &self.cwd, job, progress);
+        self.message(Some(Message {
+            text: format!("Running {} (J)", command_line),
+            kind: MessageKind::Info,
+        }));
+    }
+
+    /// Keep the screen as drawn, in `text`, for pasting into a ticket: copy
+    /// it to the clipboard, and save it to a file in the temp directory
+    pub fn save_screen(&mut self, text: String) {
+        let name = format!(
+            "cephdu-screen-{}.txt",
+            chrono::Local::now().format("%Y%m%d-%H%M%S")
+        );
+        let path = std::env::temp_dir().join(name);
+        let saved = std::fs::write(&path, &text);
+        self.clipboard = Some(text);
+        self.message(Some(match saved {
+            Ok(()) => Message {
+                text: format!("Copied the screen, and saved it to {}", path.display()),
+                kind: MessageKind::Info,
+            },
+            Err(e) => Message {
+                text: format!(
+                    "Copied the screen, but couldn't save it to {:?}: {}",
+                    path, e
+                ),
+                kind: MessageKind::Warning,
+            },
+        }));
+    }
+
+    /// What the plugin columns say about `entry`, if there are any
+    pub fn plugin_values(&self, entry: &DirEntry) -> Vec<Option<&str>> {
+        (0..self.plugins.columns.len())
+            .map(|i| self.plugin_runner.value(i, &self.cwd, &entry.name))
+            .collect()
+    }
+
+    /// Start computing the recursive size of the highlighted directory, for
+    /// filesystems that don't keep track of it themselves.
+    pub fn compute_deep_size(&mut self) {
+        let Some(selected) = self.dir_listing.selected() else {
+            return;
+        };
+        let entry = self.dir_listing.get(selected);
+        if entry.kind != EntryKind::Dir || entry.name == ".." {
+            return;
+        }
+        let message = if self.dir_listing.is_ceph() {
+            Message {
+                text: "Ceph directory sizes are already recursive".to_string(),
+                kind: MessageKind::Info,
+            }
+        } else {
+            let path = self.cwd.join(&entry.name);
+            let text = if self.deep_sizer.is_pending(&path) {
+                format!("Already computing the size of {}", entry.name)
+            } else {
+                let shown = self.cwd.join(entry.name.trim_end_matches('/'));
+                let description = format!("Adding up {}", shown.display());
+                let (job, progress) = self.jobs.start(description, path.clone());
+                self.deep_sizer.request(&path, &self.exclude, job, progress);
+                format!("Computing the size of {} in the background (J)", entry.name)
+            };
+            Message {
+                text,
+                kind: MessageKind::Info,
+            }
+        };
+        self.message(Some(message));
+    }
+
+    /// Fill in the sizes of the walks that have finished. Returns true if any did.
+    fn poll_deep_sizer(&mut self) -> bool {
+        let done = self.deep_sizer.poll();
+        if done.is_empty() {
+            return false;
+        }
+        for (path, job, result) in &done {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let mut message = match result {
+                Ok(size) => {
+                    let summary = format!(
+                        "{} in {} entries",
+                        size_str(Some(size.bytes), false),
+                        rentries_str(Some(size.entries), false)
+                    );
+                    self.jobs.finish(*job, Ok(summary));
+                    Message {
+                        text: format!("Computed the size of {}/", name),
+                        kind: MessageKind::Info,
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                    self.jobs.finish(*job, Err(e.to_string()));
+                    Message {
+                        text: format!("Stopped computing the size of {}/", name),
+                        kind: MessageKind::Info,
+                    }
+                }
+                Err(e) => {
+                    self.jobs.finish(*job, Err(e.to_string()));
+                    Message {
+                        text: format!("Error computing the size of {}/: {}", name, e),
+                        kind: MessageKind::Error,
+                    }
+                }
+            };
+            let cancelled = matches!(result, Err(e) if e.kind() == io::ErrorKind::Interrupted);
+            if path.parent() != Some(&self.cwd) && !cancelled {
+                if self.notify {
+                    self.notifications.push(format!("cephdu: {}", message.text));
+                }
+                let key = self.keymap.first_key_for(Action::GoToFinished);
+                if let Some(key) = key {
+                    message
+                        .text
+                        .push_str(&format!(". Press {} to go there", key));
+                }
+                self.finished_job = Some(*job);
+            }
+            self.message(Some(message));
+        }
+        self.apply_deep_sizes();
+        true
+    }
+
+    /// Show the computed sizes of any directories in this listing
+    fn apply_deep_sizes(&mut self) {
+        if self.dir_listing.is_ceph() {
+            return;
+        }
+        let cwd = &self.cwd;
+        let deep_sizer = &self.deep_sizer;
+        self.dir_listing
+            .set_deep_sizes(|name| deep_sizer.get(&cwd.join(name)));
+    }
+
+    /// Refresh once the cwd has changed and things have settled down, or
+    /// when a refresh that was asked for too soon is due, but not sooner
+    /// than `refresh_interval` after the last listing. Returns true if we
+    /// refreshed.
+    fn poll_watcher(&mut self) -> bool {
+        if let Some(watcher) = &mut self.watcher
+            && watcher.poll()
+        {
+            let now = Instant::now();
+            let first = self.pending_refresh.map_or(now, |(first, _)| first);
+            self.pending_refresh = Some((first, now));
+        }
+
+        let settled = self.pending_refresh.is_some_and(|(first, last)| {
+            last.elapsed() >= WATCH_DEBOUNCE || first.elapsed() >= WATCH_MAX_DELAY
+        });
+        if !(settled || self.refresh_requested) || self.listed_at.elapsed() < self.refresh_interval
+        {
+            return false;
+        }
+        // Not again on every tick if listing fails
+        self.pending_refresh = None;
+        self.refresh_requested = false;
+        self.listed_at = Instant::now();
+        // Don't let the refresh clobber a message the user hasn't seen
+        let message = self.message.take();
+        self.cd(&self.cwd.clone());
+        if self.message.is_none() {
+            self.message = message;
+        }
+        true
+    }
+
+    pub fn popup(&mut self, title: Option<&str>, bottom_title: Option<&str>, text: Option<&str>) {
+        self.popup = text.map(|x| Popup::new(title.unwrap_or(""), bottom_title.unwrap_or(""), x));
+        self.jobs_panel = None;
+        self.search_panel = None;
+        self.pool_panel = false;
+        self.retention_panel = None;
+        self.cleanup_panel = None;
+    }
+
+    /// Go to the results of the job with the id `job`
+    pub fn go_to_job(&mut self, job: usize) {
+        let Some(target) = self
+            .jobs
+            .iter()
+            .find(|j| j.id == job)
+            .map(|j| j.target.clone())
+        else {
+            return;
+        };
+        self.go_to(&target);
+    }
+
+    /// Go to the directory of `target`, with it highlighted
+    pub fn go_to(&mut self, target: &Path) {
+        let Some(parent) = target.parent() else {
+            return;
+        };
+        self.cd(parent);
+        if let Some(name) = target.file_name() {
+            let name = name.to_string_lossy();
+            if self.dir_listing.select_by_name(&name).is_none() {
+                self.dir_listing
+                    .select_by_name(&format!("{}/", name.trim_end_matches('/')));
+            }
+        }
+    }
+
+    /// Look for names matching `pattern` under the current directory, in the
+    /// background, and show what's found as it's found
+    pub fn start_search(&mut self, pattern: &str) {
+        // One search at a time
+        let running = self.search.as_ref().filter(|search| search.is_running());
+        let index = running.and_then(|search| self.jobs.iter().position(|j| j.id == search.job));
+        if let Some(i) = index {
+            self.jobs.cancel(i);
+        }
+        let description = format!("Searching {} for {}", self.cwd.display(), pattern);
+        let (job, progress) = self.jobs.start(description, self.cwd.clone());
+        self.search = Some(Search::start(
+            &self.cwd,
+            pattern,
+            &self.exclude,
+            &self.search_config,
+            self.persist,
+            job,
+            progress,
+        ));
+        self.show_search();
+    }
+
+    /// Show the results of the last search
+    pub fn show_search(&mut self) {
+        if self.search.is_none() {
+            self.message(Some(Message {
+                text: "No search yet; type a name to look for".to_string(),
+                kind: MessageKind::Info,
+            }));
+            return;
+        }
+        let (title, text) = self.search_text(0, SEARCH_WIDTH);
+        self.popup(Some(&title), Some("Enter Go to  Esc Close"), Some(&text));
+        self.search_panel = Some(0);
+        self.update_search_panel();
+    }
+
+    /// Take in what the search found, and say when it's done. Returns true
+    /// if anything changed.
+    fn poll_search(&mut self) -> bool {
+        let Some(search) = &mut self.search else {
+            return false;
+        };
+        let was_running = search.is_running();
+        if !search.poll() {
+            return false;
+        }
+        if was_running && let Some(result) = &search.done {
+            let summary = match result {
+                Ok(_) => Ok(format!("{} found", count_str(search.matches.len()))),
+                Err(e) => Err(e.clone()),
+            };
+            let job = search.job;
+            self.jobs.finish(job, summary);
+        }
+        self.update_search_panel();
+        true
+    }
+
+    /// Show the results as they are now, keeping the highlighted one in view
+    pub fn update_search_panel(&mut self) {
+        let (Some(selected), Some(search)) = (self.search_panel, &self.search) else {
+            return;
+        };
+        let selected = selected.min(search.matches.len().saturating_sub(1));
+        self.search_panel = Some(selected);
+        let width = self.popup.as_ref().map_or(0, |popup| popup.text_width);
+        let (title, text) = self.search_text(selected, width);
+        if let Some(popup) = &mut self.popup {
+            let scroll = popup.scroll();
+            popup.title = title;
+            popup.set_text(&text);
+            popup.scroll_to(
+                scroll.clamp((selected + 1).saturating_sub(POPUP_TEXT_HEIGHT), selected),
+            );
+        }
+    }
+
+    /// The title of the results, with how the search is going, and a line
+    /// per result, with the one at `selected` marked, `width` wide
+    fn search_text(&self, selected: usize, width: usize) -> (String, String) {
+        let Some(search) = &self.search else {
+            return (String::new(), String::new());
+        };
+        let found = match search.matches.len() {
+            MAX_MATCHES.. => format!("the first {}", count_str(MAX_MATCHES)),
+            n => count_str(n),
+        };
+        let status = match &search.done {
+            None => format!("{} found so far", found),
+            Some(Ok(None)) => format!("{} found", found),
+            Some(Ok(Some(time))) => {
+                let when = time
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs() as usize);
+                format!("{} found, in the index from {}", found, age_str(when))
+            }
+            Some(Err(e)) => format!("{} found, then {}", found, e),
+        };
+        let title = format!(
+            "{} under {}: {}",
+            search.pattern,
+            search.root.display(),
+            status
+        );
+        let mut lines: Vec<String> = search
+            .matches
+            .iter()
+            .enumerate()
+            .map(|(i, m)| {
+                let marker = if i == selected { '>' } else { ' ' };
+                let slash = if m.kind == EntryKind::Dir { "/" } else { "" };
+                let path = format!("{}{}", m.path.display(), slash);
+                let room = width.saturating_sub(2).max(10);
+                format!("{} {}", marker, elide_start(&path, room))
+            })
+            .collect();
+        if lines.is_empty() {
+            lines.push(match search.done {
+                None => "  searching…".to_string(),
+                Some(_) => "  nothing found".to_string(),
+            });
+        }
+        let width = width.max(title.chars().count());
+        let text = lines
+            .iter()
+            .map(|line| format!("{:w$}\n", line, w = width))
+            .collect();
+        (title, text)
+    }
+
+    /// Go to the result at `index` of the last search
+    pub fn go_to_search_result(&mut self, index: usize) {
+        let Some(target) = self
+            .search
+            .as_ref()
+            .and_then(|search| Some(search.root.join(&search.matches.get(index)?.path)))
+        else {
+            return;
+        };
+        self.go_to(&target);
+    }
+
+    /// Go to the results of the job that last finished while we were elsewhere
+    pub fn go_to_finished(&mut self) {
+        match self.finished_job {
+            Some(job) => self.go_to_job(job),
+            None => self.message(Some(Message {
+                text: "No job has finished elsewhere".to_string(),
+                kind: MessageKind::Info,
+            })),
+        }
+    }
+
+    /// Show the background jobs, with their progress, or how they ended
+    pub fn show_jobs(&mut self) {
+        if self.jobs.is_empty() {
+            self.message(Some(Message {
+                text: "No background jobs yet; d adds up a directory in the background".to_string(),
+                kind: MessageKind::Info,
+            }));
+            return;
+        }
+        let text = self.jobs_text(self.jobs.len() - 1, 0);
+        self.popup(
+            Some("Jobs"),
+            Some("x Cancel  c Clear finished  Esc Close"),
+            Some(&text),
+        );
+        self.jobs_panel = Some(self.jobs.len() - 1);
+        self.update_jobs_panel();
+    }
+
+    /// Show the jobs as they are now in the jobs panel, keeping the
+    /// highlighted one in view
+    pub fn update_jobs_panel(&mut self) {
+        let Some(selected) = self.jobs_panel else {
+            return;
+        };
+        let selected = selected.min(self.jobs.len().saturating_sub(1));
+        self.jobs_panel = Some(selected);
+        let width = self.popup.as_ref().map_or(0, |popup| popup.text_width);
+        let text = self.jobs_text(selected, width);
+        if let Some(popup) = &mut self.popup {
+            let scroll = popup.scroll();
+            popup.set_text(&text);
+            // Each job takes two lines
+            let (first, last) = (selected * 2, selected * 2 + 1);
+            popup.scroll_to(scroll.clamp((last + 1).saturating_sub(POPUP_TEXT_HEIGHT), first));
+        }
+    }
+
+    /// Two lines per job, with the one at `selected` marked, at least `width` wide
+    fn jobs_text(&self, selected: usize, width: usize) -> String {
+        let mut lines = Vec::new();
+        for (i, job) in self.jobs.iter().enumerate() {
+            let marker = if i == selected { '>' } else { ' ' };
+            let status = match &job.state {
+                JobState::Running => {
+                    let bar = match job.progress.fraction() {
+                        Some(fraction) => {
+                            let filled = (fraction * BAR_WIDTH as f64) as usize;
+                            format!(
+                                "{}{} {:>3.0}%",
+                                "█".repeat(filled),
+                                "░".repeat(BAR_WIDTH - filled),
+                                fraction * 100.0
+                            )
+                        }
+                        None => format!("{:w$}", "running", w = BAR_WIDTH + 5),
+                    };
+                    format!(
+                        "{}  {} entries",
+                        bar,
+                        rentries_str(Some(job.progress.items()), false)
+                    )
+                }
+                JobState::Done(summary) => format!("{:w$}  {}", "done", summary, w = BAR_WIDTH + 5),
+                JobState::Failed(e) => format!("{:w$}  {}", "failed", e, w = BAR_WIDTH + 5),
+                JobState::Cancelled => "cancelled".to_string(),
+            };
+            lines.push(format!(
+                "{} {}  ({})",
+                marker,
+                job.description,
+                job.elapsed_str()
+            ));
+            lines.push(format!("    {}", status));
+        }
+        // The lines are centered, so they line up only if they're as wide
+        let width = lines
+            .iter()
+            .map(|l| l.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max(width);
+        lines
+            .iter()
+            .map(|line| format!("{:w$}\n", line, w = width))
+            .collect()
+    }
+
+    pub fn message(&mut self, message: Option<Message>) {
+        self.message = message;
+    }
+
+    /// Show the key bindings in a popup, which can be searched by typing
+    pub fn help(&mut self) {
+        let help_text = self.help_text("");
+        self.popup(
+            Some("Help"),
+            Some(env!("CARGO_PKG_REPOSITORY")),
+            Some(&help_text),
+        );
+        if let Some(popup) = &mut self.popup {
+            popup.search = Some(String::new());
+        }
+    }
+
+    /// Show only the bindings that match the search typed into the help popup
+    pub fn update_help(&mut self) {
+        let Some(search) = self.popup.as_ref().and_then(|p| p.search.clone()) else {
+            return;
+        };
+        let help_text = self.help_text(&search);
+        if let Some(popup) = &mut self.popup {
+            popup.set_text(&help_text);
+            popup.bottom_title = if search.is_empty() {
+                env!("CARGO_PKG_REPOSITORY").to_string()
+            } else {
+                format!("Search: {}", search)
+            };
+        }
+    }
+
+    /// The bindings whose keys, action name, or description contain `search`,
+    /// as (keys, action, description)
+    pub fn help_matches(&self, search: &str) -> Vec<(String, Option<Action>, String)> {
+        let search = search.to_lowercase();
+        let matches = |keys: &str, name: &str, description: &str| {
+            search.is_empty()
+                || keys.to_lowercase().contains(&search)
+                || name.contains(&search)
+                || description.to_lowercase().contains(&search)
+        };
+        let mut rows: Vec<(String, Option<Action>, String)> = Action::ALL
+            .into_iter()
+            .map(|action| {
+                (
+                    self.keymap.keys_for(action),
+                    Some(action),
+                    action.description().to_string(),
+                )
+            })
+            .filter(|(keys, action, description)| {
+                !keys.is_empty() && matches(keys, action.map_or("", Action::name), description)
+            })
+            .collect();
+        // Not an action: the input thread handles it
+        let interrupt = "Interrupt changing the directory";
+        if matches("Ctrl-C", "", interrupt) {
+            rows.push(("Ctrl-C".to_string(), None, interrupt.to_string()));
+        }
+        // The site's own, which aren't actions either
+        for action in &self.plugins.actions {
+            let key = action.key.name();
+            if matches(&key, "", &action.name) {
+                rows.push((key, None, action.name.clone()));
+            }
+        }
+        rows
+    }
+
+    fn help_text(&self, search: &str) -> String {
+        let all = self.help_matches("");
+        let lhs_width = all.iter().map(|(k, _, _)| k.len()).max().unwrap_or(0);
+        let rhs_width = all.iter().map(|(_, _, d)| d.len()).max().unwrap_or(0);
 
         let mut help_text = String::new();
-        for h in navigation::HELP {
+        for (keys, _, description) in self.help_matches(search) {
             help_text.push_str(&format!(
                 "{:>lhs$}:  {:rhs$}\n",
-                h[0],
-                h[1],
+                keys,
+                description,
                 lhs = lhs_width,
                 rhs = rhs_width
             ));
         }
-        self.popup(
-            Some("Help"),
-            Some(env!("CARGO_PKG_REPOSITORY")),
-            Some(&help_text),
-        );
+        if help_text.is_empty() {
+            help_text.push_str("No matching keys\n");
+        }
+        help_text
+    }
+
+    /// A human-readable dump of the app state, for crash reports.
+    /// Show everything we know about the selected entry.
+    pub fn details(&mut self) {
+        let Some(selected) = self.dir_listing.selected() else {
+            return;
+        };
+        let entry = self.dir_listing.get(selected);
+        let or_unknown = |v: Option<usize>| v.map_or("?".to_string(), |v| v.to_string());
+
+        let mut text = format!("Path:      {}\n", self.cwd.join(&entry.name).display());
+        text.push_str(&format!("Type:      {:?}\n", entry.kind));
+        if entry.kind == EntryKind::Dir {
+            let (rbytes, stat) = match self.dir_listing.size_source() {
+                SizeSource::Rbytes => (entry.size, entry.alt_size),
+                SizeSource::Stat => (entry.alt_size, entry.size),
+            };
+            text.push_str(&format!("rbytes:    {}\n", or_unknown(rbytes)));
+            text.push_str(&format!("stat size: {}\n", or_unknown(stat)));
+            if rbytes.is_some() && stat.is_some() && rbytes != stat {
+                text.push_str("           (differs from rbytes!)\n");
+            }
+            text.push_str(&format!("Files:     {}\n", or_unknown(entry.rentries)));
+            if let Some(quota) = &entry.quota {
+                let percent = |used: usize, max: usize| (used as f64 / max as f64 * 100.0).round();
+                let mut label = "Quota:";
+                if quota.max_bytes > 0 {
+                    let used = rbytes.unwrap_or(0);
+                    text.push_str(&format!(
+                        "{:<11}{} of {} ({}% of quota)\n",
+                        label,
+                        size_str(Some(used), false),
+                        size_str(Some(quota.max_bytes), false),
+                        percent(used, quota.max_bytes)
+                    ));
+                    label = "";
+                }
+                if quota.max_files > 0 {
+                    let used = entry.rentries.unwrap_or(0);
+                    text.push_str(&format!(
+                        "{:<11}{} of {} files ({}% of quota)\n",
+                        label,
+                        used,
+                        quota.max_files,
+                        percent(used, quota.max_files)
+                    ));
+                }
+            }
+        } else {
+            text.push_str(&format!("Size:      {}\n", or_unknown(entry.size)));
+            if entry.kind == EntryKind::File
+                && let Ok(stat) = provider::get().lstat(&self.cwd.join(&entry.name))
+            {
+                text.push_str(&format!("Allocated: {}\n", stat.allocated()));
+                if stat.is_sparse() {
+                    text.push_str("           (sparse: most of it is holes)\n");
+                }
+            }
+        }
+        if let Some(ctime) = entry.ctime
+            && let Some(ctime) = chrono::DateTime::from_timestamp(ctime as i64, 0)
+        {
+            let ctime = ctime.with_timezone(&chrono::Local);
+            text.push_str(&format!(
+                "ctime:     {}\n",
+                ctime.format("%Y-%m-%d %H:%M:%S")
+            ));
+        }
+        if let Some(mode) = entry.mode {
+            text.push_str(&format!(
+                "Mode:      {} ({:04o})\n",
+                mode_str(entry.kind, mode),
+                mode
+            ));
+        }
+        if let Some(warning) = entry.permission_warning() {
+            text.push_str(&format!("           (warning: {}!)\n", warning));
+        }
+        if entry.name != ".." {
+            let (access, default) = acl::get(&self.cwd.join(&entry.name));
+            let lines = access
+                .iter()
+                .map(|e| e.to_string())
+                .chain(default.iter().map(|e| format!("default:{}", e)));
+            for (i, line) in lines.enumerate() {
+                let label = if i == 0 { "ACL:" } else { "" };
+                text.push_str(&format!("{:<11}{}\n", label, line));
+            }
+        }
+        if let (Some(user), Some(group), Some(uid), Some(gid)) =
+            (entry.user(false), entry.group(false), entry.uid, entry.gid)
+        {
+            text.push_str(&format!(
+                "Owner:     {}:{} ({}:{})\n",
+                user, group, uid, gid
+            ));
+        }
+        let path = self.cwd.join(&entry.name);
+        if entry.kind == EntryKind::Dir
+            && entry.name != ".."
+            && !self.subvolume_config.command.is_empty()
+            && let Some(subvolume) = Subvolume::of(&path)
+        {
+            text.push_str(&format!("Subvolume: {}\n", subvolume));
+            if self.subvolumes.get(&path).is_none() {
+                let volume = self.dir_listing.cephfs().and_then(|c| c.name.as_deref());
+                self.subvolumes
+                    .request(&path, subvolume, volume, &self.subvolume_config);
+            }
+            match self.subvolumes.get(&path) {
+                Some(Ok(info)) => {
+                    for (label, value) in info {
+                        text.push_str(&format!("{:<11}{}\n", label, value));
+                    }
+                }
+                Some(Err(e)) => text.push_str(&format!("           ({})\n", e)),
+                None => {
+                    text.push_str("           (asking ceph...)\n");
+                    self.detailing = Some(path);
+                }
+            }
+        }
+        if entry.name != ".." {
+            let xattrs = all_xattrs(&self.cwd.join(&entry.name), entry.kind);
+            for (i, (name, value)) in xattrs.iter().enumerate() {
+                let label = if i == 0 { "xattrs:" } else { "" };
+                text.push_str(&format!(
+                    "{:<11}{} = {}\n",
+                    label,
+                    name,
+                    xattr_value_str(value, XATTR_VALUE_MAX_CHARS)
+                ));
+            }
+        }
+
+        let title = entry.name.to_string();
+        self.popup(Some(&title), None, Some(&text));
+    }
+
+    pub fn state_summary(&self) -> String {
+        format!(
+            "cwd: {:?}{}\noriginal_cwd: {:?}\nentries: {}\nselected: {:?}\nsort_mode: {:?}\n\
+             fs: {:?}\nshow_owner: {}\nshow_group: {}\nshow_ctime: {}\nmessage: {:?}\nload_report: {:?}\n",
+            self.cwd,
+            if self.logical_paths { " (logical)" } else { "" },
+            self.original_cwd,
+            self.dir_listing.len(),
+            self.dir_listing.selected(),
+            self.dir_listing.sort_mode(),
+            self.dir_listing.fs,
+            self.show_owner,
+            self.show_group,
+            self.show_ctime,
+            self.message,
+            self.dir_listing.load_report,
+        )
     }
 
     pub fn sort_or_reverse(&mut self, sort_mode: SortMode) {
@@ -287,7 +3226,7 @@ impl App {
         if let Some(selected) = selected {
             let entry = self.dir_listing.get(selected);
             self.highlighted
-                .insert(self.cwd.clone(), (entry.name.clone(), selected));
+                .insert(self.cwd.clone(), (entry.name.to_string(), selected));
         }
     }
 
@@ -305,80 +3244,190 @@ impl App {
 }
 
 impl DirListing {
-    fn from(path: &Path, sort_mode: SortMode) -> Result<DirListing, std::io::Error> {
-        let path: PathBuf = path.canonicalize()?;
+    pub fn from(
+        path: &Path,
+        sort_mode: SortMode,
+        options: LoadOptions,
+        cancel: &AtomicBool,
+    ) -> Result<DirListing, std::io::Error> {
+        let path: PathBuf = provider::get().canonicalize(path)?;
         let fs = get_fs(&path);
+        let share = fs.and_then(|fs| Share::of(&path, fs));
+        let ceph = fs.is_some_and(FSType::is_ceph) || share.is_some_and(Share::has_ceph_stats);
 
-        let (entry_cwd, mut entries): (DirEntry, Vec<DirEntry>) = ls(&path)?;
+        let (entry_cwd, mut entries, load_report, more) = ls(&path, options, cancel)?;
 
-        // Don't trust dir sizes on non-ceph!
-        if !fs.map(FSType::is_ceph).unwrap_or(false) {
+        // Don't trust dir sizes on non-ceph! Only those from the quotas
+        // of other filesystems, whichever size source is picked.
+        if !ceph {
             entries
                 .iter_mut()
                 .filter(|e| e.kind == EntryKind::Dir)
-                .for_each(|e| {
-                    e.size = None;
-                });
+                .for_each(|e| e.alt_size = e.size);
         }
-        sort(&mut entries, sort_mode);
+        let rstats = provider::get()
+            .stat(&path)
+            .ok()
+            .map(|stat| rstats::for_dir(&path, stat.dev))
+            .filter(|backend| !ceph && !rstats::is_none(*backend))
+            .map(|backend| backend.name());
+        let partially_sorted = sort_first(&mut entries, sort_mode);
 
         let has_parent = *path != *"/";
         let dotdot = has_parent.then(|| DirEntry {
-            name: "..".to_string(),
+            name: CompactString::const_new(".."),
             kind: EntryKind::Dir,
+            loaded: true,
             size: None,
+            alt_size: None,
             rentries: None,
             ctime: None,
-            user: None,
-            group: None,
+            uid: None,
+            gid: None,
+            mode: None,
+            acl: None,
+            quota: None,
+            allocated: None,
+            timed_out: false,
         });
 
-        let (max_rentries, max_size) = entries.iter().fold((0, 0), |(max_r, max_s), entry| {
-            let r = entry.rentries.unwrap_or(0);
-            let s = entry.size.unwrap_or(0);
-            (max_r.max(r), max_s.max(s))
-        });
-        // Note a possible consistency check we're not using here:
-        // that the sum of the entry sizes add up to the cwd's r-sizes.
+        let (max_rentries, max_size, newest_ctime) =
+            entries
+                .iter()
+                .fold((0, 0, 0), |(max_r, max_s, max_c), entry| {
+                    let r = entry.rentries.unwrap_or(0);
+                    let s = entry.size.unwrap_or(0);
+                    let c = entry.ctime.unwrap_or(0);
+                    (max_r.max(r), max_s.max(s), max_c.max(c))
+                });
+        // Their sizes aren't part of this directory's
+        let mounted: HashMap<CompactString, Mount> = mounts::below(&path)
+            .into_iter()
+            .filter_map(|mount| {
+                let name = mount.mount_point.file_name()?.to_string_lossy();
+                Some((format_compact!("{}/", name), mount))
+            })
+            .collect();
+        let (child_size, child_rentries) =
+            child_sums(entries.iter().filter(|e| !mounted.contains_key(&e.name)));
+        // The mount points themselves are this filesystem's
+        let child_rentries = child_rentries
+            + entries
+                .iter()
+                .filter(|e| mounted.contains_key(&e.name))
+                .count();
+        let kinds = KindCounts::of(&entries);
+        let mount = mounts::find(&path);
+        let space = provider::get().space(&path).ok();
+        let user_quota = mount
+            .as_ref()
+            .filter(|_| !ceph)
+            .and_then(userquota::of_current_user);
         let total_rentries = entry_cwd.rentries.unwrap_or(0);
 
         // TODO: might want to display ? instead of 0 for non-ceph
-        let total_size = if fs.is_some_and(FSType::is_ceph) {
-            entry_cwd.size.unwrap_or(0)
+        let (total_size, alt_total_size) = if ceph {
+            (entry_cwd.size.unwrap_or(0), entry_cwd.alt_size.unwrap_or(0))
         } else {
-            0
+            let size = entry_cwd.size.unwrap_or(0);
+            (size, size)
         };
 
         let state = ListState::default().with_selected(Some(0));
 
-        Ok(DirListing {
+        let mut listing = DirListing {
+            path,
             entries,
+            hidden: Vec::new(),
+            hide_dotfiles: false,
             state,
             dotdot,
             sort_mode,
+            partially_sorted,
+            size_source: SizeSource::Rbytes,
             stats: ListingStats {
                 max_rentries,
                 total_rentries,
                 max_size,
                 total_size,
+                alt_total_size,
+                child_size,
+                child_rentries,
+                rctime: entry_cwd.ctime,
+                newest_ctime,
+                kinds,
             },
             fs,
-        })
+            mount,
+            mounted,
+            share,
+            rstats,
+            space,
+            user_quota,
+            load_report,
+            timeout: options.timeout,
+            viewport_height: 0,
+            owner_widths: (0, 0),
+            changed: HashMap::new(),
+            marked: HashSet::new(),
+            changed_at: None,
+            changes_highlighted: false,
+            more,
+            trends: HashMap::new(),
+            visited: None,
+            since_visit: HashMap::new(),
+            pool: None,
+            pools: HashMap::new(),
+        };
+        listing.set_size_source(options.size_source);
+        listing.set_hide_dotfiles(options.hide_dotfiles);
+        Ok(listing)
     }
 
     fn default() -> DirListing {
         DirListing {
+            path: PathBuf::new(),
             dotdot: None,
             entries: Vec::new(),
+            hidden: Vec::new(),
+            hide_dotfiles: false,
             state: ListState::default(),
             sort_mode: DEFAULT_SORT_MODE,
+            partially_sorted: false,
+            size_source: SizeSource::Rbytes,
             stats: ListingStats {
                 max_rentries: 0,
                 total_rentries: 0,
                 max_size: 0,
                 total_size: 0,
+                alt_total_size: 0,
+                child_size: 0,
+                child_rentries: 0,
+                rctime: None,
+                newest_ctime: 0,
+                kinds: KindCounts::default(),
             },
             fs: None,
+            mount: None,
+            mounted: HashMap::new(),
+            share: None,
+            rstats: None,
+            space: None,
+            user_quota: None,
+            load_report: LoadReport::default(),
+            timeout: None,
+            viewport_height: 0,
+            owner_widths: (0, 0),
+            changed: HashMap::new(),
+            marked: HashSet::new(),
+            changed_at: None,
+            changes_highlighted: false,
+            more: None,
+            trends: HashMap::new(),
+            visited: None,
+            since_visit: HashMap::new(),
+            pool: None,
+            pools: HashMap::new(),
         }
     }
 
@@ -398,12 +3447,20 @@ impl DirListing {
     }
 
     pub fn get(&self, idx: usize) -> &DirEntry {
+        match self.entry_index(idx) {
+            Some(i) => &self.entries[i],
+            None => self.dotdot.as_ref().unwrap(),
+        }
+    }
+
+    /// Map a display index to an index into `entries`, or None for the ".." entry.
+    fn entry_index(&self, idx: usize) -> Option<usize> {
         // idx = 0 is the ".." entry if we have one.
         // Otherwise, count from the back if we're displaying in reverse mode.
 
-        let idx = if let Some(entry) = self.dotdot.iter().next() {
+        let idx = if self.dotdot.is_some() {
             if idx == 0 {
-                return entry;
+                return None;
             }
             idx - 1
         } else {
@@ -411,10 +3468,69 @@ impl DirListing {
         };
 
         if self.sort_mode.is_reversed() {
-            &self.entries[self.entries.len() - idx - 1]
+            Some(self.entries.len() - idx - 1)
         } else {
-            &self.entries[idx]
+            Some(idx)
+        }
+    }
+
+    /// For lazy listings, fetch the metadata of the rows on screen, plus a margin.
+    /// The listing is not re-sorted as entries load, so that rows don't jump around.
+    /// Returns true if anything was loaded.
+    pub fn load_visible(&mut self) -> bool {
+        if !self.load_report.lazy {
+            return false;
+        }
+
+        let offset = self.state.offset();
+        let first = offset.saturating_sub(LAZY_MARGIN);
+        let last = (offset + self.viewport_height + LAZY_MARGIN).min(self.len());
+        let is_ceph = self.is_ceph();
+
+        let mut timings = Timings::default();
+        let mut loaded = false;
+        for idx in first..last {
+            let Some(i) = self.entry_index(idx) else {
+                continue;
+            };
+            let entry = &mut self.entries[i];
+            if entry.loaded {
+                continue;
+            }
+            // Once one has timed out, the others would likely wait as long
+            let result = if self.load_report.timed_out > 0 {
+                Err(timed_out_error())
+            } else {
+                load_entry(&self.path, entry, self.timeout, &mut timings)
+            };
+            match result {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                    *entry = DirEntry::timed_out(Path::new(entry.name.as_str()), entry.kind);
+                    self.load_report.timed_out += 1;
+                    loaded = true;
+                    continue;
+                }
+                Err(_) => {
+                    // Probably deleted since we listed it; don't keep retrying
+                    entry.loaded = true;
+                    continue;
+                }
+            }
+            if !is_ceph && entry.kind == EntryKind::Dir {
+                entry.alt_size = entry.size;
+            }
+            if self.size_source != SizeSource::Rbytes {
+                std::mem::swap(&mut entry.size, &mut entry.alt_size);
+            }
+
+            self.stats.max_size = self.stats.max_size.max(entry.size.unwrap_or(0));
+            self.stats.max_rentries = self.stats.max_rentries.max(entry.rentries.unwrap_or(0));
+            self.stats.newest_ctime = self.stats.newest_ctime.max(entry.ctime.unwrap_or(0));
+            loaded = true;
         }
+        self.load_report.timings.merge(&timings);
+        loaded
     }
 
     pub fn len(&self) -> usize {
@@ -475,6 +3591,22 @@ impl DirListing {
         }
     }
 
+    /// Select the first entry after the selected one (or from it, with
+    /// `inclusive`), wrapping around, whose name starts with `prefix`,
+    /// ignoring case. Returns false if there's none.
+    pub fn select_by_prefix(&mut self, prefix: &str, inclusive: bool) -> bool {
+        let len = self.len();
+        let start = self.selected().unwrap_or(0) + usize::from(!inclusive);
+        let prefix = prefix.to_lowercase();
+        let found = (0..len)
+            .map(|i| (start + i) % len)
+            .find(|&i| self.get(i).name.to_lowercase().starts_with(&prefix));
+        if let Some(i) = found {
+            self.state.select(Some(i));
+        }
+        found.is_some()
+    }
+
     pub fn select_by_name(&mut self, name: &str) -> Option<usize> {
         let idx = self.iter_entries().position(|entry| entry.name == name);
         if let Some(idx) = idx {
@@ -487,8 +3619,291 @@ impl DirListing {
         self.state.selected()
     }
 
-    pub fn state_mut(&mut self) -> &mut ListState {
-        &mut self.state
+    /// Keep the view of `old`, a previous listing of the same directory: the
+    /// same entry stays highlighted at the same position on screen. If it was
+    /// removed, its nearest neighbour in the old listing that survived is used.
+    pub fn carry_over(&mut self, old: &DirListing) {
+        self.visited = old.visited;
+        let Some(old_selected) = old.selected() else {
+            self.select_first();
+            return;
+        };
+
+        let selected = {
+            let positions: HashMap<&str, usize> = self
+                .iter_entries()
+                .enumerate()
+                .map(|(i, entry)| (entry.name.as_str(), i))
+                .collect();
+            (0..old.len())
+                .flat_map(|d| [old_selected.checked_add(d), old_selected.checked_sub(d)])
+                .flatten()
+                .filter(|&i| i < old.len())
+                .find_map(|i| positions.get(old.get(i).name.as_str()).copied())
+                .unwrap_or(0)
+        };
+        let row = old_selected.saturating_sub(old.state.offset());
+        self.state.select(Some(selected));
+        *self.state.offset_mut() = selected.saturating_sub(row);
+        self.owner_widths.0 = self.owner_widths.0.max(old.owner_widths.0);
+        self.owner_widths.1 = self.owner_widths.1.max(old.owner_widths.1);
+
+        let old_sizes: HashMap<&str, (Option<usize>, Option<usize>)> = old
+            .entries
+            .iter()
+            .map(|e| (e.name.as_str(), (e.size, e.rentries)))
+            .collect();
+        let delta = |new: Option<usize>, old: Option<usize>| {
+            new.unwrap_or(0) as isize - old.unwrap_or(0) as isize
+        };
+        let changed: HashMap<CompactString, Change> = self
+            .entries
+            .iter()
+            .filter_map(|e| {
+                let change = match old_sizes.get(e.name.as_str()) {
+                    None => Change::Added,
+                    Some(&old) if old == (e.size, e.rentries) => return None,
+                    Some(&(size, rentries)) => Change::Resized {
+                        size: delta(e.size, size),
+                        rentries: delta(e.rentries, rentries),
+                    },
+                };
+                Some((e.name.clone(), change))
+            })
+            .collect();
+        // Keep showing the earlier changes, adding up to how much entries
+        // changed since they were first shown, like while watching a cleanup
+        self.changed = old.changed.clone();
+        self.changed_at = old.changed_at;
+        self.changes_highlighted = old.changes_highlighted;
+        if !changed.is_empty() {
+            for (name, change) in changed {
+                let earlier = self.changed.get(&name).copied();
+                let change = match (earlier, change) {
+                    (Some(Change::Added), _) => Change::Added,
+                    (
+                        Some(Change::Resized { size, rentries }),
+                        Change::Resized {
+                            size: more_size,
+                            rentries: more_rentries,
+                        },
+                    ) => Change::Resized {
+                        size: size + more_size,
+                        rentries: rentries + more_rentries,
+                    },
+                    (_, change) => change,
+                };
+                self.changed.insert(name, change);
+            }
+            self.changed_at = Some(Instant::now());
+            self.changes_highlighted = true;
+        }
+        let names: HashSet<&str> = self.entries.iter().map(|e| e.name.as_str()).collect();
+        self.changed.retain(|name, _| names.contains(name.as_str()));
+
+        // Entries that are gone can't stay marked
+        self.marked = old
+            .marked
+            .iter()
+            .filter(|name| names.contains(name.as_str()))
+            .cloned()
+            .collect();
+    }
+
+    /// Find out which of the `count` entries from `first` have ACLs, which
+    /// would take an extra getxattr(2) for every entry if done for all of them
+    pub fn check_acls(&mut self, first: usize, count: usize) {
+        for idx in first..(first + count).min(self.len()) {
+            if let Some(i) = self.entry_index(idx)
+                && self.entries[i].acl.is_none()
+            {
+                let path = self.path.join(self.entries[i].name.as_str());
+                self.entries[i].acl = Some(acl::has_acl(&path));
+            }
+        }
+    }
+
+    /// Find out which data pools the directory and the `count` entries from
+    /// `first` are on, for what they cost; an extra getxattr(2) each
+    pub fn check_pools(&mut self, first: usize, count: usize) {
+        if self.pool.is_none() {
+            self.pool = Some(get_effective_pool(&self.path));
+        }
+        for idx in first..(first + count).min(self.len()) {
+            if let Some(i) = self.entry_index(idx)
+                && !self.pools.contains_key(&self.entries[i].name)
+            {
+                let entry = &self.entries[i];
+                let path = self.path.join(entry.name.as_str());
+                let pool = get_pool(&path, entry.kind == EntryKind::Dir);
+                self.pools.insert(entry.name.clone(), pool);
+            }
+        }
+    }
+
+    /// What storing `entry` costs a month. Mount points are left out, since
+    /// the rates are for this filesystem.
+    pub fn cost(&self, entry: &DirEntry, cost: &CostConfig) -> Option<f64> {
+        if self.mounted.contains_key(&entry.name) {
+            return None;
+        }
+        let pool = match self.pools.get(&entry.name) {
+            Some(Some(pool)) => Some(pool.as_str()),
+            _ => self.pool.as_ref().and_then(|pool| pool.as_deref()),
+        };
+        cost.monthly(entry.size?, pool)
+    }
+
+    /// What storing the whole directory costs a month, at the rate of its pool
+    pub fn total_cost(&self, cost: &CostConfig) -> Option<f64> {
+        let pool = self.pool.as_ref().and_then(|pool| pool.as_deref());
+        cost.monthly(self.stats.total_size, pool)
+            .filter(|_| self.is_ceph())
+    }
+
+    pub fn is_marked(&self, entry: &DirEntry) -> bool {
+        self.marked.contains(&entry.name)
+    }
+
+    pub fn marked_count(&self) -> usize {
+        self.marked.len()
+    }
+
+    /// The total size and file count of the marked entries, except those
+    /// with another filesystem mounted on them; see `marked_sums_elsewhere()`
+    pub fn marked_sums(&self) -> (usize, usize) {
+        child_sums(
+            self.entries
+                .iter()
+                .filter(|e| self.is_marked(e) && !self.mounted.contains_key(&e.name)),
+        )
+    }
+
+    /// The total sizes and file counts of the marked entries with other
+    /// filesystems mounted on them, one per filesystem, since adding up the
+    /// numbers of different filesystems would be misleading
+    pub fn marked_sums_elsewhere(&self) -> Vec<(&Mount, usize, usize)> {
+        let mut sums: Vec<(&Mount, usize, usize)> = Vec::new();
+        for entry in self.entries.iter().filter(|e| self.is_marked(e)) {
+            let Some(mount) = self.mounted.get(&entry.name) else {
+                continue;
+            };
+            let (size, rentries) = child_sums([entry]);
+            match sums.iter_mut().find(|(m, _, _)| m.same_filesystem(mount)) {
+                Some(sum) => {
+                    sum.1 += size;
+                    sum.2 += rentries;
+                }
+                None => sums.push((mount, size, rentries)),
+            }
+        }
+        sums
+    }
+
+    /// The filesystem mounted on `entry`, if it's a mount point
+    pub fn mounted_on(&self, entry: &DirEntry) -> Option<&Mount> {
+        self.mounted.get(&entry.name)
+    }
+
+    /// Mark the selected entry, or unmark it if it's marked. The parent
+    /// directory can't be marked.
+    pub fn toggle_marked(&mut self) {
+        let Some(i) = self.selected().and_then(|s| self.entry_index(s)) else {
+            return;
+        };
+        let name = &self.entries[i].name;
+        if !self.marked.remove(name) {
+            self.marked.insert(name.clone());
+        }
+    }
+
+    /// Mark the entries whose names match the glob `pattern`, with or
+    /// without the trailing slash of directories (so `*/` marks just the
+    /// directories). Returns how many matched.
+    pub fn mark_matching(&mut self, pattern: &str) -> usize {
+        let matching: Vec<CompactString> = self
+            .entries
+            .iter()
+            .filter(|e| {
+                glob::matches(pattern, &e.name)
+                    || glob::matches(pattern, e.name.trim_end_matches('/'))
+            })
+            .map(|e| e.name.clone())
+            .collect();
+        let count = matching.len();
+        self.marked.extend(matching);
+        count
+    }
+
+    pub fn mark_all(&mut self) {
+        self.marked = self.entries.iter().map(|e| e.name.clone()).collect();
+    }
+
+    pub fn invert_marked(&mut self) {
+        self.marked = self
+            .entries
+            .iter()
+            .filter(|e| !self.marked.contains(&e.name))
+            .map(|e| e.name.clone())
+            .collect();
+    }
+
+    pub fn unmark_all(&mut self) {
+        self.marked.clear();
+    }
+
+    /// Whether `entry` was added or resized in the latest refresh
+    pub fn is_changed(&self, entry: &DirEntry) -> bool {
+        self.changes_highlighted && self.changed.contains_key(&entry.name)
+    }
+
+    /// How `entry` changed in the latest refresh, for a while after it
+    pub fn change(&self, entry: &DirEntry) -> Option<Change> {
+        self.changed.get(&entry.name).copied()
+    }
+
+    /// How `entry` changed since the directory was visited before
+    pub fn since_visit(&self, entry: &DirEntry) -> Option<Change> {
+        self.since_visit.get(&entry.name).copied()
+    }
+
+    /// Stop highlighting changed rows after a while, and then stop showing
+    /// how they changed. Returns true if either stopped.
+    fn expire_changed(&mut self) -> bool {
+        let Some(elapsed) = self.changed_at.map(|t| t.elapsed()) else {
+            return false;
+        };
+        if elapsed >= CHANGE_ARROWS {
+            self.changed.clear();
+            self.changed_at = None;
+            self.changes_highlighted = false;
+            true
+        } else if self.changes_highlighted && elapsed >= CHANGE_HIGHLIGHT {
+            self.changes_highlighted = false;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Adjust the scroll offset so that the selected entry is on screen, with
+    /// `margin` entries above and below it where there are any, and return
+    /// the new offset.
+    pub fn scroll_into_view(&mut self, height: usize, margin: usize) -> usize {
+        let len = self.len();
+        let margin = margin.min(height.saturating_sub(1) / 2);
+        let mut offset = self.state.offset();
+        if let Some(selected) = self.state.selected() {
+            let selected = selected.min(len.saturating_sub(1));
+            if selected < offset + margin {
+                offset = selected.saturating_sub(margin);
+            } else if height > 0 && selected + margin >= offset + height {
+                offset = selected + margin + 1 - height;
+            }
+        }
+        offset = offset.min(len.saturating_sub(height));
+        *self.state.offset_mut() = offset;
+        offset
     }
 
     pub fn sort_mode(&self) -> SortMode {
@@ -497,66 +3912,475 @@ impl DirListing {
 
     pub fn sort(&mut self, sort_mode: SortMode) {
         if self.sort_mode.same_field(&sort_mode) {
+            // Reversing shows the other end, which has to be in order too
+            self.finish_sort();
             self.sort_mode = sort_mode;
             return;
         }
 
-        sort(&mut self.entries, sort_mode);
-
         self.sort_mode = sort_mode;
+        self.sort_entries();
+    }
+
+    /// Sort the entries in the current mode, in two steps if there are a lot
+    fn sort_entries(&mut self) {
+        self.partially_sorted = sort_first(&mut self.entries, self.sort_mode);
+    }
+
+    /// Put the rest of the entries in order, after only the first ones
+    /// displayed were. Returns true if there was anything to do.
+    pub fn finish_sort(&mut self) -> bool {
+        if !self.partially_sorted {
+            return false;
+        }
+        // Those displayed first stay where they are, and the selection stays
+        // at the same row, e.g. the last after End
+        sort_rest(&mut self.entries, self.sort_mode);
+        self.partially_sorted = false;
+        true
+    }
+
+    /// Fill in recursive sizes of directories from `deep_size`, where known.
+    pub fn set_deep_sizes(&mut self, deep_size: impl Fn(&str) -> Option<DeepSize>) {
+        for entry in self.entries.iter_mut().chain(&mut self.hidden) {
+            if entry.kind != EntryKind::Dir {
+                continue;
+            }
+            if let Some(size) = deep_size(&entry.name) {
+                entry.size = Some(size.bytes);
+                entry.rentries = Some(size.entries);
+                self.stats.max_size = self.stats.max_size.max(size.bytes);
+                self.stats.max_rentries = self.stats.max_rentries.max(size.entries);
+            }
+        }
+    }
+
+    /// Whether only some of the directory has been listed
+    pub fn has_more(&self) -> bool {
+        self.more.is_some()
+    }
+
+    /// List up to `limit` more entries of a directory that's listed one page
+    /// at a time. Their metadata is loaded as they're displayed.
+    pub fn load_more(&mut self, limit: usize, cancel: &AtomicBool) -> Result<usize, io::Error> {
+        let Some(more) = &mut self.more else {
+            return Ok(0);
+        };
+        let dirents = read_names(more, limit, cancel)?;
+        if more.peek().is_none() {
+            self.more = None;
+        }
+
+        let selected = self.selected().map(|i| self.get(i).name.clone());
+        let new_entries = unloaded_entries(&dirents);
+        self.stats.kinds.add(&new_entries);
+        if self.hide_dotfiles {
+            let (hidden, shown): (Vec<_>, Vec<_>) =
+                new_entries.into_iter().partition(DirEntry::is_dotfile);
+            self.hidden.extend(hidden);
+            self.entries.extend(shown);
+        } else {
+            self.entries.extend(new_entries);
+        }
+        self.sort_entries();
+        if let Some(name) = selected {
+            self.select_by_name(&name);
+        }
+        self.load_report.entries = self.entries.len();
+        Ok(dirents.len())
+    }
+
+    pub fn size_source(&self) -> SizeSource {
+        self.size_source
+    }
+
+    /// Show directory sizes from `source`, re-sorting if needed.
+    pub fn set_size_source(&mut self, source: SizeSource) {
+        if source == self.size_source {
+            return;
+        }
+        self.size_source = source;
+
+        for entry in self.entries.iter_mut().chain(&mut self.hidden) {
+            if entry.kind == EntryKind::Dir {
+                std::mem::swap(&mut entry.size, &mut entry.alt_size);
+            }
+        }
+        self.stats.max_size = self
+            .entries
+            .iter()
+            .map(|e| e.size.unwrap_or(0))
+            .max()
+            .unwrap_or(0);
+        std::mem::swap(&mut self.stats.total_size, &mut self.stats.alt_total_size);
+        self.stats.child_size = child_sums(
+            self.entries
+                .iter()
+                .chain(&self.hidden)
+                .filter(|e| !self.mounted.contains_key(&e.name)),
+        )
+        .0;
+
+        if self.sort_mode.field() != &SortField::Name {
+            self.sort_entries();
+        }
+    }
+
+    /// Leave the dotfiles out of the listing, or put them back. The gauges
+    /// are scaled to what's shown, but the totals still include everything.
+    pub fn set_hide_dotfiles(&mut self, hide: bool) {
+        if hide == self.hide_dotfiles {
+            return;
+        }
+        self.hide_dotfiles = hide;
+
+        // Taking entries out of the middle would muddle what's in order
+        self.finish_sort();
+        let selected = self.selected().map(|i| self.get(i).name.clone());
+        if hide {
+            let (hidden, shown) = std::mem::take(&mut self.entries)
+                .into_iter()
+                .partition(DirEntry::is_dotfile);
+            self.entries = shown;
+            self.hidden = hidden;
+            // Hidden entries can't stay marked
+            let hidden: HashSet<&str> = self.hidden.iter().map(|e| e.name.as_str()).collect();
+            self.marked.retain(|name| !hidden.contains(name.as_str()));
+        } else {
+            self.entries.append(&mut self.hidden);
+            self.sort_entries();
+        }
+        self.stats.max_size = self
+            .entries
+            .iter()
+            .map(|e| e.size.unwrap_or(0))
+            .max()
+            .unwrap_or(0);
+        self.stats.max_rentries = self
+            .entries
+            .iter()
+            .map(|e| e.rentries.unwrap_or(0))
+            .max()
+            .unwrap_or(0);
+
+        if selected.is_none_or(|name| self.select_by_name(&name).is_none()) {
+            self.select_first();
+        }
+    }
+
+    /// How many entries are hidden and their total size, if any are
+    pub fn hidden_sums(&self) -> Option<(usize, usize)> {
+        (!self.hidden.is_empty()).then(|| (self.hidden.len(), child_sums(&self.hidden).0))
+    }
+
+    /// Approximate heap usage of the listing, in bytes
+    pub fn memory_usage(&self) -> usize {
+        let names: usize = self
+            .entries
+            .iter()
+            .chain(&self.hidden)
+            .filter(|e| e.name.is_heap_allocated())
+            .map(|e| e.name.capacity())
+            .sum();
+        (self.entries.capacity() + self.hidden.capacity()) * std::mem::size_of::<DirEntry>() + names
     }
 
+    /// Whether the directory is on CephFS, or on an NFS export of it that
+    /// has Ceph's recursive stats
     pub fn is_ceph(&self) -> bool {
-        self.fs.is_some_and(|fs| fs.is_ceph())
+        self.fs.is_some_and(|fs| fs.is_ceph()) || self.share.is_some_and(Share::has_ceph_stats)
+    }
+
+    /// Which CephFS the directory is on, if it's known
+    pub fn cephfs(&self) -> Option<&CephFs> {
+        self.mount
+            .as_ref()?
+            .cephfs
+            .as_ref()
+            .filter(|_| self.is_ceph())
+    }
+
+    /// How much of the directory's recursive size and file count isn't
+    /// accounted for by the listed entries: e.g. data retained by snapshots,
+    /// or changes that haven't propagated yet. Negative if the entries add up
+    /// to more than the totals. None if we can't tell.
+    pub fn unaccounted(&self) -> Option<(isize, isize)> {
+        if !self.is_ceph() || self.load_report.lazy {
+            return None;
+        }
+        Some((
+            self.stats.total_size as isize - self.stats.child_size as isize,
+            self.stats.total_rentries as isize - self.stats.child_rentries as isize,
+        ))
+    }
+
+    /// When anything in the tree last changed, from the recursive ctime. Only
+    /// CephFS keeps that; elsewhere the directory's own ctime says little.
+    pub fn last_activity(&self) -> Option<usize> {
+        self.stats.rctime.filter(|_| self.is_ceph())
+    }
+
+    /// Ceph propagates recursive stats up the tree lazily, so the totals may
+    /// lag reality. That's likely if an entry changed more recently than the
+    /// directory's rctime says, or if anything changed in the last few seconds.
+    pub fn stats_may_be_stale(&self) -> bool {
+        let Some(rctime) = self.stats.rctime.filter(|_| self.is_ceph()) else {
+            return false;
+        };
+        let now = chrono::Local::now().timestamp().max(0) as usize;
+        self.stats.newest_ctime > rctime || now.saturating_sub(rctime) < RSTAT_PROPAGATION_SECS
+    }
+}
+
+/// Whether `e` says that a directory is gone: deleted, or on a mount that
+/// went stale or lost its server
+fn is_gone(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::NotFound
+        || matches!(
+            e.raw_os_error(),
+            Some(libc::ESTALE | libc::ENOTCONN | libc::ENODEV)
+        )
+}
+
+/// The sizes and file counts of `entries` added up. Each entry counts itself
+/// as a file, like the directory's rentries does.
+fn child_sums<'a>(entries: impl IntoIterator<Item = &'a DirEntry>) -> (usize, usize) {
+    entries.into_iter().fold((0, 0), |(size, rentries), entry| {
+        (
+            size + entry.size.unwrap_or(0),
+            rentries + entry.rentries.unwrap_or(0) + 1,
+        )
+    })
+}
+
+/// The biggest entries of `listing`, with how much of it they take, for the peek popup
+fn peek_text(listing: &DirListing) -> String {
+    let mut biggest: Vec<&DirEntry> = listing.entries.iter().chain(&listing.hidden).collect();
+    if biggest.is_empty() {
+        return "Empty".to_string();
+    }
+    biggest.sort_by_key(|e| std::cmp::Reverse(e.size.unwrap_or(0)));
+    let total = listing.stats.total_size.max(1);
+    let shown = &biggest[..biggest.len().min(PEEK_ENTRIES)];
+    let name_width = shown
+        .iter()
+        .map(|e| e.name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut lines: Vec<String> = shown
+        .iter()
+        .map(|e| {
+            let percent = 100.0 * e.size.unwrap_or(0) as f64 / total as f64;
+            format!(
+                "{:>8}  {:>5.1}%  {:nw$}",
+                size_str(e.size, false),
+                percent,
+                e.name,
+                nw = name_width
+            )
+        })
+        .collect();
+    if biggest.len() > shown.len() {
+        lines.push(format!("and {} more", biggest.len() - shown.len()));
+    }
+    lines.join("\n")
+}
+
+/// `text` cut to `room` characters by leaving out its start, for paths,
+/// since the name is at the end
+fn elide_start(text: &str, room: usize) -> String {
+    let len = text.chars().count();
+    match len > room {
+        true => format!("…{}", text.chars().skip(len - room + 1).collect::<String>()),
+        false => text.to_string(),
     }
 }
 
 fn sort(entries: &mut [DirEntry], sort_mode: SortMode) {
     match sort_mode.field() {
-        SortField::Name => entries.sort_by(|a, b| a.name.cmp(&b.name).then(a.size.cmp(&b.size))),
-        SortField::Size => {
-            entries.sort_by(|a, b| a.size.cmp(&b.size).then(a.rentries.cmp(&b.rentries)))
+        // Resolves the names of everything in the listing, but only when asked
+        SortField::Owner => entries.sort_by_cached_key(|e| (e.user(false), e.group(false), e.size)),
+        SortField::Group => entries.sort_by_cached_key(|e| (e.group(false), e.user(false), e.size)),
+        field => entries.sort_by(|a, b| compare(field, a, b)),
+    }
+}
+
+/// The order of `a` and `b` when sorting by `field`, in the normal direction
+fn compare(field: &SortField, a: &DirEntry, b: &DirEntry) -> std::cmp::Ordering {
+    match field {
+        SortField::Name => a.name.cmp(&b.name).then(a.size.cmp(&b.size)),
+        SortField::Size => a.size.cmp(&b.size).then(a.rentries.cmp(&b.rentries)),
+        SortField::Rentries => a.rentries.cmp(&b.rentries).then(a.size.cmp(&b.size)),
+        SortField::CTime => a.ctime.cmp(&b.ctime).then(a.size.cmp(&b.size)),
+        // Files, and directories with nothing in them, have no average and come last
+        SortField::Average => {
+            let key = |e: &DirEntry| {
+                let average = e.average_size();
+                (average.is_none(), average, Reverse(e.rentries))
+            };
+            key(a).cmp(&key(b))
+        }
+        SortField::Owner => {
+            (a.user(false), a.group(false), a.size).cmp(&(b.user(false), b.group(false), b.size))
         }
-        SortField::Rentries => {
-            entries.sort_by(|a, b| a.rentries.cmp(&b.rentries).then(a.size.cmp(&b.size)))
+        SortField::Group => {
+            (a.group(false), a.user(false), a.size).cmp(&(b.group(false), b.user(false), b.size))
         }
-        SortField::CTime => entries.sort_by(|a, b| a.ctime.cmp(&b.ctime).then(a.size.cmp(&b.size))),
-        SortField::Owner => entries.sort_by(|a, b| {
-            a.user
-                .cmp(&b.user)
-                .then(a.group.cmp(&b.group))
-                .then(a.size.cmp(&b.size))
-        }),
     }
 }
 
-fn ls(path: &PathBuf) -> Result<(DirEntry, Vec<DirEntry>), std::io::Error> {
-    let entry_cwd = DirEntry::from(PathBuf::from(path), fs::metadata(path)?);
-    let dir_iterator = fs::read_dir(path)?;
-    let mut entries: Vec<DirEntry> = Vec::new();
+/// Sort `entries`, or, if there are more than PARTIAL_SORT_MIN, only the
+/// PARTIAL_SORT_TOP displayed first, with the rest behind them in no order
+/// until `sort_rest()`. Returns true if it only sorted those.
+fn sort_first(entries: &mut [DirEntry], sort_mode: SortMode) -> bool {
+    let len = entries.len();
+    if len <= PARTIAL_SORT_MIN {
+        sort(entries, sort_mode);
+        return false;
+    }
+    let field = sort_mode.field();
+    // Reversed listings are displayed from the back
+    let top = if sort_mode.is_reversed() {
+        entries.select_nth_unstable_by(len - PARTIAL_SORT_TOP, |a, b| compare(field, a, b));
+        &mut entries[len - PARTIAL_SORT_TOP..]
+    } else {
+        entries.select_nth_unstable_by(PARTIAL_SORT_TOP, |a, b| compare(field, a, b));
+        &mut entries[..PARTIAL_SORT_TOP]
+    };
+    sort(top, sort_mode);
+    true
+}
 
-    for entry_result in dir_iterator {
-        if poll(Duration::from_secs(0)).unwrap_or(false) {
-            // If the user presses Ctrl-C during this loop, interrupt.
-            // TODO: this is the wrong way to do this! The whole app should use an
-            // async runtime that can handle key presses and interrupts.
+/// Sort the rest of the entries, after `sort_first()` only sorted some
+fn sort_rest(entries: &mut [DirEntry], sort_mode: SortMode) {
+    let len = entries.len();
+    let rest = if sort_mode.is_reversed() {
+        &mut entries[..len.saturating_sub(PARTIAL_SORT_TOP)]
+    } else {
+        &mut entries[PARTIAL_SORT_TOP.min(len)..]
+    };
+    sort(rest, sort_mode);
+}
 
-            if let Ok(Event::Key(key)) = event::read()
-                && key.code == KeyCode::Char('c')
-                && key.modifiers.contains(KeyModifiers::CONTROL)
-            {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::Interrupted,
-                    "Interrupted by user",
-                ));
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::{self, FsProvider};
+
+    fn install_tree() {
+        provider::set_for_test(provider::sample_tree());
+    }
+
+    fn list(sort_mode: SortMode) -> DirListing {
+        let options = Loader::new(&Config::default()).options();
+        DirListing::from(
+            Path::new("/data"),
+            sort_mode,
+            options,
+            &AtomicBool::new(false),
+        )
+        .unwrap()
+    }
+
+    fn names(listing: &DirListing) -> Vec<&str> {
+        listing.iter_entries().map(|e| e.name.as_str()).collect()
+    }
+
+    #[test]
+    fn lists_recursive_stats_of_directories() {
+        install_tree();
+        let listing = list(SortMode::Normal(SortField::Name));
+        assert!(listing.is_ceph());
+        let big = listing.iter_entries().find(|e| e.name == "big/").unwrap();
+        assert_eq!(big.kind, EntryKind::Dir);
+        assert_eq!(big.size, Some(8000));
+        assert_eq!(big.rentries, Some(2));
+        let notes = listing
+            .iter_entries()
+            .find(|e| e.name == "notes.txt")
+            .unwrap();
+        assert_eq!(notes.kind, EntryKind::File);
+        assert_eq!(notes.size, Some(9000));
+    }
+
+    #[test]
+    fn sorts_by_size_biggest_first() {
+        install_tree();
+        let listing = list(SortMode::Reversed(SortField::Size));
+        assert_eq!(
+            names(&listing),
+            ["..", "notes.txt", "big/", "mid.bin", "small/"]
+        );
+    }
+
+    #[test]
+    fn resorts_and_reverses() {
+        install_tree();
+        let mut listing = list(SortMode::Reversed(SortField::Size));
+        listing.sort(SortMode::Normal(SortField::Name));
+        assert_eq!(
+            names(&listing),
+            ["..", "big/", "mid.bin", "notes.txt", "small/"]
+        );
+        listing.sort(SortMode::Reversed(SortField::Name));
+        assert_eq!(
+            names(&listing),
+            ["..", "small/", "notes.txt", "mid.bin", "big/"]
+        );
+        listing.sort(SortMode::Reversed(SortField::CTime));
+        assert_eq!(
+            names(&listing),
+            ["..", "notes.txt", "mid.bin", "big/", "small/"]
+        );
+    }
+
+    fn selected_name(listing: &DirListing) -> &str {
+        listing.get(listing.selected().unwrap()).name.as_str()
+    }
+
+    #[test]
+    fn carries_the_selection_over_to_a_new_order() {
+        install_tree();
+        let mut old = list(SortMode::Normal(SortField::Name));
+        old.state.select(Some(1));
+        assert_eq!(selected_name(&old), "big/");
+        let mut listing = list(SortMode::Reversed(SortField::Size));
+        listing.carry_over(&old);
+        assert_eq!(selected_name(&listing), "big/");
+        assert_eq!(listing.selected(), Some(2));
+    }
+
+    #[test]
+    fn selects_the_nearest_survivor_when_entries_vanish() {
+        let fs = provider::set_for_test(provider::sample_tree());
+        let mut old = list(SortMode::Normal(SortField::Name));
+        old.state.select(Some(2));
+        assert_eq!(selected_name(&old), "mid.bin");
 
-        let entry = entry_result?;
-        let path = entry.path();
-        let metadata = entry.metadata()?;
-        entries.push(DirEntry::from(path, metadata));
+        // The one below is tried before the one above
+        fs.remove(Path::new("/data/mid.bin")).unwrap();
+        let mut listing = list(SortMode::Normal(SortField::Name));
+        listing.carry_over(&old);
+        assert_eq!(selected_name(&listing), "notes.txt");
+
+        fs.remove(Path::new("/data/notes.txt")).unwrap();
+        fs.remove(Path::new("/data/small")).unwrap();
+        let mut listing = list(SortMode::Normal(SortField::Name));
+        listing.carry_over(&old);
+        assert_eq!(selected_name(&listing), "big/");
     }
 
-    Ok((entry_cwd, entries))
+    #[test]
+    fn keeps_the_selection_on_the_same_row() {
+        install_tree();
+        let mut old = list(SortMode::Normal(SortField::Name));
+        old.state.select(Some(2));
+        *old.state.offset_mut() = 1;
+        let mut listing = list(SortMode::Reversed(SortField::Size));
+        listing.carry_over(&old);
+        assert_eq!(selected_name(&listing), "mid.bin");
+        assert_eq!(listing.selected(), Some(3));
+        // Still the second row on screen
+        assert_eq!(listing.state.offset(), 2);
+    }
 }
@@ -0,0 +1,84 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::app::DirListing;
+use crate::config::CacheLimits;
+
+/// A bounded cache of directory listings, keyed by path.
+/// Listings older than the TTL are dropped, and when the entry or byte limit
+/// is exceeded, the oldest listings are evicted first.
+pub struct ListingCache {
+    limits: CacheLimits,
+    entries: VecDeque<(PathBuf, Instant, DirListing)>,
+}
+
+impl ListingCache {
+    pub fn new(limits: CacheLimits) -> Self {
+        ListingCache {
+            limits,
+            entries: VecDeque::new(),
+        }
+    }
+
+    pub fn insert(&mut self, path: PathBuf, listing: DirListing) {
+        self.entries.retain(|(p, _, _)| *p != path);
+        self.entries.push_back((path, Instant::now(), listing));
+        self.evict();
+    }
+
+    /// Whether we have a fresh listing of `path`
+    pub fn contains(&self, path: &Path) -> bool {
+        self.entries
+            .iter()
+            .any(|(p, t, _)| p == path && t.elapsed() < self.limits.ttl())
+    }
+
+    /// A fresh listing of `path`, if we have one, left in the cache
+    pub fn get(&self, path: &Path) -> Option<&DirListing> {
+        self.entries
+            .iter()
+            .find(|(p, t, _)| p == path && t.elapsed() < self.limits.ttl())
+            .map(|(_, _, listing)| listing)
+    }
+
+    /// Remove and return a fresh listing of `path`, if we have one.
+    pub fn take(&mut self, path: &Path) -> Option<DirListing> {
+        self.expire();
+        let idx = self.entries.iter().position(|(p, _, _)| p == path)?;
+        self.entries.remove(idx).map(|(_, _, listing)| listing)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Change the bounds, evicting whatever no longer fits
+    pub fn set_limits(&mut self, limits: CacheLimits) {
+        self.limits = limits;
+        self.evict();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.entries
+            .iter()
+            .map(|(_, _, listing)| listing.memory_usage())
+            .sum()
+    }
+
+    fn expire(&mut self) {
+        let ttl = self.limits.ttl();
+        self.entries.retain(|(_, t, _)| t.elapsed() < ttl);
+    }
+
+    fn evict(&mut self) {
+        self.expire();
+        while self.entries.len() > self.limits.max_entries || self.bytes() > self.limits.max_bytes {
+            self.entries.pop_front();
+        }
+    }
+}
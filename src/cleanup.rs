@@ -0,0 +1,596 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+use crate::app::{DirEntry, EntryKind};
+use crate::config::CleanupConfig;
+use crate::fs::get_rctime;
+use crate::glob;
+use crate::jobs::Progress;
+use crate::loader::{LoadOptions, interrupted_error, ls};
+use crate::provider;
+
+/// How much of two files is compared at a time
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Why the cleanup wizard offers something
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reason {
+    /// A directory where nothing has changed since the cutoff
+    Old,
+    /// Named like what can usually go, by this pattern
+    Matches(String),
+    /// Holds the same bytes as this file, which is kept
+    CopyOf(PathBuf),
+}
+
+/// Something the cleanup wizard offers to delete
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    pub bytes: usize,
+    /// Files and directories, like rentries
+    pub files: usize,
+    pub reason: Reason,
+    /// Whether it's picked to be deleted
+    pub marked: bool,
+    /// What it was like when it was found, and for a copy what the kept file was
+    seen: Option<Seen>,
+    kept_seen: Option<Seen>,
+}
+
+/// What a file or directory was like when the wizard found it, to tell
+/// before deleting it whether it has changed since
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Seen {
+    ino: u64,
+    size: usize,
+    ctime: usize,
+}
+
+impl Seen {
+    fn of(path: &Path) -> Option<Seen> {
+        let stat = provider::get().lstat(path).ok()?;
+        Some(Seen {
+            ino: stat.ino,
+            size: stat.size,
+            ctime: stat.ctime,
+        })
+    }
+}
+
+impl Candidate {
+    fn new(path: PathBuf, entry: &DirEntry, reason: Reason) -> Candidate {
+        let files = match entry.kind {
+            EntryKind::Dir => entry.rentries.unwrap_or(0) + 1,
+            _ => 1,
+        };
+        Candidate {
+            seen: Seen::of(&path),
+            path,
+            kind: entry.kind,
+            bytes: entry.size.unwrap_or(0),
+            files,
+            reason,
+            marked: false,
+            kept_seen: None,
+        }
+    }
+
+    /// Why it mustn't be deleted after all, if it, or the file that's kept
+    /// instead of this copy, has changed since it was found or is no longer
+    /// older than `cutoff`
+    fn changed(&self, cutoff: usize, progress: &Progress) -> Option<String> {
+        let changed = "it changed since the search".to_string();
+        if self.seen.is_none() || Seen::of(&self.path) != self.seen {
+            return Some(changed);
+        }
+        match &self.reason {
+            Reason::Old if get_rctime(&self.path).is_none_or(|rctime| rctime >= cutoff) => {
+                Some(changed)
+            }
+            Reason::CopyOf(kept)
+                if self.kept_seen.is_none() || Seen::of(kept) != self.kept_seen =>
+            {
+                Some(format!("{} changed since the search", kept.display()))
+            }
+            // In case both changed within a second, and so have the same ctimes
+            Reason::CopyOf(kept) if !same_contents(kept, &self.path, progress).unwrap_or(false) => {
+                Some(format!(
+                    "it no longer holds the same bytes as {}",
+                    kept.display()
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The cleanup wizard's search for what's worth deleting under a directory,
+/// in the background: the biggest directories where nothing has changed in
+/// a while, what's named like core dumps and caches, and copies of files
+pub struct CleanupScan {
+    pub root: PathBuf,
+    pub job: usize,
+    /// How long nothing must have changed in a directory for it to be old
+    pub days: u64,
+    /// Once it's done: of each kind, the biggest first, or why it failed
+    pub result: Option<Result<Vec<Candidate>, String>>,
+    rx: Receiver<io::Result<Vec<Candidate>>>,
+}
+
+impl CleanupScan {
+    /// Look under `root` as the job `job`, without going into what matches
+    /// `exclude`. Directories are old if they haven't changed in `days`.
+    pub fn start(
+        root: &Path,
+        days: u64,
+        options: LoadOptions,
+        exclude: &[String],
+        config: &CleanupConfig,
+        job: usize,
+        progress: Arc<Progress>,
+    ) -> CleanupScan {
+        let (tx, rx) = mpsc::channel();
+        let thread_root = root.to_path_buf();
+        let cutoff = crate::purge::cutoff(days);
+        let exclude = exclude.to_vec();
+        let config = config.clone();
+        provider::spawn(move || {
+            let _ = tx.send(find(
+                &thread_root,
+                cutoff,
+                options,
+                &exclude,
+                &config,
+                &progress,
+            ));
+        });
+        CleanupScan {
+            root: root.to_path_buf(),
+            job,
+            days,
+            result: None,
+            rx,
+        }
+    }
+
+    /// Take the result if the search has finished. Returns true if it just did.
+    pub fn poll(&mut self) -> bool {
+        if self.result.is_some() {
+            return false;
+        }
+        self.result = match self.rx.try_recv() {
+            Err(TryRecvError::Empty) => return false,
+            Ok(result) => Some(result.map_err(|e| e.to_string())),
+            Err(TryRecvError::Disconnected) => Some(Err("the search died".to_string())),
+        };
+        true
+    }
+
+    /// What it found, once it's done
+    pub fn candidates(&self) -> &[Candidate] {
+        match &self.result {
+            Some(Ok(candidates)) => candidates,
+            _ => &[],
+        }
+    }
+
+    pub fn candidates_mut(&mut self) -> &mut [Candidate] {
+        match &mut self.result {
+            Some(Ok(candidates)) => candidates,
+            _ => &mut [],
+        }
+    }
+
+    /// The ones picked to be deleted
+    pub fn marked(&self) -> impl Iterator<Item = &Candidate> {
+        self.candidates().iter().filter(|c| c.marked)
+    }
+
+    /// Stop offering what's at `paths`, once it's been deleted
+    pub fn forget(&mut self, paths: &[PathBuf]) {
+        if let Some(Ok(candidates)) = &mut self.result {
+            candidates.retain(|c| !paths.contains(&c.path));
+        }
+    }
+}
+
+/// Walk `root`, staying on its filesystem. What's offered as a whole isn't
+/// gone into, so nothing in it is offered again, and no file that's kept as
+/// the original of copies is in something else that's offered.
+fn find(
+    root: &Path,
+    cutoff: usize,
+    mut options: LoadOptions,
+    exclude: &[String],
+    config: &CleanupConfig,
+    progress: &Progress,
+) -> io::Result<Vec<Candidate>> {
+    // Caches are usually hidden, and a search that leaves out what timed
+    // out would miss things, so it waits
+    options.lazy_threshold = usize::MAX;
+    options.page_size = usize::MAX;
+    options.hide_dotfiles = false;
+    options.timeout = None;
+    let fs = provider::get();
+    let root_stat = fs.lstat(root)?;
+    let dev = root_stat.dev;
+    // Directories reached again, e.g. through a bind mount, aren't offered again
+    let mut visited = HashSet::from([(root_stat.dev, root_stat.ino)]);
+    let mut old = Vec::new();
+    let mut matching = Vec::new();
+    // Files of the same size might be copies, with their ctimes
+    let mut by_size: HashMap<usize, Vec<(PathBuf, usize)>> = HashMap::new();
+    let mut stack = vec![root.to_path_buf()];
+    progress.add_total(1);
+
+    while let Some(dir) = stack.pop() {
+        if progress.is_cancelled() {
+            return Err(interrupted_error());
+        }
+        progress.add_done(1);
+        let Ok((_, entries, _, _)) = ls(&dir, options, progress.cancelled()) else {
+            continue;
+        };
+        progress.add_items(entries.len());
+        for entry in &entries {
+            let name = entry.name.trim_end_matches('/');
+            if glob::matches_any(exclude, name) {
+                continue;
+            }
+            let path = dir.join(name);
+            let is_dir = entry.kind == EntryKind::Dir;
+            if is_dir
+                && !fs
+                    .lstat(&path)
+                    .is_ok_and(|stat| stat.dev == dev && visited.insert((stat.dev, stat.ino)))
+            {
+                continue;
+            }
+            let listed = if is_dir {
+                format!("{}/", name)
+            } else {
+                name.to_string()
+            };
+            if let Some(pattern) = config.patterns.iter().find(|p| glob::matches(p, &listed)) {
+                matching.push(Candidate::new(
+                    path,
+                    entry,
+                    Reason::Matches(pattern.clone()),
+                ));
+            } else if is_dir && entry.ctime.is_some_and(|ctime| ctime < cutoff) {
+                old.push(Candidate::new(path, entry, Reason::Old));
+            } else if is_dir {
+                progress.add_total(1);
+                stack.push(path);
+            } else if entry.kind == EntryKind::File
+                && let Some(size) = entry.size.filter(|&size| size >= config.duplicate_min_size)
+            {
+                let ctime = entry.ctime.unwrap_or(0);
+                by_size.entry(size).or_default().push((path, ctime));
+            }
+        }
+    }
+
+    let copies = find_copies(by_size, progress)?;
+    let mut candidates = Vec::new();
+    for mut kind in [old, matching, copies] {
+        kind.sort_by_key(|c| Reverse(c.bytes));
+        kind.truncate(config.max_per_kind);
+        candidates.extend(kind);
+    }
+    Ok(candidates)
+}
+
+/// The files of `by_size` that hold the same bytes as another one, which is
+/// kept: of each set of copies, the one that changed longest ago. Hard links
+/// to one file aren't copies, since deleting them frees nothing, and files
+/// that can't be read aren't offered.
+fn find_copies(
+    by_size: HashMap<usize, Vec<(PathBuf, usize)>>,
+    progress: &Progress,
+) -> io::Result<Vec<Candidate>> {
+    let fs = provider::get();
+    let mut copies = Vec::new();
+    for (size, mut files) in by_size {
+        if files.len() < 2 {
+            continue;
+        }
+        files.sort_by(|(a, a_ctime), (b, b_ctime)| (a_ctime, a).cmp(&(b_ctime, b)));
+        let mut inodes = HashSet::new();
+        let mut rest: Vec<PathBuf> = files
+            .into_iter()
+            .map(|(path, _)| path)
+            .filter(|path| fs.lstat(path).is_ok_and(|s| inodes.insert((s.dev, s.ino))))
+            .collect();
+        progress.add_total(rest.len());
+        while rest.len() > 1 {
+            let kept = rest.remove(0);
+            // Before comparing, so that what changes while it's read is noticed
+            let kept_seen = Seen::of(&kept);
+            progress.add_done(1);
+            let mut different = Vec::new();
+            for path in rest {
+                let seen = Seen::of(&path);
+                match same_contents(&kept, &path, progress) {
+                    Ok(false) => {
+                        different.push(path);
+                        continue;
+                    }
+                    Ok(true) => copies.push(Candidate {
+                        path,
+                        kind: EntryKind::File,
+                        bytes: size,
+                        files: 1,
+                        reason: Reason::CopyOf(kept.clone()),
+                        marked: false,
+                        seen,
+                        kept_seen,
+                    }),
+                    Err(_) if progress.is_cancelled() => return Err(interrupted_error()),
+                    Err(_) => {}
+                }
+                progress.add_done(1);
+            }
+            rest = different;
+        }
+        progress.add_done(rest.len());
+    }
+    Ok(copies)
+}
+
+/// Whether the files `a` and `b`, of the same size, hold the same bytes
+fn same_contents(a: &Path, b: &Path, progress: &Progress) -> io::Result<bool> {
+    let fs = provider::get();
+    let (mut a, mut b) = (fs.open(a)?, fs.open(b)?);
+    let (mut a_buf, mut b_buf) = (vec![0; CHUNK_SIZE], vec![0; CHUNK_SIZE]);
+    loop {
+        if progress.is_cancelled() {
+            return Err(interrupted_error());
+        }
+        let n = read_chunk(&mut a, &mut a_buf)?;
+        if read_chunk(&mut b, &mut b_buf)? != n || a_buf[..n] != b_buf[..n] {
+            return Ok(false);
+        }
+        if n == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Fill `buf` from `file`, unless it ends first. Returns how much was read.
+fn read_chunk(file: &mut dyn Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match file.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(read) => n += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+/// How deleting what was picked in the cleanup wizard went
+#[derive(Debug, Clone, Default)]
+pub struct Deleted {
+    pub paths: Vec<PathBuf>,
+    pub bytes: usize,
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+/// Deleting what was picked in the cleanup wizard, in the background
+pub struct Deletion {
+    pub root: PathBuf,
+    pub job: usize,
+    /// Once it's done, or was cancelled
+    pub result: Option<Deleted>,
+    rx: Receiver<Deleted>,
+}
+
+impl Deletion {
+    /// Delete `candidates`, which are under `root` and were found by a scan
+    /// for what's unchanged in `days`, as the job `job`
+    pub fn start(
+        root: &Path,
+        candidates: Vec<Candidate>,
+        days: u64,
+        job: usize,
+        progress: Arc<Progress>,
+    ) -> Deletion {
+        let (tx, rx) = mpsc::channel();
+        let thread_root = root.to_path_buf();
+        provider::spawn(move || {
+            let _ = tx.send(delete(&thread_root, candidates, days, &progress));
+        });
+        Deletion {
+            root: root.to_path_buf(),
+            job,
+            result: None,
+            rx,
+        }
+    }
+
+    /// Take the result if it has finished. Returns true if it just did.
+    pub fn poll(&mut self) -> bool {
+        if self.result.is_some() {
+            return false;
+        }
+        self.result = match self.rx.try_recv() {
+            Err(TryRecvError::Empty) => return false,
+            Ok(deleted) => Some(deleted),
+            Err(TryRecvError::Disconnected) => Some(Deleted {
+                failed: vec![(self.root.clone(), "the deletion died".to_string())],
+                ..Deleted::default()
+            }),
+        };
+        true
+    }
+}
+
+/// Delete `candidates` one by one, until cancelled. What has changed since
+/// it was found is left alone: a directory that is no longer unchanged in
+/// `days`, or a copy that, or whose kept file, was written to since.
+fn delete(root: &Path, candidates: Vec<Candidate>, days: u64, progress: &Progress) -> Deleted {
+    let fs = provider::get();
+    let mut deleted = Deleted::default();
+    let cutoff = crate::purge::cutoff(days);
+    progress.add_total(candidates.len());
+    for candidate in candidates {
+        if progress.is_cancelled() {
+            break;
+        }
+        progress.add_done(1);
+        let result = match &candidate.reason {
+            _ if !candidate.path.starts_with(root) || candidate.path == root => {
+                Err(format!("not under {}", root.display()))
+            }
+            _ => match candidate.changed(cutoff, progress) {
+                Some(e) => Err(e),
+                None => fs.remove(&candidate.path).map_err(|e| e.to_string()),
+            },
+        };
+        match result {
+            Ok(()) => {
+                deleted.bytes += candidate.bytes;
+                progress.add_items(candidate.files);
+                deleted.paths.push(candidate.path);
+            }
+            Err(e) => deleted.failed.push((candidate.path, e)),
+        }
+    }
+    deleted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::loader::Loader;
+    use crate::provider::{FsProvider, MemoryFs};
+    use crate::purge::cutoff;
+
+    const DAYS: u64 = 30;
+
+    /// /scratch with a directory that hasn't changed in ages, with another
+    /// filesystem mounted in it, and one that just changed, with a core dump
+    fn tree() -> MemoryFs {
+        let mut fs = MemoryFs::new(1000, 1000);
+        fs.add_file(Path::new("/scratch/old/a"), 5000, 100);
+        fs.add_mount_point(Path::new("/scratch/old/mnt"), 100);
+        fs.add_file(Path::new("/scratch/old/mnt/data"), 7000, 100);
+        fs.add_file(Path::new("/scratch/new/b"), 3000, now());
+        fs.add_file(Path::new("/scratch/new/core"), 9000, now());
+        fs
+    }
+
+    fn now() -> usize {
+        cutoff(0)
+    }
+
+    fn search() -> Vec<Candidate> {
+        let options = Loader::new(&Config::default()).options();
+        let cutoff = cutoff(DAYS);
+        let config = CleanupConfig::default();
+        find(
+            Path::new("/scratch"),
+            cutoff,
+            options,
+            &[],
+            &config,
+            &Progress::default(),
+        )
+        .unwrap()
+    }
+
+    fn reasons(candidates: &[Candidate]) -> Vec<(&Path, &Reason)> {
+        candidates
+            .iter()
+            .map(|c| (c.path.as_path(), &c.reason))
+            .collect()
+    }
+
+    #[test]
+    fn finds_old_directories_and_matches() {
+        provider::set_for_test(tree());
+        let candidates = search();
+        assert_eq!(
+            reasons(&candidates),
+            [
+                (Path::new("/scratch/old"), &Reason::Old),
+                (
+                    Path::new("/scratch/new/core"),
+                    &Reason::Matches("core".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn deletes_but_leaves_what_is_mounted_below() {
+        let fs = provider::set_for_test(tree());
+        let deleted = delete(Path::new("/scratch"), search(), DAYS, &Progress::default());
+        assert_eq!(deleted.paths, [Path::new("/scratch/new/core")]);
+        assert_eq!(deleted.bytes, 9000);
+        assert_eq!(deleted.failed.len(), 1);
+        assert_eq!(deleted.failed[0].0, Path::new("/scratch/old"));
+        assert!(deleted.failed[0].1.contains("another filesystem"));
+        assert!(fs.lstat(Path::new("/scratch/old/a")).is_err());
+        assert!(fs.lstat(Path::new("/scratch/old/mnt/data")).is_ok());
+        assert!(fs.lstat(Path::new("/scratch/new/b")).is_ok());
+    }
+
+    #[test]
+    fn leaves_what_is_not_under_the_root() {
+        let fs = provider::set_for_test(tree());
+        let deleted = delete(
+            Path::new("/scratch/new"),
+            search(),
+            DAYS,
+            &Progress::default(),
+        );
+        assert_eq!(deleted.paths, [Path::new("/scratch/new/core")]);
+        assert_eq!(deleted.failed[0].0, Path::new("/scratch/old"));
+        assert!(fs.lstat(Path::new("/scratch/old/a")).is_ok());
+    }
+
+    #[test]
+    fn notices_what_changed_since_the_search() {
+        provider::set_for_test(tree());
+        let candidates = search();
+        let cutoff = cutoff(DAYS);
+        let progress = Progress::default();
+        assert!(
+            candidates
+                .iter()
+                .all(|c| c.changed(cutoff, &progress).is_none())
+        );
+
+        // The same tree, where the old directory had a file added since
+        let mut fs = tree();
+        fs.add_file(Path::new("/scratch/old/new-file"), 10, now());
+        provider::set_for_test(fs);
+        assert_eq!(
+            candidates[0].changed(cutoff, &progress).as_deref(),
+            Some("it changed since the search")
+        );
+        assert_eq!(candidates[1].changed(cutoff, &progress), None);
+
+        // And where the core dump is gone
+        let fs = provider::set_for_test(tree());
+        fs.remove(Path::new("/scratch/new/core")).unwrap();
+        assert_eq!(
+            candidates[1].changed(cutoff, &progress).as_deref(),
+            Some("it changed since the search")
+        );
+        let deleted = delete(Path::new("/scratch"), candidates, DAYS, &progress);
+        assert!(deleted.paths.is_empty());
+        assert_eq!(deleted.failed.len(), 2);
+    }
+}
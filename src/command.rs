@@ -0,0 +1,136 @@
+use std::io::{Read, Write};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Run `command`, giving up on it after `timeout`, since the tools of network
+/// filesystems hang when their servers can't be reached. Fails with a line
+/// to show: why it couldn't be run, or the last thing it said on stderr.
+pub fn output(command: Command, timeout: Duration) -> Result<Output, String> {
+    output_unless(command, timeout, &AtomicBool::new(false))
+}
+
+/// Like `output`, but also kill the command when `cancel` is set
+pub fn output_unless(
+    command: Command,
+    timeout: Duration,
+    cancel: &AtomicBool,
+) -> Result<Output, String> {
+    run(command, None, timeout, cancel)
+}
+
+/// Like `output_unless`, with `input` on the command's stdin
+pub fn output_with_input(
+    command: Command,
+    input: Vec<u8>,
+    timeout: Duration,
+    cancel: &AtomicBool,
+) -> Result<Output, String> {
+    run(command, Some(input), timeout, cancel)
+}
+
+/// `arg` with each "{key}" of `values` replaced by its value, in one pass, so
+/// that a value with a "{key}" of its own in it, like a path, is left as it is.
+/// Other braces are kept.
+pub fn fill(arg: &str, values: &[(&str, &str)]) -> String {
+    let mut filled = String::with_capacity(arg.len());
+    let mut rest = arg;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find('}').and_then(|end| {
+            let (_, value) = values.iter().find(|(key, _)| *key == &rest[1..end])?;
+            Some((end, value))
+        });
+        match value {
+            Some((end, value)) => {
+                filled.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                filled.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+fn run(
+    mut command: Command,
+    input: Option<Vec<u8>>,
+    timeout: Duration,
+    cancel: &AtomicBool,
+) -> Result<Output, String> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("couldn't run {}: {}", program, e))?;
+
+    // Feed it from another thread too, since it may not read all of its
+    // input before it writes, or at all
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        std::thread::spawn(move || {
+            let _ = stdin.write_all(&input);
+        });
+    }
+
+    // Drain the pipes while we wait, so that long answers don't block the command
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+            break status;
+        }
+        if cancel.load(Ordering::Relaxed) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("{} was cancelled", program));
+        }
+        if start.elapsed() > timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!(
+                "{} didn't answer in {} seconds",
+                program,
+                timeout.as_secs()
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    let output = Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    };
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().rfind(|l| !l.trim().is_empty());
+        return Err(match reason {
+            Some(reason) => format!("{}: {}", program, reason.trim()),
+            None => format!("{} failed: {}", program, output.status),
+        });
+    }
+    Ok(output)
+}
+
+/// Read all of `pipe` in another thread
+fn drain(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        bytes
+    })
+}
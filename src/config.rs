@@ -0,0 +1,830 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::ValueEnum;
+use ratatui::style::Color;
+use ratatui::style::palette::tailwind::{RED, YELLOW};
+use serde::Deserialize;
+
+use crate::app::{SizeSource, SortField};
+use crate::exit::parse_size;
+use crate::keymap::{ConfigKey, Keymap};
+use crate::loader::{DEFAULT_LAZY_THRESHOLD, DEFAULT_PAGE_SIZE};
+use crate::projects::ProjectMap;
+use crate::provider;
+use crate::script::{self, ScriptFn};
+use crate::ui::GAUGE_COLOR;
+
+/// How long a plugin script can take to load, e.g. stuck in a loop
+const SCRIPT_LOAD_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Settings read from the config file. Every field has a default, so the
+/// file (and any section of it) is optional.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Directories with more entries than this only load metadata for the visible rows
+    pub lazy_threshold: usize,
+    /// Directories with more entries than this are listed one page at a time
+    pub page_size: usize,
+    /// Give up on the metadata of a chunk of entries after this many seconds,
+    /// e.g. when the MDS hangs, rather than freezing; 0 waits for as long as
+    /// it takes
+    pub metadata_timeout_secs: u64,
+    /// Batch the stat and getxattr calls of listings through io_uring,
+    /// which needs Linux 5.19 for the getxattr ones
+    pub io_uring: bool,
+    /// Keep paths as navigated, rather than resolving symlinks
+    pub logical_paths: bool,
+    /// Refresh automatically when entries in the current directory change
+    pub live_update: bool,
+    /// List the current directory at most once per this many seconds when
+    /// refreshing, however often changes come in or r is pressed
+    pub refresh_interval_secs: u64,
+    /// Where directory sizes come from: "rbytes" or "stat"
+    pub dir_sizes: SizeSource,
+    /// Show how much of each directory's totals its entries don't add up to
+    pub show_unaccounted: bool,
+    /// Leave entries whose names start with a '.' out of listings
+    pub hide_dotfiles: bool,
+    /// Show sizes and counts in full, e.g. "4,213,002,117,632 B", instead of rounded
+    pub exact_sizes: bool,
+    /// Whether rounded sizes are "decimal", like "4.2 TB", or "binary", like "3.8 TiB"
+    pub units: Units,
+    /// How listings are sorted at startup: "name", "size", "count", "owner",
+    /// "group", "ctime", or "average"
+    pub sort: SortField,
+    /// Optional columns shown at startup: "owner", "group", "permissions", "ctime", "average", "cost"
+    pub columns: Vec<Column>,
+    /// Plain text without gauges or box drawing, for screen readers
+    pub plain: bool,
+    /// Show a line of the most useful keys at the bottom of the screen
+    pub hint_bar: bool,
+    /// Ring the bell and send a desktop notification when a background job
+    /// finishes while you're in another directory
+    pub notify: bool,
+    /// Keep at least this many entries above and below the cursor when scrolling
+    pub scroll_off: usize,
+    /// Start in the directory where the last session ended, if no path is given
+    pub resume: bool,
+    /// Remember marks and saved views between sessions, and allow the history.
+    /// Without this, nothing is written to disk, e.g. for shared accounts.
+    pub persist: bool,
+    /// Names of files and directories that recursive walks leave out, as
+    /// globs, e.g. `[".snapshot", ".git"]`, on top of those given with --exclude
+    pub exclude: Vec<String>,
+    /// Key remappings on top of the defaults
+    pub keys: Keymap,
+    /// Entries to highlight for being too big, e.g. `[[alerts]]` `size = "10 TB"`
+    pub alerts: Vec<AlertRule>,
+    /// What scratch purges remove, for `cephdu purge-preview`, e.g.
+    /// `[[retention]]` `path = "/mnt/ceph/scratch"` `days = 60`
+    pub retention: Vec<RetentionPolicy>,
+    /// Commands to run when entering directories, e.g. `[[hooks]]`
+    /// `path = "/mnt/ceph/scratch/*"` `command = ["scratch-quota", "{path}"]`
+    pub hooks: Vec<Hook>,
+    /// A file that maps users and groups to projects, e.g. "user alice
+    /// smith-lab", for adding up usage by project
+    pub projects: Option<PathBuf>,
+    pub cost: CostConfig,
+    pub theme: ThemeConfig,
+    pub cache: CacheConfig,
+    pub history: HistoryConfig,
+    pub recursive_stats: RecursiveStatsConfig,
+    pub subvolumes: SubvolumeConfig,
+    pub dashboard: DashboardConfig,
+    pub plugins: PluginConfig,
+    pub search: SearchConfig,
+    pub cleanup: CleanupConfig,
+    /// Named sets of settings that override the ones above when chosen with
+    /// --profile, e.g. `[profiles.admin]`
+    pub profiles: BTreeMap<String, toml::Table>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            lazy_threshold: DEFAULT_LAZY_THRESHOLD,
+            page_size: DEFAULT_PAGE_SIZE,
+            metadata_timeout_secs: 10,
+            io_uring: false,
+            logical_paths: false,
+            live_update: true,
+            refresh_interval_secs: 1,
+            dir_sizes: SizeSource::default(),
+            show_unaccounted: true,
+            hide_dotfiles: false,
+            exact_sizes: false,
+            units: Units::Decimal,
+            sort: SortField::Size,
+            columns: Vec::new(),
+            plain: false,
+            hint_bar: true,
+            notify: true,
+            scroll_off: 2,
+            resume: false,
+            persist: true,
+            exclude: Vec::new(),
+            keys: Keymap::default(),
+            alerts: Vec::new(),
+            retention: Vec::new(),
+            hooks: Vec::new(),
+            projects: None,
+            cost: CostConfig::default(),
+            theme: ThemeConfig::default(),
+            cache: CacheConfig::default(),
+            history: HistoryConfig::default(),
+            recursive_stats: RecursiveStatsConfig::default(),
+            subvolumes: SubvolumeConfig::default(),
+            dashboard: DashboardConfig::default(),
+            plugins: PluginConfig::default(),
+            search: SearchConfig::default(),
+            cleanup: CleanupConfig::default(),
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+/// A column that's hidden unless turned on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Column {
+    Owner,
+    Group,
+    Permissions,
+    Ctime,
+    /// The average size of the files in directories
+    Average,
+    /// What storing the entries costs a month, from `[cost]`
+    Cost,
+}
+
+/// Entries match if they're at least this big, or have at least this many
+/// files, whichever is given
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertRule {
+    pub size: Option<ConfigSize>,
+    pub files: Option<usize>,
+}
+
+impl AlertRule {
+    pub fn matches(&self, size: Option<usize>, files: Option<usize>) -> bool {
+        let over = |value: Option<usize>, limit: Option<usize>| {
+            value
+                .zip(limit)
+                .is_some_and(|(value, limit)| value >= limit)
+        };
+        over(size, self.size.map(|s| s.0)) || over(files, self.files)
+    }
+}
+
+/// Files under `path` that haven't changed in `days` days may be purged
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionPolicy {
+    pub path: PathBuf,
+    pub days: u64,
+}
+
+/// A command to run on entering a directory whose path matches the glob
+/// `path`, where `*` also matches across slashes. The first line it prints
+/// is shown in the message line.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Hook {
+    pub path: String,
+    pub command: Vec<String>,
+}
+
+/// A number of bytes, or a size like "10 TB", as on the command line
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(try_from = "toml::Value")]
+pub struct ConfigSize(pub usize);
+
+impl TryFrom<toml::Value> for ConfigSize {
+    type Error = String;
+
+    fn try_from(value: toml::Value) -> Result<Self, String> {
+        match value {
+            toml::Value::Integer(n) if n >= 0 => Ok(ConfigSize(n as usize)),
+            toml::Value::String(s) => parse_size(&s).map(ConfigSize),
+            value => Err(format!("invalid size {}", value)),
+        }
+    }
+}
+
+/// What rounded sizes are counted in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Units {
+    /// Powers of 1000: KB, MB, GB, ...
+    Decimal,
+    /// Powers of 1024: KiB, MiB, GiB, ...
+    Binary,
+}
+
+/// Colors, as names like "red" or as "#rrggbb"
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    pub gauge: ThemeColor,
+    /// Gauge colors from a percentage up, e.g. of the parent directory, so
+    /// that the biggest entries stand out
+    pub gauge_thresholds: BTreeMap<Percent, ThemeColor>,
+    /// Colors of the rows of directories with their own quota, from a
+    /// percentage of the quota up
+    pub quota_thresholds: BTreeMap<Percent, ThemeColor>,
+    /// The background of rows that match an alert rule
+    pub alert: ThemeColor,
+}
+
+impl Default for ThemeConfig {
+    fn default() -> Self {
+        ThemeConfig {
+            gauge: ThemeColor(GAUGE_COLOR),
+            gauge_thresholds: BTreeMap::new(),
+            quota_thresholds: BTreeMap::from([
+                (Percent(80), ThemeColor(YELLOW.c300)),
+                (Percent(95), ThemeColor(RED.c400)),
+            ]),
+            alert: ThemeColor(RED.c950),
+        }
+    }
+}
+
+impl ThemeConfig {
+    /// The color of a gauge that shows `fraction`
+    pub fn gauge_color(&self, fraction: Option<f64>) -> Color {
+        threshold_color(&self.gauge_thresholds, fraction.unwrap_or(0.0)).unwrap_or(self.gauge.0)
+    }
+
+    /// The color of the row of a directory that uses `fraction` of its
+    /// quota, if it's close enough to stand out
+    pub fn quota_color(&self, fraction: f64) -> Option<Color> {
+        threshold_color(&self.quota_thresholds, fraction)
+    }
+}
+
+/// The color of the highest threshold that `fraction` reaches
+fn threshold_color(thresholds: &BTreeMap<Percent, ThemeColor>, fraction: f64) -> Option<Color> {
+    thresholds
+        .iter()
+        .rev()
+        .find(|(threshold, _)| fraction * 100.0 >= threshold.0 as f64)
+        .map(|(_, color)| color.0)
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ThemeColor(pub Color);
+
+impl TryFrom<String> for ThemeColor {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, String> {
+        name.parse()
+            .map(ThemeColor)
+            .map_err(|_| format!("unknown color {:?}", name))
+    }
+}
+
+/// A whole percentage, written as a key like `80 = "red"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Percent(pub u32);
+
+impl TryFrom<String> for Percent {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        text.trim_end_matches('%')
+            .parse()
+            .map(Percent)
+            .map_err(|_| format!("not a percentage: {:?}", text))
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Listings of directories we've left, so that going back is instant
+    pub listings: CacheLimits,
+    /// Listings fetched in the background for the highlighted directory
+    pub prefetch: CacheLimits,
+    /// uid/gid to name lookups
+    pub names: NameCacheLimits,
+}
+
+/// Bounds on a cache of directory listings. The oldest listings are evicted
+/// first when a bound is exceeded.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheLimits {
+    pub max_entries: usize,
+    /// Approximate memory used by the cached listings
+    pub max_bytes: usize,
+    pub ttl_secs: u64,
+}
+
+impl Default for CacheLimits {
+    fn default() -> Self {
+        CacheLimits {
+            max_entries: 8,
+            max_bytes: 512 << 20,
+            ttl_secs: 30,
+        }
+    }
+}
+
+impl CacheLimits {
+    pub fn ttl(&self) -> Duration {
+        Duration::from_secs(self.ttl_secs)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NameCacheLimits {
+    pub max_entries: usize,
+    pub ttl_secs: u64,
+}
+
+impl Default for NameCacheLimits {
+    fn default() -> Self {
+        NameCacheLimits {
+            max_entries: 100_000,
+            ttl_secs: 3600,
+        }
+    }
+}
+
+/// Where to record directory sizes over time
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Record the sizes of the directories we visit
+    pub enabled: bool,
+    /// The SQLite database [default: ~/.local/share/cephdu/history.sqlite]
+    pub path: Option<PathBuf>,
+}
+
+impl HistoryConfig {
+    pub fn path(&self) -> PathBuf {
+        self.path
+            .clone()
+            .or_else(|| data_dir().map(|dir| dir.join("history.sqlite")))
+            .unwrap_or_else(|| PathBuf::from("cephdu-history.sqlite"))
+    }
+}
+
+/// Where directory sizes come from on filesystems that, unlike CephFS, don't
+/// keep them for every directory: the usage of quotas, from their tools
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecursiveStatsConfig {
+    /// On Lustre, for the directories at the top of a project, from `lfs quota -p`
+    pub lustre: bool,
+    /// The lfs command
+    pub lfs: PathBuf,
+    /// On GPFS, for the directories where filesets are linked, from `mmlsquota -j`
+    pub gpfs: bool,
+    /// Where mmlsfileset and mmlsquota are
+    pub gpfs_bin: PathBuf,
+}
+
+impl Default for RecursiveStatsConfig {
+    fn default() -> Self {
+        RecursiveStatsConfig {
+            lustre: true,
+            lfs: PathBuf::from("lfs"),
+            gpfs: true,
+            gpfs_bin: PathBuf::from("/usr/lpp/mmfs/bin"),
+        }
+    }
+}
+
+/// Looking up subvolumes with the ceph command, for their quotas and owners
+/// in the details popup
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SubvolumeConfig {
+    /// The command and its options, e.g. ["ceph", "--id", "admin"]. Nothing
+    /// is looked up without one.
+    pub command: Vec<String>,
+    /// The volume, i.e. the name of the CephFS, if the mount doesn't say
+    pub volume: Option<String>,
+}
+
+/// The shares `cephdu dashboard` shows without any given
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DashboardConfig {
+    /// Directories, where any component can be a glob, e.g. "/mnt/ceph/projects/*"
+    pub roots: Vec<String>,
+}
+
+/// How searches for names under a directory (/) go
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SearchConfig {
+    /// Save the paths a search walks through, so that searching the same
+    /// tree again reads them instead of walking it
+    pub index: bool,
+    /// Walk the tree again when its index is older than this
+    pub index_max_age_secs: u64,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            index: false,
+            index_max_age_secs: 24 * 3600,
+        }
+    }
+}
+
+/// What the cleanup wizard (w) offers to delete, besides old directories
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CleanupConfig {
+    /// Globs for what can usually go, like core dumps and caches, matched
+    /// against names with a / after those of directories
+    pub patterns: Vec<String>,
+    /// Files smaller than this aren't checked for copies, in bytes
+    pub duplicate_min_size: usize,
+    /// How many of each kind are offered, the biggest first
+    pub max_per_kind: usize,
+    /// Deleting more bytes than this, or more files than `typed_confirm_files`,
+    /// takes typing what's deleted rather than pressing y
+    pub typed_confirm_bytes: usize,
+    pub typed_confirm_files: usize,
+}
+
+impl Default for CleanupConfig {
+    fn default() -> Self {
+        CleanupConfig {
+            patterns: [
+                "core",
+                "core.[0-9]*",
+                "*.core",
+                "__pycache__/",
+                ".cache/",
+                ".pytest_cache/",
+                ".mypy_cache/",
+                ".ipynb_checkpoints/",
+            ]
+            .map(String::from)
+            .to_vec(),
+            duplicate_min_size: 10_000_000,
+            max_per_kind: 20,
+            typed_confirm_bytes: 100_000_000_000,
+            typed_confirm_files: 100_000,
+        }
+    }
+}
+
+/// Site extensions, as programs that cephdu runs or as rhai scripts that
+/// it loads: actions bound to keys, and columns
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PluginConfig {
+    pub actions: Vec<PluginAction>,
+    pub columns: Vec<PluginColumn>,
+    /// Scripts that add their own actions and columns, after these
+    pub scripts: Vec<PathBuf>,
+}
+
+impl PluginConfig {
+    /// Run the `scripts`, adding the actions and columns they add
+    fn load_scripts(&mut self) -> Result<(), String> {
+        for path in &self.scripts {
+            let loaded = script::load(path, SCRIPT_LOAD_TIMEOUT)?;
+            self.actions.extend(
+                loaded
+                    .actions
+                    .into_iter()
+                    .map(|(key, name, f)| PluginAction {
+                        key,
+                        name,
+                        command: Vec::new(),
+                        script: Some(f),
+                    }),
+            );
+            self.columns
+                .extend(loaded.columns.into_iter().map(|(name, f)| PluginColumn {
+                    name,
+                    command: Vec::new(),
+                    script: Some(f),
+                }));
+        }
+        Ok(())
+    }
+}
+
+/// A key that runs a command on the highlighted entry, showing what it
+/// prints. "{path}", "{name}", and "{dir}" in the arguments are replaced by
+/// the entry's path, its name, and the directory being listed.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginAction {
+    /// Written like in `--keys`; it wins over the key's own binding
+    pub key: ConfigKey,
+    /// What it does, for the help and the jobs panel
+    pub name: String,
+    pub command: Vec<String>,
+    /// What's called instead of the command, for the actions of scripts
+    #[serde(skip)]
+    pub script: Option<ScriptFn>,
+}
+
+/// A column filled in by a command that's run with "{dir}" replaced by each
+/// directory that's listed, and prints lines of "NAME<tab>VALUE" for the
+/// entries it has something to say about
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginColumn {
+    pub name: String,
+    pub command: Vec<String>,
+    /// What's called for each entry instead of the command, for the
+    /// columns of scripts
+    #[serde(skip)]
+    pub script: Option<ScriptFn>,
+}
+
+/// What storage costs, for an estimate of what each directory costs a month
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CostConfig {
+    /// Per TB (10^12 bytes) a month. Nothing is estimated without it.
+    pub per_tb_month: Option<f64>,
+    /// What goes before the amounts
+    pub currency: String,
+    /// The rates of data pools that cost more or less, by the pool names
+    /// of the directory layouts, e.g. `cephfs_ssd = 40.0`
+    pub pools: BTreeMap<String, f64>,
+}
+
+impl Default for CostConfig {
+    fn default() -> Self {
+        CostConfig {
+            per_tb_month: None,
+            currency: "$".to_string(),
+            pools: BTreeMap::new(),
+        }
+    }
+}
+
+impl CostConfig {
+    pub fn is_set(&self) -> bool {
+        self.per_tb_month.is_some() || !self.pools.is_empty()
+    }
+
+    /// Whether the rate depends on the pool, so that it has to be looked up
+    pub fn by_pool(&self) -> bool {
+        !self.pools.is_empty()
+    }
+
+    /// What `bytes` on `pool` cost a month, if there's a rate for it
+    pub fn monthly(&self, bytes: usize, pool: Option<&str>) -> Option<f64> {
+        let rate = pool
+            .and_then(|pool| self.pools.get(pool).copied())
+            .or(self.per_tb_month)?;
+        Some(bytes as f64 / 1e12 * rate)
+    }
+}
+
+/// What the command line overrides in the config file; kept so that it
+/// still wins when the file is reloaded
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    pub path: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub lazy_threshold: Option<usize>,
+    pub sort: Option<SortField>,
+    pub columns: Option<Vec<Column>>,
+    pub logical_paths: bool,
+    pub plain: bool,
+    pub no_persist: bool,
+    pub demo: bool,
+}
+
+impl Overrides {
+    /// Read the config file and apply the overrides to it
+    pub fn load(&self) -> Result<Config, String> {
+        let mut config = Config::load(self.path.as_deref(), self.profile.as_deref())?;
+        if let Some(lazy_threshold) = self.lazy_threshold {
+            config.lazy_threshold = lazy_threshold;
+        }
+        if let Some(sort) = self.sort {
+            config.sort = sort;
+        }
+        if let Some(columns) = &self.columns {
+            config.columns = columns.clone();
+        }
+        config.logical_paths |= self.logical_paths;
+        config.plain |= self.plain;
+        // Made-up sizes and paths don't belong in the history or the marks
+        config.persist &= !self.no_persist && !self.demo;
+        config.history.enabled &= config.persist;
+        config.plugins.load_scripts()?;
+        Ok(config)
+    }
+}
+
+/// Settings that can also be given as environment variables, e.g.
+/// $CEPHDU_DIR_SIZES for `dir_sizes`, so that sites can set defaults for
+/// everyone. These win over the config file. Tables are written inline, e.g.
+/// $CEPHDU_THEME='{ gauge = "blue" }', and merged into the config file's.
+const ENV_SETTINGS: &[&str] = &[
+    "lazy_threshold",
+    "page_size",
+    "metadata_timeout_secs",
+    "io_uring",
+    "logical_paths",
+    "live_update",
+    "refresh_interval_secs",
+    "dir_sizes",
+    "show_unaccounted",
+    "hide_dotfiles",
+    "exact_sizes",
+    "units",
+    "sort",
+    "columns",
+    "plain",
+    "hint_bar",
+    "notify",
+    "scroll_off",
+    "resume",
+    "persist",
+    "theme",
+];
+
+impl Config {
+    /// Read the config from `path`, or $CEPHDU_CONFIG, or from the default
+    /// location, and apply the settings of `profile` (or $CEPHDU_PROFILE) and
+    /// then the $CEPHDU_* variables on top.
+    /// A missing file at the default location is not an error.
+    pub fn load(path: Option<&Path>, profile: Option<&str>) -> Result<Config, String> {
+        let path = path
+            .map(Path::to_path_buf)
+            .or_else(|| std::env::var_os("CEPHDU_CONFIG").map(PathBuf::from));
+        let (path, explicit) = match path {
+            Some(path) => (path, true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => (PathBuf::new(), false),
+            },
+        };
+
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if !explicit && e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("Error reading config {:?}: {}", path, e)),
+        };
+
+        let parse_error = |e: toml::de::Error| format!("Error parsing config {:?}: {}", path, e);
+        let config: Config = toml::from_str(&text).map_err(parse_error)?;
+        let profile = profile
+            .map(str::to_string)
+            .or_else(|| std::env::var("CEPHDU_PROFILE").ok());
+        let env = env_settings()?;
+        if profile.is_none() && env.is_empty() {
+            return Ok(config);
+        }
+        let mut table: toml::Table = toml::from_str(&text).map_err(parse_error)?;
+        table.remove("profiles");
+        if let Some(name) = &profile {
+            let Some(settings) = config.profiles.get(name) else {
+                return Err(format!("No profile {:?} in config {:?}", name, path));
+            };
+            let mut settings = settings.clone();
+            settings.remove("profiles");
+            // Check the profile alone, to say where the error is
+            toml::Value::Table(settings.clone())
+                .try_into::<Config>()
+                .map_err(|e| format!("Error in profile {:?}: {}", name, e.message()))?;
+            merge(&mut table, settings);
+        }
+        merge(&mut table, env);
+        toml::Value::Table(table)
+            .try_into()
+            .map_err(|e| format!("Error in the $CEPHDU_* variables: {}", e))
+    }
+
+    /// How long to wait for an entry's metadata, if there's a limit
+    pub fn metadata_timeout(&self) -> Option<Duration> {
+        (self.metadata_timeout_secs > 0).then(|| Duration::from_secs(self.metadata_timeout_secs))
+    }
+
+    /// The projects from the `projects` file, or none without one
+    pub fn project_map(&self) -> Result<ProjectMap, String> {
+        match &self.projects {
+            Some(path) => ProjectMap::load(path),
+            None => Ok(ProjectMap::default()),
+        }
+    }
+
+    /// The retention policy for `dir`, which should be canonical: the one
+    /// for the closest directory above it that has one
+    pub fn retention_for(&self, dir: &Path) -> Option<&RetentionPolicy> {
+        RetentionPolicy::covering(&self.retention, dir)
+    }
+}
+
+impl RetentionPolicy {
+    /// The one of `policies` for `dir`, like `Config::retention_for`
+    pub fn covering<'a>(
+        policies: &'a [RetentionPolicy],
+        dir: &Path,
+    ) -> Option<&'a RetentionPolicy> {
+        policies
+            .iter()
+            .filter(|policy| {
+                let path = provider::get()
+                    .canonicalize(&policy.path)
+                    .unwrap_or_else(|_| policy.path.clone());
+                dir.starts_with(path)
+            })
+            .max_by_key(|policy| policy.path.components().count())
+    }
+}
+
+/// Put the settings in `over` into `base`, keeping the rest of the tables
+/// that both have, so that a profile can e.g. remap one key
+fn merge(base: &mut toml::Table, over: toml::Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(over)) => merge(base, over),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// The settings given as $CEPHDU_* variables. Values are read like in the
+/// config file, except that strings don't need quotes.
+fn env_settings() -> Result<toml::Table, String> {
+    let mut settings = toml::Table::new();
+    for &key in ENV_SETTINGS {
+        let var = format!("CEPHDU_{}", key.to_uppercase());
+        let Ok(text) = std::env::var(&var) else {
+            continue;
+        };
+        let value = toml::from_str::<toml::Table>(&format!("value = {}", text))
+            .ok()
+            .and_then(|mut table| table.remove("value"))
+            .unwrap_or(toml::Value::String(text));
+        // Check each one alone, to say which is wrong
+        let alone = toml::Table::from_iter([(key.to_string(), value.clone())]);
+        toml::Value::Table(alone)
+            .try_into::<Config>()
+            .map_err(|e| format!("Error in ${}: {}", var, e.message()))?;
+        settings.insert(key.to_string(), value);
+    }
+    Ok(settings)
+}
+
+/// $XDG_DATA_HOME/cephdu, or ~/.local/share/cephdu
+pub fn data_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", ".local/share")
+}
+
+/// $XDG_STATE_HOME/cephdu, or ~/.local/state/cephdu
+pub fn state_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_STATE_HOME", ".local/state")
+}
+
+/// $XDG_CACHE_HOME/cephdu, or ~/.cache/cephdu, for what can be made again
+pub fn cache_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CACHE_HOME", ".cache")
+}
+
+/// Replace `path`, in the state dir, with `contents` in one go, so that
+/// another session never reads half of it
+pub fn write_state_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".{}", std::process::id()));
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path)
+}
+
+/// $XDG_CONFIG_HOME/cephdu/config.toml, or ~/.config/cephdu/config.toml
+pub fn default_path() -> Option<PathBuf> {
+    Some(xdg_dir("XDG_CONFIG_HOME", ".config")?.join("config.toml"))
+}
+
+/// Our directory under the XDG base directory in `var`, or under `default`
+/// in the home directory if that's unset. Relative paths are invalid, as per the spec.
+fn xdg_dir(var: &str, default: &str) -> Option<PathBuf> {
+    let base = std::env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(default)))?;
+    Some(base.join("cephdu"))
+}
@@ -0,0 +1,147 @@
+use std::fs::Permissions;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+
+/// A command from a script, with where to send the answer
+pub type Request = (String, Sender<String>);
+
+/// A Unix socket that scripts drive a running cephdu through, one command
+/// per line, each answered with one line: "ok", a JSON document, or
+/// "error: ...". The commands are handled by the app between frames.
+pub struct ControlSocket {
+    path: PathBuf,
+    /// The `(dev, ino)` of the socket we bound, to tell it from one that
+    /// another cephdu has replaced it with since
+    id: (u64, u64),
+    rx: Receiver<Request>,
+}
+
+impl ControlSocket {
+    /// Listen on `path`, which only we can connect to
+    pub fn listen(path: &Path) -> io::Result<ControlSocket> {
+        let listener = bind_private(path)?;
+        let id = file_id(path)?;
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                // Its commands can delete files
+                if !peer_is_owner(&stream) {
+                    continue;
+                }
+                let tx = tx.clone();
+                std::thread::spawn(move || {
+                    if let Ok(reader) = stream.try_clone() {
+                        let _ = serve(BufReader::new(reader), stream, tx);
+                    }
+                });
+            }
+        });
+        Ok(ControlSocket {
+            path: path.to_path_buf(),
+            id,
+            rx,
+        })
+    }
+
+    /// The commands that have come in since the last call
+    pub fn requests(&self) -> Vec<Request> {
+        self.rx.try_iter().collect()
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        if file_id(&self.path).is_ok_and(|id| id == self.id) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+fn file_id(path: &Path) -> io::Result<(u64, u64)> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+/// Listen on a Unix socket at `path` that only this user can connect to.
+/// A socket left over from a previous run is replaced, but nothing else is.
+pub fn bind_private(path: &Path) -> io::Result<UnixListener> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and isn't a socket", path.display()),
+            ));
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Whether the peer runs as the same user as we do, e.g. in case it
+/// connected before the socket's permissions were set
+pub fn peer_is_owner(stream: &UnixStream) -> bool {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    ret == 0 && cred.uid == unsafe { libc::geteuid() }
+}
+
+/// Pass the peer's commands on to the app, and its answers back, until the
+/// peer hangs up or the app goes away
+fn serve(reader: impl BufRead, mut writer: impl Write, tx: Sender<Request>) -> io::Result<()> {
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (reply_tx, reply_rx) = mpsc::channel();
+        if tx.send((line, reply_tx)).is_err() {
+            break;
+        }
+        let Ok(reply) = reply_rx.recv() else {
+            break;
+        };
+        writeln!(writer, "{}", reply)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaves_a_socket_that_replaced_ours() {
+        let path = std::env::temp_dir().join(format!("cephdu-control-{}", std::process::id()));
+        let first = ControlSocket::listen(&path).unwrap();
+        let second = ControlSocket::listen(&path).unwrap();
+        drop(first);
+        assert!(path.exists());
+        drop(second);
+        assert!(!path.exists());
+    }
+}
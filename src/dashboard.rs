@@ -0,0 +1,235 @@
+use std::cmp::Reverse;
+use std::path::{Component, Path, PathBuf};
+
+use chrono::{Local, Utc};
+use clap::{Args, ValueEnum};
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+
+use crate::config::Config;
+use crate::exit;
+use crate::glob;
+use crate::history::{History, TREND_WEEKS, Trend};
+use crate::loader::Timings;
+use crate::provider;
+use crate::report::{ReportFormat, print_markdown_table, print_text_table};
+use crate::rstats::{self, RStats};
+use crate::schema::{SCHEMA_VERSION, ShareRecord};
+use crate::ui::{rentries_str, signed_str, size_str};
+
+/// Show the size, quota use, and recent growth of several shares side by
+/// side, e.g. every project directory, to check on them each morning
+#[derive(Args)]
+pub struct DashboardArgs {
+    /// Directories to show, where any component can be a glob, like
+    /// "/mnt/ceph/projects/*" [default: the `dashboard.roots` setting]
+    pub roots: Vec<String>,
+
+    #[arg(long, value_enum, default_value_t = DashboardSort::Size)]
+    pub sort: DashboardSort,
+
+    #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+    pub format: ReportFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DashboardSort {
+    Name,
+    Size,
+    Files,
+    /// How full the quota is
+    Quota,
+    /// How fast it has been growing, from the history database
+    Growth,
+}
+
+/// One share on the dashboard
+struct Share {
+    path: PathBuf,
+    stats: RStats,
+    trend: Option<Trend>,
+}
+
+impl Share {
+    fn quota_used(&self) -> Option<f64> {
+        let quota = self.stats.quota?;
+        Some(quota.fraction_used(self.stats.bytes?, self.stats.entries.unwrap_or(0)))
+    }
+}
+
+pub fn run(args: &DashboardArgs, config: &Config) -> Result<()> {
+    let patterns = if args.roots.is_empty() {
+        &config.dashboard.roots
+    } else {
+        &args.roots
+    };
+    if patterns.is_empty() {
+        return Err(eyre!(
+            "No directories to show: give some, or set dashboard.roots"
+        ));
+    }
+    let mut roots = Vec::new();
+    for pattern in patterns {
+        let found = expand(Path::new(pattern));
+        if found.is_empty() {
+            eprintln!("Warning: nothing matches {:?}", pattern);
+        }
+        roots.extend(found);
+    }
+    roots.sort();
+    roots.dedup();
+
+    // Growth is only known where someone has been recording it
+    let history_path = config.history.path();
+    let history = history_path
+        .exists()
+        .then(|| History::open(&history_path))
+        .transpose()?;
+    let now = Utc::now().timestamp();
+    let since = now - TREND_WEEKS as i64 * 7 * 24 * 3600;
+
+    let mut shares = Vec::new();
+    for root in roots {
+        let stat = match provider::get().stat(&root) {
+            Ok(stat) => stat,
+            Err(e) => {
+                eprintln!("Warning: skipping {:?}: {}", root, e);
+                continue;
+            }
+        };
+        let backend = rstats::for_dir(&root, stat.dev);
+        let stats = backend.load(&root, &stat, &mut Timings::default());
+        if stats.bytes.is_none() {
+            eprintln!("Warning: skipping {:?}: its size isn't known", root);
+            continue;
+        }
+        let trend = history
+            .as_ref()
+            .and_then(|history| history.samples(&root, since).ok())
+            .and_then(|samples| Trend::from_samples(&samples, now));
+        shares.push(Share {
+            path: root,
+            stats,
+            trend,
+        });
+    }
+
+    match args.sort {
+        DashboardSort::Name => {}
+        DashboardSort::Size => shares.sort_by_key(|share| Reverse(share.stats.bytes)),
+        DashboardSort::Files => shares.sort_by_key(|share| Reverse(share.stats.entries)),
+        DashboardSort::Quota => {
+            let used = |share: &Share| share.quota_used().unwrap_or(-1.0);
+            shares.sort_by(|a, b| used(b).total_cmp(&used(a)))
+        }
+        DashboardSort::Growth => {
+            shares.sort_by_key(|share| Reverse(share.trend.as_ref().map(|t| t.per_week)))
+        }
+    }
+
+    if args.format == ReportFormat::Jsonl {
+        for share in &shares {
+            let record = ShareRecord {
+                schema_version: SCHEMA_VERSION,
+                path: share.path.clone(),
+                bytes: share.stats.bytes,
+                rentries: share.stats.entries,
+                max_bytes: share.stats.quota.map(|q| q.max_bytes),
+                max_files: share.stats.quota.map(|q| q.max_files),
+                growth_per_week: share.trend.as_ref().map(|t| t.per_week),
+            };
+            println!("{}", record.to_json());
+        }
+        return Ok(());
+    }
+
+    let rows: Vec<[String; 6]> = shares
+        .iter()
+        .map(|share| {
+            let quota = match share.quota_used() {
+                Some(used) => format!("{:.0}%", used * 100.0),
+                None => String::new(),
+            };
+            let (growth, sparkline) = match &share.trend {
+                Some(trend) => (
+                    format!(
+                        "{}{}",
+                        if trend.per_week > 0 { "+" } else { "" },
+                        signed_str(trend.per_week, |s| size_str(Some(s), false))
+                    ),
+                    trend.sparkline.clone(),
+                ),
+                None => (String::new(), String::new()),
+            };
+            [
+                size_str(share.stats.bytes, false),
+                rentries_str(share.stats.entries, false),
+                quota,
+                growth,
+                sparkline,
+                share.path.display().to_string(),
+            ]
+        })
+        .collect();
+    let header = ["Size", "Files", "Quota", "Per week", "Trend", "Path"];
+    let generated = Local::now().format("%Y-%m-%d %H:%M");
+    match args.format {
+        ReportFormat::Text => {
+            println!("cephdu dashboard, {}", generated);
+            print_text_table(&header, 4, &rows);
+        }
+        ReportFormat::Jsonl => unreachable!("printed above"),
+        ReportFormat::Markdown => {
+            println!("# cephdu dashboard\n");
+            println!("Generated {}.\n", generated);
+            print_markdown_table(&header, 4, &rows);
+        }
+    }
+    Ok(())
+}
+
+/// The directories that `pattern` matches, a component at a time, in order
+fn expand(pattern: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::new()];
+    for component in pattern.components() {
+        let Component::Normal(name) = component else {
+            paths.iter_mut().for_each(|path| path.push(component));
+            continue;
+        };
+        let Some(name) = name.to_str().filter(|name| name.contains(['*', '?', '['])) else {
+            paths.iter_mut().for_each(|path| path.push(name));
+            continue;
+        };
+        let mut matched = Vec::new();
+        for dir in &paths {
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir.as_path()
+            };
+            let Ok(entries) = provider::get().read_dir(dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let Some(file_name) = entry.path.file_name() else {
+                    continue;
+                };
+                let file_name = file_name.to_string_lossy();
+                // Like the shell, only match dotfiles with a leading dot
+                if file_name.starts_with('.') && !name.starts_with('.') {
+                    continue;
+                }
+                if glob::matches(name, &file_name) && provider::get().stat(&entry.path).is_ok() {
+                    matched.push(entry.path);
+                }
+            }
+        }
+        matched.sort();
+        paths = matched;
+    }
+    paths
+        .into_iter()
+        .filter_map(|path| exit::resolve(&path).ok())
+        .filter(|path| crate::fs::is_dir(path))
+        .collect()
+}
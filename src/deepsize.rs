@@ -0,0 +1,140 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+use crate::app::EntryKind;
+use crate::glob;
+use crate::jobs::Progress;
+use crate::loader::interrupted_error;
+use crate::provider;
+
+/// The recursive size of a directory, computed by walking it
+#[derive(Debug, Clone, Copy)]
+pub struct DeepSize {
+    pub bytes: usize,
+    /// The number of files and directories inside, not counting itself
+    pub entries: usize,
+}
+
+/// Computes recursive sizes in the background, for filesystems that don't
+/// keep recursive stats like CephFS does. Results are kept for the session.
+/// Each walk is a job, by the id it was requested with.
+pub struct DeepSizer {
+    pending: Vec<(PathBuf, usize, Receiver<std::io::Result<DeepSize>>)>,
+    sizes: HashMap<PathBuf, DeepSize>,
+}
+
+impl DeepSizer {
+    pub fn new() -> Self {
+        DeepSizer {
+            pending: Vec::new(),
+            sizes: HashMap::new(),
+        }
+    }
+
+    pub fn is_pending(&self, path: &Path) -> bool {
+        self.pending.iter().any(|(p, _, _)| p == path)
+    }
+
+    /// Start walking `path` in the background as the job `job`, reporting
+    /// to `progress`, and leaving out the names that match `exclude`
+    pub fn request(
+        &mut self,
+        path: &Path,
+        exclude: &[String],
+        job: usize,
+        progress: Arc<Progress>,
+    ) {
+        let (tx, rx) = mpsc::channel();
+        let thread_path = path.to_path_buf();
+        let exclude = exclude.to_vec();
+        provider::spawn(move || {
+            let _ = tx.send(walk(&thread_path, &exclude, &progress));
+        });
+        self.pending.push((path.to_path_buf(), job, rx));
+    }
+
+    /// Collect the walks that have finished. Returns the paths and jobs of
+    /// those that did, with their sizes, or why they failed.
+    pub fn poll(&mut self) -> Vec<(PathBuf, usize, std::io::Result<DeepSize>)> {
+        let mut done = Vec::new();
+        self.pending.retain(|(path, job, rx)| match rx.try_recv() {
+            Err(TryRecvError::Empty) => true,
+            Ok(result) => {
+                if let Ok(size) = &result {
+                    self.sizes.insert(path.clone(), *size);
+                }
+                done.push((path.clone(), *job, result));
+                false
+            }
+            Err(TryRecvError::Disconnected) => false,
+        });
+        done
+    }
+
+    pub fn get(&self, path: &Path) -> Option<DeepSize> {
+        self.sizes.get(path).copied()
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Forget the computed sizes. Walks in flight will still finish.
+    pub fn clear(&mut self) {
+        self.sizes.clear();
+    }
+}
+
+/// Add up the apparent sizes of everything under `root`, like `du -s --apparent-size`.
+/// Stays on one filesystem, doesn't follow symlinks, and counts hard-linked
+/// files, and directories that are also bind-mounted under it, once.
+/// Subdirectories we can't read are skipped, and so is what matches `exclude`.
+/// Its progress is in directories, and it stops when cancelled.
+fn walk(root: &Path, exclude: &[String], progress: &Progress) -> std::io::Result<DeepSize> {
+    let fs = provider::get();
+    let root_stat = fs.lstat(root)?;
+    let dev = root_stat.dev;
+    let mut size = DeepSize {
+        bytes: 0,
+        entries: 0,
+    };
+    let mut seen = HashSet::from([(root_stat.dev, root_stat.ino)]);
+    let mut stack = vec![root.to_path_buf()];
+    progress.add_total(1);
+
+    while let Some(dir) = stack.pop() {
+        if progress.is_cancelled() {
+            return Err(interrupted_error());
+        }
+        progress.add_done(1);
+        let Ok(read_dir) = fs.read_dir(&dir) else {
+            continue;
+        };
+        for entry in read_dir.flatten() {
+            if entry
+                .path
+                .file_name()
+                .is_some_and(|name| glob::matches_any(exclude, &name.to_string_lossy()))
+            {
+                continue;
+            }
+            let Ok(stat) = fs.lstat(&entry.path) else {
+                continue;
+            };
+            let is_dir = stat.kind == EntryKind::Dir;
+            size.entries += 1;
+            progress.add_items(1);
+            if (stat.nlink > 1 || is_dir) && !seen.insert((stat.dev, stat.ino)) {
+                continue;
+            }
+            size.bytes += stat.size;
+            if is_dir && stat.dev == dev {
+                progress.add_total(1);
+                stack.push(entry.path);
+            }
+        }
+    }
+    Ok(size)
+}
@@ -0,0 +1,184 @@
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::acl::{self, AclEntry};
+use crate::provider::{self, MemoryFs};
+
+/// Where the demo tree appears
+pub const DEMO_ROOT: &str = "/cephdu-demo";
+
+const USERS: [&str; 8] = [
+    "alice", "bob", "carol", "dave", "erin", "frank", "grace", "heidi",
+];
+const PROJECTS: [&str; 5] = [
+    "climate-model",
+    "genomics",
+    "cryo-em",
+    "galaxy-sims",
+    "neuro-imaging",
+];
+const DAY: u64 = 24 * 3600;
+/// The id of the made-up cluster
+const DEMO_FSID: &str = "3f1c2a9b-6d4e-4b8a-9c71-0e5d2f8a7b64";
+
+/// A small deterministic random number generator, so every demo looks the same
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        // xorshift64*
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n.max(1)
+    }
+
+    /// A size spread over several orders of magnitude, like real files
+    fn size(&mut self, max_exponent: u32) -> u64 {
+        let exponent = self.below(max_exponent as u64 + 1) as u32;
+        10u64.pow(exponent) * (1 + self.below(9))
+    }
+}
+
+/// Make up a directory tree, in memory, and answer all filesystem calls
+/// from it instead of the real filesystem, so that the whole UI can be tried
+/// out without a Ceph mount. Returns the root of the tree.
+pub fn install() -> Option<PathBuf> {
+    provider::set(Box::new(tree())).then(|| PathBuf::from(DEMO_ROOT))
+}
+
+/// The made-up directory tree, under `DEMO_ROOT`
+pub fn tree() -> MemoryFs {
+    let root = PathBuf::from(DEMO_ROOT);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut rng = Rng(0x5eed_cafe);
+    let mut files: Vec<(PathBuf, u64)> = Vec::new();
+
+    for user in USERS {
+        let home = root.join("users").join(user);
+        for run in 0..=rng.below(12) {
+            let dir = home.join("runs").join(format!("run-{:03}", run));
+            for (i, ext) in ["log", "out", "h5"].iter().enumerate() {
+                files.push((
+                    dir.join(format!("result.{}", ext)),
+                    rng.size(4 + 3 * i as u32),
+                ));
+            }
+        }
+        for i in 0..rng.below(30) {
+            files.push((
+                home.join("notes").join(format!("note-{}.md", i)),
+                rng.size(4),
+            ));
+        }
+        files.push((home.join(".bashrc"), 3_771));
+    }
+    for project in PROJECTS {
+        let dir = root.join("projects").join(project);
+        for i in 0..rng.below(40) {
+            let name = format!("checkpoint-{:04}.bin", i);
+            files.push((dir.join("checkpoints").join(name), rng.size(10)));
+        }
+        for i in 0..rng.below(200) {
+            let name = format!("sample-{:05}.dat", i);
+            files.push((dir.join("raw").join(name), rng.size(8)));
+        }
+        files.push((dir.join("README.md"), rng.size(4)));
+    }
+    // One big flat directory, to see how many entries are handled
+    for i in 0..5_000 {
+        let name = format!("frame-{:06}.tif", i);
+        files.push((root.join("scratch").join("frames").join(name), rng.size(6)));
+    }
+
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+    let mut fs = MemoryFs::new(uid, gid);
+    for (path, size) in files {
+        let ctime = now - rng.below(365 * DAY);
+        fs.add_file(&path, size as usize, ctime as usize);
+    }
+    // Another filesystem mounted inside the tree, whose sizes aren't CephFS's
+    fs.add_mount_point(&root.join("projects/archive"), (now - 400 * DAY) as usize);
+    for i in 0..12 {
+        let name = format!("tape-{:02}.tar", i);
+        let path = root.join("projects/archive").join(name);
+        fs.add_file(&path, rng.size(9) as usize, (now - 400 * DAY) as usize);
+    }
+    // Something for the permission audit (p) to find
+    fs.add_file(
+        &root.join("users/dave/bin/backup-helper"),
+        48_213,
+        (now - 90 * DAY) as usize,
+    );
+    fs.set_mode(&root.join("users/dave/bin/backup-helper"), 0o4755);
+    // A checkpoint written with holes, whose size is mostly not on disk
+    let sparse = root.join("projects/genomics/checkpoints/model-final.ckpt");
+    fs.add_file(&sparse, 64_000_000_000, (now - 3 * DAY) as usize);
+    fs.set_allocated(&sparse, 2_300_000_000);
+    fs.set_mode(&root.join("projects/genomics/raw"), 0o777);
+    fs.set_mode(&root.join("scratch"), 0o1777);
+    // Raw data on an erasure-coded pool, for the pool breakdown (L)
+    fs.set_xattr(
+        &root.join("projects/genomics/raw"),
+        "ceph.dir.layout.pool",
+        b"cephfs.ec-8-3".to_vec(),
+    );
+    // Projects with quotas: one about to run out, the other with room to spare
+    fs.set_xattr(
+        &root.join("projects/genomics"),
+        "ceph.quota.max_bytes",
+        b"84000000000".to_vec(),
+    );
+    fs.set_xattr(
+        &root.join("projects/cryo-em"),
+        "ceph.quota.max_files",
+        b"400".to_vec(),
+    );
+    // A project shared with another user, like an ACL the kernel would store
+    let shared = [
+        AclEntry {
+            tag: 0x01,
+            perm: 7,
+            id: 0,
+        },
+        AclEntry {
+            tag: 0x02,
+            perm: 7,
+            id: uid + 1,
+        },
+        AclEntry {
+            tag: 0x04,
+            perm: 5,
+            id: 0,
+        },
+        AclEntry {
+            tag: 0x10,
+            perm: 7,
+            id: 0,
+        },
+        AclEntry {
+            tag: 0x20,
+            perm: 5,
+            id: 0,
+        },
+    ];
+    let cryo_em = root.join("projects/cryo-em");
+    fs.set_xattr(&cryo_em, "system.posix_acl_access", acl::encode(&shared));
+    fs.set_xattr(&cryo_em, "system.posix_acl_default", acl::encode(&shared));
+    fs.set_xattr(&cryo_em, "user.project", b"EM-2291".to_vec());
+    fs.set_capacity(1_200_000_000_000);
+    fs.set_mount_table(&format!(
+        "admin@{}.hpc_data=/ {} ceph rw,relatime,name=admin,acl 0 0\n\
+         fileserver:/archive {}/projects/archive nfs4 rw,relatime,vers=4.2 0 0\n",
+        DEMO_FSID, DEMO_ROOT, DEMO_ROOT
+    ));
+    fs.set_xattr(&cryo_em, "user.description", b"Cryo-electron microscopy of membrane protein complexes, from the Krios and Glacios scopes".to_vec());
+    fs
+}
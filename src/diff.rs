@@ -0,0 +1,140 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use clap::{Args, ValueEnum};
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+
+use crate::report::print_text_table;
+use crate::schema::{self, DiffRecord, DirRecord, SCHEMA_VERSION};
+use crate::ui::{rentries_str, signed_str, size_str};
+
+/// Show which directories grew or shrank between two snapshots saved from
+/// `report --format jsonl` or `scan --format jsonl`
+#[derive(Args)]
+pub struct DiffArgs {
+    /// The older snapshot
+    pub old: PathBuf,
+
+    /// The newer snapshot
+    pub new: PathBuf,
+
+    /// How many directories to list, biggest change first; jsonl lists all that changed
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+
+    #[arg(long, value_enum, default_value_t = DiffFormat::Text)]
+    pub format: DiffFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DiffFormat {
+    Text,
+    /// One JSON object per directory that changed
+    Jsonl,
+}
+
+pub fn run(args: &DiffArgs) -> Result<()> {
+    let read = |path: &Path| -> Result<BTreeMap<PathBuf, DirRecord>> {
+        let records = schema::read_records(path).map_err(|e| eyre!("Error reading {}", e))?;
+        Ok(records.into_iter().map(|r| (r.path.clone(), r)).collect())
+    };
+    let old = read(&args.old)?;
+    let new = read(&args.new)?;
+
+    let mut paths: Vec<&PathBuf> = old.keys().chain(new.keys()).collect();
+    paths.sort();
+    paths.dedup();
+    let mut diffs: Vec<DiffRecord> = paths
+        .into_iter()
+        .map(|path| diff(path, old.get(path), new.get(path)))
+        .filter(|d| (d.bytes_delta, d.rentries_delta) != (0, 0) || d.bytes_before.is_none())
+        .collect();
+    diffs.sort_by_key(|d| Reverse(d.bytes_delta.unsigned_abs()));
+
+    if args.format == DiffFormat::Jsonl {
+        for d in &diffs {
+            println!("{}", d.to_json());
+        }
+        return Ok(());
+    }
+
+    // The walked directory is at depth 0 in both
+    let root_of = |records: &BTreeMap<PathBuf, DirRecord>| {
+        records
+            .values()
+            .find(|r| r.depth == 0)
+            .map(|r| r.path.clone())
+    };
+    println!(
+        "cephdu diff of {} and {}",
+        args.old.display(),
+        args.new.display()
+    );
+    if let Some(root) = root_of(&old).filter(|root| Some(root) == root_of(&new).as_ref()) {
+        let total = diff(&root, old.get(&root), new.get(&root));
+        println!(
+            "Total: {} in {} files",
+            bytes_str(total.bytes_delta),
+            files_str(total.rentries_delta)
+        );
+    }
+    diffs.truncate(args.top);
+    let rows: Vec<[String; 4]> = diffs
+        .iter()
+        .map(|d| {
+            [
+                bytes_str(d.bytes_delta),
+                files_str(d.rentries_delta),
+                format!("{}/", d.path.display()),
+                match (d.bytes_before, d.bytes_after) {
+                    (None, _) => "new".to_string(),
+                    (_, None) => "gone".to_string(),
+                    _ => String::new(),
+                },
+            ]
+        })
+        .collect();
+    println!("\nBiggest changes:");
+    print_text_table(&["Size", "Files", "Path", ""], 2, &rows);
+    Ok(())
+}
+
+/// A change in size, e.g. "+1.2 GB"
+fn bytes_str(delta: isize) -> String {
+    let plus = if delta > 0 { "+" } else { "" };
+    format!(
+        "{}{}",
+        plus,
+        signed_str(delta, |s| size_str(Some(s), false))
+    )
+}
+
+/// A change in the number of files, e.g. "+1.2 K"
+fn files_str(delta: isize) -> String {
+    let plus = if delta > 0 { "+" } else { "" };
+    format!(
+        "{}{}",
+        plus,
+        signed_str(delta, |r| rentries_str(Some(r), false))
+    )
+}
+
+fn diff(path: &Path, old: Option<&DirRecord>, new: Option<&DirRecord>) -> DiffRecord {
+    let delta = |before: Option<usize>, after: Option<usize>| {
+        after.unwrap_or(0) as isize - before.unwrap_or(0) as isize
+    };
+    let (bytes_before, bytes_after) = (old.map(|r| r.bytes), new.map(|r| r.bytes));
+    let (rentries_before, rentries_after) = (old.map(|r| r.rentries), new.map(|r| r.rentries));
+    DiffRecord {
+        schema_version: SCHEMA_VERSION,
+        path: path.to_path_buf(),
+        bytes_before,
+        bytes_after,
+        bytes_delta: delta(bytes_before, bytes_after),
+        rentries_before,
+        rentries_after,
+        rentries_delta: delta(rentries_before, rentries_after),
+    }
+}
@@ -0,0 +1,148 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use clap::{ArgAction, Args};
+use color_eyre::Result;
+
+use crate::config::Config;
+use crate::exit;
+use crate::report::{self, Row, WalkArgs};
+
+/// Print directory sizes like du(1), from the recursive stats, so that
+/// scripts can switch over by changing the command name
+#[derive(Args)]
+pub struct DuArgs {
+    /// Directories to measure
+    #[arg(default_value = ".")]
+    pub paths: Vec<PathBuf>,
+
+    /// Only print the total for each directory, like --max-depth 0
+    #[arg(short, long, conflicts_with = "max_depth")]
+    pub summarize: bool,
+
+    /// Print sizes in 1024-byte blocks, rounded up (the default)
+    #[arg(short, group = "unit")]
+    pub k: bool,
+
+    /// Print sizes like 1.5K, 234M, and 2.0G, in powers of 1024
+    #[arg(short, long, group = "unit")]
+    pub human_readable: bool,
+
+    /// Print sizes in bytes
+    #[arg(short, long, group = "unit")]
+    pub bytes: bool,
+
+    /// Print directories at most N levels below each PATH [default: all of them]
+    #[arg(short = 'd', long, value_name = "N")]
+    pub max_depth: Option<usize>,
+
+    #[command(flatten)]
+    pub walk: WalkArgs,
+
+    /// Print help
+    #[arg(long, action = ArgAction::Help)]
+    pub help: Option<bool>,
+}
+
+pub fn run(args: &DuArgs, config: &Config) -> Result<()> {
+    let max_depth = match (args.summarize, args.max_depth) {
+        (true, _) => 0,
+        (false, Some(depth)) => depth,
+        (false, None) => usize::MAX,
+    };
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    for path in &args.paths {
+        let root = exit::resolve(path)?;
+        // The walk gives parents first, but du lists them after what's in them
+        let mut pending: Vec<Row> = Vec::new();
+        let mut result = Ok(());
+        let mut print = |row: &Row| {
+            if result.is_ok() {
+                result = writeln!(out, "{}\t{}", size(args, row.size), shown(path, &root, row));
+            }
+        };
+        report::collect(&root, max_depth, &args.walk, config, &mut |row| {
+            while pending.last().is_some_and(|last| last.depth >= row.depth) {
+                print(&pending.pop().expect("just checked"));
+            }
+            pending.push(row.clone());
+        })?;
+        while let Some(row) = pending.pop() {
+            print(&row);
+        }
+        result?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// The path of `row` as du would print it: starting with `path` as it was given
+fn shown(path: &Path, root: &Path, row: &Row) -> String {
+    match row.path.strip_prefix(root) {
+        Ok(rest) if rest.as_os_str().is_empty() => path.display().to_string(),
+        Ok(rest) => path.join(rest).display().to_string(),
+        Err(_) => row.path.display().to_string(),
+    }
+}
+
+fn size(args: &DuArgs, bytes: usize) -> String {
+    if args.bytes {
+        bytes.to_string()
+    } else if args.human_readable {
+        human_size(bytes)
+    } else {
+        bytes.div_ceil(1024).to_string()
+    }
+}
+
+/// A size like `du -h` prints it: rounded up, with one decimal below 10
+fn human_size(bytes: usize) -> String {
+    if bytes < 1024 {
+        return bytes.to_string();
+    }
+    let mut value = bytes as f64;
+    for unit in ["K", "M", "G", "T", "P", "E"] {
+        value /= 1024.0;
+        let rounded = if value < 10.0 {
+            (value * 10.0).ceil() / 10.0
+        } else {
+            value.ceil()
+        };
+        if rounded < 1024.0 || unit == "E" {
+            return if rounded < 10.0 {
+                format!("{:.1}{}", rounded, unit)
+            } else {
+                format!("{:.0}{}", rounded, unit)
+            };
+        }
+    }
+    unreachable!("the last unit always returns")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounds_sizes_up_like_du() {
+        assert_eq!(human_size(0), "0");
+        assert_eq!(human_size(1023), "1023");
+        assert_eq!(human_size(1024), "1.0K");
+        assert_eq!(human_size(1025), "1.1K");
+        assert_eq!(human_size(1536), "1.5K");
+        assert_eq!(human_size(9 * 1024 + 1), "9.1K");
+        assert_eq!(human_size(10 * 1024), "10K");
+        assert_eq!(human_size(10 * 1024 + 1), "11K");
+    }
+
+    #[test]
+    fn moves_up_a_unit_at_the_boundaries() {
+        // Just under 10K rounds up to 10, which loses the decimal
+        assert_eq!(human_size(10 * 1024 - 1), "10K");
+        // Just under 1M would round up to 1024K
+        assert_eq!(human_size((1 << 20) - 1), "1.0M");
+        assert_eq!(human_size(1 << 20), "1.0M");
+        assert_eq!(human_size((1 << 30) + 1), "1.1G");
+        assert_eq!(human_size(usize::MAX), "16E");
+    }
+}
@@ -0,0 +1,158 @@
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use color_eyre::Result;
+
+use crate::provider;
+use crate::ui::size_str;
+
+/// Exit codes for the non-interactive modes, so that scripts can tell what went wrong
+pub const EXIT_ERROR: i32 = 1;
+pub const EXIT_NOT_FOUND: i32 = 2;
+pub const EXIT_NOT_CEPH: i32 = 3;
+pub const EXIT_OVER_THRESHOLD: i32 = 4;
+
+/// A failure with its own exit code
+#[derive(Debug)]
+pub enum Failure {
+    NotFound(PathBuf),
+    NotCeph(PathBuf),
+    OverThreshold {
+        path: PathBuf,
+        size: usize,
+        limit: usize,
+    },
+}
+
+impl Failure {
+    pub fn code(&self) -> i32 {
+        match self {
+            Failure::NotFound(_) => EXIT_NOT_FOUND,
+            Failure::NotCeph(_) => EXIT_NOT_CEPH,
+            Failure::OverThreshold { .. } => EXIT_OVER_THRESHOLD,
+        }
+    }
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Failure::NotFound(path) => write!(f, "{:?} not found", path),
+            Failure::NotCeph(path) => write!(f, "{:?} is not a Ceph directory", path),
+            Failure::OverThreshold { path, size, limit } => write!(
+                f,
+                "{:?} is {}, over the limit of {}",
+                path,
+                size_str(Some(*size), false),
+                size_str(Some(*limit), false)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// The exit code for an error from one of the non-interactive modes
+pub fn code(error: &color_eyre::Report) -> i32 {
+    error
+        .downcast_ref::<Failure>()
+        .map_or(EXIT_ERROR, Failure::code)
+}
+
+/// Canonicalize a path given on the command line, failing with
+/// `EXIT_NOT_FOUND` if it doesn't exist.
+pub fn resolve(path: &Path) -> Result<PathBuf> {
+    match provider::get().canonicalize(path) {
+        Ok(path) => Ok(path),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Err(Failure::NotFound(path.to_path_buf()).into())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Options for using a non-interactive mode as a check in a script
+#[derive(Args)]
+pub struct GateArgs {
+    /// Exit with code 4 if the directory is bigger than SIZE, e.g. 500G or 2TiB
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub fail_if_over: Option<usize>,
+
+    /// Don't print anything except errors; just set the exit code
+    #[arg(short, long)]
+    pub quiet: bool,
+}
+
+impl GateArgs {
+    pub fn check(&self, path: &Path, size: usize) -> Result<()> {
+        match self.fail_if_over {
+            Some(limit) if size > limit => Err(Failure::OverThreshold {
+                path: path.to_path_buf(),
+                size,
+                limit,
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parse a size like "1500", "1.5K", "2GB" (powers of 1000) or "2GiB" (powers of 1024)
+pub fn parse_size(s: &str) -> Result<usize, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size {:?}", s))?;
+
+    let unit = unit.trim().to_ascii_uppercase();
+    let (prefix, base) = match unit.strip_suffix("IB") {
+        Some(prefix) => (prefix, 1024f64),
+        None => (unit.strip_suffix('B').unwrap_or(&unit), 1000f64),
+    };
+    let exponent = match prefix {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        "P" => 5,
+        "E" => 6,
+        _ => return Err(format!("invalid size unit in {:?}", s)),
+    };
+    let size = number * base.powi(exponent);
+    // `as` would quietly make it usize::MAX
+    if size >= usize::MAX as f64 {
+        return Err(format!("size {:?} is too big", s));
+    }
+    Ok(size as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes_with_units() {
+        assert_eq!(parse_size("1500"), Ok(1500));
+        assert_eq!(parse_size(" 1.5K "), Ok(1500));
+        assert_eq!(parse_size("2kb"), Ok(2000));
+        assert_eq!(parse_size("2GiB"), Ok(2 << 30));
+        assert_eq!(parse_size("3 TB"), Ok(3_000_000_000_000));
+        assert_eq!(parse_size("1EiB"), Ok(1 << 60));
+    }
+
+    #[test]
+    fn rejects_bad_and_overflowing_sizes() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("K").is_err());
+        assert!(parse_size("1.2.3").is_err());
+        assert!(parse_size("5X").is_err());
+        assert!(parse_size("5KiBB").is_err());
+        assert!(parse_size("16EiB").is_err());
+        assert!(parse_size("99999999999999999999").is_err());
+    }
+}
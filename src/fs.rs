@@ -1,14 +1,37 @@
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
 use std::mem::MaybeUninit;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
 use std::os::unix::ffi::OsStrExt;
-use std::path::Path;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
 use lazy_static::lazy_static;
 
+use crate::app::EntryKind;
+use crate::provider::{self, DirIter, Dirent, FsProvider, Space, Stat};
+use crate::uring;
+
 const ATTR_BUF_SIZE: usize = 64;
-const DIR_RBYTES_ATTR: &str = "ceph.dir.rbytes";
-const DIR_RCTIME_ATTR: &str = "ceph.dir.rctime";
-const DIR_RENTRIES_ATTR: &str = "ceph.dir.rentries";
+pub const DIR_RBYTES_ATTR: &str = "ceph.dir.rbytes";
+pub const DIR_RCTIME_ATTR: &str = "ceph.dir.rctime";
+pub const DIR_RENTRIES_ATTR: &str = "ceph.dir.rentries";
+/// Both of a directory's quota limits
+const QUOTA_ATTR: &CStr = c"ceph.quota";
+/// The data pools of layouts. Directories only have one if it was set on
+/// them; the others inherit their parent's, and files keep what they got.
+const DIR_POOL_ATTR: &CStr = c"ceph.dir.layout.pool";
+const FILE_POOL_ATTR: &CStr = c"ceph.file.layout.pool";
+/// The f_type statfs gives for CephFS
+const CEPH_SUPER_MAGIC: i64 = 0x00c36400;
+/// The f_types of NFS and of the SMB clients, from linux/magic.h
+const NFS_SUPER_MAGIC: i64 = 0x6969;
+const SMB_SUPER_MAGICS: [i64; 3] = [0x517b, 0xff534d42, 0xfe534d42];
+/// The f_types of Lustre and of GPFS (IBM Storage Scale)
+const LUSTRE_SUPER_MAGIC: i64 = 0x0bd00bd0;
+const GPFS_SUPER_MAGIC: i64 = 0x47504653;
 
 lazy_static! {
     static ref DIR_RBYTES_ATTR_C: CString = CString::new(DIR_RBYTES_ATTR).unwrap();
@@ -16,9 +39,16 @@ lazy_static! {
     static ref DIR_RENTRIES_ATTR_C: CString = CString::new(DIR_RENTRIES_ATTR).unwrap();
 }
 
+type NameCache = std::sync::Mutex<std::collections::HashMap<u32, (Arc<str>, Instant)>>;
+
+static NAME_CACHE_MAX_ENTRIES: AtomicUsize = AtomicUsize::new(100_000);
+static NAME_CACHE_TTL_SECS: AtomicU64 = AtomicU64::new(3600);
+
 lazy_static! {
-    static ref NAME_CACHE: std::sync::Mutex<std::collections::HashMap<u32, String>> =
-        std::sync::Mutex::new(std::collections::HashMap::new());
+    // Names are interned, so that a listing of a million files owned by one user
+    // holds one copy of the name.
+    static ref USER_CACHE: NameCache = std::sync::Mutex::new(std::collections::HashMap::new());
+    static ref GROUP_CACHE: NameCache = std::sync::Mutex::new(std::collections::HashMap::new());
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -27,67 +57,702 @@ pub struct FSType {
 }
 
 impl FSType {
+    pub const CEPH: FSType = FSType {
+        inner: CEPH_SUPER_MAGIC,
+    };
+
     pub fn is_ceph(self: FSType) -> bool {
         // TODO: what's the "official" f_type?
-        self.inner == 0x00c36400 || self.inner == 0x65735546
+        self.inner == CEPH_SUPER_MAGIC || self.inner == 0x65735546
     }
-}
 
-pub fn id_to_name(id: u32) -> Option<String> {
-    if let Some(name) = NAME_CACHE.lock().unwrap().get(&id) {
-        return Some(name.clone());
+    pub fn is_nfs(self: FSType) -> bool {
+        self.inner == NFS_SUPER_MAGIC
     }
 
-    let name = id_to_name_uncached(id)?;
+    pub fn is_smb(self: FSType) -> bool {
+        SMB_SUPER_MAGICS.contains(&self.inner)
+    }
+
+    pub fn is_lustre(self: FSType) -> bool {
+        self.inner == LUSTRE_SUPER_MAGIC
+    }
+
+    pub fn is_gpfs(self: FSType) -> bool {
+        self.inner == GPFS_SUPER_MAGIC
+    }
+}
 
-    NAME_CACHE.lock().unwrap().insert(id, name.clone());
-    Some(name)
+/// A network share of a directory, which may be CephFS on the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Share {
+    /// NFS, and whether it passes Ceph's xattrs through, as NFS-Ganesha can
+    Nfs { ceph_xattrs: bool },
+    /// SMB, which never passes them through
+    Smb,
 }
 
-fn id_to_name_uncached(id: u32) -> Option<String> {
-    let maxsize: usize = {
-        let sysconf_value = unsafe { libc::sysconf(libc::_SC_GETPW_R_SIZE_MAX) };
-        if sysconf_value <= 0 {
-            1024
+impl Share {
+    /// What kind of share `path`, on `fs`, is on, if it's on one
+    pub fn of(path: &Path, fs: FSType) -> Option<Share> {
+        if fs.is_nfs() {
+            Some(Share::Nfs {
+                ceph_xattrs: get_rbytes(path).is_some(),
+            })
+        } else if fs.is_smb() {
+            Some(Share::Smb)
         } else {
-            sysconf_value as usize
+            None
         }
-    };
+    }
+
+    /// NFS-Ganesha can export CephFS with its xattrs, and then the recursive
+    /// stats can be trusted like on CephFS itself
+    pub fn has_ceph_stats(self) -> bool {
+        self == Share::Nfs { ceph_xattrs: true }
+    }
+}
+
+/// Whether `path` has Ceph's recursive stats: it's on CephFS, or on an NFS
+/// export of it
+pub fn has_ceph_stats(path: &Path) -> bool {
+    get_fs(path)
+        .is_some_and(|fs| fs.is_ceph() || Share::of(path, fs).is_some_and(Share::has_ceph_stats))
+}
+
+/// The name of the user with this uid, or the uid itself if it has no name
+pub fn user_name(uid: u32) -> Arc<str> {
+    cached_name(&USER_CACHE, uid, uid_to_name_uncached)
+}
+
+/// The name of the group with this gid, or the gid itself if it has no name
+pub fn group_name(gid: u32) -> Arc<str> {
+    cached_name(&GROUP_CACHE, gid, gid_to_name_uncached)
+}
+
+/// Bound the user and group name caches. Names are looked up again after `ttl`.
+pub fn set_name_cache_limits(max_entries: usize, ttl: Duration) {
+    NAME_CACHE_MAX_ENTRIES.store(max_entries, Ordering::Relaxed);
+    NAME_CACHE_TTL_SECS.store(ttl.as_secs(), Ordering::Relaxed);
+}
+
+pub fn clear_name_caches() {
+    USER_CACHE.lock().unwrap().clear();
+    GROUP_CACHE.lock().unwrap().clear();
+}
+
+fn cached_name(cache: &NameCache, id: u32, lookup: fn(u32) -> Option<String>) -> Arc<str> {
+    let ttl = Duration::from_secs(NAME_CACHE_TTL_SECS.load(Ordering::Relaxed));
+    if let Some((name, fetched)) = cache.lock().unwrap().get(&id)
+        && fetched.elapsed() < ttl
+    {
+        return name.clone();
+    }
+
+    // Don't hold the lock during the lookup, which may go over the network
+    let name: Arc<str> = Arc::from(lookup(id).unwrap_or_else(|| id.to_string()));
+
+    let mut cache = cache.lock().unwrap();
+    if cache.len() >= NAME_CACHE_MAX_ENTRIES.load(Ordering::Relaxed) {
+        cache.retain(|_, (_, fetched)| fetched.elapsed() < ttl);
+        if cache.len() >= NAME_CACHE_MAX_ENTRIES.load(Ordering::Relaxed) {
+            cache.clear();
+        }
+    }
+    cache.insert(id, (name.clone(), Instant::now()));
+    name
+}
+
+/// How many distinct user and group names are interned
+pub fn interned_count() -> usize {
+    USER_CACHE.lock().unwrap().len() + GROUP_CACHE.lock().unwrap().len()
+}
+
+/// Resident set size of this process, in bytes
+pub fn rss() -> Option<usize> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: usize = statm.split_whitespace().nth(1)?.parse().ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * page_size.max(0) as usize)
+}
+
+fn uid_to_name_uncached(id: u32) -> Option<String> {
+    lookup_entry(
+        libc::_SC_GETPW_R_SIZE_MAX,
+        |pwd, buf, len, result| unsafe { libc::getpwuid_r(id, pwd, buf, len, result) },
+        |pwd: &libc::passwd| entry_name(pwd.pw_name),
+    )
+}
+
+/// The uid of the user with this name, or of a uid given as a number
+pub fn user_id(name: &str) -> Option<u32> {
+    if let Ok(uid) = name.parse() {
+        return Some(uid);
+    }
+    let name = CString::new(name).ok()?;
+    lookup_entry(
+        libc::_SC_GETPW_R_SIZE_MAX,
+        |pwd, buf, len, result| unsafe { libc::getpwnam_r(name.as_ptr(), pwd, buf, len, result) },
+        |pwd: &libc::passwd| Some(pwd.pw_uid),
+    )
+}
+
+/// The gid of the group with this name, or of a gid given as a number
+pub fn group_id(name: &str) -> Option<u32> {
+    if let Ok(gid) = name.parse() {
+        return Some(gid);
+    }
+    let name = CString::new(name).ok()?;
+    lookup_entry(
+        libc::_SC_GETGR_R_SIZE_MAX,
+        |grp, buf, len, result| unsafe { libc::getgrnam_r(name.as_ptr(), grp, buf, len, result) },
+        |grp: &libc::group| Some(grp.gr_gid),
+    )
+}
 
-    let mut pwd_struct: MaybeUninit<libc::passwd> = MaybeUninit::uninit();
-    let mut buf: Vec<libc::c_char> = Vec::with_capacity(maxsize as usize);
-    let mut result_ptr_raw: MaybeUninit<*mut libc::passwd> = MaybeUninit::uninit();
-
-    let (result, result_ptr) = unsafe {
-        let res = libc::getpwuid_r(
-            id,
-            pwd_struct.as_mut_ptr(),
-            buf.as_mut_ptr() as *mut libc::c_char,
-            maxsize as libc::size_t,
-            result_ptr_raw.as_mut_ptr(),
-        );
-        buf.set_len(maxsize as usize);
-        (res, result_ptr_raw.assume_init())
+fn gid_to_name_uncached(id: u32) -> Option<String> {
+    lookup_entry(
+        libc::_SC_GETGR_R_SIZE_MAX,
+        |grp, buf, len, result| unsafe { libc::getgrgid_r(id, grp, buf, len, result) },
+        |grp: &libc::group| entry_name(grp.gr_name),
+    )
+}
+
+/// The most that the strings of a user or group, e.g. its members, can take
+const MAX_ENTRY_BUF_SIZE: usize = 1 << 20;
+
+/// Look a user or group up with one of the get{pw,gr}*_r(3) functions,
+/// `lookup(record, buf, buflen, result)`, and `read` what's wanted from its
+/// record while the buffer that its strings are in is still there. The
+/// buffer starts at what sysconf(3) says for `size_name`, and doubles while
+/// it's too small, e.g. for a group with thousands of members, up to
+/// MAX_ENTRY_BUF_SIZE.
+fn lookup_entry<T, R>(
+    size_name: libc::c_int,
+    lookup: impl Fn(*mut T, *mut libc::c_char, libc::size_t, *mut *mut T) -> libc::c_int,
+    read: impl FnOnce(&T) -> Option<R>,
+) -> Option<R> {
+    let size_max = unsafe { libc::sysconf(size_name) };
+    let mut size = if size_max <= 0 {
+        1024
+    } else {
+        size_max as usize
     };
+    loop {
+        let mut buf: Vec<libc::c_char> = Vec::with_capacity(size);
+        let mut record = MaybeUninit::<T>::uninit();
+        let mut result: *mut T = std::ptr::null_mut();
+        match lookup(record.as_mut_ptr(), buf.as_mut_ptr(), size, &mut result) {
+            libc::ERANGE if size < MAX_ENTRY_BUF_SIZE => size *= 2,
+            libc::EINTR => {}
+            0 if !result.is_null() => return read(unsafe { record.assume_init_ref() }),
+            _ => return None,
+        }
+    }
+}
 
-    if result != 0 || result_ptr.is_null() {
+/// A user's or group's name, unless it's blank
+fn entry_name(name: *const libc::c_char) -> Option<String> {
+    let name = unsafe { CStr::from_ptr(name) }
+        .to_string_lossy()
+        .trim()
+        .to_owned();
+    (!name.is_empty()).then_some(name)
+}
+
+pub fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    let result = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if result != 0 {
         return None;
     }
+    let name = std::ffi::CStr::from_bytes_until_nul(&buf).ok()?;
+    Some(name.to_string_lossy().into_owned())
+}
 
-    let name = unsafe {
-        let pwd_struct = pwd_struct.assume_init();
-        std::ffi::CStr::from_ptr(pwd_struct.pw_name)
+/// The current directory as the shell sees it ($PWD), which may go through
+/// symlinks. Falls back to the physical directory if $PWD is unset or stale.
+pub fn logical_current_dir() -> std::io::Result<PathBuf> {
+    let physical = std::env::current_dir()?;
+    if let Some(pwd) = std::env::var_os("PWD").map(PathBuf::from)
+        && pwd.is_absolute()
+        && let (Ok(a), Ok(b)) = (pwd.metadata(), physical.metadata())
+        && (a.dev(), a.ino()) == (b.dev(), b.ino())
+    {
+        return Ok(normalize_lexically(&pwd));
     }
-    .to_string_lossy()
-    .trim()
-    .to_owned();
-    if name.is_empty() {
-        return None;
+    Ok(physical)
+}
+
+/// Remove "." and resolve ".." by dropping the previous component, without
+/// touching the filesystem (so symlinks are not followed).
+pub fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            c => out.push(c),
+        }
     }
-    Some(name)
+    out
 }
 
 pub fn get_fs(path: &Path) -> Option<FSType> {
+    provider::get().fs_type(path)
+}
+
+/// The real filesystem, through the kernel
+pub struct RealFs;
+
+impl From<std::fs::Metadata> for Stat {
+    fn from(metadata: std::fs::Metadata) -> Self {
+        Stat {
+            kind: EntryKind::from(metadata.file_type()),
+            size: metadata.len() as usize,
+            ctime: metadata.ctime() as usize,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            mode: metadata.mode() & 0o7777,
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            nlink: metadata.nlink(),
+            blocks: metadata.blocks(),
+        }
+    }
+}
+
+impl From<libc::stat> for Stat {
+    #[allow(clippy::unnecessary_cast)]
+    fn from(stat: libc::stat) -> Self {
+        let kind = match stat.st_mode & libc::S_IFMT {
+            libc::S_IFDIR => EntryKind::Dir,
+            libc::S_IFLNK => EntryKind::Symlink,
+            _ => EntryKind::File,
+        };
+        Stat {
+            kind,
+            size: stat.st_size as usize,
+            ctime: stat.st_ctime as usize,
+            uid: stat.st_uid,
+            gid: stat.st_gid,
+            mode: stat.st_mode & 0o7777,
+            dev: stat.st_dev as u64,
+            ino: stat.st_ino as u64,
+            nlink: stat.st_nlink as u64,
+            blocks: stat.st_blocks as u64,
+        }
+    }
+}
+
+impl From<libc::statx> for Stat {
+    fn from(statx: libc::statx) -> Self {
+        let kind = match u32::from(statx.stx_mode) & libc::S_IFMT {
+            libc::S_IFDIR => EntryKind::Dir,
+            libc::S_IFLNK => EntryKind::Symlink,
+            _ => EntryKind::File,
+        };
+        Stat {
+            kind,
+            size: statx.stx_size as usize,
+            ctime: statx.stx_ctime.tv_sec as usize,
+            uid: statx.stx_uid,
+            gid: statx.stx_gid,
+            mode: u32::from(statx.stx_mode) & 0o7777,
+            dev: libc::makedev(statx.stx_dev_major, statx.stx_dev_minor),
+            ino: statx.stx_ino,
+            nlink: u64::from(statx.stx_nlink),
+            blocks: statx.stx_blocks,
+        }
+    }
+}
+
+/// The directory being browsed, held open so that what's in it is looked
+/// up relative to it, with fstatat(2), rather than by its path: the
+/// listing keeps working if it or a directory above it is renamed, and a
+/// deep path isn't walked again for every entry
+struct PinnedDir {
+    path: PathBuf,
+    fd: Arc<OwnedFd>,
+}
+
+static PINNED: RwLock<Option<PinnedDir>> = RwLock::new(None);
+
+/// The pinned directory's fd and the rest of `path` after it, if `path` is
+/// in it. The fd is shared so that a call that hangs doesn't hold the lock.
+fn pinned(path: &Path) -> Option<(Arc<OwnedFd>, PathBuf)> {
+    let pinned = PINNED.read().ok()?;
+    let pinned = pinned.as_ref()?;
+    let rest = path.strip_prefix(&pinned.path).ok()?;
+    Some((pinned.fd.clone(), rest.to_path_buf()))
+}
+
+/// `rest` under the directory `fd`, as a path through /proc, for the calls
+/// that have no *at(2) variant, like lgetxattr(2)
+fn fd_path(fd: &OwnedFd, rest: &Path) -> PathBuf {
+    let rest = if rest.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        rest
+    };
+    Path::new("/proc/self/fd")
+        .join(fd.as_raw_fd().to_string())
+        .join(rest)
+}
+
+/// fstatat(2) of `rest` under the directory `fd`, or of the directory
+/// itself if `rest` is empty
+fn stat_at(fd: &OwnedFd, rest: &Path, follow: bool) -> std::io::Result<Stat> {
+    let c_rest = CString::new(rest.as_os_str().as_bytes())?;
+    let mut flags = if follow { 0 } else { libc::AT_SYMLINK_NOFOLLOW };
+    if rest.as_os_str().is_empty() {
+        flags |= libc::AT_EMPTY_PATH;
+    }
+    let mut buf = MaybeUninit::<libc::stat>::uninit();
+    if unsafe { libc::fstatat(fd.as_raw_fd(), c_rest.as_ptr(), buf.as_mut_ptr(), flags) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let stat = Stat::from(unsafe { buf.assume_init() });
+    // The fd keeps a deleted directory around, but it's gone all the same
+    if rest.as_os_str().is_empty() && stat.nlink == 0 {
+        return Err(std::io::Error::from(std::io::ErrorKind::NotFound));
+    }
+    Ok(stat)
+}
+
+/// Open the directory `name` under `dirfd`, without following a symlink
+fn open_dir_at(dirfd: RawFd, name: &CStr) -> std::io::Result<OwnedFd> {
+    let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+    let fd = unsafe { libc::openat(dirfd, name.as_ptr(), flags) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// The names in the directory `fd`, without "." and ".."
+fn dir_names(fd: &OwnedFd) -> std::io::Result<Vec<CString>> {
+    // closedir(3) closes the fd it was given, so it gets its own
+    let dup = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_DUPFD_CLOEXEC, 0) };
+    if dup < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let dir = unsafe { libc::fdopendir(dup) };
+    if dir.is_null() {
+        let e = std::io::Error::last_os_error();
+        unsafe { libc::close(dup) };
+        return Err(e);
+    }
+    let mut names = Vec::new();
+    loop {
+        let entry = unsafe { libc::readdir(dir) };
+        if entry.is_null() {
+            break;
+        }
+        let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) };
+        if name != c"." && name != c".." {
+            names.push(name.to_owned());
+        }
+    }
+    unsafe { libc::closedir(dir) };
+    Ok(names)
+}
+
+fn unlink_at(dirfd: &OwnedFd, name: &CStr, flags: libc::c_int) -> std::io::Result<()> {
+    if unsafe { libc::unlinkat(dirfd.as_raw_fd(), name.as_ptr(), flags) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Delete `name` under the directory `parent`, which is at `path`, with
+/// everything in it if it's a directory. Everything is looked up relative to
+/// the directory it's in, so that nothing is deleted outside it if a
+/// directory is swapped for a symlink, and no directory on another
+/// filesystem is gone into: mounts below it are left in place, with the
+/// directories above them, and the first of them is the error.
+fn remove_tree(parent: &OwnedFd, name: &CStr, path: &Path) -> std::io::Result<()> {
+    let c_path = |name: &CStr| PathBuf::from(std::ffi::OsStr::from_bytes(name.to_bytes()));
+    let stat = stat_at(parent, &c_path(name), false)?;
+    if stat.kind != EntryKind::Dir {
+        return unlink_at(parent, name, 0);
+    }
+
+    /// A directory being emptied, and what's left in it
+    struct Frame {
+        fd: OwnedFd,
+        name: CString,
+        path: PathBuf,
+        names: Vec<CString>,
+    }
+    let fd = open_dir_at(parent.as_raw_fd(), name)?;
+    let mut stack = vec![Frame {
+        names: dir_names(&fd)?,
+        fd,
+        name: name.to_owned(),
+        path: path.to_path_buf(),
+    }];
+    let mut error = None;
+
+    while let Some(frame) = stack.last_mut() {
+        let Some(name) = frame.names.pop() else {
+            let frame = stack.pop().expect("there's a last frame");
+            let parent = stack.last().map_or(parent, |parent| &parent.fd);
+            if let Err(e) = unlink_at(parent, &frame.name, libc::AT_REMOVEDIR) {
+                error.get_or_insert(e);
+            }
+            continue;
+        };
+        let path = frame.path.join(c_path(&name));
+        let result = match stat_at(&frame.fd, &c_path(&name), false) {
+            Ok(entry) if entry.kind == EntryKind::Dir && entry.dev != stat.dev => {
+                Err(std::io::Error::other(format!(
+                    "{} is on another filesystem, so it was left in place",
+                    path.display()
+                )))
+            }
+            Ok(entry) if entry.kind == EntryKind::Dir => open_dir_at(frame.fd.as_raw_fd(), &name)
+                .and_then(|fd| {
+                    stack.push(Frame {
+                        names: dir_names(&fd)?,
+                        fd,
+                        name,
+                        path,
+                    });
+                    Ok(())
+                }),
+            Ok(_) => unlink_at(&frame.fd, &name, 0),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            error.get_or_insert(e);
+        }
+    }
+    error.map_or(Ok(()), Err)
+}
+
+impl FsProvider for RealFs {
+    fn stat(&self, path: &Path) -> std::io::Result<Stat> {
+        match pinned(path) {
+            Some((fd, rest)) => stat_at(&fd, &rest, true),
+            None => std::fs::metadata(path).map(Stat::from),
+        }
+    }
+
+    fn lstat(&self, path: &Path) -> std::io::Result<Stat> {
+        match pinned(path) {
+            Some((fd, rest)) => stat_at(&fd, &rest, false),
+            None => std::fs::symlink_metadata(path).map(Stat::from),
+        }
+    }
+
+    fn read_dir(&self, path: &Path) -> std::io::Result<DirIter> {
+        let read_dir = match pinned(path) {
+            Some((fd, rest)) => std::fs::read_dir(fd_path(&fd, &rest))?,
+            None => std::fs::read_dir(path)?,
+        };
+        // Named by `path`, not by where they were read from
+        let path = path.to_path_buf();
+        Ok(Box::new(read_dir.map(move |entry| {
+            entry.map(|entry| Dirent {
+                path: path.join(entry.file_name()),
+                kind: entry
+                    .file_type()
+                    .map(EntryKind::from)
+                    .unwrap_or(EntryKind::File),
+            })
+        })))
+    }
+
+    fn xattr(&self, path: &Path, name: &CStr) -> Option<Vec<u8>> {
+        match pinned(path) {
+            Some((fd, rest)) => get_xattr(&fd_path(&fd, &rest), name),
+            None => get_xattr(path, name),
+        }
+    }
+
+    // Through io_uring, if it's enabled
+    fn lstat_many(&self, paths: &[PathBuf]) -> Vec<std::io::Result<Stat>> {
+        // The fds of the pinned directory are held until the calls are done
+        let pins: Vec<_> = paths.iter().map(|path| pinned(path)).collect();
+        let requests: Option<Vec<_>> = paths
+            .iter()
+            .zip(&pins)
+            .map(|(path, pin)| {
+                let (dirfd, path) = match pin {
+                    Some((fd, rest)) => (fd.as_raw_fd(), rest.as_path()),
+                    None => (libc::AT_FDCWD, path.as_path()),
+                };
+                Some((dirfd, CString::new(path.as_os_str().as_bytes()).ok()?))
+            })
+            .collect();
+        match requests.and_then(uring::statx) {
+            Some(results) => results
+                .into_iter()
+                .zip(&pins)
+                .map(|(result, pin)| {
+                    let stat = Stat::from(result?);
+                    // As in `stat_at()`, a pinned directory that's been deleted is gone
+                    match pin {
+                        Some((_, rest)) if rest.as_os_str().is_empty() && stat.nlink == 0 => {
+                            Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+                        }
+                        _ => Ok(stat),
+                    }
+                })
+                .collect(),
+            None => paths.iter().map(|path| self.lstat(path)).collect(),
+        }
+    }
+
+    // getxattr(2) follows symlinks, so only directories should be asked about
+    fn xattr_many(&self, requests: &[(&Path, &'static CStr)]) -> Vec<Option<Vec<u8>>> {
+        let paths: Vec<PathBuf> = requests
+            .iter()
+            .map(|(path, _)| match pinned(path) {
+                Some((fd, rest)) => fd_path(&fd, &rest),
+                None => path.to_path_buf(),
+            })
+            .collect();
+        let batch: Option<Vec<_>> = paths
+            .iter()
+            .zip(requests)
+            .map(|(path, (_, name))| Some((CString::new(path.as_os_str().as_bytes()).ok()?, *name)))
+            .collect();
+        let Some(results) = batch.and_then(uring::getxattr) else {
+            return requests
+                .iter()
+                .map(|(path, name)| self.xattr(path, name))
+                .collect();
+        };
+        results
+            .into_iter()
+            .zip(requests)
+            .map(|(result, (path, name))| match result {
+                Ok(value) => Some(value),
+                // Too long for the batch's buffers, or a kernel without GETXATTR
+                Err(e) if matches!(e.raw_os_error(), Some(libc::ERANGE | libc::EINVAL)) => {
+                    self.xattr(path, name)
+                }
+                Err(_) => None,
+            })
+            .collect()
+    }
+
+    fn list_xattrs(&self, path: &Path) -> std::io::Result<Vec<CString>> {
+        let pinned = pinned(path);
+        let path = match &pinned {
+            Some((fd, rest)) => &fd_path(fd, rest),
+            None => path,
+        };
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        let size = unsafe { libc::llistxattr(c_path.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut buf = vec![0u8; size as usize];
+        let size = unsafe {
+            libc::llistxattr(
+                c_path.as_ptr(),
+                buf.as_mut_ptr() as *mut libc::c_char,
+                buf.len() as libc::size_t,
+            )
+        };
+        if size < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        buf.truncate(size as usize);
+        // The names are NUL-terminated, one after the other
+        Ok(buf
+            .split_inclusive(|&b| b == 0)
+            .filter_map(|name| CStr::from_bytes_with_nul(name).ok())
+            .map(CStr::to_owned)
+            .collect())
+    }
+
+    fn fs_type(&self, path: &Path) -> Option<FSType> {
+        statfs_type(path)
+    }
+
+    // Through the pinned directory, this is where it is now, if it's been renamed
+    fn canonicalize(&self, path: &Path) -> std::io::Result<PathBuf> {
+        match pinned(path) {
+            Some((fd, rest)) => fd_path(&fd, &rest).canonicalize(),
+            None => path.canonicalize(),
+        }
+    }
+
+    fn open(&self, path: &Path) -> std::io::Result<Box<dyn std::io::Read + Send>> {
+        let file = match pinned(path) {
+            Some((fd, rest)) => std::fs::File::open(fd_path(&fd, &rest))?,
+            None => std::fs::File::open(path)?,
+        };
+        Ok(Box::new(file))
+    }
+
+    fn remove(&self, path: &Path) -> std::io::Result<()> {
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+            return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput));
+        };
+        let parent_path = match pinned(parent) {
+            Some((fd, rest)) => fd_path(&fd, &rest),
+            None => parent.to_path_buf(),
+        };
+        let parent_fd = open_dir_at(
+            libc::AT_FDCWD,
+            &CString::new(parent_path.as_os_str().as_bytes())?,
+        )?;
+        remove_tree(&parent_fd, &CString::new(name.as_bytes())?, path)
+    }
+
+    fn pin_dir(&self, path: &Path) {
+        // Opened through the old one, in case that's where it is
+        let opened = match pinned(path) {
+            Some((fd, rest)) => std::fs::File::open(fd_path(&fd, &rest)),
+            None => std::fs::File::open(path),
+        };
+        let pin = opened
+            .ok()
+            .filter(|_| Path::new("/proc/self/fd").is_dir())
+            .map(|file| PinnedDir {
+                path: path.to_path_buf(),
+                fd: Arc::new(OwnedFd::from(file)),
+            });
+        if let Ok(mut pinned) = PINNED.write() {
+            *pinned = pin;
+        }
+    }
+
+    fn mount_table(&self) -> std::io::Result<String> {
+        std::fs::read_to_string("/proc/self/mounts")
+    }
+
+    fn space(&self, path: &Path) -> std::io::Result<Space> {
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        let mut buf: libc::statvfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statvfs(c_path.as_ptr(), &mut buf) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        #[allow(clippy::unnecessary_cast)]
+        let bytes = |blocks| blocks as usize * buf.f_frsize as usize;
+        Ok(Space {
+            total: bytes(buf.f_blocks),
+            free: bytes(buf.f_bfree),
+            avail: bytes(buf.f_bavail),
+        })
+    }
+}
+
+/// Whether `path` is a directory, following symlinks
+pub fn is_dir(path: &Path) -> bool {
+    provider::get()
+        .stat(path)
+        .is_ok_and(|stat| stat.kind == EntryKind::Dir)
+}
+
+fn statfs_type(path: &Path) -> Option<FSType> {
     let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
 
     // Create and zero-initialize a statfs buffer
@@ -106,43 +771,197 @@ pub fn get_fs(path: &Path) -> Option<FSType> {
     })
 }
 
-fn get_xattr(path: &Path, attr: &CString) -> Option<String> {
+fn get_xattr(path: &Path, attr: &CStr) -> Option<Vec<u8>> {
     /* Allocate an oversized buffer so that we can halve the number of
      * getxattr(2) calls */
     let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
 
-    let mut buf = [0u8; ATTR_BUF_SIZE];
-    let bytes_read = unsafe {
-        libc::lgetxattr(
-            c_path.as_ptr(),
-            attr.as_ptr(),
-            buf.as_mut_ptr() as *mut libc::c_void,
-            ATTR_BUF_SIZE as libc::size_t,
-        )
-    };
-    if bytes_read < 0 {
-        return None;
+    let mut buf = vec![0u8; ATTR_BUF_SIZE];
+    loop {
+        let bytes_read = unsafe {
+            libc::lgetxattr(
+                c_path.as_ptr(),
+                attr.as_ptr(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len() as libc::size_t,
+            )
+        };
+        if bytes_read >= 0 {
+            buf.truncate(bytes_read as usize);
+            return Some(buf);
+        }
+        // Values that don't fit, like big ACLs, need another call for the size
+        if std::io::Error::last_os_error().raw_os_error() != Some(libc::ERANGE) {
+            return None;
+        }
+        let size =
+            unsafe { libc::lgetxattr(c_path.as_ptr(), attr.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            return None;
+        }
+        buf = vec![0u8; size as usize];
     }
-    Some(String::from_utf8_lossy(&buf[..(bytes_read as usize)]).to_string())
+}
+
+/// CephFS's virtual xattrs, which aren't listed by listxattr(2) but can be read
+const CEPH_DIR_VXATTRS: &[&CStr] = &[
+    c"ceph.dir.entries",
+    c"ceph.dir.files",
+    c"ceph.dir.subdirs",
+    c"ceph.dir.rentries",
+    c"ceph.dir.rfiles",
+    c"ceph.dir.rsubdirs",
+    c"ceph.dir.rbytes",
+    c"ceph.dir.rctime",
+    c"ceph.dir.layout",
+    c"ceph.dir.pin",
+    c"ceph.quota.max_bytes",
+    c"ceph.quota.max_files",
+];
+const CEPH_FILE_VXATTRS: &[&CStr] = &[c"ceph.file.layout"];
+
+/// All the extended attributes of `path` and their values: the listed ones,
+/// and CephFS's virtual ones that are set
+pub fn all_xattrs(path: &Path, kind: EntryKind) -> Vec<(String, Vec<u8>)> {
+    let provider = provider::get();
+    let vxattrs = match kind {
+        EntryKind::Dir => CEPH_DIR_VXATTRS,
+        EntryKind::File => CEPH_FILE_VXATTRS,
+        EntryKind::Symlink => &[],
+    };
+    let listed = provider.list_xattrs(path).unwrap_or_default();
+    vxattrs
+        .iter()
+        .map(|&name| name.to_owned())
+        .chain(listed)
+        .filter_map(|name| {
+            let value = provider.xattr(path, &name)?;
+            Some((name.to_string_lossy().into_owned(), value))
+        })
+        .collect()
+}
+
+/// An xattr that's a number, like the recursive stats, as text
+fn get_xattr_str(path: &Path, attr: &CStr) -> Option<String> {
+    let value = provider::get().xattr(path, attr)?;
+    Some(String::from_utf8_lossy(&value).trim().to_string())
 }
 
 pub fn get_rentries(path: &Path) -> Option<usize> {
-    let rentries = get_xattr(path, &DIR_RENTRIES_ATTR_C)?;
+    let rentries = get_xattr_str(path, &DIR_RENTRIES_ATTR_C)?;
     // convert rentries xattr from string to unsigned
-    let rentries = rentries.trim().parse::<usize>().ok()?;
+    let rentries = rentries.parse::<usize>().ok()?;
     Some(rentries)
 }
 
 pub fn get_rbytes(path: &Path) -> Option<usize> {
-    let rbytes = get_xattr(path, &DIR_RBYTES_ATTR_C)?;
+    let rbytes = get_xattr_str(path, &DIR_RBYTES_ATTR_C)?;
     // convert rbytes xattr from string to unsigned
-    let rbytes = rbytes.trim().parse::<usize>().ok()?;
+    let rbytes = rbytes.parse::<usize>().ok()?;
     Some(rbytes)
 }
 
+/// The xattrs that `get_rentries()`, `get_rbytes()`, `get_rctime()`, and
+/// `get_quota()` read, in that order, for reading them in a batch
+pub const CEPH_RSTATS_ATTRS: [&CStr; 4] = [
+    c"ceph.dir.rentries",
+    c"ceph.dir.rbytes",
+    c"ceph.dir.rctime",
+    QUOTA_ATTR,
+];
+
+/// A directory's CephFS quota. Zero means no limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub max_bytes: usize,
+    pub max_files: usize,
+}
+
+impl Quota {
+    /// Parse the value of ceph.quota, e.g. "max_bytes=100000000 max_files=0"
+    pub fn parse(text: &str) -> Option<Quota> {
+        let mut quota = Quota {
+            max_bytes: 0,
+            max_files: 0,
+        };
+        for field in text.split_whitespace() {
+            let (name, value) = field.split_once('=')?;
+            let value = value.parse().ok()?;
+            match name {
+                "max_bytes" => quota.max_bytes = value,
+                "max_files" => quota.max_files = value,
+                _ => (),
+            }
+        }
+        Some(quota)
+    }
+
+    /// How much of the quota a directory with `bytes` and `files` uses: of
+    /// whichever limit it's closer to
+    pub fn fraction_used(&self, bytes: usize, files: usize) -> f64 {
+        let fraction = |used: usize, max: usize| {
+            if max == 0 {
+                0.0
+            } else {
+                used as f64 / max as f64
+            }
+        };
+        fraction(bytes, self.max_bytes).max(fraction(files, self.max_files))
+    }
+}
+
+/// The quota set on the directory `path`, if it has its own
+pub fn get_quota(path: &Path) -> Option<Quota> {
+    let quota = Quota::parse(&get_xattr_str(path, QUOTA_ATTR)?)?;
+    (quota.max_bytes > 0 || quota.max_files > 0).then_some(quota)
+}
+
+/// The data pool of the layout set on `path` itself, or of the file at `path`
+pub fn get_pool(path: &Path, is_dir: bool) -> Option<String> {
+    get_xattr_str(
+        path,
+        if is_dir {
+            DIR_POOL_ATTR
+        } else {
+            FILE_POOL_ATTR
+        },
+    )
+}
+
+/// The data pool that new files in the directory `path` go to: of the
+/// closest layout set at or above it. None on the filesystem's default pool.
+pub fn get_effective_pool(path: &Path) -> Option<String> {
+    path.ancestors().find_map(|dir| get_pool(dir, true))
+}
+
 pub fn get_rctime(path: &Path) -> Option<usize> {
-    let rctime = get_xattr(path, &DIR_RCTIME_ATTR_C)?;
+    let rctime = get_xattr_str(path, &DIR_RCTIME_ATTR_C)?;
     // convert rctime xattr from string ("seconds.nanos") to unsigned
-    let rctime = rctime.trim().split(".").next()?.parse::<usize>().ok()?;
+    let rctime = rctime.split(".").next()?.parse::<usize>().ok()?;
     Some(rctime)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalized(path: &str) -> PathBuf {
+        normalize_lexically(Path::new(path))
+    }
+
+    #[test]
+    fn normalizes_dots_without_the_filesystem() {
+        assert_eq!(normalized("/data/./big/"), Path::new("/data/big"));
+        assert_eq!(normalized("/data/big/../small"), Path::new("/data/small"));
+        assert_eq!(normalized("/data/a/b/../../c"), Path::new("/data/c"));
+        // Not resolved, even though no such directory exists
+        assert_eq!(normalized("/nowhere/../data"), Path::new("/data"));
+    }
+
+    #[test]
+    fn stops_at_the_root() {
+        assert_eq!(normalized("/.."), Path::new("/"));
+        assert_eq!(normalized("/../../data"), Path::new("/data"));
+        assert_eq!(normalized("/data/../../.."), Path::new("/"));
+    }
+}
@@ -0,0 +1,102 @@
+/// Whether `name` matches the shell-style glob `pattern`: `*` matches any
+/// run of characters, `?` any one character, and `[abc]`, `[a-z]`, or
+/// `[!abc]` one character from (or not from) a set.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches_at(&pattern, &name)
+}
+
+/// Whether `name` matches any of `patterns`
+pub fn matches_any(patterns: &[String], name: &str) -> bool {
+    patterns.iter().any(|pattern| matches(pattern, name))
+}
+
+fn matches_at(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => {
+            // Consecutive stars are the same as one, and save a lot of backtracking
+            let rest = &rest[rest.iter().take_while(|&&c| c == '*').count()..];
+            (0..=name.len()).any(|i| matches_at(rest, &name[i..]))
+        }
+        Some(('?', rest)) => !name.is_empty() && matches_at(rest, &name[1..]),
+        Some(('[', rest)) if let Some((set, after)) = split_class(rest) => {
+            name.first().is_some_and(|&c| class_contains(set, c)) && matches_at(after, &name[1..])
+        }
+        Some((&c, rest)) => name.first() == Some(&c) && matches_at(rest, &name[1..]),
+    }
+}
+
+/// Split the part of a pattern after a `[` into the set and what follows the
+/// closing `]`, or None if it isn't closed (and the `[` is literal). A `]`
+/// first in the set is part of it.
+fn split_class(pattern: &[char]) -> Option<(&[char], &[char])> {
+    let start = usize::from(pattern.first() == Some(&'!'));
+    let end = start + 1 + pattern.get(start + 1..)?.iter().position(|&c| c == ']')?;
+    Some((&pattern[..end], &pattern[end + 1..]))
+}
+
+fn class_contains(set: &[char], c: char) -> bool {
+    let (negated, set) = match set.split_first() {
+        Some(('!', rest)) => (true, rest),
+        _ => (false, set),
+    };
+    let mut found = false;
+    let mut i = 0;
+    while i < set.len() {
+        if set.get(i + 1) == Some(&'-') && i + 2 < set.len() {
+            found |= (set[i]..=set[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            found |= set[i] == c;
+            i += 1;
+        }
+    }
+    found != negated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_stars_and_question_marks() {
+        assert!(matches("*", ""));
+        assert!(matches("*.log", "run.log"));
+        assert!(!matches("*.log", "run.log.gz"));
+        // The first ".tar" isn't the one the star stops at
+        assert!(matches("*.tar.gz", "a.tar.tar.gz"));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(!matches("a*b*c", "aXbYbZ"));
+        assert!(matches("**x", "yyx"));
+        assert!(matches("?.txt", "a.txt"));
+        assert!(!matches("?.txt", ".txt"));
+        assert!(matches("é?", "éé"));
+    }
+
+    #[test]
+    fn matches_classes() {
+        assert!(matches("[abc].dat", "b.dat"));
+        assert!(!matches("[abc].dat", "d.dat"));
+        assert!(matches("file[0-9]", "file7"));
+        assert!(!matches("file[0-9]", "filex"));
+        assert!(matches("[!.]*", "visible"));
+        assert!(!matches("[!.]*", ".hidden"));
+        // A `]` first in the set is part of it, and a `-` at the end is literal
+        assert!(matches("[]x]", "]"));
+        assert!(matches("[a-]", "-"));
+        // An unclosed `[` is literal
+        assert!(matches("[abc", "[abc"));
+        assert!(!matches("[abc", "a"));
+    }
+
+    #[test]
+    fn matches_any_pattern() {
+        let patterns = [".git".to_string(), "*.tmp".to_string()];
+        assert!(matches_any(&patterns, ".git"));
+        assert!(matches_any(&patterns, "x.tmp"));
+        assert!(!matches_any(&patterns, "src"));
+        assert!(!matches_any(&[], "src"));
+    }
+}
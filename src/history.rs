@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, Local, Utc};
+use clap::{Args, ValueEnum};
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use rusqlite::{Connection, OptionalExtension, params};
+
+use crate::config::Config;
+use crate::exit::{self, GateArgs};
+use crate::report::{self, WalkArgs};
+use crate::ui::{rentries_str, size_str};
+
+/// Don't record a directory again if it was recorded this recently (seconds)
+const MIN_SAMPLE_INTERVAL: i64 = 3600;
+const WEEK: i64 = 7 * 24 * 3600;
+/// How long to wait for another process that's writing to the history
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+/// How many weeks the trend sparklines cover
+pub const TREND_WEEKS: usize = 8;
+const SPARK_CHARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS samples (
+    path TEXT NOT NULL,
+    time INTEGER NOT NULL,
+    bytes INTEGER NOT NULL,
+    rentries INTEGER NOT NULL,
+    PRIMARY KEY (path, time)
+);
+CREATE TABLE IF NOT EXISTS visits (
+    path TEXT PRIMARY KEY,
+    time INTEGER NOT NULL
+);
+";
+
+/// The size of a directory at some point in time
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    /// Unix time, in seconds
+    pub time: i64,
+    pub bytes: usize,
+    pub rentries: usize,
+}
+
+/// How a directory's size has changed recently
+#[derive(Debug, Clone)]
+pub struct Trend {
+    /// One character per week, oldest first; blank for weeks without samples
+    pub sparkline: String,
+    /// Average growth per week, in bytes
+    pub per_week: isize,
+}
+
+impl Trend {
+    /// Summarize `samples` (oldest first) over the `TREND_WEEKS` before `now`.
+    /// Needs at least two samples a day or more apart.
+    pub fn from_samples(samples: &[Sample], now: i64) -> Option<Trend> {
+        let start = now - TREND_WEEKS as i64 * WEEK;
+        let samples: Vec<&Sample> = samples.iter().filter(|s| s.time >= start).collect();
+        let (first, last) = (samples.first()?, samples.last()?);
+        let span = last.time - first.time;
+        if span < 24 * 3600 {
+            return None;
+        }
+        let per_week =
+            ((last.bytes as f64 - first.bytes as f64) * WEEK as f64 / span as f64) as isize;
+
+        // The latest sample in each week
+        let mut weeks: [Option<usize>; TREND_WEEKS] = [None; TREND_WEEKS];
+        for sample in &samples {
+            let week = (((sample.time - start) / WEEK) as usize).min(TREND_WEEKS - 1);
+            weeks[week] = Some(sample.bytes);
+        }
+        let min = weeks.iter().flatten().min().copied().unwrap_or(0);
+        let max = weeks.iter().flatten().max().copied().unwrap_or(0);
+        let sparkline = weeks
+            .iter()
+            .map(|week| match week {
+                None => ' ',
+                Some(_) if max == min => SPARK_CHARS[0],
+                Some(bytes) => {
+                    let level = (bytes - min) * (SPARK_CHARS.len() - 1) / (max - min);
+                    SPARK_CHARS[level]
+                }
+            })
+            .collect();
+        Some(Trend {
+            sparkline,
+            per_week,
+        })
+    }
+}
+
+/// A database of directory sizes over time, recorded as directories are
+/// visited or scanned, so that we can tell what's growing.
+pub struct History {
+    conn: Connection,
+}
+
+impl History {
+    pub fn open(path: &Path) -> rusqlite::Result<History> {
+        if let Some(parent) = path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        let conn = Connection::open(path)?;
+        // A `cephdu scan` from cron may be writing at the same time. The
+        // journal isn't WAL, which needs memory shared between processes,
+        // since home directories are often on network filesystems
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(History { conn })
+    }
+
+    /// Record the sizes of some directories, as of `time`. Directories that
+    /// were recorded less than an hour ago are skipped.
+    pub fn record<'a>(
+        &mut self,
+        time: i64,
+        dirs: impl IntoIterator<Item = (&'a Path, usize, usize)>,
+    ) -> rusqlite::Result<usize> {
+        let tx = self.conn.transaction()?;
+        let mut recorded = 0;
+        {
+            let mut insert = tx.prepare_cached(
+                "INSERT OR IGNORE INTO samples (path, time, bytes, rentries)
+                 SELECT ?1, ?2, ?3, ?4
+                 WHERE NOT EXISTS (SELECT 1 FROM samples WHERE path = ?1 AND time > ?5)",
+            )?;
+            for (path, bytes, rentries) in dirs {
+                recorded += insert.execute(params![
+                    path.to_string_lossy(),
+                    time,
+                    bytes as i64,
+                    rentries as i64,
+                    time - MIN_SAMPLE_INTERVAL,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(recorded)
+    }
+
+    /// The recorded sizes of the immediate subdirectories of `dir` since
+    /// `since`, by name, oldest first
+    pub fn child_samples(
+        &self,
+        dir: &Path,
+        since: i64,
+    ) -> rusqlite::Result<HashMap<String, Vec<Sample>>> {
+        // Paths under dir sort between "dir/" and "dir0", since '0' follows '/'
+        let dir = dir.to_string_lossy();
+        let dir = dir.trim_end_matches('/');
+        let mut query = self.conn.prepare_cached(
+            "SELECT path, time, bytes, rentries FROM samples
+             WHERE path > ?1 AND path < ?2 AND time >= ?3 ORDER BY path, time",
+        )?;
+        let rows = query.query_map(
+            params![format!("{}/", dir), format!("{}0", dir), since],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    Sample {
+                        time: row.get(1)?,
+                        bytes: row.get::<_, i64>(2)? as usize,
+                        rentries: row.get::<_, i64>(3)? as usize,
+                    },
+                ))
+            },
+        )?;
+
+        let mut children: HashMap<String, Vec<Sample>> = HashMap::new();
+        for row in rows {
+            let (path, sample) = row?;
+            let name = &path[dir.len() + 1..];
+            if !name.contains('/') {
+                children.entry(name.to_string()).or_default().push(sample);
+            }
+        }
+        Ok(children)
+    }
+
+    /// The latest recorded size of each immediate subdirectory of `dir` as
+    /// of `time`, by name
+    pub fn child_samples_at(
+        &self,
+        dir: &Path,
+        time: i64,
+    ) -> rusqlite::Result<HashMap<String, Sample>> {
+        let dir = dir.to_string_lossy();
+        let dir = dir.trim_end_matches('/');
+        let mut query = self.conn.prepare_cached(
+            "SELECT path, MAX(time), bytes, rentries FROM samples
+             WHERE path > ?1 AND path < ?2 AND time <= ?3 GROUP BY path",
+        )?;
+        let rows = query.query_map(
+            params![format!("{}/", dir), format!("{}0", dir), time],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    Sample {
+                        time: row.get(1)?,
+                        bytes: row.get::<_, i64>(2)? as usize,
+                        rentries: row.get::<_, i64>(3)? as usize,
+                    },
+                ))
+            },
+        )?;
+
+        let mut children = HashMap::new();
+        for row in rows {
+            let (path, sample) = row?;
+            let name = &path[dir.len() + 1..];
+            if !name.contains('/') {
+                children.insert(name.to_string(), sample);
+            }
+        }
+        Ok(children)
+    }
+
+    /// Record that `dir` was looked at, at `time`, returning when it was
+    /// looked at before, if ever
+    pub fn visit(&mut self, dir: &Path, time: i64) -> rusqlite::Result<Option<i64>> {
+        let path = dir.to_string_lossy();
+        let tx = self.conn.transaction()?;
+        let previous = tx
+            .query_row(
+                "SELECT time FROM visits WHERE path = ?1",
+                params![path],
+                |row| row.get(0),
+            )
+            .optional()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO visits (path, time) VALUES (?1, ?2)",
+            params![path, time],
+        )?;
+        tx.commit()?;
+        Ok(previous)
+    }
+
+    /// The recorded sizes of `path` since `since`, oldest first
+    pub fn samples(&self, path: &Path, since: i64) -> rusqlite::Result<Vec<Sample>> {
+        let mut query = self.conn.prepare_cached(
+            "SELECT time, bytes, rentries FROM samples
+             WHERE path = ?1 AND time >= ?2 ORDER BY time",
+        )?;
+        query
+            .query_map(params![path.to_string_lossy(), since], |row| {
+                Ok(Sample {
+                    time: row.get(0)?,
+                    bytes: row.get::<_, i64>(1)? as usize,
+                    rentries: row.get::<_, i64>(2)? as usize,
+                })
+            })?
+            .collect()
+    }
+}
+
+/// Record the sizes of a directory and its subdirectories in the history database
+#[derive(Args)]
+pub struct ScanArgs {
+    /// Directory to scan
+    pub path: PathBuf,
+
+    /// How many levels of subdirectories to record
+    #[arg(long, visible_alias = "max-depth", default_value_t = 2)]
+    pub depth: usize,
+
+    /// Also print the directories: "jsonl" prints one JSON object per
+    /// directory as the walk progresses
+    #[arg(long, value_enum, default_value_t = ScanFormat::Quiet)]
+    pub format: ScanFormat,
+
+    #[command(flatten)]
+    pub walk: WalkArgs,
+
+    #[command(flatten)]
+    pub gate: GateArgs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ScanFormat {
+    Quiet,
+    Jsonl,
+}
+
+/// Show how the size of a directory has changed, from the history database
+#[derive(Args)]
+pub struct ShowHistoryArgs {
+    /// Directory to show
+    pub path: PathBuf,
+
+    /// How many days back to go
+    #[arg(long, default_value_t = 90)]
+    pub days: i64,
+}
+
+pub fn run_scan(args: &ScanArgs, config: &Config) -> Result<()> {
+    let root = exit::resolve(&args.path)?;
+    // Open the database first, so that we don't walk for nothing
+    let mut history = History::open(&config.history.path())?;
+    let mut rows = Vec::new();
+    report::collect(&root, args.depth, &args.walk, config, &mut |row| {
+        if args.format == ScanFormat::Jsonl && !args.gate.quiet {
+            println!("{}", row.to_json());
+        }
+        rows.push(row.clone());
+    })?;
+
+    let dirs = rows
+        .iter()
+        .map(|row| (row.path.as_path(), row.size, row.rentries));
+    let recorded = history.record(Utc::now().timestamp(), dirs)?;
+    if !args.gate.quiet {
+        eprintln!(
+            "Recorded {} of {} directories in {:?}",
+            recorded,
+            rows.len(),
+            config.history.path()
+        );
+    }
+    // The root row comes first
+    let size = rows.first().map_or(0, |row| row.size);
+    args.gate.check(&root, size)
+}
+
+pub fn run_show(args: &ShowHistoryArgs, config: &Config) -> Result<()> {
+    let path = exit::resolve(&args.path)?;
+    let history = History::open(&config.history.path())?;
+    let since = Utc::now().timestamp() - args.days * 24 * 3600;
+    let samples = history.samples(&path, since)?;
+    if samples.is_empty() {
+        return Err(eyre!(
+            "No history for {:?} in the last {} days",
+            path,
+            args.days
+        ));
+    }
+
+    println!(
+        "{:16}  {:>9}  {:>9}  {:>10}",
+        "Time", "Size", "Files", "Change"
+    );
+    let mut previous: Option<Sample> = None;
+    for sample in samples {
+        let time = DateTime::from_timestamp(sample.time, 0)
+            .map(|t| t.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default();
+        let change = previous
+            .map(|p| {
+                let delta = sample.bytes as isize - p.bytes as isize;
+                let sign = if delta < 0 { "-" } else { "+" };
+                format!("{}{}", sign, size_str(Some(delta.unsigned_abs()), false))
+            })
+            .unwrap_or_default();
+        println!(
+            "{:16}  {:>9}  {:>9}  {:>10}",
+            time,
+            size_str(Some(sample.bytes), false),
+            rentries_str(Some(sample.rentries), false),
+            change
+        );
+        previous = Some(sample);
+    }
+    Ok(())
+}
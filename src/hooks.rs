@@ -0,0 +1,90 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::Duration;
+
+use crate::command;
+use crate::config::Hook;
+use crate::glob;
+
+/// How long a hook can take before it's given up on
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Runs the commands of the hooks that match the directories we enter, in
+/// the background, so that navigating doesn't wait for them
+pub struct HookRunner {
+    pending: Vec<(PathBuf, Receiver<Result<String, String>>)>,
+}
+
+impl HookRunner {
+    pub fn new() -> Self {
+        HookRunner {
+            pending: Vec::new(),
+        }
+    }
+
+    /// Start the commands of the `hooks` whose pattern matches `dir`
+    pub fn entered(&mut self, hooks: &[Hook], dir: &Path) {
+        let path = dir.to_string_lossy();
+        for hook in hooks.iter().filter(|hook| glob::matches(&hook.path, &path)) {
+            let (tx, rx) = mpsc::channel();
+            let command = hook.command.clone();
+            let thread_dir = dir.to_path_buf();
+            std::thread::spawn(move || {
+                let _ = tx.send(run(&command, &thread_dir));
+            });
+            self.pending.push((dir.to_path_buf(), rx));
+        }
+    }
+
+    /// Collect the hooks that have finished, with the directory they ran
+    /// for, and the first line they printed, or why they failed
+    pub fn poll(&mut self) -> Vec<(PathBuf, Result<String, String>)> {
+        let mut done = Vec::new();
+        self.pending.retain(|(dir, rx)| match rx.try_recv() {
+            Err(TryRecvError::Empty) => true,
+            Ok(result) => {
+                done.push((dir.clone(), result));
+                false
+            }
+            Err(TryRecvError::Disconnected) => false,
+        });
+        done
+    }
+}
+
+/// Run `command` for `dir`, with "{path}" in its arguments replaced by the
+/// directory, which is also in $CEPHDU_PATH
+fn run(command: &[String], dir: &Path) -> Result<String, String> {
+    let (program, args) = command.split_first().ok_or("no command")?;
+    let path = dir.to_string_lossy();
+    let mut cmd = Command::new(program);
+    cmd.args(
+        args.iter()
+            .map(|arg| command::fill(arg, &[("path", &path)])),
+    )
+    .env("CEPHDU_PATH", dir);
+    let output = command::output(cmd, TIMEOUT)?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(stdout
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or("")
+        .trim()
+        .to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_a_path_with_braces_as_it_is() {
+        let command = ["echo", "{path}:{path}", "{name}"].map(String::from);
+        let dir = Path::new("/data/{path}");
+        assert_eq!(
+            run(&command, dir).unwrap(),
+            "/data/{path}:/data/{path} {name}"
+        );
+    }
+}
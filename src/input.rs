@@ -0,0 +1,147 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+
+/// Set by the input thread when the user presses Ctrl-C, so that long-running
+/// work on other threads can check for cancellation without touching the terminal.
+pub static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// A step of a key script given with `--keys`
+#[derive(Debug, Clone, Copy)]
+pub enum Scripted {
+    Key(KeyEvent),
+    /// Give background work (e.g. prefetching) time to finish
+    Wait(Duration),
+}
+
+/// Terminal events, read on a dedicated thread. Events that arrive while the
+/// main thread is busy are queued rather than dropped.
+pub struct Input {
+    rx: Receiver<Event>,
+    terminal: bool,
+}
+
+impl Input {
+    /// Feed the `script` first, as if it had been typed, and then the
+    /// terminal's events. Without `terminal`, the input ends with the script.
+    pub fn spawn(script: Vec<Scripted>, terminal: bool) -> Input {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for step in script {
+                match step {
+                    Scripted::Key(key) => {
+                        if tx.send(Event::Key(key)).is_err() {
+                            return;
+                        }
+                    }
+                    Scripted::Wait(duration) => thread::sleep(duration),
+                }
+            }
+            if !terminal {
+                return;
+            }
+            while let Ok(event) = event::read() {
+                if let Event::Key(key) = event
+                    && key.kind != KeyEventKind::Release
+                    && key.code == KeyCode::Char('c')
+                    && key.modifiers.contains(KeyModifiers::CONTROL)
+                {
+                    // It's for interrupting, so it isn't a key for the app,
+                    // which doesn't tell Ctrl-C from c
+                    INTERRUPTED.store(true, Ordering::Relaxed);
+                    continue;
+                }
+                if tx.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+        Input { rx, terminal }
+    }
+
+    /// Whether events come from the terminal, or only from a key script
+    pub fn is_terminal(&self) -> bool {
+        self.terminal
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Event, RecvTimeoutError> {
+        self.rx.recv_timeout(timeout)
+    }
+}
+
+pub fn clear_interrupt() {
+    INTERRUPTED.store(false, Ordering::Relaxed);
+}
+
+/// Parse a key script like "jjj<enter>s". Characters stand for themselves,
+/// and special keys are written in angle brackets: <enter>, <esc>, <tab>,
+/// <bs>, <space>, <up>, <down>, <left>, <right>, <pgup>, <pgdn>, <home>,
+/// <end>, <f1>..<f12>, <c-x> for Ctrl-X, <lt> for "<", and <wait:MS> to
+/// pause. Newlines are ignored, so that scripts can be split over lines.
+pub fn parse_keys(script: &str) -> Result<Vec<Scripted>, String> {
+    let mut steps = Vec::new();
+    let mut chars = script.chars();
+    while let Some(c) = chars.next() {
+        let key = match c {
+            '\n' | '\r' => continue,
+            '<' => {
+                let name: String = chars.by_ref().take_while(|&c| c != '>').collect();
+                match parse_special(&name.to_ascii_lowercase()) {
+                    Some(step) => {
+                        steps.push(step);
+                        continue;
+                    }
+                    None => return Err(format!("unknown key <{}>", name)),
+                }
+            }
+            c => KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE),
+        };
+        steps.push(Scripted::Key(key));
+    }
+    Ok(steps)
+}
+
+fn parse_special(name: &str) -> Option<Scripted> {
+    if let Some(ms) = name.strip_prefix("wait:") {
+        return ms
+            .parse()
+            .ok()
+            .map(|ms| Scripted::Wait(Duration::from_millis(ms)));
+    }
+    let mut modifiers = KeyModifiers::NONE;
+    let code = if let Some(c) = name.strip_prefix("c-") {
+        let mut chars = c.chars();
+        let (Some(c), None) = (chars.next(), chars.next()) else {
+            return None;
+        };
+        modifiers = KeyModifiers::CONTROL;
+        KeyCode::Char(c)
+    } else if let Some(n) = name.strip_prefix('f')
+        && let Ok(n) = n.parse::<u8>()
+        && (1..=12).contains(&n)
+    {
+        KeyCode::F(n)
+    } else {
+        match name {
+            "enter" | "cr" => KeyCode::Enter,
+            "esc" => KeyCode::Esc,
+            "tab" => KeyCode::Tab,
+            "bs" | "backspace" => KeyCode::Backspace,
+            "space" => KeyCode::Char(' '),
+            "lt" => KeyCode::Char('<'),
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "pgup" | "pageup" => KeyCode::PageUp,
+            "pgdn" | "pagedown" => KeyCode::PageDown,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            _ => return None,
+        }
+    };
+    Some(Scripted::Key(KeyEvent::new(code, modifiers)))
+}
@@ -0,0 +1,188 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// How many finished jobs are kept for the jobs panel
+const MAX_FINISHED: usize = 50;
+/// The width of the progress bars in the jobs panel
+pub const BAR_WIDTH: usize = 20;
+
+/// How far along a job is, updated by the thread doing it. Its total is
+/// what it knows it has to do so far, which can grow, e.g. as a walk finds
+/// more directories.
+#[derive(Debug, Default)]
+pub struct Progress {
+    done: AtomicUsize,
+    total: AtomicUsize,
+    /// What it has gone through, e.g. files and directories seen
+    items: AtomicUsize,
+    cancelled: AtomicBool,
+}
+
+impl Progress {
+    pub fn add_done(&self, n: usize) {
+        self.done.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn add_total(&self, n: usize) {
+        self.total.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn add_items(&self, n: usize) {
+        self.items.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// The fraction done, if the total is known
+    pub fn fraction(&self) -> Option<f64> {
+        let total = self.total.load(Ordering::Relaxed);
+        let done = self.done.load(Ordering::Relaxed);
+        (total > 0).then(|| (done as f64 / total as f64).min(1.0))
+    }
+
+    pub fn items(&self) -> usize {
+        self.items.load(Ordering::Relaxed)
+    }
+
+    /// Ask the thread to stop, which it does at its next check
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// The flag that's set when it's cancelled, for what checks one
+    pub fn cancelled(&self) -> &AtomicBool {
+        &self.cancelled
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobState {
+    Running,
+    /// With a summary of the result, e.g. "4.2 GB in 1,234 files"
+    Done(String),
+    Failed(String),
+    Cancelled,
+}
+
+/// Something long-running in the background, like adding up a directory
+#[derive(Debug)]
+pub struct Job {
+    pub id: usize,
+    /// What it does, e.g. "Adding up /mnt/nfs/alice"
+    pub description: String,
+    /// Where its results are, e.g. the directory that was added up
+    pub target: PathBuf,
+    pub state: JobState,
+    pub progress: Arc<Progress>,
+    started: Instant,
+    ended: Option<Instant>,
+}
+
+impl Job {
+    /// How long it ran, or has been running
+    pub fn elapsed(&self) -> Duration {
+        self.ended.unwrap_or_else(Instant::now) - self.started
+    }
+
+    /// The elapsed time like a stopwatch, e.g. "0:07" or "1:02:03"
+    pub fn elapsed_str(&self) -> String {
+        let secs = self.elapsed().as_secs();
+        match secs / 3600 {
+            0 => format!("{}:{:02}", secs / 60, secs % 60),
+            hours => format!("{}:{:02}:{:02}", hours, secs / 60 % 60, secs % 60),
+        }
+    }
+}
+
+/// The background jobs of the session, running and finished, oldest first,
+/// for the jobs panel (J)
+#[derive(Debug, Default)]
+pub struct Jobs {
+    jobs: Vec<Job>,
+    next_id: usize,
+}
+
+impl Jobs {
+    /// Add a running job, with its results at `target`. Returns its id, and
+    /// the progress for its thread to update.
+    pub fn start(&mut self, description: String, target: PathBuf) -> (usize, Arc<Progress>) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let progress = Arc::new(Progress::default());
+        self.jobs.push(Job {
+            id,
+            description,
+            target,
+            state: JobState::Running,
+            progress: progress.clone(),
+            started: Instant::now(),
+            ended: None,
+        });
+        (id, progress)
+    }
+
+    /// Record how the job `id` ended: with a summary of what it did, or an
+    /// error. Jobs that were cancelled end up cancelled either way.
+    pub fn finish(&mut self, id: usize, result: Result<String, String>) {
+        let Some(job) = self.jobs.iter_mut().find(|job| job.id == id) else {
+            return;
+        };
+        job.state = match result {
+            _ if job.progress.is_cancelled() => JobState::Cancelled,
+            Ok(summary) => JobState::Done(summary),
+            Err(e) => JobState::Failed(e),
+        };
+        job.ended = Some(Instant::now());
+
+        let finished = self.jobs.iter().filter(|job| job.ended.is_some()).count();
+        let mut excess = finished.saturating_sub(MAX_FINISHED);
+        self.jobs.retain(|job| {
+            let drop = excess > 0 && job.ended.is_some();
+            excess -= usize::from(drop);
+            !drop
+        });
+    }
+
+    /// Ask the job at `index` to stop, if it's running. Returns true if it was.
+    pub fn cancel(&mut self, index: usize) -> bool {
+        match self.jobs.get(index) {
+            Some(job) if job.state == JobState::Running => {
+                job.progress.cancel();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Forget the jobs that have finished
+    pub fn clear_finished(&mut self) {
+        self.jobs.retain(|job| job.state == JobState::Running);
+    }
+
+    pub fn running(&self) -> usize {
+        self.jobs
+            .iter()
+            .filter(|job| job.state == JobState::Running)
+            .count()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Job> {
+        self.jobs.get(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.iter()
+    }
+}
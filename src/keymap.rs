@@ -0,0 +1,493 @@
+use std::collections::HashMap;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::Deserialize;
+
+use crate::input::{Scripted, parse_keys};
+
+/// Something a key can be bound to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    Down,
+    Up,
+    PageDown,
+    PageUp,
+    HalfPageDown,
+    HalfPageUp,
+    Open,
+    Parent,
+    DrillToBiggest,
+    Peek,
+    PanLeft,
+    PanRight,
+    SortName,
+    SortSize,
+    SortCount,
+    SortOwner,
+    ToggleOwner,
+    SortGroup,
+    ToggleGroup,
+    ToggleNumericIds,
+    TogglePermissions,
+    SortCtime,
+    ToggleCtime,
+    SortAverage,
+    ToggleAverage,
+    ToggleCost,
+    ProjectUsage,
+    PoolUsage,
+    Retention,
+    Cleanup,
+    Jobs,
+    GoToFinished,
+    ExportScreen,
+    Pipe,
+    Search,
+    Help,
+    First,
+    Last,
+    Refresh,
+    FlushCaches,
+    ReloadConfig,
+    OriginalDir,
+    ToggleDebug,
+    LoadMore,
+    LoadAll,
+    DeepSize,
+    Details,
+    ToggleSizeSource,
+    ToggleDotfiles,
+    ToggleExactSizes,
+    SaveView,
+    ToggleHints,
+    JumpToName,
+    SetMark,
+    GoToMark,
+    ToggleMarked,
+    MarkMatching,
+    MarkAll,
+    InvertMarked,
+    UnmarkAll,
+}
+
+impl Action {
+    /// In the order they're listed in the help
+    pub const ALL: [Action; 61] = [
+        Action::Quit,
+        Action::Down,
+        Action::Up,
+        Action::PageDown,
+        Action::PageUp,
+        Action::HalfPageDown,
+        Action::HalfPageUp,
+        Action::Open,
+        Action::Parent,
+        Action::DrillToBiggest,
+        Action::Peek,
+        Action::PanLeft,
+        Action::PanRight,
+        Action::SortName,
+        Action::SortSize,
+        Action::SortCount,
+        Action::SortOwner,
+        Action::ToggleOwner,
+        Action::SortGroup,
+        Action::ToggleGroup,
+        Action::ToggleNumericIds,
+        Action::TogglePermissions,
+        Action::SortCtime,
+        Action::ToggleCtime,
+        Action::SortAverage,
+        Action::ToggleAverage,
+        Action::ToggleCost,
+        Action::ProjectUsage,
+        Action::PoolUsage,
+        Action::Retention,
+        Action::Cleanup,
+        Action::Jobs,
+        Action::GoToFinished,
+        Action::ExportScreen,
+        Action::Pipe,
+        Action::Search,
+        Action::Help,
+        Action::First,
+        Action::Last,
+        Action::JumpToName,
+        Action::Refresh,
+        Action::FlushCaches,
+        Action::ReloadConfig,
+        Action::OriginalDir,
+        Action::SetMark,
+        Action::GoToMark,
+        Action::ToggleMarked,
+        Action::MarkMatching,
+        Action::MarkAll,
+        Action::InvertMarked,
+        Action::UnmarkAll,
+        Action::ToggleDebug,
+        Action::LoadMore,
+        Action::LoadAll,
+        Action::DeepSize,
+        Action::Details,
+        Action::ToggleSizeSource,
+        Action::ToggleDotfiles,
+        Action::ToggleExactSizes,
+        Action::SaveView,
+        Action::ToggleHints,
+    ];
+
+    /// The name used in the `[keys]` section of the config file
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::Down => "down",
+            Action::Up => "up",
+            Action::PageDown => "page-down",
+            Action::PageUp => "page-up",
+            Action::HalfPageDown => "half-page-down",
+            Action::HalfPageUp => "half-page-up",
+            Action::Open => "open",
+            Action::Parent => "parent",
+            Action::PanLeft => "pan-left",
+            Action::PanRight => "pan-right",
+            Action::SortName => "sort-name",
+            Action::SortSize => "sort-size",
+            Action::SortCount => "sort-count",
+            Action::SortOwner => "sort-owner",
+            Action::ToggleOwner => "toggle-owner",
+            Action::SortGroup => "sort-group",
+            Action::ToggleGroup => "toggle-group",
+            Action::ToggleNumericIds => "toggle-numeric-ids",
+            Action::TogglePermissions => "toggle-permissions",
+            Action::SortCtime => "sort-ctime",
+            Action::ToggleCtime => "toggle-ctime",
+            Action::SortAverage => "sort-average",
+            Action::ToggleAverage => "toggle-average",
+            Action::ToggleCost => "toggle-cost",
+            Action::ProjectUsage => "project-usage",
+            Action::PoolUsage => "pool-usage",
+            Action::Retention => "retention",
+            Action::Cleanup => "cleanup",
+            Action::Jobs => "jobs",
+            Action::GoToFinished => "go-to-finished",
+            Action::ExportScreen => "export-screen",
+            Action::Pipe => "pipe",
+            Action::Search => "search",
+            Action::Help => "help",
+            Action::First => "first",
+            Action::Last => "last",
+            Action::Refresh => "refresh",
+            Action::FlushCaches => "flush-caches",
+            Action::ReloadConfig => "reload-config",
+            Action::OriginalDir => "original-dir",
+            Action::DrillToBiggest => "drill-to-biggest",
+            Action::Peek => "peek",
+            Action::ToggleDebug => "toggle-debug",
+            Action::LoadMore => "load-more",
+            Action::LoadAll => "load-all",
+            Action::DeepSize => "deep-size",
+            Action::Details => "details",
+            Action::ToggleSizeSource => "toggle-size-source",
+            Action::ToggleDotfiles => "toggle-dotfiles",
+            Action::ToggleExactSizes => "toggle-exact-sizes",
+            Action::SaveView => "save-view",
+            Action::ToggleHints => "toggle-hints",
+            Action::JumpToName => "jump-to-name",
+            Action::SetMark => "set-mark",
+            Action::GoToMark => "go-to-mark",
+            Action::ToggleMarked => "toggle-marked",
+            Action::MarkMatching => "mark-matching",
+            Action::MarkAll => "mark-all",
+            Action::InvertMarked => "invert-marked",
+            Action::UnmarkAll => "unmark-all",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Action::Quit => "Quit",
+            Action::Down => "Move cursor down",
+            Action::Up => "Move cursor up",
+            Action::PageDown => "Jump cursor down",
+            Action::PageUp => "Jump cursor up",
+            Action::HalfPageDown => "Move cursor down half a page",
+            Action::HalfPageUp => "Move cursor up half a page",
+            Action::Open => "Open directory",
+            Action::Parent => "Go to parent directory",
+            Action::PanLeft => "Scroll the columns after the gauges back",
+            Action::PanRight => "Scroll the columns after the gauges, to see what's cut off",
+            Action::SortName => "Sort by name",
+            Action::SortSize => "Sort by size",
+            Action::SortCount => "Sort by file count",
+            Action::SortOwner => "Sort by owner",
+            Action::ToggleOwner => "Toggle show owner",
+            Action::SortGroup => "Sort by group",
+            Action::ToggleGroup => "Toggle show group",
+            Action::ToggleNumericIds => "Toggle showing uids and gids instead of names",
+            Action::TogglePermissions => "Toggle show permissions, flagging risky ones",
+            Action::SortCtime => "Sort by change time (ctime)",
+            Action::ToggleCtime => "Toggle show change time (ctime)",
+            Action::SortAverage => "Sort by average file size, smallest first",
+            Action::ToggleAverage => "Toggle show average file size",
+            Action::ToggleCost => "Toggle show monthly storage cost",
+            Action::ProjectUsage => "Show usage by project, or by owner",
+            Action::PoolUsage => "Show usage by data pool",
+            Action::Retention => "Simulate deleting what's older than some days",
+            Action::Cleanup => "Find what's worth deleting, and delete it",
+            Action::Jobs => "Show background jobs, to follow or cancel them",
+            Action::GoToFinished => "Go to what the last job that finished elsewhere did",
+            Action::ExportScreen => "Copy the screen as text, and save it to a file",
+            Action::Pipe => {
+                "Feed the listing, or the marked paths, to a command, and show its output"
+            }
+            Action::Search => "Find names under this directory, or show the last results",
+            Action::Help => "Show this help message",
+            Action::First => "Select first entry",
+            Action::Last => "Select last entry",
+            Action::Refresh => "Refresh",
+            Action::FlushCaches => "Flush all caches and refresh",
+            Action::ReloadConfig => "Reload the config file",
+            Action::OriginalDir => "Go to original directory",
+            Action::DrillToBiggest => {
+                "Keep opening the biggest subdirectory while it holds most of the space"
+            }
+            Action::Peek => "Show the biggest entries of the selected directory without opening it",
+            Action::ToggleDebug => "Toggle debug overlay",
+            Action::LoadMore => "List more of a gigantic directory",
+            Action::LoadAll => "List all of a gigantic directory",
+            Action::DeepSize => "Compute the size of a non-Ceph directory",
+            Action::Details => "Show details of the selected entry",
+            Action::ToggleSizeSource => "Toggle directory sizes between rbytes and stat",
+            Action::ToggleDotfiles => "Show or hide dotfiles",
+            Action::ToggleExactSizes => "Toggle between exact and rounded sizes",
+            Action::SaveView => "Remember the sort and columns for this directory and below",
+            Action::ToggleHints => "Toggle the key hint bar",
+            Action::JumpToName => "Type the start of a name to jump to it",
+            Action::SetMark => "Mark the current directory with the next letter typed",
+            Action::GoToMark => "Go to the directory marked with the next letter typed",
+            Action::ToggleMarked => "Mark or unmark the selected entry",
+            Action::MarkMatching => "Mark the entries matching a glob, like *.log",
+            Action::MarkAll => "Mark all entries",
+            Action::InvertMarked => "Invert which entries are marked",
+            Action::UnmarkAll => "Unmark all entries",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Action> {
+        Action::ALL.into_iter().find(|action| action.name() == name)
+    }
+}
+
+const DEFAULT_BINDINGS: &[(KeyCode, Action)] = &[
+    (KeyCode::Char('q'), Action::Quit),
+    (KeyCode::Esc, Action::Quit),
+    (KeyCode::Down, Action::Down),
+    (KeyCode::Char('j'), Action::Down),
+    (KeyCode::Up, Action::Up),
+    (KeyCode::Char('k'), Action::Up),
+    (KeyCode::PageDown, Action::PageDown),
+    (KeyCode::PageUp, Action::PageUp),
+    (KeyCode::Enter, Action::Open),
+    (KeyCode::Backspace, Action::Parent),
+    (KeyCode::Left, Action::PanLeft),
+    (KeyCode::Right, Action::PanRight),
+    (KeyCode::Char('n'), Action::SortName),
+    (KeyCode::Char('s'), Action::SortSize),
+    (KeyCode::Char('c'), Action::SortCount),
+    (KeyCode::Char('C'), Action::SortCount),
+    (KeyCode::Char('U'), Action::SortOwner),
+    (KeyCode::Char('u'), Action::ToggleOwner),
+    (KeyCode::Char('O'), Action::SortGroup),
+    (KeyCode::Char('o'), Action::ToggleGroup),
+    (KeyCode::Char('N'), Action::ToggleNumericIds),
+    (KeyCode::Char('p'), Action::TogglePermissions),
+    (KeyCode::Char('T'), Action::SortCtime),
+    (KeyCode::Char('t'), Action::ToggleCtime),
+    (KeyCode::Char('Z'), Action::SortAverage),
+    (KeyCode::Char('z'), Action::ToggleAverage),
+    (KeyCode::Char('$'), Action::ToggleCost),
+    (KeyCode::Char('W'), Action::ProjectUsage),
+    (KeyCode::Char('L'), Action::PoolUsage),
+    (KeyCode::Char('a'), Action::Retention),
+    (KeyCode::Char('w'), Action::Cleanup),
+    (KeyCode::Char('J'), Action::Jobs),
+    (KeyCode::Char('F'), Action::GoToFinished),
+    (KeyCode::Char('Y'), Action::ExportScreen),
+    (KeyCode::Char('|'), Action::Pipe),
+    (KeyCode::Char('/'), Action::Search),
+    (KeyCode::Char('?'), Action::Help),
+    (KeyCode::Char('h'), Action::Help),
+    (KeyCode::Home, Action::First),
+    (KeyCode::Char('g'), Action::First),
+    (KeyCode::End, Action::Last),
+    (KeyCode::Char('G'), Action::Last),
+    (KeyCode::Char('r'), Action::Refresh),
+    (KeyCode::F(5), Action::Refresh),
+    (KeyCode::Char('R'), Action::FlushCaches),
+    (KeyCode::Char(' '), Action::OriginalDir),
+    (KeyCode::Char('>'), Action::DrillToBiggest),
+    (KeyCode::Char('P'), Action::Peek),
+    (KeyCode::Char('D'), Action::ToggleDebug),
+    (KeyCode::Char('+'), Action::LoadMore),
+    (KeyCode::Char('*'), Action::LoadAll),
+    (KeyCode::Char('d'), Action::DeepSize),
+    (KeyCode::Char('i'), Action::Details),
+    (KeyCode::Char('S'), Action::ToggleSizeSource),
+    (KeyCode::Char('.'), Action::ToggleDotfiles),
+    (KeyCode::Char('b'), Action::ToggleExactSizes),
+    (KeyCode::Char('V'), Action::SaveView),
+    (KeyCode::Char('H'), Action::ToggleHints),
+    (KeyCode::Char('f'), Action::JumpToName),
+    (KeyCode::Char('m'), Action::SetMark),
+    (KeyCode::Char('\''), Action::GoToMark),
+    (KeyCode::Char('x'), Action::ToggleMarked),
+    (KeyCode::Char('M'), Action::MarkMatching),
+    (KeyCode::Char('A'), Action::MarkAll),
+    (KeyCode::Char('I'), Action::InvertMarked),
+    (KeyCode::Char('X'), Action::UnmarkAll),
+];
+
+/// The same, with Ctrl
+const DEFAULT_CTRL_BINDINGS: &[(char, Action)] = &[
+    ('d', Action::HalfPageDown),
+    ('u', Action::HalfPageUp),
+    ('r', Action::ReloadConfig),
+];
+
+/// A key as it's bound: Shift is part of the character, and only Ctrl and
+/// Alt are told apart.
+type Key = (KeyCode, KeyModifiers);
+
+fn normalize(key: KeyEvent) -> Key {
+    (
+        key.code,
+        key.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT),
+    )
+}
+
+/// Which keys do what: the defaults, with the `[keys]` section of the config
+/// file applied on top. That maps keys (written like in `--keys`, e.g. "x"
+/// or "<c-d>") to action names, or to "none" to unbind a key.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "HashMap<String, String>")]
+pub struct Keymap {
+    bindings: Vec<(Key, Action)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap {
+            bindings: DEFAULT_BINDINGS
+                .iter()
+                .map(|&(code, action)| ((code, KeyModifiers::NONE), action))
+                .chain(
+                    DEFAULT_CTRL_BINDINGS
+                        .iter()
+                        .map(|&(c, action)| ((KeyCode::Char(c), KeyModifiers::CONTROL), action)),
+                )
+                .collect(),
+        }
+    }
+}
+
+/// Parse a single key, written like in `--keys`
+fn parse_key(key_str: &str) -> Result<Key, String> {
+    match parse_keys(key_str)?.as_slice() {
+        [Scripted::Key(key)] => Ok(normalize(*key)),
+        _ => Err(format!("expected a single key, not {:?}", key_str)),
+    }
+}
+
+/// A key given in the config outside of `[keys]`, e.g. for a plugin's action
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ConfigKey(Key);
+
+impl TryFrom<String> for ConfigKey {
+    type Error = String;
+
+    fn try_from(key_str: String) -> Result<Self, Self::Error> {
+        parse_key(&key_str).map(ConfigKey)
+    }
+}
+
+impl ConfigKey {
+    pub fn matches(self, key: KeyEvent) -> bool {
+        normalize(key) == self.0
+    }
+
+    pub fn name(self) -> String {
+        key_name(self.0)
+    }
+}
+
+impl TryFrom<HashMap<String, String>> for Keymap {
+    type Error = String;
+
+    fn try_from(remappings: HashMap<String, String>) -> Result<Self, Self::Error> {
+        let mut keymap = Keymap::default();
+        for (key_str, action_str) in remappings {
+            let key = parse_key(&key_str)?;
+            keymap.bindings.retain(|(k, _)| *k != key);
+            if action_str == "none" {
+                continue;
+            }
+            let action = Action::from_name(&action_str)
+                .ok_or_else(|| format!("unknown action {:?}", action_str))?;
+            keymap.bindings.push((key, action));
+        }
+        Ok(keymap)
+    }
+}
+
+impl Keymap {
+    pub fn action(&self, key: KeyEvent) -> Option<Action> {
+        let key = normalize(key);
+        self.bindings
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|&(_, action)| action)
+    }
+
+    /// The name of the first key bound to `action`, for the hint bar
+    pub fn first_key_for(&self, action: Action) -> Option<String> {
+        self.bindings
+            .iter()
+            .find(|(_, a)| *a == action)
+            .map(|&(key, _)| key_name(key))
+    }
+
+    /// The names of the keys bound to `action`, e.g. "End, G"
+    pub fn keys_for(&self, action: Action) -> String {
+        let mut keys: Vec<String> = self
+            .bindings
+            .iter()
+            .filter(|(_, a)| *a == action)
+            .map(|&(key, _)| key_name(key))
+            .collect();
+        // Named keys before letters, like "Down, j"
+        keys.sort_by_key(|k| k.chars().count() == 1);
+        keys.join(", ")
+    }
+}
+
+fn key_name((code, modifiers): Key) -> String {
+    let name = match code {
+        KeyCode::Char(' ') => "Space".to_string(),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        KeyCode::PageUp => "Page Up".to_string(),
+        KeyCode::PageDown => "Page Down".to_string(),
+        code => format!("{:?}", code),
+    };
+    let mut prefix = String::new();
+    if modifiers.contains(KeyModifiers::CONTROL) {
+        prefix.push_str("Ctrl-");
+    }
+    if modifiers.contains(KeyModifiers::ALT) {
+        prefix.push_str("Alt-");
+    }
+    prefix.push_str(&name);
+    prefix
+}
@@ -0,0 +1,555 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::app::{DirEntry, EntryKind, SizeSource};
+use crate::config::Config;
+use crate::provider::{self, DirIter, Dirent, Stat};
+use crate::rstats::{self, RStats};
+use crate::{timeout, uring};
+
+/// Number of entries to load between re-evaluations of the strategy, which
+/// are also given up on together if they time out
+const SAMPLE_SIZE: usize = 64;
+/// How many entries are loaded in one batch by `Strategy::Batched`, which
+/// are also given up on together if the batch times out
+const BATCH_SIZE: usize = 256;
+/// Mean per-call latency above which we spread the calls over several threads
+const SLOW_CALL: Duration = Duration::from_millis(1);
+/// Mean per-call latency below which we step back to a cheaper strategy
+const FAST_CALL: Duration = Duration::from_micros(200);
+const PARALLEL_THREADS: usize = 8;
+/// Directories with more entries than this only load metadata for the visible rows
+pub const DEFAULT_LAZY_THRESHOLD: usize = 100_000;
+/// Only this many entries are listed up front; the rest are listed on request
+pub const DEFAULT_PAGE_SIZE: usize = 500_000;
+
+/// The unread part of a directory that was listed one page at a time
+pub type MoreEntries = std::iter::Peekable<DirIter>;
+
+/// How much work to do, and how, when fetching the metadata of a listing.
+/// The variants are ordered from least to most aggressive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Strategy {
+    /// Fetch everything from a single thread
+    #[default]
+    Full,
+    /// Spread the stat/getxattr calls over several threads
+    Parallel,
+    /// Hand the stat/getxattr calls to the kernel in batches through
+    /// io_uring, which runs them side by side; see `uring`
+    Batched,
+}
+
+impl Strategy {
+    pub fn threads(self) -> usize {
+        match self {
+            Strategy::Full | Strategy::Batched => 1,
+            Strategy::Parallel => PARALLEL_THREADS,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Strategy::Full => "full",
+            Strategy::Parallel => "parallel",
+            Strategy::Batched => "io_uring",
+        }
+    }
+
+    /// Pick the next strategy given the latency we just observed. Batching
+    /// is on or off, whatever the latency.
+    fn adapt(self, timings: &Timings) -> Strategy {
+        match timings.mean_call() {
+            _ if self == Strategy::Batched => self,
+            None => self,
+            Some(mean) if mean >= SLOW_CALL => Strategy::Parallel,
+            Some(mean) if mean < FAST_CALL => Strategy::Full,
+            Some(_) => self,
+        }
+    }
+}
+
+/// Accumulated latency of the metadata syscalls made during a listing
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timings {
+    pub stat_calls: usize,
+    pub stat_time: Duration,
+    pub xattr_calls: usize,
+    pub xattr_time: Duration,
+}
+
+impl Timings {
+    pub fn time_stat<T>(&mut self, f: impl FnOnce() -> T) -> T {
+        self.time_stats(1, f)
+    }
+
+    pub fn time_xattr<T>(&mut self, f: impl FnOnce() -> T) -> T {
+        self.time_xattrs(1, f)
+    }
+
+    /// Time a batch of `calls` stat calls
+    pub fn time_stats<T>(&mut self, calls: usize, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let res = f();
+        self.stat_calls += calls;
+        self.stat_time += start.elapsed();
+        res
+    }
+
+    /// Time a batch of `calls` getxattr calls
+    pub fn time_xattrs<T>(&mut self, calls: usize, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let res = f();
+        self.xattr_calls += calls;
+        self.xattr_time += start.elapsed();
+        res
+    }
+
+    pub fn mean_stat(&self) -> Option<Duration> {
+        mean(self.stat_time, self.stat_calls)
+    }
+
+    pub fn mean_xattr(&self) -> Option<Duration> {
+        mean(self.xattr_time, self.xattr_calls)
+    }
+
+    fn mean_call(&self) -> Option<Duration> {
+        mean(
+            self.stat_time + self.xattr_time,
+            self.stat_calls + self.xattr_calls,
+        )
+    }
+
+    pub fn merge(&mut self, other: &Timings) {
+        self.stat_calls += other.stat_calls;
+        self.stat_time += other.stat_time;
+        self.xattr_calls += other.xattr_calls;
+        self.xattr_time += other.xattr_time;
+    }
+}
+
+fn mean(total: Duration, calls: usize) -> Option<Duration> {
+    (calls > 0).then(|| total / calls as u32)
+}
+
+/// Everything that controls how a listing is loaded
+#[derive(Debug, Clone, Copy)]
+pub struct LoadOptions {
+    pub strategy: Strategy,
+    /// Above this many entries, only names are listed up front
+    pub lazy_threshold: usize,
+    /// At most this many entries are listed up front
+    pub page_size: usize,
+    pub size_source: SizeSource,
+    /// Leave entries whose names start with a '.' out of the listing
+    pub hide_dotfiles: bool,
+    /// How long to wait for an entry's metadata before giving up on it
+    pub timeout: Option<Duration>,
+}
+
+/// What happened while loading a directory listing
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoadReport {
+    /// The strategy in effect when the load finished
+    pub strategy: Strategy,
+    /// Whether the metadata was deferred until the entries are displayed
+    pub lazy: bool,
+    pub entries: usize,
+    pub elapsed: Duration,
+    pub timings: Timings,
+    /// Entries whose metadata didn't come in time
+    pub timed_out: usize,
+}
+
+/// Remembers the strategy chosen for previous listings, so that the next
+/// directory starts out with what worked last time.
+#[derive(Debug)]
+pub struct Loader {
+    strategy: Strategy,
+    pub lazy_threshold: usize,
+    pub page_size: usize,
+    pub size_source: SizeSource,
+    pub hide_dotfiles: bool,
+    pub timeout: Option<Duration>,
+}
+
+impl Loader {
+    pub fn new(config: &Config) -> Self {
+        Loader {
+            strategy: Strategy::default(),
+            lazy_threshold: config.lazy_threshold,
+            page_size: config.page_size,
+            size_source: config.dir_sizes,
+            hide_dotfiles: config.hide_dotfiles,
+            timeout: config.metadata_timeout(),
+        }
+    }
+
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    pub fn options(&self) -> LoadOptions {
+        LoadOptions {
+            strategy: if uring::available() {
+                Strategy::Batched
+            } else {
+                self.strategy
+            },
+            lazy_threshold: self.lazy_threshold,
+            page_size: self.page_size,
+            size_source: self.size_source,
+            hide_dotfiles: self.hide_dotfiles,
+            timeout: self.timeout,
+        }
+    }
+
+    pub fn record(&mut self, report: LoadReport) {
+        // What to fall back to if batching is turned off stays as it was
+        if report.strategy != Strategy::Batched {
+            self.strategy = report.strategy.adapt(&report.timings);
+        }
+    }
+}
+
+/// List a directory, returning the entry for the directory itself and its children.
+/// The strategy may be escalated partway through if the metadata calls turn out to be slow.
+/// If the directory is very large, the children are returned unloaded; see `load_entry()`.
+/// If it's gigantic, only the first page of children is listed, and the rest
+/// can be read from the returned `MoreEntries`.
+/// The listing is abandoned if `cancel` is set. Children whose metadata
+/// times out are returned marked as such, along with the others loaded
+/// with them and the ones after them, without waiting for those too.
+pub fn ls(
+    path: &Path,
+    options: LoadOptions,
+    cancel: &AtomicBool,
+) -> Result<(DirEntry, Vec<DirEntry>, LoadReport, Option<MoreEntries>), std::io::Error> {
+    let start = Instant::now();
+    let mut strategy = options.strategy;
+    let mut timings = Timings::default();
+
+    let fs = provider::get();
+    let entry_cwd = load_timed(path, false, options.timeout, &mut timings)?;
+
+    // readdir is cheap compared to stat/getxattr, so get all the names first
+    let mut read_dir = fs.read_dir(path)?.peekable();
+    let dirents = read_names(&mut read_dir, options.page_size, cancel)?;
+    if cancel.load(Ordering::Relaxed) {
+        return Err(interrupted_error());
+    }
+    let more = read_dir.peek().is_some().then_some(read_dir);
+
+    if dirents.len() > options.lazy_threshold || more.is_some() {
+        let entries = unloaded_entries(&dirents);
+        let mut report = report(strategy, entries.len(), start, timings);
+        report.lazy = true;
+        return Ok((entry_cwd, entries, report, more));
+    }
+
+    let mut entries: Vec<DirEntry> = Vec::with_capacity(dirents.len());
+    let mut rest = &dirents[..];
+    let timed_out = AtomicUsize::new(0);
+
+    if strategy == Strategy::Batched {
+        entries = ls_batched(&dirents, options.timeout, &timed_out, &mut timings, cancel)?;
+        rest = &[];
+    }
+
+    while strategy.threads() == 1 && !rest.is_empty() {
+        let (sample_dirents, tail) = rest.split_at(rest.len().min(SAMPLE_SIZE));
+        rest = tail;
+
+        if cancel.load(Ordering::Relaxed) {
+            return Err(interrupted_error());
+        }
+        let mut sample = Timings::default();
+        let timeout = options.timeout;
+        entries.extend(load_chunk(
+            sample_dirents,
+            load_each,
+            timeout,
+            &timed_out,
+            &mut sample,
+        )?);
+        timings.merge(&sample);
+        strategy = strategy.adapt(&sample).max(strategy);
+    }
+
+    if !rest.is_empty() {
+        let timeout = options.timeout;
+        entries.extend(ls_parallel(
+            rest,
+            strategy,
+            timeout,
+            &timed_out,
+            &mut timings,
+            cancel,
+        )?);
+    }
+
+    let mut report = report(strategy, entries.len(), start, timings);
+    report.timed_out = timed_out.into_inner();
+    Ok((entry_cwd, entries, report, None))
+}
+
+/// Read up to `limit` names from a directory. Stops early, with what was
+/// read so far, if `cancel` is set.
+pub fn read_names(
+    read_dir: &mut MoreEntries,
+    limit: usize,
+    cancel: &AtomicBool,
+) -> Result<Vec<Dirent>, std::io::Error> {
+    let mut dirents = Vec::new();
+    for entry_result in read_dir.take(limit) {
+        dirents.push(entry_result?);
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+    }
+    Ok(dirents)
+}
+
+/// Entries with only names and kinds, to be loaded later with `load_entry()`
+pub fn unloaded_entries(dirents: &[Dirent]) -> Vec<DirEntry> {
+    dirents
+        .iter()
+        .map(|d| DirEntry::unloaded(&d.path, d.kind))
+        .collect()
+}
+
+/// Fetch the metadata of an entry that was listed unloaded, giving up after `timeout`
+pub fn load_entry(
+    dir: &Path,
+    entry: &mut DirEntry,
+    timeout: Option<Duration>,
+    timings: &mut Timings,
+) -> Result<(), std::io::Error> {
+    let path = dir.join(&entry.name);
+    let (loaded, t) = timeout::call(timeout, move || {
+        let fs = provider::get();
+        let mut t = Timings::default();
+        let stat = t.time_stat(|| fs.lstat(&path))?;
+        // The recursive stats can hang as well as the stat
+        let mut loaded = DirEntry::unloaded(&path, stat.kind);
+        loaded.load(&path, &stat, &mut t);
+        Ok::<_, io::Error>((loaded, t))
+    })??;
+    *entry = DirEntry {
+        acl: entry.acl,
+        ..loaded
+    };
+    timings.merge(&t);
+    Ok(())
+}
+
+/// The entry at `path`, with its metadata fetched on a worker thread, giving
+/// up after `timeout`. The path is followed if it's a symlink, unless `lstat`.
+fn load_timed(
+    path: &Path,
+    lstat: bool,
+    timeout: Option<Duration>,
+    timings: &mut Timings,
+) -> io::Result<DirEntry> {
+    let path = path.to_path_buf();
+    let (entry, t) = timeout::call(timeout, move || {
+        let fs = provider::get();
+        let mut t = Timings::default();
+        let stat = t.time_stat(|| {
+            if lstat {
+                fs.lstat(&path)
+            } else {
+                fs.stat(&path)
+            }
+        })?;
+        Ok::<_, io::Error>((DirEntry::from(path, stat, &mut t), t))
+    })??;
+    timings.merge(&t);
+    Ok(entry)
+}
+
+/// Load `dirents` with `load` on a worker thread, all of them given up on
+/// together if that takes longer than `timeout`, so that the round trip is
+/// made once per chunk rather than per entry. Once something has timed out
+/// (counted in `timed_out`), they're marked as timed out without trying.
+fn load_chunk(
+    dirents: &[Dirent],
+    load: fn(&[Dirent]) -> (Vec<DirEntry>, Timings),
+    timeout: Option<Duration>,
+    timed_out: &AtomicUsize,
+    timings: &mut Timings,
+) -> io::Result<Vec<DirEntry>> {
+    let result = if timed_out.load(Ordering::Relaxed) > 0 {
+        Err(timeout::timed_out_error())
+    } else {
+        let chunk = dirents.to_vec();
+        timeout::call(timeout, move || load(&chunk))
+    };
+    match result {
+        Ok((entries, t)) => {
+            timings.merge(&t);
+            Ok(entries)
+        }
+        Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+            timed_out.fetch_add(dirents.len(), Ordering::Relaxed);
+            Ok(dirents
+                .iter()
+                .map(|d| DirEntry::timed_out(&d.path, d.kind))
+                .collect())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Load `dirents` one lstat at a time. As with `load_batch()`, an entry
+/// that can't be stat'ed is left without metadata.
+fn load_each(dirents: &[Dirent]) -> (Vec<DirEntry>, Timings) {
+    let fs = provider::get();
+    let mut t = Timings::default();
+    let entries = dirents
+        .iter()
+        .map(|d| match t.time_stat(|| fs.lstat(&d.path)) {
+            Ok(stat) => DirEntry::from(d.path.clone(), stat, &mut t),
+            Err(_) => unstatable(d),
+        })
+        .collect();
+    (entries, t)
+}
+
+/// An entry that was listed but couldn't be stat'ed, probably because it was
+/// deleted since, marked as loaded so that it isn't tried again
+fn unstatable(dirent: &Dirent) -> DirEntry {
+    DirEntry {
+        loaded: true,
+        ..DirEntry::unloaded(&dirent.path, dirent.kind)
+    }
+}
+
+fn report(strategy: Strategy, entries: usize, start: Instant, timings: Timings) -> LoadReport {
+    LoadReport {
+        strategy,
+        lazy: false,
+        entries,
+        elapsed: start.elapsed(),
+        timings,
+        timed_out: 0,
+    }
+}
+
+/// Load `dirents` a batch at a time; see `load_batch()`
+fn ls_batched(
+    dirents: &[Dirent],
+    timeout: Option<Duration>,
+    timed_out: &AtomicUsize,
+    timings: &mut Timings,
+    cancel: &AtomicBool,
+) -> io::Result<Vec<DirEntry>> {
+    let mut entries = Vec::with_capacity(dirents.len());
+    for chunk in dirents.chunks(BATCH_SIZE) {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(interrupted_error());
+        }
+        entries.extend(load_chunk(chunk, load_batch, timeout, timed_out, timings)?);
+    }
+    Ok(entries)
+}
+
+/// Load `dirents` with one batch of lstat calls, and then one batch of the
+/// recursive stats of the directories among them per filesystem. An entry
+/// that can't be stat'ed is left without metadata rather than failing the
+/// others; see `unstatable()`.
+fn load_batch(dirents: &[Dirent]) -> (Vec<DirEntry>, Timings) {
+    let mut t = Timings::default();
+    let paths: Vec<PathBuf> = dirents.iter().map(|d| d.path.clone()).collect();
+    let stats = t.time_stats(paths.len(), || provider::get().lstat_many(&paths));
+    let stats: Vec<Option<Stat>> = stats.into_iter().map(Result::ok).collect();
+
+    let mut rstats = vec![RStats::default(); paths.len()];
+    let mut devs: Vec<u64> = stats
+        .iter()
+        .flatten()
+        .filter(|stat| stat.kind == EntryKind::Dir)
+        .map(|stat| stat.dev)
+        .collect();
+    devs.sort_unstable();
+    devs.dedup();
+    for dev in devs {
+        let (indices, dirs): (Vec<usize>, Vec<(&Path, &Stat)>) = paths
+            .iter()
+            .zip(&stats)
+            .enumerate()
+            .filter_map(|(i, (path, stat))| Some((i, (path.as_path(), stat.as_ref()?))))
+            .filter(|(_, (_, stat))| stat.kind == EntryKind::Dir && stat.dev == dev)
+            .unzip();
+        let backend = rstats::for_dir(dirs[0].0, dev);
+        for (i, loaded) in indices.into_iter().zip(backend.load_many(&dirs, &mut t)) {
+            rstats[i] = loaded;
+        }
+    }
+
+    let entries = dirents
+        .iter()
+        .zip(&stats)
+        .zip(rstats)
+        .map(|((dirent, stat), rstats)| match stat {
+            Some(stat) => {
+                let mut entry = DirEntry::unloaded(&dirent.path, stat.kind);
+                entry.load_with(stat, rstats);
+                entry
+            }
+            None => unstatable(dirent),
+        })
+        .collect();
+    (entries, t)
+}
+
+fn ls_parallel(
+    dirents: &[Dirent],
+    strategy: Strategy,
+    timeout: Option<Duration>,
+    timed_out: &AtomicUsize,
+    timings: &mut Timings,
+    cancel: &AtomicBool,
+) -> Result<Vec<DirEntry>, std::io::Error> {
+    let chunk_size = dirents.len().div_ceil(strategy.threads()).max(1);
+    let fs = provider::get();
+
+    let results = std::thread::scope(|s| {
+        let handles: Vec<_> = dirents
+            .chunks(chunk_size)
+            .map(|chunk| {
+                s.spawn(move || {
+                    provider::set_for_thread(fs);
+                    let mut t = Timings::default();
+                    let mut out = Vec::with_capacity(chunk.len());
+                    for sample in chunk.chunks(SAMPLE_SIZE) {
+                        if cancel.load(Ordering::Relaxed) {
+                            return Err(interrupted_error());
+                        }
+                        out.extend(load_chunk(sample, load_each, timeout, timed_out, &mut t)?);
+                    }
+                    Ok((out, t))
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("metadata worker panicked"))
+            .collect::<Vec<_>>()
+    });
+
+    let mut entries = Vec::with_capacity(dirents.len());
+    for result in results {
+        let (chunk, t) = result?;
+        entries.extend(chunk);
+        timings.merge(&t);
+    }
+    Ok(entries)
+}
+
+pub fn interrupted_error() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Interrupted, "Interrupted by user")
+}
@@ -1,20 +1,76 @@
 use app::Message;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use color_eyre::Result;
+use config::{Column, Overrides};
+use control::ControlSocket;
 use crossterm::event::{self, Event};
+use input::{Input, Scripted};
 use ratatui::Terminal;
-use ratatui::backend::Backend;
-use std::path::PathBuf;
+use ratatui::backend::{Backend, TestBackend};
+use ratatui::buffer::Buffer;
+use std::ffi::OsStr;
+use std::io::IsTerminal;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+use terminal::{ColorMode, HyperlinkMode};
 
+use crate::app::SortField;
+
+mod acl;
+mod agent;
 mod app;
+mod cache;
+mod cleanup;
+mod command;
+mod config;
+mod control;
+mod dashboard;
+mod deepsize;
+mod demo;
+mod diff;
+mod du;
+mod exit;
 mod fs;
+mod glob;
+mod history;
+mod hooks;
+mod input;
+mod jobs;
+mod keymap;
+mod loader;
+mod marks;
+mod metrics;
+mod mounts;
 mod navigation;
+mod pipe;
+mod plugins;
+mod pools;
 mod popup;
+mod prefetch;
+mod projects;
+mod provider;
+mod purge;
+mod report;
+mod rstats;
+mod schema;
+mod script;
+mod search;
+mod subvolume;
+mod terminal;
+mod timeout;
 mod ui;
+mod uring;
+mod userquota;
+mod views;
+mod watch;
 
 use crate::{app::App, ui::ui};
 
 const DEFAULT_DIR: Option<&str> = option_env!("CEPHDU_DEFAULT_DIR");
+/// How often the event loop wakes up to check on background work
+const TICK: Duration = Duration::from_millis(100);
 
 /// Display ceph space and file count (inode) usage in an interactive terminal
 #[derive(Parser)]
@@ -27,20 +83,180 @@ Note the following differences from 'ls -l':
   * The size shown is recursive for directories (may also be true for
     'ls -l' depending on ceph deployment)
 "#)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Path to the directory to display
     path: Option<std::path::PathBuf>,
+
+    /// Directories with more entries than this load sizes only for the visible rows
+    /// [default: 100000, or lazy_threshold from the config file]
+    #[arg(long, value_name = "N")]
+    lazy_threshold: Option<usize>,
+
+    /// Keep paths as navigated instead of resolving symlinks, so ".." goes back
+    /// the way you came, and allow entering symlinks to directories
+    #[arg(short = 'L', long)]
+    logical: bool,
+
+    /// Path to the config file [default: $CEPHDU_CONFIG, or ~/.config/cephdu/config.toml]
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Use the settings in [profiles.NAME] of the config file on top of the
+    /// others [default: $CEPHDU_PROFILE]
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// How to sort the listing at startup [default: sort from the config file]
+    #[arg(long, value_enum, value_name = "FIELD")]
+    sort: Option<SortField>,
+
+    /// Optional columns to show at startup, e.g. "owner,ctime"
+    /// [default: columns from the config file]
+    #[arg(long, value_enum, value_name = "LIST", value_delimiter = ',')]
+    columns: Option<Vec<Column>>,
+
+    /// Draw plain text, one line per entry, without gauges or box drawing,
+    /// for screen readers. The selected entry is described on the top line.
+    #[arg(long)]
+    plain: bool,
+
+    /// Don't write anything to disk: no marks or views are remembered and no
+    /// history is recorded, e.g. on shared accounts
+    #[arg(long)]
+    no_persist: bool,
+
+    /// Draw in the terminal's normal screen, in this many lines below the
+    /// prompt, instead of taking over the whole screen. The last view stays
+    /// in the scrollback.
+    #[arg(long, value_name = "HEIGHT", value_parser = clap::value_parser!(u16).range(5..))]
+    inline: Option<u16>,
+
+    /// Print one frame of the TUI and exit, e.g. for a MOTD banner or a
+    /// ticket. The frame is $COLUMNS x $LINES, or the size of the terminal.
+    #[arg(long, conflicts_with = "inline")]
+    once: bool,
+
+    /// Color the frame printed by --once, or by --keys when stdout isn't a terminal
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorMode::Auto)]
+    color: ColorMode,
+
+    /// Make entry names clickable links (OSC 8) in the terminal
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = HyperlinkMode::Auto)]
+    hyperlinks: HyperlinkMode,
+
+    /// Write a report with the app state to this file if cephdu crashes or is killed
+    #[arg(long, value_name = "FILE")]
+    crash_report: Option<PathBuf>,
+
+    /// Browse a made-up directory tree that looks like it's on CephFS,
+    /// to try cephdu out without a Ceph mount
+    #[arg(long, conflicts_with = "path")]
+    demo: bool,
+
+    /// Type these keys first, e.g. "jjj<enter>s"; see the README for the
+    /// names of special keys. If stdout isn't a terminal, the screen is
+    /// printed once they've been handled, instead of starting the TUI.
+    #[arg(long, value_name = "KEYS")]
+    keys: Option<String>,
+
+    /// Like --keys, but read the keys from FILE
+    #[arg(long, value_name = "FILE", conflicts_with = "keys")]
+    keys_file: Option<PathBuf>,
+
+    /// Take commands from scripts on this Unix socket, like "cd PATH",
+    /// "query selected", or "export json"; see the README
+    #[arg(long, value_name = "PATH")]
+    control_socket: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    Report(report::ReportArgs),
+    ExportMetrics(metrics::ExportMetricsArgs),
+    Scan(history::ScanArgs),
+    History(history::ShowHistoryArgs),
+    Diff(diff::DiffArgs),
+    PurgePreview(purge::PurgePreviewArgs),
+    #[command(disable_help_flag = true)]
+    Du(du::DuArgs),
+    Agent(agent::AgentArgs),
+    Dashboard(dashboard::DashboardArgs),
 }
 
 fn main() -> Result<()> {
     let args = Cli::parse();
 
     let path_was_explicit = args.path.is_some();
+    // The demo has to be installed before anything touches the filesystem
+    let demo_root = args
+        .demo
+        .then(|| demo::install().expect("nothing has touched the filesystem yet"));
+
+    let overrides = Overrides {
+        path: args.config.clone(),
+        profile: args.profile.clone(),
+        lazy_threshold: args.lazy_threshold,
+        sort: args.sort,
+        columns: args.columns.clone(),
+        logical_paths: args.logical,
+        plain: args.plain,
+        no_persist: args.no_persist,
+        demo: args.demo,
+    };
+    let config = overrides.load().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let names = config.cache.names;
+    fs::set_name_cache_limits(names.max_entries, Duration::from_secs(names.ttl_secs));
+    rstats::configure(&config.recursive_stats);
+    uring::configure(config.io_uring);
+    ui::set_exact_sizes(config.exact_sizes);
+    ui::set_units(config.units);
+
+    let path: PathBuf = demo_root
+        .or(args.path)
+        .or_else(|| (config.resume && config.persist).then(last_dir).flatten())
+        .unwrap_or_else(default_dir);
+
+    if let Some(command) = &args.command {
+        // Exit quietly when piped into e.g. `head`, like other command line tools
+        unsafe { libc::signal(libc::SIGPIPE, libc::SIG_DFL) };
+        let result = match command {
+            Command::Report(report_args) => report::run(report_args, &config),
+            Command::ExportMetrics(metrics_args) => metrics::run(metrics_args, &config),
+            Command::Scan(scan_args) => history::run_scan(scan_args, &config),
+            Command::History(history_args) => history::run_show(history_args, &config),
+            Command::Diff(diff_args) => diff::run(diff_args),
+            Command::PurgePreview(purge_args) => purge::run(purge_args, &config),
+            Command::Du(du_args) => du::run(du_args, &config),
+            Command::Agent(agent_args) => agent::run(agent_args, &config),
+            Command::Dashboard(dashboard_args) => dashboard::run(dashboard_args, &config),
+        };
+        if let Err(e) = result {
+            eprintln!("Error: {}", e);
+            std::process::exit(exit::code(&e));
+        }
+        return Ok(());
+    }
 
-    let path: PathBuf = args.path.unwrap_or_else(default_dir);
+    let scripted = args.keys.is_some() || args.keys_file.is_some();
+    if !scripted && !args.once && !std::io::stdout().is_terminal() {
+        // Like ls, print the listing instead of starting the TUI in a pipe
+        unsafe { libc::signal(libc::SIGPIPE, libc::SIG_DFL) };
+        if let Err(e) = pipe::print(&path, &config) {
+            eprintln!("Error listing {:?}: {}", path, e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
-    let mut app = App::new(Some(&path)).unwrap_or_else(|e| {
-        let mut app = App::new(Some(&PathBuf::from("."))).unwrap_or_else(|_| {
+    let mut app = App::new(Some(&path), &config).unwrap_or_else(|e| {
+        let mut app = App::new(Some(&PathBuf::from(".")), &config).unwrap_or_else(|_| {
             eprintln!("Error opening {:?}: {}", path, e);
             std::process::exit(1);
         });
@@ -53,39 +269,147 @@ fn main() -> Result<()> {
         }
         app
     });
+    app.overrides = overrides;
+    if let Some(socket) = &args.control_socket {
+        app.control = Some(ControlSocket::listen(socket).unwrap_or_else(|e| {
+            eprintln!("Error listening on {:?}: {}", socket, e);
+            std::process::exit(1);
+        }));
+    }
+
+    let script = match (&args.keys, &args.keys_file) {
+        (Some(keys), _) => Some(keys.clone()),
+        (None, Some(file)) => Some(std::fs::read_to_string(file).unwrap_or_else(|e| {
+            eprintln!("Error reading {:?}: {}", file, e);
+            std::process::exit(1);
+        })),
+        (None, None) => None,
+    };
+    let script = script.map_or(Ok(vec![]), |s| input::parse_keys(&s));
+    let script = script.unwrap_or_else(|e| {
+        eprintln!("Error in --keys: {}", e);
+        std::process::exit(1);
+    });
+
+    if args.once || (scripted && !std::io::stdout().is_terminal()) {
+        let terminal = run_headless(&mut app, script, headless_size())?;
+        let buffer = terminal.backend().buffer();
+        if args.color.enabled() {
+            print!("{}", terminal::screen_ansi(buffer)?);
+        } else {
+            print!("{}", screen_text(buffer));
+        }
+        return Ok(());
+    }
+
+    if args.hyperlinks.enabled() {
+        app.hyperlinks = Some(fs::hostname().unwrap_or_default());
+    }
 
     color_eyre::install()?;
-    let mut terminal = ratatui::init();
+    let mut terminal = terminal::init(args.inline);
+    terminal::install_handlers(args.crash_report)?;
 
-    run_app(&mut terminal, &mut app)?;
+    run_app(&mut terminal, &mut app, Input::spawn(script, true))?;
+    if args.inline.is_some() {
+        // Leave the prompt below the last line we drew
+        let area = terminal.get_frame().area();
+        terminal.set_cursor_position((0, area.bottom().saturating_sub(1)))?;
+    }
 
     // cleanup terminal
-    ratatui::restore();
+    terminal::restore();
+
+    if config.resume
+        && config.persist
+        && let Err(e) = save_last_dir(&app.cwd)
+    {
+        eprintln!("Error saving the last directory: {}", e);
+    }
 
     Ok(())
 }
 
-fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> Result<()> {
+fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App, input: Input) -> Result<()> {
+    let headless = !input.is_terminal();
+    let mut title_path = PathBuf::new();
+    let mut redraw = true;
     while !app.should_exit {
-        terminal.draw(|f| ui(f, app))?;
+        if app.cwd != title_path && !headless {
+            title_path = app.cwd.clone();
+            terminal::set_title(&title_path);
+        }
+
+        if redraw {
+            let mut links = vec![];
+            let frame = terminal.draw(|f| links = ui(f, app))?;
+            if !links.is_empty() {
+                terminal::write_hyperlinks(frame.buffer, &links)?;
+            }
+            if app.export_screen {
+                app.export_screen = false;
+                app.save_screen(screen_text(frame.buffer));
+            }
+            terminal::record_state(app.state_summary());
+        }
 
-        if let Event::Key(key) = event::read()? {
-            if key.kind == event::KeyEventKind::Release {
-                continue;
+        redraw = match input.recv_timeout(TICK) {
+            Ok(Event::Key(key)) => {
+                if key.kind != event::KeyEventKind::Release {
+                    app.handle_key(key);
+                }
+                true
+            }
+            Ok(_) => true,
+            Err(RecvTimeoutError::Timeout) => false,
+            // Without a terminal, we're done when the key script is
+            Err(RecvTimeoutError::Disconnected) if headless => return Ok(()),
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(color_eyre::eyre::eyre!("Lost the terminal input thread"));
             }
-            app.handle_key(key);
+        };
+        redraw |= app.tick();
+        for notification in app.notifications.drain(..) {
+            if !headless {
+                terminal::notify(&notification);
+            }
+        }
+        if let Some(text) = app.clipboard.take() {
+            if !headless {
+                terminal::copy(&text);
+            }
+            // To show where it was saved
+            redraw = true;
         }
     }
     Ok(())
 }
 
+/// Run `script` without a terminal, on a screen of `width` x `height`, and
+/// draw the screen once more at the end, so that it shows what came in last
+fn run_headless(
+    app: &mut App,
+    script: Vec<Scripted>,
+    (width, height): (u16, u16),
+) -> Result<Terminal<TestBackend>> {
+    let mut terminal = Terminal::new(TestBackend::new(width, height))?;
+    run_app(&mut terminal, app, Input::spawn(script, false))?;
+    terminal.draw(|f| {
+        ui(f, app);
+    })?;
+    Ok(terminal)
+}
+
 /// Returns the cwd if it is a ceph dir.
-/// If not, returns DEFAULT_DIR if set.
+/// If not, returns $CEPHDU_DEFAULT_DIR, or DEFAULT_DIR if set at build time.
 /// If not, the cwd is returned.
-/// Instances of $USER in DEFAULT_DIR are replaced with the current username.
+/// Instances of $USER in the default dir are replaced with the current username.
 fn default_dir() -> PathBuf {
     let cwd = PathBuf::from(".");
-    if DEFAULT_DIR.is_none() {
+    let default_dir = std::env::var("CEPHDU_DEFAULT_DIR")
+        .ok()
+        .or(DEFAULT_DIR.map(String::from));
+    if default_dir.is_none() {
         // short-circuit testing if cwd is ceph
         return cwd;
     }
@@ -94,7 +418,7 @@ fn default_dir() -> PathBuf {
         return cwd;
     }
 
-    DEFAULT_DIR
+    default_dir
         .and_then(|dir| {
             if dir.contains("$USER") {
                 match std::env::var("USER") {
@@ -107,3 +431,86 @@ fn default_dir() -> PathBuf {
         })
         .unwrap_or(PathBuf::from("."))
 }
+
+/// Where the last session ended, for `resume`
+fn last_dir_path() -> Option<PathBuf> {
+    Some(config::state_dir()?.join("last-dir"))
+}
+
+/// The directory the last session ended in, if it's still there
+fn last_dir() -> Option<PathBuf> {
+    let bytes = std::fs::read(last_dir_path()?).ok()?;
+    let dir = PathBuf::from(OsStr::from_bytes(
+        bytes.strip_suffix(b"\n").unwrap_or(&bytes),
+    ));
+    fs::is_dir(&dir).then_some(dir)
+}
+
+fn save_last_dir(dir: &Path) -> std::io::Result<()> {
+    let Some(path) = last_dir_path() else {
+        return Ok(());
+    };
+    let mut contents = dir.as_os_str().as_bytes().to_vec();
+    contents.push(b'\n');
+    config::write_state_file(&path, &contents)
+}
+
+/// The screen size to render at without the TUI: $COLUMNS x $LINES, or the
+/// size of the terminal if stdout is one, or 80x24
+fn headless_size() -> (u16, u16) {
+    let var = |name, default| {
+        std::env::var(name)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default)
+    };
+    let (columns, lines) = if std::io::stdout().is_terminal() {
+        crossterm::terminal::size().unwrap_or((80, 24))
+    } else {
+        (80, 24)
+    };
+    (var("COLUMNS", columns), var("LINES", lines))
+}
+
+/// The text on screen, one line per row, without styles
+fn screen_text(buffer: &Buffer) -> String {
+    let mut text = String::new();
+    for y in 0..buffer.area.height {
+        let line: String = (0..buffer.area.width)
+            .map(|x| buffer[(x, y)].symbol())
+            .collect();
+        text.push_str(line.trim_end());
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    /// Replay `keys` in the demo, like `cephdu --demo --keys KEYS`, and
+    /// return the screen at the end
+    fn demo_screen(keys: &str) -> String {
+        provider::set_for_test(demo::tree());
+        let mut config = Config {
+            persist: false,
+            live_update: false,
+            ..Config::default()
+        };
+        config.history.enabled = false;
+        let mut app = App::new(Some(&PathBuf::from(demo::DEMO_ROOT)), &config).unwrap();
+        let script = input::parse_keys(keys).unwrap();
+        let terminal = run_headless(&mut app, script, (80, 24)).unwrap();
+        screen_text(terminal.backend().buffer())
+    }
+
+    #[test]
+    fn demo_projects() {
+        assert_eq!(
+            demo_screen("j<enter>"),
+            include_str!("../tests/snapshots/demo-projects.txt")
+        );
+    }
+}
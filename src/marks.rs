@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use crate::config::{state_dir, write_state_file};
+
+/// Where marks set with `m` are kept between sessions: one per line, the
+/// letter, a space, and the directory
+fn path() -> Option<PathBuf> {
+    Some(state_dir()?.join("marks"))
+}
+
+/// The marks saved by earlier sessions. A missing or unreadable file just
+/// means there aren't any.
+pub fn load() -> HashMap<char, PathBuf> {
+    let Some(text) = path().and_then(|path| std::fs::read(path).ok()) else {
+        return HashMap::new();
+    };
+    text.split(|&b| b == b'\n')
+        .filter_map(|line| {
+            let (&letter, rest) = line.split_first()?;
+            let dir = rest.strip_prefix(b" ").filter(|dir| !dir.is_empty())?;
+            letter
+                .is_ascii_alphabetic()
+                .then(|| (letter as char, PathBuf::from(OsStr::from_bytes(dir))))
+        })
+        .collect()
+}
+
+/// Save the mark `letter` for `dir`, keeping the other saved marks, which
+/// may be from another session that's still running.
+pub fn save(letter: char, dir: &Path) -> io::Result<()> {
+    let Some(path) = path() else {
+        return Ok(());
+    };
+    if dir.as_os_str().as_bytes().contains(&b'\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "can't save a mark for a path with a newline",
+        ));
+    }
+    let mut marks = load();
+    marks.insert(letter, dir.to_path_buf());
+    let mut letters: Vec<char> = marks.keys().copied().collect();
+    letters.sort();
+
+    let mut text = Vec::new();
+    for letter in letters {
+        text.push(letter as u8);
+        text.push(b' ');
+        text.extend(marks[&letter].as_os_str().as_bytes());
+        text.push(b'\n');
+    }
+    write_state_file(&path, &text)
+}
@@ -0,0 +1,160 @@
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use clap::Args;
+use color_eyre::Result;
+
+use crate::config::Config;
+use crate::exit;
+use crate::fs::user_name;
+use crate::report::{self, WalkArgs};
+use crate::schema::SCHEMA_VERSION;
+
+/// How long a scraper gets to send its request, or to take the response,
+/// before it's dropped so that the next one can be served
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Print directory sizes and file counts as Prometheus metrics, or serve them over HTTP
+#[derive(Args)]
+pub struct ExportMetricsArgs {
+    /// Directory to export
+    pub path: PathBuf,
+
+    /// How many levels of subdirectories to include
+    #[arg(long, visible_alias = "max-depth", default_value_t = 1)]
+    pub depth: usize,
+
+    /// Serve the metrics at http://ADDR/metrics instead of printing them once,
+    /// e.g. 0.0.0.0:9811. They are collected afresh for every scrape.
+    #[arg(long, value_name = "ADDR")]
+    pub listen: Option<String>,
+
+    #[command(flatten)]
+    pub walk: WalkArgs,
+}
+
+pub fn run(args: &ExportMetricsArgs, config: &Config) -> Result<()> {
+    let root = exit::resolve(&args.path)?;
+    let Some(addr) = &args.listen else {
+        print!("{}", collect(&root, args.depth, &args.walk, config)?);
+        return Ok(());
+    };
+
+    let listener = TcpListener::bind(addr)?;
+    eprintln!("Serving metrics for {:?} at http://{}/metrics", root, addr);
+    // Scrapes are rare, so serve them one at a time
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else {
+            continue;
+        };
+        if stream.set_read_timeout(Some(CLIENT_TIMEOUT)).is_err()
+            || stream.set_write_timeout(Some(CLIENT_TIMEOUT)).is_err()
+        {
+            continue;
+        }
+        let mut request_line = String::new();
+        if BufReader::new(&stream)
+            .read_line(&mut request_line)
+            .is_err()
+        {
+            continue;
+        }
+        let target = request_line.split_whitespace().nth(1).unwrap_or("");
+        let (status, body) = if target == "/metrics" || target.starts_with("/metrics?") {
+            match collect(&root, args.depth, &args.walk, config) {
+                Ok(body) => ("200 OK", body),
+                Err(e) => ("500 Internal Server Error", format!("{}\n", e)),
+            }
+        } else {
+            ("404 Not Found", "Metrics are at /metrics\n".to_string())
+        };
+        let _ = write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+    }
+    Ok(())
+}
+
+/// The metrics for `root` and its subdirectories, in the Prometheus text format
+fn collect(
+    root: &std::path::Path,
+    depth: usize,
+    walk: &WalkArgs,
+    config: &Config,
+) -> Result<String> {
+    let start = Instant::now();
+    let mut rows = Vec::new();
+    report::collect(root, depth, walk, config, &mut |row| rows.push(row.clone()))?;
+    let labels: Vec<String> = rows
+        .iter()
+        .map(|row| {
+            let owner = row
+                .uid
+                .map(|uid| user_name(uid).to_string())
+                .unwrap_or_default();
+            format!(
+                "path=\"{}\",depth=\"{}\",owner=\"{}\"",
+                escape(&row.path.to_string_lossy()),
+                row.depth,
+                escape(&owner)
+            )
+        })
+        .collect();
+
+    let mut out = String::new();
+    let mut metric = |name: &str, help: &str, value: &dyn Fn(&report::Row) -> usize| {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} gauge", name);
+        for (row, labels) in rows.iter().zip(&labels) {
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels, value(row));
+        }
+    };
+    metric(
+        "cephdu_directory_bytes",
+        "Recursive size of the directory (ceph.dir.rbytes)",
+        &|row| row.size,
+    );
+    metric(
+        "cephdu_directory_entries",
+        "Recursive count of files and directories inside the directory (ceph.dir.rentries)",
+        &|row| row.rentries,
+    );
+    let _ = writeln!(
+        out,
+        "# HELP cephdu_info Which cephdu, and schema, wrote these metrics"
+    );
+    let _ = writeln!(out, "# TYPE cephdu_info gauge");
+    let _ = writeln!(
+        out,
+        "cephdu_info{{version=\"{}\",schema_version=\"{}\"}} 1",
+        env!("CARGO_PKG_VERSION"),
+        SCHEMA_VERSION
+    );
+    let _ = writeln!(
+        out,
+        "# HELP cephdu_collect_duration_seconds Time taken to collect these metrics"
+    );
+    let _ = writeln!(out, "# TYPE cephdu_collect_duration_seconds gauge");
+    let _ = writeln!(
+        out,
+        "cephdu_collect_duration_seconds {}",
+        start.elapsed().as_secs_f64()
+    );
+    Ok(out)
+}
+
+/// Escape a label value for the Prometheus text format
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,140 @@
+use std::ffi::CStr;
+use std::path::{Path, PathBuf};
+
+use crate::provider;
+
+/// The vxattr with the id of the Ceph cluster a CephFS mount is from
+const CLUSTER_FSID_ATTR: &CStr = c"ceph.cluster_fsid";
+
+/// A line of the mount table
+#[derive(Debug, Clone)]
+pub struct Mount {
+    /// What's mounted, e.g. "mon1,mon2:/" or "admin@<fsid>.hpc_data=/"
+    pub source: String,
+    pub mount_point: PathBuf,
+    pub fs_type: String,
+    pub options: String,
+    /// Which CephFS this is, if it is one
+    pub cephfs: Option<CephFs>,
+}
+
+/// Which of possibly several clusters, and filesystems on them, a CephFS
+/// mount is from
+#[derive(Debug, Clone, Default)]
+pub struct CephFs {
+    pub name: Option<String>,
+    pub fsid: Option<String>,
+}
+
+impl Mount {
+    /// The value of a mount option, e.g. "mds_namespace"
+    pub fn option(&self, name: &str) -> Option<&str> {
+        self.options
+            .split(',')
+            .find_map(|option| option.strip_prefix(name)?.strip_prefix('='))
+    }
+
+    /// Whether `other` is the same filesystem, so that their sizes can be
+    /// added up
+    pub fn same_filesystem(&self, other: &Mount) -> bool {
+        match (&self.cephfs, &other.cephfs) {
+            (Some(a), Some(b)) if a.fsid.is_some() => a.fsid == b.fsid && a.name == b.name,
+            _ => self.fs_type == other.fs_type && self.source == other.source,
+        }
+    }
+
+    fn find_cephfs(&self) -> Option<CephFs> {
+        if self.fs_type != "ceph" && self.fs_type != "fuse.ceph-fuse" {
+            return None;
+        }
+        let mut cephfs = CephFs::default();
+        // The new device syntax: <client>@<fsid>.<fs name>=<path>
+        if let Some((_, rest)) = self.source.split_once('@')
+            && let Some((id, _)) = rest.split_once('=')
+            && let Some((fsid, name)) = id.split_once('.')
+        {
+            cephfs.fsid = Some(fsid.to_string()).filter(|fsid| !fsid.is_empty() && fsid != "?");
+            cephfs.name = Some(name.to_string()).filter(|name| !name.is_empty());
+        }
+        cephfs.name = cephfs.name.or_else(|| {
+            self.option("fs")
+                .or_else(|| self.option("mds_namespace"))
+                .or_else(|| self.option("client_fs"))
+                .map(str::to_string)
+        });
+        cephfs.fsid = cephfs.fsid.or_else(|| {
+            let fsid = provider::get().xattr(&self.mount_point, CLUSTER_FSID_ATTR)?;
+            String::from_utf8(fsid)
+                .ok()
+                .map(|fsid| fsid.trim().to_string())
+        });
+        Some(cephfs)
+    }
+}
+
+/// The mount that `path`, which should be canonical, is on
+pub fn find(path: &Path) -> Option<Mount> {
+    let table = provider::get().mount_table().ok()?;
+    let mut mount = table
+        .lines()
+        .filter_map(parse_line)
+        .filter(|mount| path.starts_with(&mount.mount_point))
+        // The last of several at the same place is the one on top
+        .max_by_key(|mount| mount.mount_point.components().count())?;
+    mount.cephfs = mount.find_cephfs();
+    Some(mount)
+}
+
+/// What's mounted right below `dir`, which should be canonical, by the
+/// mount points' names
+pub fn below(dir: &Path) -> Vec<Mount> {
+    let Ok(table) = provider::get().mount_table() else {
+        return Vec::new();
+    };
+    let mut mounts: Vec<Mount> = Vec::new();
+    for mount in table.lines().filter_map(parse_line) {
+        if mount.mount_point.parent() == Some(dir) {
+            // The last of several at the same place is the one on top
+            mounts.retain(|m| m.mount_point != mount.mount_point);
+            mounts.push(mount);
+        }
+    }
+    for mount in &mut mounts {
+        mount.cephfs = mount.find_cephfs();
+    }
+    mounts
+}
+
+/// Parse a line of /proc/self/mounts, like fstab(5)
+fn parse_line(line: &str) -> Option<Mount> {
+    let mut fields = line.split(' ').map(unescape);
+    Some(Mount {
+        source: fields.next()?,
+        mount_point: PathBuf::from(fields.next()?),
+        fs_type: fields.next()?,
+        options: fields.next()?,
+        cephfs: None,
+    })
+}
+
+/// The kernel writes spaces, tabs, newlines, and backslashes in octal, e.g. "\040"
+fn unescape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(i) = rest.find('\\') {
+        out.push_str(&rest[..i]);
+        let octal = rest.get(i + 1..i + 4);
+        match octal.and_then(|o| u8::from_str_radix(o, 8).ok()) {
+            Some(c) => {
+                out.push(c as char);
+                rest = &rest[i + 4..];
+            }
+            None => {
+                out.push('\\');
+                rest = &rest[i + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
@@ -1,38 +1,68 @@
-use crossterm::event::{KeyCode, KeyEvent};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use crate::app;
-use crate::app::App;
+use crate::app::{App, Message, MessageKind, Prompt, PromptKind};
+use crate::keymap::Action;
+use crate::marks;
 use crate::ui::POPUP_TEXT_HEIGHT;
 
-static PAGE_BY: usize = 10;
-
-pub const HELP: &[[&str; 2]] = &[
-    ["q, Esc", "Quit"],
-    ["Down, j", "Move cursor down"],
-    ["Up, k", "Move cursor up"],
-    ["Page Down", "Jump cursor down"],
-    ["Page Up", "Jump cursor up"],
-    ["Enter", "Open directory"],
-    ["Backspace", "Go to parent directory"],
-    ["n", "Sort by name"],
-    ["s", "Sort by size"],
-    ["c, C", "Sort by file count"],
-    ["U", "Sort by owner"],
-    ["u", "Toggle show owner"],
-    ["T", "Sort by modified time"],
-    ["t", "Toggle show modified time"],
-    ["?, h", "Show this help message"],
-    ["Home, g", "Select first entry"],
-    ["End, G", "Select last entry"],
-    ["r, F5", "Refresh"],
-    ["Ctrl-C", "Interrupt changing the directory"],
-    ["Space", "Go to original directory"],
-];
+/// Jumping to a name ends when nothing has been typed for this long
+const JUMP_TIMEOUT: Duration = Duration::from_millis(1500);
+/// Left and Right pan the rows by this many columns
+const PAN_BY: usize = 8;
 
 impl App {
     pub fn handle_key(&mut self, key: KeyEvent) {
+        if self.popup.as_ref().is_some_and(|p| p.search.is_some()) {
+            self.handle_search_key(key);
+            return;
+        }
+        if self.prompt.is_some() {
+            self.handle_prompt_key(key);
+            return;
+        }
+        if self.jump.is_some() && self.handle_jump_key(key) {
+            return;
+        }
+        if self.confirm_cleanup {
+            let answer = match key.code {
+                KeyCode::Char(c) => c.to_string(),
+                _ => String::new(),
+            };
+            self.answer_deletion(&answer);
+            return;
+        }
+        if let Some(action) = self.pending_mark.take() {
+            self.handle_mark_key(action, key);
+            return;
+        }
+        if self.jobs_panel.is_some() && self.popup.is_some() {
+            self.handle_jobs_key(key);
+            return;
+        }
+        if self.search_panel.is_some() && self.popup.is_some() {
+            self.handle_search_results_key(key);
+            return;
+        }
+        if self.cleanup_panel.is_some() && self.popup.is_some() {
+            self.handle_cleanup_key(key);
+            return;
+        }
+        if self.retention_panel.is_some() && self.popup.is_some() && self.handle_retention_key(key)
+        {
+            return;
+        }
+        // Again in the pool popup, to count every file
+        if self.pool_panel
+            && self.popup.is_some()
+            && self.keymap.action(key) == Some(Action::PoolUsage)
+        {
+            self.pool_usage();
+            return;
+        }
         if self.popup.is_some() {
             match key.code {
                 KeyCode::Esc
@@ -54,12 +84,12 @@ impl App {
                 }
                 KeyCode::PageUp => {
                     if let Some(popup) = &mut self.popup {
-                        popup.scroll_by(-(PAGE_BY as isize));
+                        popup.scroll_by(-(POPUP_TEXT_HEIGHT as isize));
                     }
                 }
                 KeyCode::PageDown => {
                     if let Some(popup) = &mut self.popup {
-                        popup.scroll_by(PAGE_BY as isize);
+                        popup.scroll_by(POPUP_TEXT_HEIGHT as isize);
                     }
                 }
                 KeyCode::Home | KeyCode::Char('g') => {
@@ -69,7 +99,7 @@ impl App {
                 }
                 KeyCode::End | KeyCode::Char('G') => {
                     if let Some(popup) = &mut self.popup {
-                        popup.scroll_to(POPUP_TEXT_HEIGHT);
+                        popup.scroll_to(usize::MAX);
                     }
                 }
                 _ => {}
@@ -77,68 +107,769 @@ impl App {
             return;
         }
 
+        if self.handle_count_key(key) {
+            return;
+        }
+        let count = self.end_count();
+        if let Some(i) = self.plugins.actions.iter().position(|a| a.key.matches(key)) {
+            self.run_plugin_action(i);
+            return;
+        }
+        if let Some(action) = self.keymap.action(key) {
+            self.run_counted(action, count.unwrap_or(1));
+        }
+    }
+
+    /// In the jobs panel, move between the jobs, and cancel them
+    fn handle_jobs_key(&mut self, key: KeyEvent) {
+        let Some(selected) = self.jobs_panel else {
+            return;
+        };
+        let last = self.jobs.len().saturating_sub(1);
         match key.code {
+            KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('J') => {
+                self.popup(None, None, None);
+                return;
+            }
             KeyCode::Enter => {
-                if let Some(selected) = self.dir_listing.selected() {
-                    let entry = self.dir_listing.get(selected);
-                    if entry.kind == app::EntryKind::Dir {
-                        self.cd(&PathBuf::from(&entry.name));
-                    }
+                self.popup(None, None, None);
+                if let Some(job) = self.jobs.get(selected) {
+                    self.go_to_job(job.id);
                 }
+                return;
             }
-            KeyCode::Down | KeyCode::Char('j') => {
-                self.dir_listing.select_next(1);
+            KeyCode::Down | KeyCode::Char('j') => self.jobs_panel = Some((selected + 1).min(last)),
+            KeyCode::Up | KeyCode::Char('k') => self.jobs_panel = Some(selected.saturating_sub(1)),
+            KeyCode::Home | KeyCode::Char('g') => self.jobs_panel = Some(0),
+            KeyCode::End | KeyCode::Char('G') => self.jobs_panel = Some(last),
+            KeyCode::Char('x') | KeyCode::Delete => {
+                if self.jobs.cancel(selected) {
+                    self.message(Some(Message {
+                        text: "Cancelling the job".to_string(),
+                        kind: MessageKind::Info,
+                    }));
+                }
             }
-            KeyCode::Up | KeyCode::Char('k') => {
-                self.dir_listing.select_prev(1);
+            KeyCode::Char('c') => {
+                self.jobs.clear_finished();
+                if self.jobs.is_empty() {
+                    self.popup(None, None, None);
+                    return;
+                }
             }
-            KeyCode::Home | KeyCode::Char('g') => {
-                self.dir_listing.select_first();
+            _ => return,
+        }
+        self.update_jobs_panel();
+    }
+
+    fn handle_search_results_key(&mut self, key: KeyEvent) {
+        let (Some(selected), Some(search)) = (self.search_panel, &self.search) else {
+            return;
+        };
+        let last = search.matches.len().saturating_sub(1);
+        let page = POPUP_TEXT_HEIGHT.saturating_sub(1);
+        self.search_panel = Some(match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => {
+                self.popup(None, None, None);
+                return;
             }
-            KeyCode::End | KeyCode::Char('G') => {
-                self.dir_listing.select_last();
+            KeyCode::Enter => {
+                self.popup(None, None, None);
+                self.go_to_search_result(selected);
+                return;
             }
-            KeyCode::PageUp => {
-                self.dir_listing.select_prev(PAGE_BY);
+            KeyCode::Down | KeyCode::Char('j') => (selected + 1).min(last),
+            KeyCode::Up | KeyCode::Char('k') => selected.saturating_sub(1),
+            KeyCode::PageDown => (selected + page).min(last),
+            KeyCode::PageUp => selected.saturating_sub(page),
+            KeyCode::Home | KeyCode::Char('g') => 0,
+            KeyCode::End | KeyCode::Char('G') => last,
+            _ => return,
+        });
+        self.update_search_panel();
+    }
+
+    /// In the cleanup wizard, move between what it found, mark it, go to it,
+    /// and delete what's marked
+    fn handle_cleanup_key(&mut self, key: KeyEvent) {
+        let (Some(selected), Some(scan)) = (self.cleanup_panel, &mut self.cleanup) else {
+            return;
+        };
+        let last = scan.candidates().len().saturating_sub(1);
+        let page = POPUP_TEXT_HEIGHT.saturating_sub(1);
+        self.cleanup_panel = Some(match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => {
+                self.popup(None, None, None);
+                return;
             }
-            KeyCode::PageDown => {
-                self.dir_listing.select_next(PAGE_BY);
+            KeyCode::Enter => {
+                self.ask_to_delete();
+                return;
+            }
+            KeyCode::Char('x') | KeyCode::Char(' ') => {
+                if let Some(candidate) = scan.candidates_mut().get_mut(selected) {
+                    candidate.marked = !candidate.marked;
+                }
+                (selected + 1).min(last)
+            }
+            KeyCode::Right | KeyCode::Char('l') => {
+                let Some(path) = scan.candidates().get(selected).map(|c| c.path.clone()) else {
+                    return;
+                };
+                self.popup(None, None, None);
+                self.go_to(&path);
+                return;
+            }
+            KeyCode::Down | KeyCode::Char('j') => (selected + 1).min(last),
+            KeyCode::Up | KeyCode::Char('k') => selected.saturating_sub(1),
+            KeyCode::PageDown => (selected + page).min(last),
+            KeyCode::PageUp => selected.saturating_sub(page),
+            KeyCode::Home | KeyCode::Char('g') => 0,
+            KeyCode::End | KeyCode::Char('G') => last,
+            _ if self.keymap.action(key) == Some(Action::Cleanup) => {
+                self.cleanup();
+                return;
+            }
+            _ => return,
+        });
+        self.update_cleanup_panel();
+    }
+
+    /// In the retention simulator, step through the ages, or type one in.
+    /// Returns true if the key was handled.
+    fn handle_retention_key(&mut self, key: KeyEvent) -> bool {
+        let (Some(days), Some(scan)) = (self.retention_panel, &self.retention_scan) else {
+            return false;
+        };
+        let ages = scan.ages();
+        let age = match key.code {
+            KeyCode::Left | KeyCode::Char('-') => ages.iter().rev().find(|&&d| d < days),
+            KeyCode::Right | KeyCode::Char('+') => ages.iter().find(|&&d| d > days),
+            _ if self.keymap.action(key) == Some(Action::Retention) => {
+                self.popup(None, None, None);
+                self.start_retention_prompt();
+                return true;
+            }
+            _ => return false,
+        };
+        if let Some(&age) = age {
+            self.simulate_retention(age);
+        }
+        true
+    }
+
+    /// Digits typed before a motion are a count, like in vim. A digit that's
+    /// bound to something only starts a count if it isn't 0. Returns true if
+    /// the key was part of the count.
+    fn handle_count_key(&mut self, key: KeyEvent) -> bool {
+        if key.modifiers.contains(KeyModifiers::CONTROL) {
+            return false;
+        }
+        match (key.code, self.count) {
+            (KeyCode::Char(c @ '0'..='9'), Some(count)) => {
+                let digit = c as usize - '0' as usize;
+                self.count = Some(count.saturating_mul(10).saturating_add(digit));
+            }
+            (KeyCode::Char(c @ '1'..='9'), None) if self.keymap.action(key).is_none() => {
+                self.count = Some(c as usize - '0' as usize);
+            }
+            (KeyCode::Esc, Some(_)) => {
+                self.end_count();
+                return true;
+            }
+            _ => return false,
+        }
+        if let Some(count) = self.count {
+            self.message(Some(Message {
+                text: format!("Count: {}", count),
+                kind: MessageKind::Info,
+            }));
+        }
+        true
+    }
+
+    /// Forget the pending count, returning it
+    fn end_count(&mut self) -> Option<usize> {
+        let count = self.count.take();
+        if count.is_some()
+            && self
+                .message
+                .as_ref()
+                .is_some_and(|m| m.text.starts_with("Count: "))
+        {
+            self.message(None);
+        }
+        count
+    }
+
+    /// Keys typed into a searchable popup filter it. Enter runs the first
+    /// matching action.
+    fn handle_search_key(&mut self, key: KeyEvent) {
+        let Some(popup) = &mut self.popup else {
+            return;
+        };
+        let Some(search) = &mut popup.search else {
+            return;
+        };
+        match key.code {
+            KeyCode::Esc if !search.is_empty() => search.clear(),
+            KeyCode::Esc => self.popup = None,
+            KeyCode::Enter => {
+                let search = std::mem::take(search);
+                self.popup = None;
+                if !search.is_empty()
+                    && let Some((_, Some(action), _)) = self.help_matches(&search).first()
+                {
+                    self.run(*action);
+                }
+                return;
             }
             KeyCode::Backspace => {
-                self.cd(&"..".into());
+                search.pop();
             }
-            KeyCode::Esc | KeyCode::Char('q') => {
-                self.should_exit = true;
+            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => search.push(c),
+            KeyCode::Down => {
+                popup.scroll_by(1);
+                return;
             }
-            KeyCode::Char('n') => self.sort_or_reverse(app::SortMode::Normal(app::SortField::Name)),
-            KeyCode::Char('s') => {
-                self.sort_or_reverse(app::SortMode::Reversed(app::SortField::Size))
+            KeyCode::Up => {
+                popup.scroll_by(-1);
+                return;
             }
-            KeyCode::Char('c') | KeyCode::Char('C') => {
-                self.sort_or_reverse(app::SortMode::Reversed(app::SortField::Rentries))
+            KeyCode::PageDown => {
+                popup.scroll_by(POPUP_TEXT_HEIGHT as isize);
+                return;
             }
-            KeyCode::Char('U') => {
-                self.sort_or_reverse(app::SortMode::Normal(app::SortField::Owner))
+            KeyCode::PageUp => {
+                popup.scroll_by(-(POPUP_TEXT_HEIGHT as isize));
+                return;
             }
-            KeyCode::Char('T') => {
-                self.sort_or_reverse(app::SortMode::Reversed(app::SortField::CTime))
+            _ => return,
+        }
+        self.update_help();
+    }
+
+    /// While a name is being typed to jump to, letters extend it and move
+    /// the cursor. Returns false if the key ended the jump and should be
+    /// handled as usual.
+    fn handle_jump_key(&mut self, key: KeyEvent) -> bool {
+        let Some((prefix, at)) = &mut self.jump else {
+            return false;
+        };
+        match key.code {
+            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                prefix.push(c);
+                *at = Instant::now();
+                let prefix = prefix.clone();
+                // A single letter moves to the next match, so that typing it
+                // again keeps going
+                let inclusive = prefix.chars().count() > 1;
+                if !self.dir_listing.select_by_prefix(&prefix, inclusive) {
+                    self.message(Some(Message {
+                        text: format!("No name starts with {:?}", prefix),
+                        kind: MessageKind::Warning,
+                    }));
+                    return true;
+                }
             }
-            KeyCode::Char(' ') => {
-                self.cd(&self.original_cwd.clone());
+            KeyCode::Backspace => {
+                prefix.pop();
+                *at = Instant::now();
             }
-            KeyCode::Char('u') => {
-                self.show_owner = !self.show_owner;
+            KeyCode::Esc => {
+                self.end_jump();
+                return true;
             }
-            KeyCode::Char('t') => {
-                self.show_ctime = !self.show_ctime;
+            _ => {
+                self.end_jump();
+                return false;
             }
-            KeyCode::Char('r') | KeyCode::F(5) => {
-                self.cd(&self.cwd.clone());
+        }
+        self.show_jump();
+        true
+    }
+
+    fn start_jump(&mut self) {
+        self.jump = Some((String::new(), Instant::now()));
+        self.show_jump();
+    }
+
+    fn show_jump(&mut self) {
+        if let Some((prefix, _)) = &self.jump {
+            self.message(Some(Message {
+                text: format!("Jump to: {}", prefix),
+                kind: MessageKind::Info,
+            }));
+        }
+    }
+
+    pub fn end_jump(&mut self) {
+        self.jump = None;
+        if self
+            .message
+            .as_ref()
+            .is_some_and(|m| m.text.starts_with("Jump to: "))
+        {
+            self.message(None);
+        }
+    }
+
+    /// End the jump if nothing's been typed for a while. Returns true if it ended.
+    pub fn expire_jump(&mut self) -> bool {
+        match &self.jump {
+            Some((_, at)) if at.elapsed() >= JUMP_TIMEOUT => {
+                self.end_jump();
+                true
             }
-            KeyCode::Char('?') | KeyCode::Char('h') => {
-                self.help();
+            _ => false,
+        }
+    }
+
+    /// Run `action`, repeated `count` times if it's a motion. Pages are as
+    /// tall as the list on screen, less a line to keep for context.
+    pub fn run_counted(&mut self, action: Action, count: usize) {
+        let height = self.dir_listing.viewport_height;
+        let page = height.saturating_sub(1).max(1);
+        let half_page = (height / 2).max(1);
+        match action {
+            Action::Down => self.dir_listing.select_next(count),
+            Action::Up => self.dir_listing.select_prev(count),
+            Action::PageDown => self.dir_listing.select_next(page.saturating_mul(count)),
+            Action::PageUp => self.dir_listing.select_prev(page.saturating_mul(count)),
+            Action::HalfPageDown => self
+                .dir_listing
+                .select_next(half_page.saturating_mul(count)),
+            Action::HalfPageUp => self
+                .dir_listing
+                .select_prev(half_page.saturating_mul(count)),
+            action => self.run(action),
+        }
+    }
+
+    /// Keys typed into a prompt edit its text; Enter runs it and Esc cancels it
+    fn handle_prompt_key(&mut self, key: KeyEvent) {
+        let Some(prompt) = &mut self.prompt else {
+            return;
+        };
+        match key.code {
+            KeyCode::Enter => {
+                if let Some(prompt) = self.prompt.take() {
+                    self.message(None);
+                    self.submit_prompt(prompt);
+                }
+                return;
+            }
+            KeyCode::Esc => {
+                self.prompt = None;
+                self.message(None);
+                return;
+            }
+            KeyCode::Backspace => {
+                prompt.text.pop();
+            }
+            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                prompt.text.push(c)
             }
             _ => {}
         }
+        self.show_prompt();
+    }
+
+    fn start_prompt(&mut self, label: &str, action: Action) {
+        self.prompt = Some(Prompt {
+            label: label.to_string(),
+            text: String::new(),
+            kind: PromptKind::Action(action),
+        });
+        self.show_prompt();
+    }
+
+    fn start_retention_prompt(&mut self) {
+        if !self.dir_listing.is_ceph() {
+            self.message(Some(Message {
+                text: "The ages of whole trees are only known on CephFS".to_string(),
+                kind: MessageKind::Info,
+            }));
+            return;
+        }
+        self.prompt = Some(Prompt {
+            label: "Simulate deleting what's older than, in days".to_string(),
+            text: self.retention_days().to_string(),
+            kind: PromptKind::Action(Action::Retention),
+        });
+        self.show_prompt();
+    }
+
+    pub fn show_prompt(&mut self) {
+        if let Some(prompt) = &self.prompt {
+            self.message(Some(Message {
+                text: format!("{}: {}", prompt.label, prompt.text),
+                kind: MessageKind::Info,
+            }));
+        }
+    }
+
+    fn submit_prompt(&mut self, prompt: Prompt) {
+        let action = match prompt.kind {
+            PromptKind::ConfirmDelete => {
+                self.answer_deletion(&prompt.text);
+                return;
+            }
+            PromptKind::Action(action) => action,
+        };
+        if prompt.text.is_empty() {
+            if action == Action::Search {
+                self.show_search();
+            }
+            return;
+        }
+        if action == Action::Search {
+            self.start_search(&prompt.text);
+        } else if action == Action::Pipe {
+            self.pipe_through(&prompt.text);
+        } else if action == Action::Retention {
+            match prompt.text.trim().parse::<u64>() {
+                Ok(days) if days > 0 => self.simulate_retention(days),
+                _ => self.message(Some(Message {
+                    text: format!("Not a number of days: {}", prompt.text),
+                    kind: MessageKind::Error,
+                })),
+            }
+        } else if action == Action::MarkMatching {
+            let count = self.dir_listing.mark_matching(&prompt.text);
+            self.message(Some(Message {
+                text: format!(
+                    "{} matched {}; {} marked in all",
+                    count,
+                    prompt.text,
+                    self.dir_listing.marked_count()
+                ),
+                kind: if count == 0 {
+                    MessageKind::Warning
+                } else {
+                    MessageKind::Info
+                },
+            }));
+        }
+    }
+
+    /// The key after `m` or `'` names the mark; anything but a letter cancels
+    fn handle_mark_key(&mut self, action: Action, key: KeyEvent) {
+        let letter = match key.code {
+            KeyCode::Char(c) if c.is_ascii_alphabetic() => c,
+            _ => {
+                self.message(None);
+                return;
+            }
+        };
+        let text = if action == Action::SetMark {
+            self.marks.insert(letter, self.cwd.clone());
+            if self.persist
+                && let Err(e) = marks::save(letter, &self.cwd)
+            {
+                self.message(Some(Message {
+                    text: format!(
+                        "Marked {} as '{}, but couldn't save it: {}",
+                        self.cwd.display(),
+                        letter,
+                        e
+                    ),
+                    kind: MessageKind::Warning,
+                }));
+                return;
+            }
+            format!("Marked {} as '{}", self.cwd.display(), letter)
+        } else if let Some(dir) = self.marks.get(&letter).cloned() {
+            self.cd(&dir);
+            return;
+        } else {
+            format!("No mark '{}", letter)
+        };
+        self.message(Some(Message {
+            text,
+            kind: MessageKind::Info,
+        }));
+    }
+
+    fn start_mark(&mut self, action: Action) {
+        self.pending_mark = Some(action);
+        let text = if action == Action::SetMark {
+            "Mark this directory as: (a letter)".to_string()
+        } else {
+            let mut marks: Vec<char> = self.marks.keys().copied().collect();
+            marks.sort();
+            match marks.len() {
+                0 => "Go to mark: (no marks set yet, set one with m)".to_string(),
+                _ => format!("Go to mark: {}", marks.into_iter().collect::<String>()),
+            }
+        };
+        self.message(Some(Message {
+            text,
+            kind: MessageKind::Info,
+        }));
+    }
+
+    pub fn run(&mut self, action: Action) {
+        match action {
+            Action::Open => {
+                if let Some(selected) = self.dir_listing.selected() {
+                    let entry = self.dir_listing.get(selected);
+                    if self.can_enter(entry) {
+                        let name = PathBuf::from(entry.name.as_str());
+                        self.cd(&name);
+                    }
+                }
+            }
+            Action::Down
+            | Action::Up
+            | Action::PageDown
+            | Action::PageUp
+            | Action::HalfPageDown
+            | Action::HalfPageUp => self.run_counted(action, 1),
+            Action::First => self.dir_listing.select_first(),
+            Action::Last => self.dir_listing.select_last(),
+            Action::Parent => self.cd(Path::new("..")),
+            Action::DrillToBiggest => self.drill_to_biggest(),
+            Action::Peek => self.peek(),
+            Action::PanLeft => self.pan = self.pan.saturating_sub(PAN_BY),
+            Action::PanRight => self.pan = (self.pan + PAN_BY).min(self.pan_limit),
+            Action::Quit => self.should_exit = true,
+            Action::SortName => self.sort_or_reverse(app::SortField::Name.initial_mode()),
+            Action::SortSize => self.sort_or_reverse(app::SortField::Size.initial_mode()),
+            Action::SortCount => self.sort_or_reverse(app::SortField::Rentries.initial_mode()),
+            Action::SortOwner => self.sort_or_reverse(app::SortField::Owner.initial_mode()),
+            Action::SortGroup => self.sort_or_reverse(app::SortField::Group.initial_mode()),
+            Action::SortCtime => self.sort_or_reverse(app::SortField::CTime.initial_mode()),
+            Action::SortAverage => self.sort_or_reverse(app::SortField::Average.initial_mode()),
+            Action::OriginalDir => self.cd(&self.original_cwd.clone()),
+            Action::ToggleOwner => self.show_owner = !self.show_owner,
+            Action::ToggleGroup => self.show_group = !self.show_group,
+            Action::TogglePermissions => self.show_permissions = !self.show_permissions,
+            Action::ToggleNumericIds => {
+                self.numeric_ids = !self.numeric_ids;
+                // Names and ids are different widths
+                self.dir_listing.owner_widths = (0, 0);
+            }
+            Action::ToggleCtime => self.show_ctime = !self.show_ctime,
+            Action::ToggleAverage => self.show_average = !self.show_average,
+            Action::ToggleCost => self.toggle_cost(),
+            Action::ProjectUsage => self.project_usage(),
+            Action::PoolUsage => self.pool_usage(),
+            Action::Jobs => self.show_jobs(),
+            Action::GoToFinished => self.go_to_finished(),
+            Action::ExportScreen => self.export_screen = true,
+            Action::Pipe => self.start_prompt("Pipe through", action),
+            Action::Retention => self.start_retention_prompt(),
+            Action::Cleanup => self.cleanup(),
+            Action::Search => {
+                self.start_prompt("Find under here (Enter for the last results)", action)
+            }
+            Action::ToggleDebug => self.show_debug = !self.show_debug,
+            Action::LoadMore => self.load_more(false),
+            Action::LoadAll => self.load_more(true),
+            Action::DeepSize => self.compute_deep_size(),
+            Action::Details => self.details(),
+            Action::ToggleSizeSource => self.toggle_size_source(),
+            Action::ToggleDotfiles => self.toggle_dotfiles(),
+            Action::ToggleExactSizes => self.toggle_exact_sizes(),
+            Action::SaveView => self.save_view(),
+            Action::FlushCaches => self.flush_caches(),
+            Action::ReloadConfig => self.reload_config(),
+            Action::Refresh => self.refresh(),
+            Action::Help => self.help(),
+            Action::ToggleHints => self.show_hints = !self.show_hints,
+            Action::JumpToName => self.start_jump(),
+            Action::SetMark | Action::GoToMark => self.start_mark(action),
+            Action::ToggleMarked => {
+                self.dir_listing.toggle_marked();
+                self.dir_listing.select_next(1);
+            }
+            Action::MarkMatching => self.start_prompt("Mark matching", action),
+            Action::MarkAll => self.dir_listing.mark_all(),
+            Action::InvertMarked => self.dir_listing.invert_marked(),
+            Action::UnmarkAll => self.dir_listing.unmark_all(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::provider;
+
+    /// An app in /data, which has two directories and two files, without
+    /// anything touching the real filesystem
+    fn app() -> App {
+        provider::set_for_test(provider::sample_tree());
+        app_in_data()
+    }
+
+    /// An app in /data, on whatever filesystem the test installed
+    fn app_in_data() -> App {
+        App::new(Some(&PathBuf::from("/data")), &config()).unwrap()
+    }
+
+    fn config() -> Config {
+        let mut config = Config {
+            persist: false,
+            live_update: false,
+            ..Config::default()
+        };
+        config.history.enabled = false;
+        config
+    }
+
+    /// Tick until `done`, as the event loop would, for the background jobs
+    fn tick_until(app: &mut App, done: impl Fn(&App) -> bool) {
+        for _ in 0..500 {
+            if done(app) {
+                return;
+            }
+            app.tick();
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        panic!("timed out");
+    }
+
+    /// Open the cleanup wizard, which offers /data/big and then /data/small
+    /// as old, and mark /data/big
+    fn mark_big_for_cleanup(app: &mut App) {
+        press(app, "w");
+        tick_until(app, |app| {
+            app.cleanup.as_ref().is_some_and(|s| s.result.is_some())
+        });
+        let candidates = app.cleanup.as_ref().unwrap().candidates();
+        assert_eq!(candidates[0].path, Path::new("/data/big"));
+        press(app, "x");
+    }
+
+    fn deleted(app: &mut App) -> bool {
+        tick_until(app, |app| {
+            app.deletion.as_ref().is_none_or(|d| d.result.is_some())
+        });
+        provider::get().lstat(Path::new("/data/big")).is_err()
+    }
+
+    fn press(app: &mut App, keys: &str) {
+        for c in keys.chars() {
+            let code = match c {
+                '\n' => KeyCode::Enter,
+                '\x08' => KeyCode::Backspace,
+                c => KeyCode::Char(c),
+            };
+            app.handle_key(KeyEvent::new(code, KeyModifiers::NONE));
+        }
+    }
+
+    fn selected(app: &App) -> String {
+        let listing = &app.dir_listing;
+        let index = listing.selected().unwrap();
+        listing.iter_entries().nth(index).unwrap().name.to_string()
+    }
+
+    #[test]
+    fn moves_through_the_listing() {
+        let mut app = app();
+        assert_eq!(selected(&app), "..");
+        press(&mut app, "jj");
+        assert_eq!(selected(&app), "big/");
+        press(&mut app, "G");
+        assert_eq!(selected(&app), "small/");
+        press(&mut app, "k");
+        assert_eq!(selected(&app), "mid.bin");
+        press(&mut app, "g3j");
+        assert_eq!(selected(&app), "mid.bin");
+    }
+
+    #[test]
+    fn enters_directories_and_comes_back_to_them() {
+        let mut app = app();
+        press(&mut app, "jj\n");
+        assert_eq!(app.cwd, Path::new("/data/big"));
+        assert_eq!(selected(&app), "..");
+        press(&mut app, "\x08");
+        assert_eq!(app.cwd, Path::new("/data"));
+        assert_eq!(selected(&app), "big/");
+    }
+
+    #[test]
+    fn sorts_by_name() {
+        let mut app = app();
+        press(&mut app, "jn");
+        let names: Vec<_> = app
+            .dir_listing
+            .iter_entries()
+            .map(|e| e.name.as_str())
+            .collect();
+        assert_eq!(names, ["..", "big/", "mid.bin", "notes.txt", "small/"]);
+        // The cursor stays on the same row
+        assert_eq!(selected(&app), "big/");
+        press(&mut app, "n");
+        let names: Vec<_> = app
+            .dir_listing
+            .iter_entries()
+            .map(|e| e.name.as_str())
+            .collect();
+        assert_eq!(names, ["..", "small/", "notes.txt", "mid.bin", "big/"]);
+    }
+
+    #[test]
+    fn tries_prefetching_a_directory_that_cant_be_listed_once() {
+        let mut fs = provider::sample_tree();
+        fs.set_unreadable(Path::new("/data/big"));
+        let fs = provider::set_for_test(fs);
+        let mut app = app_in_data();
+        press(&mut app, "jj");
+        assert_eq!(selected(&app), "big/");
+        app.tick();
+        std::thread::sleep(crate::prefetch::PREFETCH_DELAY);
+        for _ in 0..20 {
+            app.tick();
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(fs.denied(), 1);
+        assert_eq!(app.prefetcher.in_flight(), None);
+    }
+
+    #[test]
+    fn deletes_with_y_below_the_threshold() {
+        let mut app = app();
+        mark_big_for_cleanup(&mut app);
+        press(&mut app, "\n");
+        assert!(app.prompt.is_none());
+        assert!(app.confirm_cleanup);
+        press(&mut app, "n");
+        assert!(!deleted(&mut app));
+        press(&mut app, "\ny");
+        assert!(deleted(&mut app));
+        assert!(provider::get().lstat(Path::new("/data/small")).is_ok());
+    }
+
+    #[test]
+    fn takes_typing_the_name_above_the_threshold() {
+        provider::set_for_test(provider::sample_tree());
+        let mut config = config();
+        config.cleanup.typed_confirm_bytes = 1000;
+        let mut app = App::new(Some(&PathBuf::from("/data")), &config).unwrap();
+        mark_big_for_cleanup(&mut app);
+        press(&mut app, "\n");
+        assert!(!app.confirm_cleanup);
+        assert_eq!(
+            app.prompt.as_ref().map(|p| p.kind),
+            Some(PromptKind::ConfirmDelete)
+        );
+        // y isn't enough, and neither is the wrong name
+        press(&mut app, "y\n");
+        assert!(app.prompt.is_none());
+        assert!(!deleted(&mut app));
+        press(&mut app, "\nsmall\n");
+        assert!(!deleted(&mut app));
+        assert_eq!(
+            app.message.as_ref().map(|m| m.text.as_str()),
+            Some("Nothing was deleted")
+        );
+        press(&mut app, "\nbig\n");
+        assert!(deleted(&mut app));
     }
 }
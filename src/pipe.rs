@@ -0,0 +1,79 @@
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+
+use chrono::{Datelike, Local};
+
+use crate::app::DirListing;
+use crate::config::{Column, Config};
+use crate::loader::Loader;
+use crate::ui::{cost_str, ctime_str, rentries_str, size_str};
+
+/// Print the listing of `path` as plain lines, sorted and with the columns
+/// from the config, for when stdout is a pipe, like `ls` does:
+/// `cephdu /path | head` shows the biggest entries.
+pub fn print(path: &Path, config: &Config) -> io::Result<()> {
+    let mut options = Loader::new(config).options();
+    // All of it, since nobody scrolls through a pipe
+    options.lazy_threshold = usize::MAX;
+    options.page_size = usize::MAX;
+    let mut listing = DirListing::from(
+        path,
+        config.sort.initial_mode(),
+        options,
+        &AtomicBool::new(false),
+    )?;
+    listing.finish_sort();
+
+    let column = |column| config.columns.contains(&column);
+    let show_cost = column(Column::Cost) && config.cost.is_set();
+    if show_cost && config.cost.by_pool() {
+        listing.check_pools(0, listing.len());
+    }
+    let entries: Vec<_> = listing.iter_entries().filter(|e| e.name != "..").collect();
+    let users: Vec<String> = entries
+        .iter()
+        .map(|e| e.user(false).as_deref().unwrap_or("?").to_string())
+        .collect();
+    let groups: Vec<String> = entries
+        .iter()
+        .map(|e| e.group(false).as_deref().unwrap_or("?").to_string())
+        .collect();
+    let width = |names: &[String]| names.iter().map(|n| n.chars().count()).max().unwrap_or(0);
+    let (user_width, group_width) = (width(&users), width(&groups));
+    let current_year = Local::now().year() as isize;
+
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    for (i, entry) in entries.iter().enumerate() {
+        let mut line = format!(
+            "{:>8}  {:>7}",
+            size_str(entry.size, true),
+            rentries_str(entry.rentries, true)
+        );
+        if column(Column::Average) {
+            let average = entry.average_size().map(|a| size_str(Some(a), true));
+            line.push_str(&format!("  {:>8}", average.unwrap_or_default()));
+        }
+        if show_cost {
+            let cost = listing.cost(entry, &config.cost);
+            let cost = cost.map(|c| cost_str(c, &config.cost.currency) + "/mo");
+            line.push_str(&format!("  {:>10}", cost.unwrap_or_default()));
+        }
+        if column(Column::Owner) {
+            line.push_str(&format!("  {:uw$}", users[i], uw = user_width));
+        }
+        if column(Column::Group) {
+            line.push_str(&format!("  {:gw$}", groups[i], gw = group_width));
+        }
+        if column(Column::Permissions) {
+            let mode = entry.mode_str().unwrap_or_default();
+            line.push_str(&format!("  {:10}", mode));
+        }
+        if column(Column::Ctime) {
+            let ctime = entry.ctime.map(|c| ctime_str(c, current_year));
+            line.push_str(&format!("  {:12}", ctime.unwrap_or_default()));
+        }
+        writeln!(out, "{}  {}", line, entry.name)?;
+    }
+    out.flush()
+}
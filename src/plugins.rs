@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::Duration;
+
+use rhai::{Array, Dynamic};
+
+use crate::command;
+use crate::config::{PluginAction, PluginColumn};
+use crate::jobs::Progress;
+use crate::script;
+
+/// How long an action can run before it's given up on
+const ACTION_TIMEOUT: Duration = Duration::from_secs(300);
+/// How long a column's command can take, for each directory
+const COLUMN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What a plugin's command printed, or why it failed
+type Outcome = Receiver<Result<String, String>>;
+
+/// An action or pipe in flight
+struct Running {
+    job: usize,
+    /// The action's name, or the command line of the pipe
+    name: String,
+    /// Whether its output always goes in a popup, rather than only when
+    /// there's more than a line of it
+    popup: bool,
+    outcome: Outcome,
+}
+
+/// How an action or pipe went
+pub struct Finished {
+    pub job: usize,
+    pub name: String,
+    pub popup: bool,
+    pub result: Result<String, String>,
+}
+
+/// Runs the commands of plugins in the background: the actions as jobs,
+/// and the columns for each directory that's listed
+pub struct PluginRunner {
+    actions: Vec<Running>,
+    columns: Vec<(PathBuf, usize, Outcome)>,
+    /// What the columns said about the entries of `values_dir`, by column,
+    /// and then by name
+    values_dir: PathBuf,
+    values: Vec<HashMap<String, String>>,
+}
+
+impl PluginRunner {
+    pub fn new() -> Self {
+        PluginRunner {
+            actions: Vec::new(),
+            columns: Vec::new(),
+            values_dir: PathBuf::new(),
+            values: Vec::new(),
+        }
+    }
+
+    /// Run `action` on the entry at `path`, in `dir`, as the job `job`. A
+    /// script's action is called with `selected`, and sees `listing`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn run_action(
+        &mut self,
+        action: &PluginAction,
+        path: &Path,
+        dir: &Path,
+        listing: Array,
+        selected: Dynamic,
+        job: usize,
+        progress: Arc<Progress>,
+    ) {
+        let (tx, rx) = mpsc::channel();
+        if let Some(f) = action.script.clone() {
+            let dir = dir.to_path_buf();
+            std::thread::spawn(move || {
+                let args = vec![selected.clone()];
+                let (mut printed, result) =
+                    script::call(&f, args, &dir, listing, selected, ACTION_TIMEOUT, progress);
+                let result = result.map(|value| {
+                    if !value.is_unit() {
+                        printed.push_str(&value.to_string());
+                    }
+                    printed.trim_end().to_string()
+                });
+                let _ = tx.send(result);
+            });
+            self.actions.push(Running {
+                job,
+                name: action.name.clone(),
+                popup: false,
+                outcome: rx,
+            });
+            return;
+        }
+        let cmd = substitute(&action.command, Some(path), dir);
+        std::thread::spawn(move || {
+            let result = cmd.and_then(|cmd| {
+                let output = command::output_unless(cmd, ACTION_TIMEOUT, progress.cancelled())?;
+                Ok(String::from_utf8_lossy(&output.stdout)
+                    .trim_end()
+                    .to_string())
+            });
+            let _ = tx.send(result);
+        });
+        self.actions.push(Running {
+            job,
+            name: action.name.clone(),
+            popup: false,
+            outcome: rx,
+        });
+    }
+
+    /// Feed `input` to the shell command `command_line`, run in `dir`, as
+    /// the job `job`, like an action named after the command
+    pub fn run_pipe(
+        &mut self,
+        command_line: &str,
+        input: Vec<u8>,
+        dir: &Path,
+        job: usize,
+        progress: Arc<Progress>,
+    ) {
+        let (tx, rx) = mpsc::channel();
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command_line);
+        // Not in the demo's made-up tree
+        if dir.is_dir() {
+            cmd.current_dir(dir);
+        }
+        std::thread::spawn(move || {
+            let result =
+                command::output_with_input(cmd, input, ACTION_TIMEOUT, progress.cancelled()).map(
+                    |output| {
+                        String::from_utf8_lossy(&output.stdout)
+                            .trim_end()
+                            .to_string()
+                    },
+                );
+            let _ = tx.send(result);
+        });
+        self.actions.push(Running {
+            job,
+            name: command_line.to_string(),
+            popup: true,
+            outcome: rx,
+        });
+    }
+
+    /// Collect the actions and pipes that have finished, with what they
+    /// printed, or why they failed
+    pub fn poll_actions(&mut self) -> Vec<Finished> {
+        let mut done = Vec::new();
+        self.actions
+            .retain(|running| match running.outcome.try_recv() {
+                Err(TryRecvError::Empty) => true,
+                Ok(result) => {
+                    done.push(Finished {
+                        job: running.job,
+                        name: running.name.clone(),
+                        popup: running.popup,
+                        result,
+                    });
+                    false
+                }
+                Err(TryRecvError::Disconnected) => false,
+            });
+        done
+    }
+
+    /// Fill in the `columns` for `dir`, whose entries are `listing` for the
+    /// columns of scripts. What they said about another directory is
+    /// forgotten, but a refresh keeps the old values until the new ones are in.
+    pub fn list(&mut self, columns: &[PluginColumn], dir: &Path, listing: &Array) {
+        if self.values_dir != dir {
+            self.values_dir = dir.to_path_buf();
+            self.values = vec![HashMap::new(); columns.len()];
+        }
+        for (i, column) in columns.iter().enumerate() {
+            if self.columns.iter().any(|(d, c, _)| d == dir && *c == i) {
+                continue;
+            }
+            let (tx, rx) = mpsc::channel();
+            if let Some(f) = column.script.clone() {
+                let (script_dir, listing) = (dir.to_path_buf(), listing.clone());
+                std::thread::spawn(move || {
+                    let _ = tx.send(script::column(&f, &script_dir, listing, COLUMN_TIMEOUT));
+                });
+                self.columns.push((dir.to_path_buf(), i, rx));
+                continue;
+            }
+            let cmd = substitute(&column.command, None, dir);
+            std::thread::spawn(move || {
+                let result = cmd.and_then(|cmd| {
+                    let output = command::output(cmd, COLUMN_TIMEOUT)?;
+                    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+                });
+                let _ = tx.send(result);
+            });
+            self.columns.push((dir.to_path_buf(), i, rx));
+        }
+    }
+
+    /// Take in the values of the columns that are done. Returns true if
+    /// any were for the directory we're in, and the errors of those that failed.
+    pub fn poll_columns(&mut self, columns: &[PluginColumn]) -> (bool, Vec<String>) {
+        let mut changed = false;
+        let mut errors = Vec::new();
+        let mut done = Vec::new();
+        self.columns.retain(|(dir, i, rx)| match rx.try_recv() {
+            Err(TryRecvError::Empty) => true,
+            Ok(result) => {
+                done.push((dir.clone(), *i, result));
+                false
+            }
+            Err(TryRecvError::Disconnected) => false,
+        });
+        for (dir, i, result) in done {
+            if dir != self.values_dir || i >= self.values.len() {
+                continue;
+            }
+            match result {
+                Ok(text) => {
+                    self.values[i] = text
+                        .lines()
+                        .filter_map(|line| line.split_once('\t'))
+                        .map(|(name, value)| {
+                            let name = name.trim_end_matches('/');
+                            (name.to_string(), value.trim().to_string())
+                        })
+                        .collect();
+                    changed = true;
+                }
+                Err(e) => {
+                    let name = columns.get(i).map_or("", |c| c.name.as_str());
+                    errors.push(format!("The {} column: {}", name, e));
+                }
+            }
+        }
+        (changed, errors)
+    }
+
+    /// What column `column` says about the entry `name` of `dir`
+    pub fn value(&self, column: usize, dir: &Path, name: &str) -> Option<&str> {
+        if dir != self.values_dir {
+            return None;
+        }
+        let name = name.trim_end_matches('/');
+        self.values.get(column)?.get(name).map(String::as_str)
+    }
+}
+
+/// The command `command`, with "{path}", "{name}", and "{dir}" in its
+/// arguments replaced, and the same in $CEPHDU_PATH, $CEPHDU_NAME, and
+/// $CEPHDU_DIR, so that scripts don't have to parse them out
+fn substitute(command: &[String], path: Option<&Path>, dir: &Path) -> Result<Command, String> {
+    let (program, args) = command.split_first().ok_or("no command")?;
+    let dir_str = dir.to_string_lossy();
+    let path_str = path.map(|path| path.to_string_lossy()).unwrap_or_default();
+    let name = path
+        .and_then(Path::file_name)
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let mut cmd = Command::new(program);
+    let values = [("path", &*path_str), ("name", &*name), ("dir", &*dir_str)];
+    cmd.args(args.iter().map(|arg| command::fill(arg, &values)))
+        .env("CEPHDU_DIR", dir);
+    if let Some(path) = path {
+        cmd.env("CEPHDU_PATH", path).env("CEPHDU_NAME", &*name);
+    }
+    Ok(cmd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(cmd: &Command) -> Vec<String> {
+        cmd.get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn substitutes_placeholders_only_once() {
+        let command = ["tool", "--in={dir}", "{path}", "{name}", "{other}"].map(String::from);
+        let cmd = substitute(
+            &command,
+            Some(Path::new("/data/{dir}/{name}")),
+            Path::new("/data/{dir}"),
+        )
+        .unwrap();
+        assert_eq!(
+            args(&cmd),
+            [
+                "--in=/data/{dir}",
+                "/data/{dir}/{name}",
+                "{name}",
+                "{other}"
+            ]
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+use crate::app::{DirEntry, EntryKind};
+use crate::fs::{get_effective_pool, get_pool};
+use crate::glob;
+use crate::jobs::Progress;
+use crate::loader::interrupted_error;
+use crate::provider;
+
+/// What's on the filesystem's default data pool is called, since CephFS
+/// doesn't name it on directories without a layout of their own
+pub const DEFAULT_POOL: &str = "(default pool)";
+
+/// How much of a tree is on one data pool
+#[derive(Debug, Clone)]
+pub struct PoolUsage {
+    pub pool: String,
+    pub bytes: usize,
+    /// Files and directories, like rentries
+    pub files: usize,
+}
+
+/// Add up `entries` of `dir` by data pool, biggest first. Files are on the
+/// pool of their layout, and directories count as a whole on the pool that
+/// new files in them go to, which is `dir_pool` unless they have a layout
+/// of their own, so what was written before a layout changed is off.
+pub fn usage<'a>(
+    dir: &Path,
+    dir_pool: Option<&str>,
+    entries: impl IntoIterator<Item = &'a DirEntry>,
+) -> Vec<PoolUsage> {
+    let mut usage = Usage::default();
+    for entry in entries {
+        let is_dir = entry.kind == EntryKind::Dir;
+        let pool = get_pool(&dir.join(entry.name.as_str()), is_dir);
+        let pool = pool.as_deref().or(dir_pool).unwrap_or(DEFAULT_POOL);
+        let files = if is_dir {
+            entry.rentries.unwrap_or(0) + 1
+        } else {
+            1
+        };
+        usage.add(pool, entry.size.unwrap_or(0), files);
+    }
+    usage.sorted()
+}
+
+#[derive(Default)]
+struct Usage(HashMap<String, (usize, usize)>);
+
+impl Usage {
+    fn add(&mut self, pool: &str, bytes: usize, files: usize) {
+        let (b, f) = self.0.entry(pool.to_string()).or_default();
+        *b += bytes;
+        *f += files;
+    }
+
+    fn sorted(self) -> Vec<PoolUsage> {
+        let mut usage: Vec<PoolUsage> = self
+            .0
+            .into_iter()
+            .map(|(pool, (bytes, files))| PoolUsage { pool, bytes, files })
+            .collect();
+        usage.sort_by_key(|u| (Reverse(u.bytes), u.pool.clone()));
+        usage
+    }
+}
+
+/// Adding up a whole tree by data pool in the background, a file at a
+/// time, which is exact, unlike `usage()`, but takes a getxattr(2) per file
+pub struct PoolScan {
+    pub root: PathBuf,
+    pub job: usize,
+    /// Once it's done: the pools, biggest first, or why it failed
+    pub result: Option<Result<Vec<PoolUsage>, String>>,
+    rx: Receiver<std::io::Result<Vec<PoolUsage>>>,
+}
+
+impl PoolScan {
+    /// Walk `root` as the job `job`, leaving out the names that match `exclude`
+    pub fn start(root: &Path, exclude: &[String], job: usize, progress: Arc<Progress>) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let thread_root = root.to_path_buf();
+        let exclude = exclude.to_vec();
+        provider::spawn(move || {
+            let _ = tx.send(walk(&thread_root, &exclude, &progress));
+        });
+        PoolScan {
+            root: root.to_path_buf(),
+            job,
+            result: None,
+            rx,
+        }
+    }
+
+    /// Take the result if the walk has finished. Returns true if it just did.
+    pub fn poll(&mut self) -> bool {
+        if self.result.is_some() {
+            return false;
+        }
+        self.result = match self.rx.try_recv() {
+            Err(TryRecvError::Empty) => return false,
+            Ok(result) => Some(result.map_err(|e| e.to_string())),
+            Err(TryRecvError::Disconnected) => Some(Err("the walk died".to_string())),
+        };
+        true
+    }
+}
+
+/// Add up the files under `root` by the pool of their layout, staying on one
+/// filesystem and counting hard-linked files, and directories that are also
+/// bind-mounted under it, once. Directories count as files on their
+/// parent's pool, without any bytes of their own.
+fn walk(root: &Path, exclude: &[String], progress: &Progress) -> std::io::Result<Vec<PoolUsage>> {
+    let fs = provider::get();
+    let root_stat = fs.lstat(root)?;
+    let dev = root_stat.dev;
+    let mut usage = Usage::default();
+    let mut seen = HashSet::from([(root_stat.dev, root_stat.ino)]);
+    let mut stack = vec![(root.to_path_buf(), get_effective_pool(root))];
+    progress.add_total(1);
+
+    while let Some((dir, parent_pool)) = stack.pop() {
+        if progress.is_cancelled() {
+            return Err(interrupted_error());
+        }
+        progress.add_done(1);
+        let pool = get_pool(&dir, true).or(parent_pool);
+        let Ok(read_dir) = fs.read_dir(&dir) else {
+            continue;
+        };
+        for entry in read_dir.flatten() {
+            if entry
+                .path
+                .file_name()
+                .is_some_and(|name| glob::matches_any(exclude, &name.to_string_lossy()))
+            {
+                continue;
+            }
+            let Ok(stat) = fs.lstat(&entry.path) else {
+                continue;
+            };
+            progress.add_items(1);
+            let dir_pool = pool.as_deref().unwrap_or(DEFAULT_POOL);
+            match stat.kind {
+                EntryKind::Dir => {
+                    usage.add(dir_pool, 0, 1);
+                    if stat.dev == dev && seen.insert((stat.dev, stat.ino)) {
+                        progress.add_total(1);
+                        stack.push((entry.path, pool.clone()));
+                    }
+                }
+                _ if stat.nlink > 1 && !seen.insert((stat.dev, stat.ino)) => {}
+                EntryKind::File => {
+                    let file_pool = get_pool(&entry.path, false);
+                    usage.add(file_pool.as_deref().unwrap_or(dir_pool), stat.size, 1);
+                }
+                EntryKind::Symlink => usage.add(dir_pool, stat.size, 1),
+            }
+        }
+    }
+    Ok(usage.sorted())
+}
@@ -9,15 +9,19 @@ pub struct Popup {
     pub text: String,
     pub text_width: usize,
     pub text_height: usize,
+    /// How many lines of text fit, fewer than POPUP_TEXT_HEIGHT on a small terminal
+    visible_height: usize,
     scroll: usize,
     pub scrollbar_state: ScrollbarState,
+    /// What's been typed to filter the popup, if it's searchable
+    pub search: Option<String>,
 }
 
 impl Popup {
     pub fn new(title: &str, bottom_title: &str, text: &str) -> Self {
         let text_width = text
             .lines()
-            .map(|line| line.len())
+            .map(|line| line.chars().count())
             .max()
             .unwrap_or(0)
             .max(title.len())
@@ -29,10 +33,29 @@ impl Popup {
             text: text.to_string(),
             text_width,
             text_height,
+            visible_height: POPUP_TEXT_HEIGHT,
             scroll: 0,
             scrollbar_state: ScrollbarState::default()
                 .position(0)
                 .content_length(text_height.saturating_sub(POPUP_TEXT_HEIGHT)),
+            search: None,
+        }
+    }
+
+    /// Replace the text, keeping the size of the popup
+    pub fn set_text(&mut self, text: &str) {
+        self.text = text.to_string();
+        self.text_height = text.lines().count();
+        self.scrollbar_state = self.scrollbar_state.content_length(self.max_scroll());
+        self.scroll_to(0);
+    }
+
+    /// Fit in `height` lines of text, after the terminal is resized
+    pub fn set_visible_height(&mut self, height: usize) {
+        if height != self.visible_height {
+            self.visible_height = height;
+            self.scrollbar_state = self.scrollbar_state.content_length(self.max_scroll());
+            self.scroll_to(self.scroll);
         }
     }
     pub fn scroll(&self) -> usize {
@@ -50,6 +73,50 @@ impl Popup {
     }
 
     fn max_scroll(&self) -> usize {
-        self.text_height.saturating_sub(POPUP_TEXT_HEIGHT)
+        self.text_height.saturating_sub(self.visible_height)
+    }
+}
+
+/// `text` as a block for a popup, whose lines are centered: tab-separated
+/// fields lined up in columns, and the lines padded to the same width, at
+/// least `min_width`, so that they start at the same place
+pub fn left_aligned(text: &str, min_width: usize) -> String {
+    let rows: Vec<Vec<&str>> = text
+        .lines()
+        .map(|line| line.split('\t').collect())
+        .collect();
+    let mut widths: Vec<usize> = Vec::new();
+    for row in &rows {
+        // The last field of a row doesn't need lining up
+        for (i, field) in row.iter().enumerate().take(row.len() - 1) {
+            if widths.len() <= i {
+                widths.push(0);
+            }
+            widths[i] = widths[i].max(field.chars().count());
+        }
     }
+    let lines: Vec<String> = rows
+        .iter()
+        .map(|row| {
+            let last = row.len() - 1;
+            row.iter()
+                .enumerate()
+                .map(|(i, field)| match i {
+                    _ if i == last => field.to_string(),
+                    _ => format!("{:w$}  ", field, w = widths[i]),
+                })
+                .collect()
+        })
+        .collect();
+    let width = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(min_width);
+    lines
+        .iter()
+        .map(|line| format!("{:width$}", line, width = width))
+        .collect::<Vec<_>>()
+        .join("\n")
 }
@@ -0,0 +1,131 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::Duration;
+
+use crate::app::{DirListing, SortMode};
+use crate::cache::ListingCache;
+use crate::config::CacheLimits;
+use crate::loader::LoadOptions;
+use crate::provider;
+
+/// How long the cursor has to rest on a directory before we start listing it
+pub const PREFETCH_DELAY: Duration = Duration::from_millis(250);
+
+struct Pending {
+    path: PathBuf,
+    rx: Receiver<Result<DirListing, std::io::Error>>,
+    cancel: Arc<AtomicBool>,
+}
+
+/// Lists directories in the background, so that entering them is instant.
+/// At most one listing is in flight at a time; finished listings are kept
+/// in a small cache.
+pub struct Prefetcher {
+    cache: ListingCache,
+    pending: Option<Pending>,
+    /// The last directory we couldn't list, which isn't tried again until
+    /// the cursor moves or the listing is refreshed
+    failed: Option<PathBuf>,
+}
+
+impl Prefetcher {
+    pub fn new(limits: CacheLimits) -> Self {
+        Prefetcher {
+            cache: ListingCache::new(limits),
+            pending: None,
+            failed: None,
+        }
+    }
+
+    /// Start listing `path` in the background, unless we already have it.
+    /// Supersedes any listing that's still in flight.
+    pub fn request(&mut self, path: &Path, sort_mode: SortMode, options: LoadOptions) {
+        if self.cache.contains(path) || self.failed.as_deref() == Some(path) {
+            return;
+        }
+        if let Some(pending) = &self.pending {
+            if pending.path == path {
+                return;
+            }
+            pending.cancel.store(true, Ordering::Relaxed);
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let thread_path = path.to_path_buf();
+        let thread_cancel = cancel.clone();
+        provider::spawn(move || {
+            let _ = tx.send(DirListing::from(
+                &thread_path,
+                sort_mode,
+                options,
+                &thread_cancel,
+            ));
+        });
+
+        self.pending = Some(Pending {
+            path: path.to_path_buf(),
+            rx,
+            cancel,
+        });
+    }
+
+    /// Collect the in-flight listing if it has finished.
+    pub fn poll(&mut self) {
+        let Some(pending) = &self.pending else {
+            return;
+        };
+        match pending.rx.try_recv() {
+            Err(TryRecvError::Empty) => return,
+            Ok(Ok(listing)) => {
+                let path = pending.path.clone();
+                self.cache.insert(path, listing);
+            }
+            // Errors will be reported if the user actually enters the directory
+            Ok(Err(_)) | Err(TryRecvError::Disconnected) => {
+                self.failed = Some(pending.path.clone());
+            }
+        }
+        self.pending = None;
+    }
+
+    /// A fresh listing of `path`, if we have one, without taking it
+    pub fn get(&mut self, path: &Path) -> Option<&DirListing> {
+        self.poll();
+        self.cache.get(path)
+    }
+
+    /// Remove and return a fresh listing of `path`, if we have one.
+    pub fn take(&mut self, path: &Path) -> Option<DirListing> {
+        self.poll();
+        self.cache.take(path)
+    }
+
+    /// Let the directory we last failed to list be tried again
+    pub fn forget_failure(&mut self) {
+        self.failed = None;
+    }
+
+    /// Drop everything, including the listing in flight.
+    pub fn clear(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            pending.cancel.store(true, Ordering::Relaxed);
+        }
+        self.cache.clear();
+        self.failed = None;
+    }
+
+    pub fn set_limits(&mut self, limits: CacheLimits) {
+        self.cache.set_limits(limits);
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn in_flight(&self) -> Option<&Path> {
+        self.pending.as_ref().map(|p| p.path.as_path())
+    }
+}
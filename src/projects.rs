@@ -0,0 +1,92 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::app::DirEntry;
+use crate::fs::{group_id, user_id, user_name};
+
+/// Which project, lab, or allocation users and groups belong to, as read
+/// from the `projects` file, for adding up usage the way it's granted
+/// rather than by Unix owner
+#[derive(Debug, Clone, Default)]
+pub struct ProjectMap {
+    users: HashMap<u32, String>,
+    groups: HashMap<u32, String>,
+}
+
+impl ProjectMap {
+    /// Read a file of lines like "user alice smith-lab" or "group 5012
+    /// smith-lab", with users and groups given by name or id, and comments
+    /// starting with '#'
+    pub fn load(path: &Path) -> Result<ProjectMap, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read the projects file {:?}: {}", path, e))?;
+        let mut map = ProjectMap::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |what: &str| format!("{}:{}: {}", path.display(), i + 1, what);
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [kind, name, project] = fields[..] else {
+                return Err(error("expected e.g. \"user alice smith-lab\""));
+            };
+            match kind {
+                "user" => {
+                    let uid = user_id(name).ok_or_else(|| error("no such user"))?;
+                    map.users.insert(uid, project.to_string());
+                }
+                "group" => {
+                    let gid = group_id(name).ok_or_else(|| error("no such group"))?;
+                    map.groups.insert(gid, project.to_string());
+                }
+                _ => return Err(error("lines start with \"user\" or \"group\"")),
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty() && self.groups.is_empty()
+    }
+
+    /// The project of what `uid` and `gid` own: the user's, if they're
+    /// mapped, or else the group's
+    pub fn project_of(&self, uid: u32, gid: u32) -> Option<&str> {
+        self.users
+            .get(&uid)
+            .or_else(|| self.groups.get(&gid))
+            .map(String::as_str)
+    }
+
+    /// How much of `entries` each project has, biggest first, as (project,
+    /// size, files). What isn't mapped to a project is added up by owner,
+    /// e.g. "(user alice)", or just "alice" without any projects. Each entry
+    /// counts itself as a file, like rentries.
+    pub fn usage<'a>(
+        &self,
+        entries: impl IntoIterator<Item = &'a DirEntry>,
+    ) -> Vec<(String, usize, usize)> {
+        let mut usage: HashMap<String, (usize, usize)> = HashMap::new();
+        for entry in entries {
+            let (Some(uid), Some(gid)) = (entry.uid, entry.gid) else {
+                continue;
+            };
+            let project = match self.project_of(uid, gid) {
+                Some(project) => project.to_string(),
+                None if self.is_empty() => user_name(uid).to_string(),
+                None => format!("(user {})", user_name(uid)),
+            };
+            let (size, files) = usage.entry(project).or_default();
+            *size += entry.size.unwrap_or(0);
+            *files += entry.rentries.unwrap_or(0) + 1;
+        }
+        let mut usage: Vec<(String, usize, usize)> = usage
+            .into_iter()
+            .map(|(project, (size, files))| (project, size, files))
+            .collect();
+        usage.sort_by_key(|(project, size, _)| (Reverse(*size), project.clone()));
+        usage
+    }
+}
@@ -0,0 +1,522 @@
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CStr, CString};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread::JoinHandle;
+
+use crate::app::EntryKind;
+use crate::fs::{
+    DIR_RBYTES_ATTR, DIR_RCTIME_ATTR, DIR_RENTRIES_ATTR, FSType, RealFs, normalize_lexically,
+};
+
+/// The parts of stat(2) that cephdu uses
+#[derive(Debug, Clone, Copy)]
+pub struct Stat {
+    pub kind: EntryKind,
+    pub size: usize,
+    pub ctime: usize,
+    pub uid: u32,
+    pub gid: u32,
+    /// The permission bits, including setuid, setgid, and sticky
+    pub mode: u32,
+    pub dev: u64,
+    pub ino: u64,
+    pub nlink: u64,
+    /// How much is allocated on disk, in 512-byte blocks
+    pub blocks: u64,
+}
+
+/// Files smaller than this aren't called sparse, as filesystems that keep
+/// small files inline allocate no blocks for them
+const SPARSE_MIN_SIZE: usize = 1 << 20;
+
+impl Stat {
+    /// The bytes allocated on disk, which can be more than the size, or far
+    /// less for a sparse file
+    pub fn allocated(&self) -> usize {
+        self.blocks as usize * 512
+    }
+
+    /// Whether it's a file with less than half of its size allocated, like
+    /// a checkpoint that was written with holes
+    pub fn is_sparse(&self) -> bool {
+        self.kind == EntryKind::File
+            && self.size >= SPARSE_MIN_SIZE
+            && self.allocated() < self.size / 2
+    }
+}
+
+/// The parts of statvfs(3) that cephdu uses, in bytes
+#[derive(Debug, Clone, Copy)]
+pub struct Space {
+    pub total: usize,
+    pub free: usize,
+    /// What's free for unprivileged users
+    pub avail: usize,
+}
+
+/// An entry read from a directory, before it has been stat'ed
+#[derive(Debug, Clone)]
+pub struct Dirent {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+}
+
+pub type DirIter = Box<dyn Iterator<Item = io::Result<Dirent>> + Send>;
+
+/// Where listings come from: the stat, readdir, and getxattr calls that
+/// cephdu makes, so that they can be answered from something other than the
+/// kernel, like the made-up tree of `--demo`. Reading and deleting files is
+/// only for the cleanup wizard, and providers can leave it out.
+pub trait FsProvider: Send + Sync {
+    /// stat(2), following symlinks
+    fn stat(&self, path: &Path) -> io::Result<Stat>;
+    /// lstat(2), not following symlinks
+    fn lstat(&self, path: &Path) -> io::Result<Stat>;
+    fn read_dir(&self, path: &Path) -> io::Result<DirIter>;
+    /// The value of an extended attribute, if it's set
+    fn xattr(&self, path: &Path, name: &CStr) -> Option<Vec<u8>>;
+    /// The names of the extended attributes that are set, like listxattr(2).
+    /// CephFS leaves out its virtual ones, like ceph.dir.rbytes.
+    fn list_xattrs(&self, path: &Path) -> io::Result<Vec<CString>>;
+    /// The filesystem `path` is on
+    fn fs_type(&self, path: &Path) -> Option<FSType>;
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+    /// What's mounted where, in the format of /proc/self/mounts
+    fn mount_table(&self) -> io::Result<String>;
+    /// How full the filesystem with `path` on it is, like df(1)
+    fn space(&self, path: &Path) -> io::Result<Space>;
+    /// lstat(2) of each of `paths`, for a listing, which a provider can
+    /// batch
+    fn lstat_many(&self, paths: &[PathBuf]) -> Vec<io::Result<Stat>> {
+        paths.iter().map(|path| self.lstat(path)).collect()
+    }
+    /// The value of each `(path, name)` extended attribute that's set, which
+    /// a provider can batch
+    fn xattr_many(&self, requests: &[(&Path, &'static CStr)]) -> Vec<Option<Vec<u8>>> {
+        requests
+            .iter()
+            .map(|(path, name)| self.xattr(path, name))
+            .collect()
+    }
+    /// Hold `path` open as the directory being browsed, for what's in it to
+    /// be looked up relative to it rather than by path
+    fn pin_dir(&self, _path: &Path) {}
+    /// Open a file to read what's in it
+    fn open(&self, _path: &Path) -> io::Result<Box<dyn io::Read + Send>> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+    /// Delete `path`, with everything under it if it's a directory, but
+    /// nothing on another filesystem mounted below it
+    fn remove(&self, _path: &Path) -> io::Result<()> {
+        Err(io::Error::from(io::ErrorKind::ReadOnlyFilesystem))
+    }
+}
+
+static PROVIDER: OnceLock<Box<dyn FsProvider>> = OnceLock::new();
+
+thread_local! {
+    /// The provider of this thread, if it has its own, e.g. each test's
+    static THREAD_PROVIDER: Cell<Option<&'static dyn FsProvider>> = const { Cell::new(None) };
+}
+
+/// The provider that all filesystem access goes through: this thread's, if
+/// one was installed with `set_for_thread()`, or else the real filesystem,
+/// unless another one was installed with `set()`.
+pub fn get() -> &'static dyn FsProvider {
+    THREAD_PROVIDER
+        .get()
+        .unwrap_or_else(|| PROVIDER.get_or_init(|| Box::new(RealFs)).as_ref())
+}
+
+/// Use `provider` on this thread, and on the threads it starts with
+/// `spawn()`, whatever the rest of the process uses. Tests running in
+/// parallel can each have their own this way.
+pub fn set_for_thread(provider: &'static dyn FsProvider) {
+    THREAD_PROVIDER.set(Some(provider));
+}
+
+/// Use `fs` on this thread for the rest of a test, and return it to look at
+#[cfg(test)]
+pub fn set_for_test(fs: MemoryFs) -> &'static MemoryFs {
+    let fs = Box::leak(Box::new(fs));
+    set_for_thread(fs);
+    fs
+}
+
+/// /data with two directories and two files, of different sizes, for tests
+#[cfg(test)]
+pub fn sample_tree() -> MemoryFs {
+    let mut fs = MemoryFs::new(1000, 1000);
+    fs.add_file(Path::new("/data/big/a"), 5000, 300);
+    fs.add_file(Path::new("/data/big/b"), 3000, 200);
+    fs.add_file(Path::new("/data/small/c"), 10, 100);
+    fs.add_file(Path::new("/data/mid.bin"), 4000, 400);
+    fs.add_file(Path::new("/data/notes.txt"), 9000, 500);
+    fs
+}
+
+/// Start a thread, like `std::thread::spawn`, that uses the same provider
+/// as this one
+pub fn spawn<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> JoinHandle<T> {
+    let provider = get();
+    std::thread::spawn(move || {
+        set_for_thread(provider);
+        f()
+    })
+}
+
+/// Use `provider` instead of the real filesystem. Must be called before the
+/// filesystem is first accessed; returns false if it's too late.
+pub fn set(provider: Box<dyn FsProvider>) -> bool {
+    PROVIDER.set(provider).is_ok()
+}
+
+/// A filesystem that only exists in memory, with the recursive stats that
+/// CephFS would keep. Directories are created as needed when entries are added.
+pub struct MemoryFs {
+    nodes: HashMap<PathBuf, Node>,
+    uid: u32,
+    gid: u32,
+    next_ino: u64,
+    mount_table: String,
+    capacity: usize,
+    /// Directories with another filesystem mounted on them
+    mount_points: HashSet<PathBuf>,
+    /// Directories that can't be listed, as if we lacked the permission
+    unreadable: HashSet<PathBuf>,
+    /// How many times listing one of those was tried
+    denied: AtomicUsize,
+    /// What has been deleted since, with everything under it
+    removed: Mutex<HashSet<PathBuf>>,
+}
+
+struct Node {
+    stat: Stat,
+    /// In order of creation
+    children: Vec<PathBuf>,
+    rbytes: usize,
+    /// Including the directory itself, like ceph.dir.rentries
+    rentries: usize,
+    rctime: usize,
+    /// Other extended attributes, like ACLs
+    xattrs: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryFs {
+    /// An empty filesystem, with everything owned by `uid` and `gid`
+    pub fn new(uid: u32, gid: u32) -> Self {
+        let mut fs = MemoryFs {
+            nodes: HashMap::new(),
+            uid,
+            gid,
+            next_ino: 1,
+            mount_table: String::new(),
+            capacity: 0,
+            mount_points: HashSet::new(),
+            unreadable: HashSet::new(),
+            denied: AtomicUsize::new(0),
+            removed: Mutex::new(HashSet::new()),
+        };
+        let root = fs.node(EntryKind::Dir, 0, 0);
+        fs.nodes.insert(PathBuf::from("/"), root);
+        fs
+    }
+
+    pub fn add_file(&mut self, path: &Path, size: usize, ctime: usize) {
+        self.add(path, EntryKind::File, size, ctime);
+    }
+
+    pub fn add_dir(&mut self, path: &Path, ctime: usize) {
+        self.add(path, EntryKind::Dir, 0, ctime);
+    }
+
+    /// Add a directory with another filesystem mounted on it: what's added
+    /// below it is on another device, and like on CephFS, isn't counted in
+    /// the recursive stats of the directories above it
+    pub fn add_mount_point(&mut self, path: &Path, ctime: usize) {
+        let path = normalize_lexically(&Path::new("/").join(path));
+        self.add_dir(&path, ctime);
+        let dev = self.mount_points.len() as u64 + 2;
+        if let Some(node) = self.nodes.get_mut(&path) {
+            node.stat.dev = dev;
+        }
+        self.mount_points.insert(path);
+    }
+
+    /// Pretend that this is what's mounted, in the format of /proc/self/mounts
+    pub fn set_mount_table(&mut self, table: &str) {
+        self.mount_table = table.to_string();
+    }
+
+    /// Pretend the filesystem holds this many bytes. What's free is what the
+    /// entries don't use.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+    }
+
+    /// Set an extended attribute of an entry that's been added
+    pub fn set_xattr(&mut self, path: &Path, name: &str, value: Vec<u8>) {
+        if let Ok((path, _)) = self.find(path)
+            && let Some(node) = self.nodes.get_mut(&path)
+        {
+            node.xattrs.insert(name.to_string(), value);
+        }
+    }
+
+    /// Make a file that's been added sparse, with only `allocated` bytes on disk
+    pub fn set_allocated(&mut self, path: &Path, allocated: usize) {
+        if let Ok((path, _)) = self.find(path)
+            && let Some(node) = self.nodes.get_mut(&path)
+        {
+            node.stat.blocks = allocated.div_ceil(512) as u64;
+        }
+    }
+
+    /// Change the permission bits of an entry that's been added
+    pub fn set_mode(&mut self, path: &Path, mode: u32) {
+        if let Ok((path, _)) = self.find(path)
+            && let Some(node) = self.nodes.get_mut(&path)
+        {
+            node.stat.mode = mode;
+        }
+    }
+
+    /// Make listing a directory that's been added fail with EACCES
+    #[cfg(test)]
+    pub fn set_unreadable(&mut self, path: &Path) {
+        if let Ok((path, _)) = self.find(path) {
+            self.unreadable.insert(path);
+        }
+    }
+
+    /// How many times listing an unreadable directory was tried
+    #[cfg(test)]
+    pub fn denied(&self) -> usize {
+        self.denied.load(Ordering::Relaxed)
+    }
+
+    fn node(&mut self, kind: EntryKind, size: usize, ctime: usize) -> Node {
+        self.next_ino += 1;
+        Node {
+            stat: Stat {
+                kind,
+                size,
+                ctime,
+                uid: self.uid,
+                gid: self.gid,
+                mode: if kind == EntryKind::Dir { 0o755 } else { 0o644 },
+                dev: 1,
+                ino: self.next_ino,
+                nlink: 1,
+                blocks: size.div_ceil(512) as u64,
+            },
+            children: Vec::new(),
+            rbytes: size,
+            rentries: 1,
+            rctime: ctime,
+            xattrs: HashMap::new(),
+        }
+    }
+
+    /// Add an entry, unless there is one already, creating its parents, and
+    /// account for it in the recursive stats of every directory above it.
+    fn add(&mut self, path: &Path, kind: EntryKind, size: usize, ctime: usize) {
+        let path = normalize_lexically(&Path::new("/").join(path));
+        let Some(parent) = path.parent().map(Path::to_path_buf) else {
+            return;
+        };
+        if self.nodes.contains_key(&path) {
+            return;
+        }
+        if !self.nodes.contains_key(&parent) {
+            self.add_dir(&parent, ctime);
+        }
+
+        let mut node = self.node(kind, size, ctime);
+        node.stat.dev = self.nodes.get(&parent).map_or(1, |parent| parent.stat.dev);
+        self.nodes.insert(path.clone(), node);
+        if let Some(parent) = self.nodes.get_mut(&parent) {
+            parent.children.push(path.clone());
+            // Like CephFS without the rbytes mount option, a directory's
+            // st_size is its number of entries
+            parent.stat.size = parent.children.len();
+        }
+        for dir in path.ancestors().skip(1) {
+            if let Some(node) = self.nodes.get_mut(dir) {
+                node.rbytes += size;
+                node.rentries += 1;
+                node.rctime = node.rctime.max(ctime);
+            }
+            if self.mount_points.contains(dir) {
+                break;
+            }
+        }
+    }
+
+    fn find(&self, path: &Path) -> io::Result<(PathBuf, &Node)> {
+        let path = normalize_lexically(&Path::new("/").join(path));
+        match self.nodes.get(&path) {
+            Some(_) if self.is_removed(&path) => Err(io::Error::from(io::ErrorKind::NotFound)),
+            Some(node) => Ok((path, node)),
+            None => Err(io::Error::from(io::ErrorKind::NotFound)),
+        }
+    }
+
+    fn is_removed(&self, path: &Path) -> bool {
+        self.removed
+            .lock()
+            .is_ok_and(|removed| removed.contains(path))
+    }
+}
+
+impl FsProvider for MemoryFs {
+    fn stat(&self, path: &Path) -> io::Result<Stat> {
+        self.find(path).map(|(_, node)| node.stat)
+    }
+
+    // There are no symlinks
+    fn lstat(&self, path: &Path) -> io::Result<Stat> {
+        self.stat(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<DirIter> {
+        let (path, node) = self.find(path)?;
+        if node.stat.kind != EntryKind::Dir {
+            return Err(io::Error::from(io::ErrorKind::NotADirectory));
+        }
+        if self.unreadable.contains(&path) {
+            self.denied.fetch_add(1, Ordering::Relaxed);
+            return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+        }
+        let dirents: Vec<io::Result<Dirent>> = node
+            .children
+            .iter()
+            .filter(|child| !self.is_removed(child))
+            .map(|child| {
+                Ok(Dirent {
+                    path: child.clone(),
+                    kind: self.nodes[child].stat.kind,
+                })
+            })
+            .collect();
+        Ok(Box::new(dirents.into_iter()))
+    }
+
+    fn xattr(&self, path: &Path, name: &CStr) -> Option<Vec<u8>> {
+        let (path, node) = self.find(path).ok()?;
+        let name = name.to_str().ok()?;
+        let is_dir = node.stat.kind == EntryKind::Dir;
+        let value = match name {
+            DIR_RBYTES_ATTR if is_dir => node.rbytes.to_string(),
+            DIR_RENTRIES_ATTR if is_dir => node.rentries.to_string(),
+            DIR_RCTIME_ATTR if is_dir => format!("{}.000000000", node.rctime),
+            "ceph.quota" if is_dir => {
+                let limit = |name| {
+                    node.xattrs
+                        .get(name)
+                        .map(|v| String::from_utf8_lossy(v).to_string())
+                };
+                let (max_bytes, max_files) =
+                    (limit("ceph.quota.max_bytes"), limit("ceph.quota.max_files"));
+                if max_bytes.is_none() && max_files.is_none() {
+                    return None;
+                }
+                format!(
+                    "max_bytes={} max_files={}",
+                    max_bytes.as_deref().unwrap_or("0"),
+                    max_files.as_deref().unwrap_or("0")
+                )
+            }
+            // Like a file's layout, what was set on the directory it was created in
+            "ceph.file.layout.pool" if !is_dir => {
+                return path.ancestors().find_map(|dir| {
+                    self.nodes
+                        .get(dir)?
+                        .xattrs
+                        .get("ceph.dir.layout.pool")
+                        .cloned()
+                });
+            }
+            _ => return node.xattrs.get(name).cloned(),
+        };
+        Some(value.into_bytes())
+    }
+
+    fn list_xattrs(&self, path: &Path) -> io::Result<Vec<CString>> {
+        let (_, node) = self.find(path)?;
+        // Like CephFS, leave out the virtual ones
+        let mut names: Vec<CString> = node
+            .xattrs
+            .keys()
+            .filter(|name| !name.starts_with("ceph."))
+            .filter_map(|name| CString::new(name.as_str()).ok())
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    fn fs_type(&self, path: &Path) -> Option<FSType> {
+        self.find(path).ok().map(|_| FSType::CEPH)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.find(path).map(|(path, _)| path)
+    }
+
+    fn mount_table(&self) -> io::Result<String> {
+        Ok(self.mount_table.clone())
+    }
+
+    // Like the real one, what's mounted below `path` is left in place, with
+    // the directories above it. The recursive stats stay as they were.
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        let (path, node) = self.find(path)?;
+        let dev = node.stat.dev;
+        let mut under: Vec<&PathBuf> = self.nodes.keys().filter(|p| p.starts_with(&path)).collect();
+        // The deepest first, so that directories are empty when they're reached
+        under.sort_by_key(|p| std::cmp::Reverse(p.components().count()));
+        let mut removed = self
+            .removed
+            .lock()
+            .map_err(|_| io::Error::other("poisoned"))?;
+        let mut kept = None;
+        for p in under {
+            let node = &self.nodes[p];
+            if removed.contains(p) {
+                continue;
+            }
+            if node.stat.dev != dev {
+                if self.mount_points.contains(p) {
+                    kept = Some(p);
+                }
+                continue;
+            }
+            if node.children.iter().all(|child| removed.contains(child)) {
+                removed.insert(p.clone());
+            }
+        }
+        match kept {
+            Some(p) => Err(io::Error::other(format!(
+                "{} is on another filesystem, so it was left in place",
+                p.display()
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn space(&self, path: &Path) -> io::Result<Space> {
+        self.find(path)?;
+        let free = self
+            .capacity
+            .saturating_sub(self.nodes[Path::new("/")].rbytes);
+        Ok(Space {
+            total: self.capacity,
+            free,
+            avail: free,
+        })
+    }
+}
@@ -0,0 +1,479 @@
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Local};
+use clap::Args;
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+
+use crate::app::{DirEntry, EntryKind};
+use crate::config::Config;
+use crate::exit::{self, Failure};
+use crate::fs::has_ceph_stats;
+use crate::glob;
+use crate::jobs::Progress;
+use crate::loader::{LoadOptions, interrupted_error, ls};
+use crate::provider;
+use crate::report::{WalkArgs, Walker, print_text_table};
+use crate::ui::{rentries_str, size_str};
+
+const DAY: u64 = 24 * 3600;
+
+/// The ages that the retention simulator steps through, in days
+pub const RETENTION_STEPS: [u64; 8] = [7, 14, 30, 60, 90, 180, 365, 730];
+
+/// Show how much the next scratch purge would take, per directory, according
+/// to the retention policy in the config
+#[derive(Args)]
+pub struct PurgePreviewArgs {
+    /// Directory to look at
+    pub path: PathBuf,
+
+    /// How many levels of subdirectories to list. What's older than the
+    /// policy is still counted all the way down.
+    #[arg(long, default_value_t = 1)]
+    pub depth: usize,
+
+    /// How many directories to list
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+
+    /// Count files that haven't changed in this many days, instead of
+    /// following the policy
+    #[arg(long, value_name = "N")]
+    pub days: Option<u64>,
+
+    /// Leave out files and directories whose names match GLOB (can be
+    /// repeated, and adds to the `exclude` setting)
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+}
+
+/// What a purge would take from a directory, and how much it holds
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Purgeable {
+    pub bytes: usize,
+    /// Files and directories, like rentries
+    pub files: usize,
+}
+
+impl Purgeable {
+    fn add(&mut self, other: Purgeable) {
+        self.bytes += other.bytes;
+        self.files += other.files;
+    }
+}
+
+/// A directory down to `--depth`
+struct PurgeRow {
+    path: PathBuf,
+    size: usize,
+    purgeable: Purgeable,
+}
+
+pub fn run(args: &PurgePreviewArgs, config: &Config) -> Result<()> {
+    let root = exit::resolve(&args.path)?;
+    if !has_ceph_stats(&root) {
+        return Err(Failure::NotCeph(root.clone()).into());
+    }
+    let days = match (args.days, config.retention_for(&root)) {
+        (Some(days), _) => days,
+        (None, Some(policy)) => policy.days,
+        (None, None) => {
+            return Err(eyre!(
+                "No retention policy covers {:?}; add a [[retention]] to the config, or use --days",
+                root
+            ));
+        }
+    };
+    let cutoff = cutoff(days);
+
+    // What's mounted below isn't in the rbytes, nor up to the policy
+    let walk = WalkArgs {
+        one_file_system: true,
+        exclude: args.exclude.clone(),
+    };
+    let mut preview = Preview {
+        walker: Walker::new(&root, usize::MAX, &walk, config)?,
+        cutoff,
+        max_depth: args.depth,
+    };
+    let (entry_root, entries, _, _) = ls(&root, preview.walker.options, &AtomicBool::new(false))?;
+    let mut rows = Vec::new();
+    let total = preview.walk(&root, &entries, &mut rows);
+    let size = entry_root.size.unwrap_or(0);
+
+    rows.retain(|row| row.purgeable.files > 0);
+    rows.sort_by_key(|row| Reverse(row.purgeable.bytes));
+    rows.truncate(args.top);
+    let percent =
+        |part: usize, of: usize| format!("{:.0}%", part as f64 / of.max(1) as f64 * 100.0);
+    let header = ["Purgeable", "Files", "Percent", "Path"];
+    let directories: Vec<[String; 4]> = rows
+        .iter()
+        .map(|row| {
+            let path = row.path.strip_prefix(&root).unwrap_or(&row.path);
+            [
+                size_str(Some(row.purgeable.bytes), false),
+                rentries_str(Some(row.purgeable.files), false),
+                percent(row.purgeable.bytes, row.size),
+                format!("{}/", path.display()),
+            ]
+        })
+        .collect();
+
+    println!("Purge preview for {}", root.display());
+    println!(
+        "Files unchanged for {} days, since {}: {} of {} ({}), {} files",
+        days,
+        date_str(cutoff),
+        size_str(Some(total.bytes), false),
+        size_str(Some(size), false),
+        percent(total.bytes, size),
+        rentries_str(Some(total.files), false)
+    );
+    if !directories.is_empty() {
+        println!("\nMost purgeable directories (depth {}):", args.depth);
+        print_text_table(&header, 2, &directories);
+    }
+    Ok(())
+}
+
+/// Adds up what's older than `cutoff`, going only into the directories that
+/// changed since then: the recursive ctime of the others says that nothing
+/// in them did, so all of their rbytes count
+struct Preview {
+    walker: Walker,
+    cutoff: usize,
+    max_depth: usize,
+}
+
+/// A directory that `Preview` came across, which adds what's purgeable in
+/// it to the one it's in once everything has been walked
+struct Walked {
+    row: PurgeRow,
+    depth: usize,
+    parent: usize,
+}
+
+impl Preview {
+    /// What's purgeable under `root`, whose `entries` have been listed,
+    /// adding a row for every directory down to `max_depth`. Files count by
+    /// their ctime, which is never older than the mtime that purges usually
+    /// go by.
+    fn walk(&mut self, root: &Path, entries: &[DirEntry], rows: &mut Vec<PurgeRow>) -> Purgeable {
+        let mut walked = vec![Walked {
+            row: PurgeRow {
+                path: root.to_path_buf(),
+                size: 0,
+                purgeable: Purgeable::default(),
+            },
+            depth: 0,
+            parent: 0,
+        }];
+        // The directories to go into, by their index in `walked`
+        let mut stack = Vec::new();
+        self.add_entries(0, entries, &mut walked, &mut stack);
+        while let Some(i) = stack.pop() {
+            let path = walked[i].row.path.clone();
+            match ls(&path, self.walker.options, &AtomicBool::new(false)) {
+                Ok((_, children, _, _)) => self.add_entries(i, &children, &mut walked, &mut stack),
+                Err(e) => eprintln!("Warning: skipping {:?}: {}", path, e),
+            }
+        }
+
+        // Directories come after the ones they're in, so going backwards
+        // adds each one up before it's added to its parent
+        for i in (1..walked.len()).rev() {
+            let (parent, purgeable) = (walked[i].parent, walked[i].row.purgeable);
+            walked[parent].row.purgeable.add(purgeable);
+        }
+        let total = walked[0].row.purgeable;
+        rows.extend(
+            walked
+                .into_iter()
+                .skip(1)
+                .filter(|dir| dir.depth <= self.max_depth)
+                .map(|dir| dir.row),
+        );
+        total
+    }
+
+    /// Count what's purgeable among `entries` of `walked[parent]`: old files
+    /// in it, and old directories as a whole. Push the other directories to
+    /// go into on `stack`.
+    fn add_entries(
+        &mut self,
+        parent: usize,
+        entries: &[DirEntry],
+        walked: &mut Vec<Walked>,
+        stack: &mut Vec<usize>,
+    ) {
+        let dir = walked[parent].row.path.clone();
+        let depth = walked[parent].depth + 1;
+        for entry in entries {
+            if self.walker.excludes(entry) {
+                continue;
+            }
+            let old = entry.ctime.is_some_and(|ctime| ctime < self.cutoff);
+            if entry.kind != EntryKind::Dir {
+                if old {
+                    walked[parent].row.purgeable.add(Purgeable {
+                        bytes: entry.size.unwrap_or(0),
+                        files: 1,
+                    });
+                }
+                continue;
+            }
+            let path = dir.join(entry.name.trim_end_matches('/'));
+            if !self.walker.admit(&path) {
+                continue;
+            }
+            let purgeable = if old {
+                Purgeable {
+                    bytes: entry.size.unwrap_or(0),
+                    files: entry.rentries.unwrap_or(0) + 1,
+                }
+            } else {
+                stack.push(walked.len());
+                Purgeable::default()
+            };
+            walked.push(Walked {
+                row: PurgeRow {
+                    path,
+                    size: entry.size.unwrap_or(0),
+                    purgeable,
+                },
+                depth,
+                parent,
+            });
+        }
+    }
+}
+
+/// The time `days` ago, which files older than are purgeable
+pub fn cutoff(days: u64) -> usize {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    now.saturating_sub(days * DAY) as usize
+}
+
+/// The day of `time`, like 2024-05-31
+pub fn date_str(time: usize) -> String {
+    DateTime::from_timestamp(time as i64, 0)
+        .map(|t| t.with_timezone(&Local).format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+/// What deleting everything older than some number of days would free under
+/// a directory, counted in the background for the retention simulator. The
+/// walk for the longest age gets the shorter ones too, since it goes into
+/// every directory that they would.
+pub struct RetentionScan {
+    pub root: PathBuf,
+    pub job: usize,
+    /// The ages counted, shortest first
+    pub days: Vec<u64>,
+    /// Once it's done: what's older than each of `days`, and what the tree
+    /// holds in all, or why it failed
+    pub result: Option<Result<(Vec<Purgeable>, Purgeable), String>>,
+    rx: Receiver<std::io::Result<(Vec<Purgeable>, Purgeable)>>,
+}
+
+impl RetentionScan {
+    /// Count what under `root` is older than each of `days`, as the job
+    /// `job`, without going into what matches `exclude`
+    pub fn start(
+        root: &Path,
+        mut days: Vec<u64>,
+        options: LoadOptions,
+        exclude: &[String],
+        job: usize,
+        progress: Arc<Progress>,
+    ) -> RetentionScan {
+        days.sort_unstable();
+        days.dedup();
+        let cutoffs: Vec<usize> = days.iter().map(|&days| cutoff(days)).collect();
+        let (tx, rx) = mpsc::channel();
+        let thread_root = root.to_path_buf();
+        let exclude = exclude.to_vec();
+        provider::spawn(move || {
+            let _ = tx.send(simulate(
+                &thread_root,
+                &cutoffs,
+                options,
+                &exclude,
+                &progress,
+            ));
+        });
+        RetentionScan {
+            root: root.to_path_buf(),
+            job,
+            days,
+            result: None,
+            rx,
+        }
+    }
+
+    /// Take the result if the walk has finished. Returns true if it just did.
+    pub fn poll(&mut self) -> bool {
+        if self.result.is_some() {
+            return false;
+        }
+        self.result = match self.rx.try_recv() {
+            Err(TryRecvError::Empty) => return false,
+            Ok(result) => Some(result.map_err(|e| e.to_string())),
+            Err(TryRecvError::Disconnected) => Some(Err("the walk died".to_string())),
+        };
+        true
+    }
+
+    /// The ages to step through: the usual ones, and the ones asked for
+    pub fn ages(&self) -> Vec<u64> {
+        let mut ages: Vec<u64> = RETENTION_STEPS
+            .into_iter()
+            .chain(self.days.clone())
+            .collect();
+        ages.sort_unstable();
+        ages.dedup();
+        ages
+    }
+
+    /// What's older than `days`, once it has been counted
+    pub fn older_than(&self, days: u64) -> Option<Purgeable> {
+        let i = self.days.iter().position(|&d| d == days)?;
+        Some(self.result.as_ref()?.as_ref().ok()?.0[i])
+    }
+}
+
+/// What's older than each of `cutoffs`, newest first, under `root`, staying
+/// on its filesystem, and what it holds in all
+fn simulate(
+    root: &Path,
+    cutoffs: &[usize],
+    mut options: LoadOptions,
+    exclude: &[String],
+    progress: &Progress,
+) -> std::io::Result<(Vec<Purgeable>, Purgeable)> {
+    // Everything counts, hidden or not, and a count that leaves out what
+    // timed out would be wrong, so it waits
+    options.lazy_threshold = usize::MAX;
+    options.page_size = usize::MAX;
+    options.hide_dotfiles = false;
+    options.timeout = None;
+    let root_stat = provider::get().lstat(root)?;
+    let mut walk = AgeWalk {
+        dev: root_stat.dev,
+        seen: HashSet::from([(root_stat.dev, root_stat.ino)]),
+        options,
+        exclude,
+        progress,
+    };
+    progress.add_total(1);
+    let (_, entries, _, _) = ls(root, options, progress.cancelled())?;
+    progress.add_done(1);
+    progress.add_items(entries.len());
+    // Everything is older than now
+    let mut totals = vec![Purgeable::default(); cutoffs.len() + 1];
+    let cutoffs: Vec<usize> = [usize::MAX].into_iter().chain(cutoffs.to_vec()).collect();
+    walk.add_older(root, &entries, &cutoffs, &mut totals)?;
+    let all = totals.remove(0);
+    Ok((totals, all))
+}
+
+/// The walk of the retention simulator, like `Preview`'s, for several
+/// cutoffs at once
+struct AgeWalk<'a> {
+    /// The filesystem to stay on
+    dev: u64,
+    /// The (dev, inode) of every directory so far
+    seen: HashSet<(u64, u64)>,
+    options: LoadOptions,
+    exclude: &'a [String],
+    progress: &'a Progress,
+}
+
+impl AgeWalk<'_> {
+    /// Add what's older than each of `cutoffs`, newest first, under `root`,
+    /// whose `entries` have been listed, to `totals`. A directory that's
+    /// older than some of them counts as a whole for those, and is gone into
+    /// for the rest. Stays on the filesystem of `root`, and counts
+    /// directories that are also bind-mounted under it once.
+    fn add_older(
+        &mut self,
+        root: &Path,
+        entries: &[DirEntry],
+        cutoffs: &[usize],
+        totals: &mut [Purgeable],
+    ) -> std::io::Result<()> {
+        // Directories to go into, with the first cutoff they aren't older than
+        let mut stack = Vec::new();
+        self.add_entries(root, entries, 0, cutoffs, totals, &mut stack);
+
+        while let Some((dir, start)) = stack.pop() {
+            if self.progress.is_cancelled() {
+                return Err(interrupted_error());
+            }
+            // What can't be listed is left out
+            if let Ok((_, children, _, _)) = ls(&dir, self.options, self.progress.cancelled()) {
+                self.progress.add_items(children.len());
+                self.add_entries(&dir, &children, start, cutoffs, totals, &mut stack);
+            }
+            self.progress.add_done(1);
+        }
+        Ok(())
+    }
+
+    /// Add what's older than `cutoffs[start..]` among `entries` of `dir` to
+    /// `totals`, and push the directories to go into for the rest
+    fn add_entries(
+        &mut self,
+        dir: &Path,
+        entries: &[DirEntry],
+        start: usize,
+        cutoffs: &[usize],
+        totals: &mut [Purgeable],
+        stack: &mut Vec<(PathBuf, usize)>,
+    ) {
+        for entry in entries {
+            let name = entry.name.trim_end_matches('/');
+            if glob::matches_any(self.exclude, name) {
+                continue;
+            }
+            let path = dir.join(name);
+            let is_dir = entry.kind == EntryKind::Dir;
+            if is_dir
+                && !provider::get().lstat(&path).is_ok_and(|stat| {
+                    stat.dev == self.dev && self.seen.insert((stat.dev, stat.ino))
+                })
+            {
+                continue;
+            }
+            let old = start
+                + cutoffs[start..]
+                    .partition_point(|&cutoff| entry.ctime.is_some_and(|c| c < cutoff));
+            let whole = Purgeable {
+                bytes: entry.size.unwrap_or(0),
+                files: if is_dir {
+                    entry.rentries.unwrap_or(0) + 1
+                } else {
+                    1
+                },
+            };
+            for total in &mut totals[start..old] {
+                total.add(whole);
+            }
+            if is_dir && old < cutoffs.len() {
+                self.progress.add_total(1);
+                stack.push((path, old));
+            }
+        }
+    }
+}
@@ -0,0 +1,597 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+
+use chrono::Local;
+use clap::{Args, ValueEnum};
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+
+use crate::app::{DirEntry, EntryKind};
+use crate::config::{Config, CostConfig};
+use crate::exit::{self, Failure, GateArgs};
+use crate::fs::{get_effective_pool, has_ceph_stats, user_id, user_name};
+use crate::glob;
+use crate::loader::{LoadOptions, Loader, ls};
+use crate::provider;
+use crate::schema::{DirRecord, SCHEMA_VERSION};
+use crate::ui::{cost_str, rentries_str, size_str};
+
+/// Print a summary of the biggest directories and owners, e.g. for a nightly cron mail
+#[derive(Args)]
+pub struct ReportArgs {
+    /// Directory to summarize
+    pub path: PathBuf,
+
+    /// How many levels of subdirectories to include
+    #[arg(long, visible_alias = "max-depth", default_value_t = 2)]
+    pub depth: usize,
+
+    /// How many directories and owners to list
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+
+    #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+    pub format: ReportFormat,
+
+    /// Only count what this user (a name or uid) owns
+    #[arg(long, value_name = "USER")]
+    pub owner: Option<String>,
+
+    #[command(flatten)]
+    pub walk: WalkArgs,
+
+    #[command(flatten)]
+    pub gate: GateArgs,
+}
+
+/// Options for walking down a tree
+#[derive(Args, Clone, Default)]
+pub struct WalkArgs {
+    /// Don't go into directories on other filesystems, like mounts below PATH
+    #[arg(short = 'x', long)]
+    pub one_file_system: bool,
+
+    /// Leave out files and directories whose names match GLOB, like ".snapshot"
+    /// (can be repeated, and adds to the `exclude` setting)
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    Text,
+    Markdown,
+    /// One JSON object per directory, printed as the walk progresses
+    Jsonl,
+}
+
+/// A directory found while walking down to `--depth`
+#[derive(Debug, Clone)]
+pub struct Row {
+    pub path: PathBuf,
+    pub depth: usize,
+    pub size: usize,
+    pub rentries: usize,
+    pub rctime: Option<usize>,
+    pub uid: Option<u32>,
+}
+
+impl Row {
+    pub fn from_entry(path: PathBuf, depth: usize, entry: &DirEntry) -> Row {
+        Row {
+            path,
+            depth,
+            size: entry.size.unwrap_or(0),
+            rentries: entry.rentries.unwrap_or(0),
+            rctime: entry.ctime,
+            uid: entry.uid,
+        }
+    }
+
+    /// One line of JSON, for `--format jsonl`
+    pub fn to_json(&self) -> String {
+        self.to_record().to_json()
+    }
+
+    pub fn to_record(&self) -> DirRecord {
+        DirRecord {
+            schema_version: SCHEMA_VERSION,
+            path: self.path.clone(),
+            kind: None,
+            depth: self.depth,
+            bytes: self.size,
+            rentries: self.rentries,
+            rctime: self.rctime,
+            uid: self.uid,
+            owner: self.uid.map(|uid| user_name(uid).to_string()),
+        }
+    }
+}
+
+/// An entry of a listing, of any kind, at `path`, `depth` below the directory
+/// that was listed, like the control socket and the agent send it
+pub fn entry_record(path: PathBuf, depth: usize, entry: &DirEntry) -> DirRecord {
+    let mut record = Row::from_entry(path, depth, entry).to_record();
+    record.kind = Some(entry.kind.name().to_string());
+    record
+}
+
+/// List `root` and its subdirectories down to `max_depth`, using the
+/// recursive stats. Returns the entries for `root` itself and its children,
+/// and a row for every directory found below `root`, leaving out what's excluded.
+/// `on_row` is called for `root` and then every other directory as the walk
+/// progresses, so that results can be streamed.
+pub fn collect(
+    root: &Path,
+    max_depth: usize,
+    walk: &WalkArgs,
+    config: &Config,
+    on_row: &mut dyn FnMut(&Row),
+) -> Result<(DirEntry, Vec<DirEntry>, Vec<Row>)> {
+    if !has_ceph_stats(root) {
+        return Err(Failure::NotCeph(root.to_path_buf()).into());
+    }
+
+    let mut walker = Walker::new(root, max_depth, walk, config)?;
+    let (entry_root, mut entries, _, _) = ls(root, walker.options, &AtomicBool::new(false))?;
+    entries.retain(|entry| !walker.excludes(entry));
+    on_row(&Row::from_entry(root.to_path_buf(), 0, &entry_root));
+    let mut rows = Vec::new();
+    walker.collect_rows(root, &entries, 1, &mut rows, on_row);
+    Ok((entry_root, entries, rows))
+}
+
+/// Walks down a tree without going around in circles through a bind mount
+/// of a directory inside itself, or counting a directory that's mounted in
+/// two places twice, and, if asked, without leaving the filesystem
+pub struct Walker {
+    pub options: LoadOptions,
+    max_depth: usize,
+    /// The filesystem to stay on, for --one-file-system
+    dev: Option<u64>,
+    /// The (dev, inode) of every directory so far
+    visited: HashSet<(u64, u64)>,
+    /// Names to leave out, from the config and --exclude
+    exclude: Vec<String>,
+}
+
+impl Walker {
+    pub fn new(root: &Path, max_depth: usize, walk: &WalkArgs, config: &Config) -> Result<Walker> {
+        let mut options = Loader::new(config).options();
+        options.lazy_threshold = usize::MAX;
+        options.page_size = usize::MAX;
+        // A report that quietly leaves out what timed out would be wrong,
+        // so it waits
+        options.timeout = None;
+        let stat = provider::get().lstat(root)?;
+        Ok(Walker {
+            options,
+            max_depth,
+            dev: walk.one_file_system.then_some(stat.dev),
+            visited: HashSet::from([(stat.dev, stat.ino)]),
+            exclude: config
+                .exclude
+                .iter()
+                .chain(&walk.exclude)
+                .cloned()
+                .collect(),
+        })
+    }
+
+    /// Whether `entry` is to be left out, for matching an exclude pattern
+    pub fn excludes(&self, entry: &DirEntry) -> bool {
+        glob::matches_any(&self.exclude, entry.name.trim_end_matches('/'))
+    }
+
+    /// Whether to include the directory at `path`: not if it's on another
+    /// filesystem than it should be, or was already found somewhere else
+    pub fn admit(&mut self, path: &Path) -> bool {
+        // Listing it will say what's wrong
+        let Ok(stat) = provider::get().lstat(path) else {
+            return true;
+        };
+        if self.dev.is_some_and(|dev| dev != stat.dev) {
+            return false;
+        }
+        if !self.visited.insert((stat.dev, stat.ino)) {
+            eprintln!(
+                "Warning: skipping {:?}: already counted, e.g. through a bind mount",
+                path
+            );
+            return false;
+        }
+        true
+    }
+
+    /// Find what `uid` owns among `entries` of `dir`, recursing into the
+    /// directories they don't own until `max_depth`
+    fn collect_owned(
+        &mut self,
+        dir: &Path,
+        entries: &[DirEntry],
+        depth: usize,
+        uid: u32,
+        owned: &mut Owned,
+        on_row: &mut dyn FnMut(&Row),
+    ) {
+        for entry in entries {
+            if self.excludes(entry) {
+                continue;
+            }
+            let path = dir.join(entry.name.trim_end_matches('/'));
+            let is_dir = entry.kind == EntryKind::Dir;
+            if is_dir && !self.admit(&path) {
+                continue;
+            }
+            if entry.uid == Some(uid) && is_dir {
+                let row = Row::from_entry(path, depth, entry);
+                on_row(&row);
+                owned.dirs.push(row);
+            } else if entry.uid == Some(uid) {
+                owned.file_size += entry.size.unwrap_or(0);
+                owned.files += 1;
+            } else if is_dir && depth < self.max_depth {
+                match ls(&path, self.options, &AtomicBool::new(false)) {
+                    Ok((_, children, _, _)) => {
+                        self.collect_owned(&path, &children, depth + 1, uid, owned, on_row)
+                    }
+                    Err(e) => eprintln!("Warning: skipping {:?}: {}", path, e),
+                }
+            }
+        }
+    }
+
+    /// Add the subdirectories among `entries` of `dir`, and recurse into them
+    /// until `max_depth`. Directories that can't be listed are skipped with a warning.
+    fn collect_rows(
+        &mut self,
+        dir: &Path,
+        entries: &[DirEntry],
+        depth: usize,
+        rows: &mut Vec<Row>,
+        on_row: &mut dyn FnMut(&Row),
+    ) {
+        if depth > self.max_depth {
+            return;
+        }
+        for entry in entries.iter().filter(|e| e.kind == EntryKind::Dir) {
+            if self.excludes(entry) {
+                continue;
+            }
+            let path = dir.join(entry.name.trim_end_matches('/'));
+            if !self.admit(&path) {
+                continue;
+            }
+            let row = Row::from_entry(path.clone(), depth, entry);
+            on_row(&row);
+            rows.push(row);
+            if depth < self.max_depth {
+                match ls(&path, self.options, &AtomicBool::new(false)) {
+                    Ok((_, children, _, _)) => {
+                        self.collect_rows(&path, &children, depth + 1, rows, on_row)
+                    }
+                    Err(e) => eprintln!("Warning: skipping {:?}: {}", path, e),
+                }
+            }
+        }
+    }
+}
+
+pub fn run(args: &ReportArgs, config: &Config) -> Result<()> {
+    let root = exit::resolve(&args.path)?;
+    if let Some(owner) = &args.owner {
+        let uid = user_id(owner).ok_or_else(|| eyre!("No such user: {}", owner))?;
+        return run_owner(args, config, &root, uid);
+    }
+    if args.format == ReportFormat::Jsonl && !args.gate.quiet {
+        let (entry_root, _, _) = collect(&root, args.depth, &args.walk, config, &mut |row| {
+            println!("{}", row.to_json())
+        })?;
+        return args.gate.check(&root, entry_root.size.unwrap_or(0));
+    }
+    let (entry_root, entries, mut rows) =
+        collect(&root, args.depth, &args.walk, config, &mut |_| {})?;
+    if args.gate.quiet {
+        return args.gate.check(&root, entry_root.size.unwrap_or(0));
+    }
+
+    // Owners are credited with the top-level entries they own; finding out
+    // who owns what further down would mean crawling the tree.
+    let mut owners: HashMap<u32, (usize, usize)> = HashMap::new();
+    for entry in &entries {
+        if let Some(uid) = entry.uid {
+            let (size, rentries) = owners.entry(uid).or_default();
+            *size += entry.size.unwrap_or(0);
+            *rentries += entry.rentries.unwrap_or(0) + 1;
+        }
+    }
+
+    rows.sort_by_key(|row| Reverse(row.size));
+    rows.truncate(args.top);
+
+    let mut projects = match &config.projects {
+        Some(_) => config.project_map().map_err(|e| eyre!(e))?.usage(&entries),
+        None => Vec::new(),
+    };
+    projects.truncate(args.top);
+
+    let mut owners: Vec<(u32, (usize, usize))> = owners.into_iter().collect();
+    owners.sort_by_key(|(_, (size, _))| Reverse(*size));
+    owners.truncate(args.top);
+
+    let cost = Cost::new(&config.cost);
+    let directories: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| {
+            let path = row.path.strip_prefix(&root).unwrap_or(&row.path);
+            let mut cells = vec![
+                size_str(Some(row.size), false),
+                rentries_str(Some(row.rentries), false),
+                row.uid
+                    .map(|uid| user_name(uid).to_string())
+                    .unwrap_or_default(),
+                format!("{}/", path.display()),
+            ];
+            if let Some(cost) = &cost {
+                cells.insert(2, cost.of(row.size, &row.path));
+            }
+            cells
+        })
+        .collect();
+    let owners: Vec<[String; 4]> = owners
+        .iter()
+        .map(|(uid, (size, rentries))| {
+            [
+                size_str(Some(*size), false),
+                rentries_str(Some(*rentries), false),
+                user_name(*uid).to_string(),
+                String::new(),
+            ]
+        })
+        .collect();
+    let projects: Vec<[String; 3]> = projects
+        .into_iter()
+        .map(|(project, size, files)| {
+            [
+                size_str(Some(size), false),
+                rentries_str(Some(files), false),
+                project,
+            ]
+        })
+        .collect();
+
+    let mut total = format!(
+        "{} in {} files",
+        size_str(entry_root.size, false),
+        rentries_str(entry_root.rentries, false)
+    );
+    if let Some(cost) = &cost {
+        let monthly = cost.of(entry_root.size.unwrap_or(0), &root);
+        total.push_str(&format!(", about {} a month", monthly));
+    }
+    let generated = Local::now().format("%Y-%m-%d %H:%M");
+    let dirs_title = format!("Biggest directories (depth {})", args.depth);
+    let owners_title = "Biggest owners (of top-level entries)";
+    let (header, numbers) = match cost {
+        Some(_) => (vec!["Size", "Files", "Cost", "Owner", "Path"], 3),
+        None => (vec!["Size", "Files", "Owner", "Path"], 2),
+    };
+    let owners_header = ["Size", "Files", "Owner"];
+    let projects_title = "Biggest projects (of top-level entries)";
+    let projects_header = ["Size", "Files", "Project"];
+
+    match args.format {
+        ReportFormat::Text => {
+            println!("cephdu report for {}", root.display());
+            println!("Generated {}", generated);
+            println!("Total: {}", total);
+            println!("\n{}:", dirs_title);
+            print_text_table(&header, numbers, &directories);
+            println!("\n{}:", owners_title);
+            print_text_table(&owners_header, 2, &owners);
+            if !projects.is_empty() {
+                println!("\n{}:", projects_title);
+                print_text_table(&projects_header, 2, &projects);
+            }
+        }
+        ReportFormat::Jsonl => unreachable!("streamed above"),
+        ReportFormat::Markdown => {
+            println!("# cephdu report for `{}`", root.display());
+            println!();
+            println!("Generated {}. Total: {}.", generated, total);
+            println!("\n## {}\n", dirs_title);
+            print_markdown_table(&header, numbers, &directories);
+            println!("\n## {}\n", owners_title);
+            print_markdown_table(&owners_header, 2, &owners);
+            if !projects.is_empty() {
+                println!("\n## {}\n", projects_title);
+                print_markdown_table(&projects_header, 2, &projects);
+            }
+        }
+    }
+    args.gate.check(&root, entry_root.size.unwrap_or(0))
+}
+
+/// What one user owns under a directory: the topmost directories they own
+/// (everything in those is counted as theirs, since the recursive stats
+/// can't be broken down by owner), and the files they own outside of those.
+#[derive(Default)]
+struct Owned {
+    dirs: Vec<Row>,
+    file_size: usize,
+    files: usize,
+}
+
+impl Owned {
+    fn size(&self) -> usize {
+        self.file_size + self.dirs.iter().map(|row| row.size).sum::<usize>()
+    }
+
+    /// Each directory counts itself as a file, like rentries does
+    fn rentries(&self) -> usize {
+        self.files + self.dirs.iter().map(|row| row.rentries + 1).sum::<usize>()
+    }
+}
+
+/// The report for `--owner`: what `uid` owns under `root`, looking for it
+/// down to `--depth` in the directories they don't own
+fn run_owner(args: &ReportArgs, config: &Config, root: &Path, uid: u32) -> Result<()> {
+    if !has_ceph_stats(root) {
+        return Err(Failure::NotCeph(root.to_path_buf()).into());
+    }
+    let mut walker = Walker::new(root, args.depth, &args.walk, config)?;
+    let stream = args.format == ReportFormat::Jsonl && !args.gate.quiet;
+    let mut on_row = |row: &Row| {
+        if stream {
+            println!("{}", row.to_json())
+        }
+    };
+    let (entry_root, entries, _, _) = ls(root, walker.options, &AtomicBool::new(false))?;
+    let mut owned = Owned::default();
+    if entry_root.uid == Some(uid) {
+        let row = Row::from_entry(root.to_path_buf(), 0, &entry_root);
+        on_row(&row);
+        owned.dirs.push(row);
+    } else {
+        walker.collect_owned(root, &entries, 1, uid, &mut owned, &mut on_row);
+    }
+    if stream || args.gate.quiet {
+        return args.gate.check(root, owned.size());
+    }
+
+    let name = user_name(uid);
+    let mut total = format!(
+        "{} in {} files",
+        size_str(Some(owned.size()), false),
+        rentries_str(Some(owned.rentries()), false)
+    );
+    let cost = Cost::new(&config.cost);
+    if let Some(cost) = &cost {
+        total.push_str(&format!(", about {} a month", cost.of(owned.size(), root)));
+    }
+    let loose_files = format!(
+        "{} in {} files outside of those directories",
+        size_str(Some(owned.file_size), false),
+        rentries_str(Some(owned.files), false)
+    );
+    owned.dirs.sort_by_key(|row| Reverse(row.size));
+    owned.dirs.truncate(args.top);
+    let directories: Vec<Vec<String>> = owned
+        .dirs
+        .iter()
+        .map(|row| {
+            let path = row.path.strip_prefix(root).unwrap_or(&row.path);
+            let mut cells = vec![
+                size_str(Some(row.size), false),
+                rentries_str(Some(row.rentries), false),
+                format!("{}/", path.display()),
+            ];
+            if let Some(cost) = &cost {
+                cells.insert(2, cost.of(row.size, &row.path));
+            }
+            cells
+        })
+        .collect();
+    let generated = Local::now().format("%Y-%m-%d %H:%M");
+    let dirs_title = format!("Biggest directories owned by {}", name);
+    let (header, numbers) = match cost {
+        Some(_) => (vec!["Size", "Files", "Cost", "Path"], 3),
+        None => (vec!["Size", "Files", "Path"], 2),
+    };
+
+    match args.format {
+        ReportFormat::Text => {
+            println!("cephdu report for {}, owner {}", root.display(), name);
+            println!("Generated {}", generated);
+            println!("Total: {}", total);
+            println!("Files: {}", loose_files);
+            println!("\n{}:", dirs_title);
+            print_text_table(&header, numbers, &directories);
+        }
+        ReportFormat::Jsonl => unreachable!("streamed above"),
+        ReportFormat::Markdown => {
+            println!("# cephdu report for `{}`, owner {}", root.display(), name);
+            println!();
+            println!("Generated {}. Total: {}.", generated, total);
+            println!("Files: {}.", loose_files);
+            println!("\n## {}\n", dirs_title);
+            print_markdown_table(&header, numbers, &directories);
+        }
+    }
+    args.gate.check(root, owned.size())
+}
+
+/// What directories cost a month, for the reports of sites with rates
+struct Cost<'a> {
+    config: &'a CostConfig,
+}
+
+impl Cost<'_> {
+    fn new(config: &CostConfig) -> Option<Cost<'_>> {
+        config.is_set().then_some(Cost { config })
+    }
+
+    /// What `bytes` in the directory `path` cost, or "?" if its pool has no rate
+    fn of(&self, bytes: usize, path: &Path) -> String {
+        let pool = self
+            .config
+            .by_pool()
+            .then(|| get_effective_pool(path))
+            .flatten();
+        match self.config.monthly(bytes, pool.as_deref()) {
+            Some(cost) => cost_str(cost, &self.config.currency),
+            None => "?".to_string(),
+        }
+    }
+}
+
+/// Right-align the first `numbers` columns, left-align the rest
+pub fn print_text_table(header: &[&str], numbers: usize, rows: &[impl AsRef<[String]>]) {
+    let columns = header.len();
+    let widths: Vec<usize> = (0..columns)
+        .map(|i| {
+            rows.iter()
+                .map(|row| row.as_ref()[i].chars().count())
+                .chain([header[i].chars().count()])
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let line = |cells: &[&str]| {
+        let line: Vec<String> = cells
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| {
+                if i < numbers {
+                    format!("{:>w$}", cell, w = widths[i])
+                } else {
+                    format!("{:w$}", cell, w = widths[i])
+                }
+            })
+            .collect();
+        println!("  {}", line.join("  ").trim_end());
+    };
+    line(header);
+    for row in rows {
+        let cells: Vec<&str> = row.as_ref()[..columns].iter().map(|s| s.as_str()).collect();
+        line(&cells);
+    }
+}
+
+pub fn print_markdown_table(header: &[&str], numbers: usize, rows: &[impl AsRef<[String]>]) {
+    let columns = header.len();
+    println!("| {} |", header.join(" | "));
+    let align: Vec<&str> = (0..columns)
+        .map(|i| if i < numbers { "---:" } else { "---" })
+        .collect();
+    println!("| {} |", align.join(" | "));
+    for row in rows {
+        let cells: Vec<String> = row.as_ref()[..columns]
+            .iter()
+            .map(|cell| cell.replace('|', "\\|"))
+            .collect();
+        println!("| {} |", cells.join(" | "));
+    }
+}
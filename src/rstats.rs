@@ -0,0 +1,386 @@
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{LazyLock, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::command;
+use crate::config::RecursiveStatsConfig;
+use crate::fs::{
+    CEPH_RSTATS_ATTRS, Quota, get_fs, get_quota, get_rbytes, get_rctime, get_rentries,
+    has_ceph_stats,
+};
+use crate::loader::Timings;
+use crate::mounts::{self, Mount};
+use crate::provider::{self, Stat};
+
+/// How long to wait for lfs and the GPFS commands
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// How long what the commands said is used for. A listing asks again for
+/// every directory in it whenever it's refreshed, so while they may be a
+/// bit stale, quota usage is anyway.
+const COMMAND_TTL: Duration = Duration::from_secs(60);
+
+/// What a directory holds, all the way down
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RStats {
+    pub bytes: Option<usize>,
+    /// Files and directories, including the directory itself, like ceph.dir.rentries
+    pub entries: Option<usize>,
+    /// When anything in it last changed
+    pub ctime: Option<usize>,
+    pub quota: Option<Quota>,
+}
+
+/// Where the recursive stats of directories come from, which depends on the
+/// filesystem: CephFS keeps them for every directory, while on others, the
+/// quota usage of some directories can be asked for
+pub trait RecursiveStats: Send + Sync {
+    /// Where they come from, for the info popup, e.g. "Lustre project quotas"
+    fn name(&self) -> &'static str;
+
+    /// The stats of the directory `path`, as far as they're known, timing
+    /// the calls it takes with `timings`
+    fn load(&self, path: &Path, stat: &Stat, timings: &mut Timings) -> RStats;
+
+    /// The stats of each of `dirs`, which a backend can fetch in a batch
+    fn load_many(&self, dirs: &[(&Path, &Stat)], timings: &mut Timings) -> Vec<RStats> {
+        dirs.iter()
+            .map(|(path, stat)| self.load(path, stat, timings))
+            .collect()
+    }
+}
+
+/// Filesystems that don't keep track of them
+struct NoStats;
+
+impl RecursiveStats for NoStats {
+    fn name(&self) -> &'static str {
+        "nowhere"
+    }
+
+    fn load(&self, _: &Path, _: &Stat, _: &mut Timings) -> RStats {
+        RStats::default()
+    }
+}
+
+/// CephFS, whose MDS keeps them in virtual xattrs, and NFS exports of it
+/// that pass those through
+struct CephStats;
+
+impl RecursiveStats for CephStats {
+    fn name(&self) -> &'static str {
+        "CephFS"
+    }
+
+    fn load(&self, path: &Path, _: &Stat, timings: &mut Timings) -> RStats {
+        // One after the other, to take advantage of the MDS caching them
+        let entries = timings.time_xattr(|| get_rentries(path));
+        let bytes = timings.time_xattr(|| get_rbytes(path));
+        let ctime = timings.time_xattr(|| get_rctime(path));
+        let quota = timings.time_xattr(|| get_quota(path));
+        RStats {
+            bytes,
+            entries,
+            ctime,
+            quota,
+        }
+    }
+
+    fn load_many(&self, dirs: &[(&Path, &Stat)], timings: &mut Timings) -> Vec<RStats> {
+        let requests: Vec<(&Path, &'static CStr)> = dirs
+            .iter()
+            .flat_map(|(path, _)| CEPH_RSTATS_ATTRS.map(|name| (*path, name)))
+            .collect();
+        let values = timings.time_xattrs(requests.len(), || provider::get().xattr_many(&requests));
+        values
+            .chunks(CEPH_RSTATS_ATTRS.len())
+            .map(|values| {
+                let text = |i: usize| {
+                    let value = values.get(i)?.as_ref()?;
+                    Some(String::from_utf8_lossy(value).trim().to_string())
+                };
+                RStats {
+                    entries: text(0).and_then(|v| v.parse().ok()),
+                    bytes: text(1).and_then(|v| v.parse().ok()),
+                    // "seconds.nanos"
+                    ctime: text(2).and_then(|v| v.split('.').next()?.parse().ok()),
+                    quota: text(3)
+                        .and_then(|v| Quota::parse(&v))
+                        .filter(|q| q.max_bytes > 0 || q.max_files > 0),
+                }
+            })
+            .collect()
+    }
+}
+
+/// What the settings say to use; see `configure()`
+static CONFIG: LazyLock<RwLock<RecursiveStatsConfig>> = LazyLock::new(Default::default);
+/// Which backend each device (`Stat::dev`) gets, since finding out takes a statfs
+static BACKENDS: LazyLock<Mutex<HashMap<u64, &'static dyn RecursiveStats>>> =
+    LazyLock::new(Default::default);
+
+pub fn configure(config: &RecursiveStatsConfig) {
+    *CONFIG.write().expect("not poisoned") = config.clone();
+    BACKENDS.lock().expect("not poisoned").clear();
+}
+
+/// The backend for the directory `path`, on the device `dev`
+pub fn for_dir(path: &Path, dev: u64) -> &'static dyn RecursiveStats {
+    if let Some(&backend) = BACKENDS.lock().expect("not poisoned").get(&dev) {
+        return backend;
+    }
+    let config = CONFIG.read().expect("not poisoned").clone();
+    let backend: &'static dyn RecursiveStats = match get_fs(path) {
+        _ if has_ceph_stats(path) => &CephStats,
+        Some(fs) if fs.is_lustre() && config.lustre => &LustreStats,
+        Some(fs) if fs.is_gpfs() && config.gpfs => &GpfsStats,
+        _ => &NoStats,
+    };
+    BACKENDS.lock().expect("not poisoned").insert(dev, backend);
+    backend
+}
+
+/// Whether `backend` gives any stats at all
+pub fn is_none(backend: &dyn RecursiveStats) -> bool {
+    backend.name() == NoStats.name()
+}
+
+/// What commands said recently, by what was asked
+type CommandCache<K, V> = LazyLock<Mutex<HashMap<K, (V, Instant)>>>;
+
+static MOUNTS: CommandCache<u64, Option<Mount>> = LazyLock::new(Default::default);
+
+/// The mount that `path`, on the device `dev`, is on, without reading the
+/// mount table for every directory
+fn mount_of(path: &Path, dev: u64) -> Option<Mount> {
+    cached(&MOUNTS, dev, COMMAND_TTL, || mounts::find(path))
+}
+
+/// `key`'s value in `cache`, or what `get` says if it's missing or too old
+fn cached<K, V>(cache: &CommandCache<K, V>, key: K, ttl: Duration, get: impl FnOnce() -> V) -> V
+where
+    K: std::hash::Hash + Eq,
+    V: Clone,
+{
+    if let Some((value, time)) = cache.lock().expect("not poisoned").get(&key)
+        && time.elapsed() < ttl
+    {
+        return value.clone();
+    }
+    let value = get();
+    cache
+        .lock()
+        .expect("not poisoned")
+        .insert(key, (value.clone(), Instant::now()));
+    value
+}
+
+/// Lustre keeps no recursive stats, but a directory whose tree makes up
+/// a project (`lfs project -p ID -s DIR`) has the usage of the project's quota
+struct LustreStats;
+
+/// FS_IOC_FSGETXATTR, from linux/fs.h: _IOR('X', 31, struct fsxattr)
+const FS_IOC_FSGETXATTR: libc::c_ulong = 0x801c_581f;
+/// New files and directories get the project ID of the directory they're in
+const FS_XFLAG_PROJINHERIT: u32 = 0x200;
+
+#[repr(C)]
+#[derive(Default)]
+struct FsXattr {
+    xflags: u32,
+    extsize: u32,
+    nextents: u32,
+    projid: u32,
+    cowextsize: u32,
+    pad: [u8; 8],
+}
+
+static LFS_QUOTAS: CommandCache<(PathBuf, u32), Option<RStats>> = LazyLock::new(Default::default);
+
+impl LustreStats {
+    /// The project ID of `path`, if it passes it on to what's created in it
+    fn project(path: &Path) -> Option<u32> {
+        let dir = std::fs::File::open(path).ok()?;
+        let mut fsx = FsXattr::default();
+        let res = unsafe { libc::ioctl(dir.as_raw_fd(), FS_IOC_FSGETXATTR, &mut fsx) };
+        (res == 0 && fsx.projid != 0 && fsx.xflags & FS_XFLAG_PROJINHERIT != 0)
+            .then_some(fsx.projid)
+    }
+
+    /// The usage and limits of project `id` on the filesystem mounted at
+    /// `mount_point`, from `lfs quota -q -p ID MOUNT`, e.g.
+    /// "/mnt/lustre 1234 0 2048 - 56 0 0 -", with the sizes in kilobytes
+    fn quota(mount_point: &Path, id: u32) -> Option<RStats> {
+        let lfs = CONFIG.read().expect("not poisoned").lfs.clone();
+        let mut cmd = Command::new(lfs);
+        cmd.args(["quota", "-q", "-p", &id.to_string()])
+            .arg(mount_point);
+        let output = command::output(cmd, TIMEOUT).ok()?;
+        let text = String::from_utf8_lossy(&output.stdout);
+        // A long filesystem name is on a line of its own
+        let fields: Vec<&str> = text.split_whitespace().skip(1).collect();
+        // Over its quota, the usage is marked with a '*'
+        let number =
+            |i: usize| -> Option<usize> { fields.get(i)?.trim_end_matches('*').parse().ok() };
+        let (kbytes, kbytes_limit) = (number(0)?, number(2)?);
+        let (files, files_limit) = (number(4)?, number(6)?);
+        Some(RStats {
+            bytes: Some(kbytes * 1024),
+            entries: Some(files),
+            ctime: None,
+            quota: Some(Quota {
+                max_bytes: kbytes_limit * 1024,
+                max_files: files_limit,
+            })
+            .filter(|q| q.max_bytes > 0 || q.max_files > 0),
+        })
+    }
+}
+
+impl RecursiveStats for LustreStats {
+    fn name(&self) -> &'static str {
+        "Lustre project quotas"
+    }
+
+    fn load(&self, path: &Path, stat: &Stat, timings: &mut Timings) -> RStats {
+        let Some(id) = timings.time_xattr(|| Self::project(path)) else {
+            return RStats::default();
+        };
+        // Everything below the top of the project has the same ID
+        let parent = path.parent().and_then(Self::project);
+        if parent == Some(id) {
+            return RStats::default();
+        }
+        let Some(mount) = mount_of(path, stat.dev) else {
+            return RStats::default();
+        };
+        let key = (mount.mount_point, id);
+        let stats = timings.time_xattr(|| {
+            cached(&LFS_QUOTAS, key.clone(), COMMAND_TTL, || {
+                Self::quota(&key.0, id)
+            })
+        });
+        stats.unwrap_or_default()
+    }
+}
+
+/// GPFS (IBM Storage Scale) keeps no recursive stats either, but the
+/// directory a fileset is linked at has the usage of the fileset's quota
+struct GpfsStats;
+
+static GPFS_FILESETS: CommandCache<String, HashMap<PathBuf, String>> =
+    LazyLock::new(Default::default);
+static GPFS_QUOTAS: CommandCache<(String, String), Option<RStats>> =
+    LazyLock::new(Default::default);
+
+impl GpfsStats {
+    /// Run one of the mm commands with -Y, which prints a header line and
+    /// then lines of fields separated by colons, and return each line as
+    /// the fields by the names in the header
+    fn query(name: &str, args: &[&str]) -> Option<Vec<HashMap<String, String>>> {
+        let bin = CONFIG.read().expect("not poisoned").gpfs_bin.clone();
+        let mut cmd = Command::new(bin.join(name));
+        cmd.args(args).arg("-Y");
+        let output = command::output(cmd, TIMEOUT).ok()?;
+        let text = String::from_utf8_lossy(&output.stdout);
+        let mut lines = text.lines().map(|line| line.split(':').collect::<Vec<_>>());
+        let header = lines.find(|fields| fields.get(2) == Some(&"HEADER"))?;
+        Some(
+            lines
+                .filter(|fields| fields.get(2) != Some(&"HEADER"))
+                .map(|fields| {
+                    header
+                        .iter()
+                        .zip(fields)
+                        .map(|(name, value)| (name.to_string(), percent_decode(value)))
+                        .collect()
+                })
+                .collect(),
+        )
+    }
+
+    /// The filesets of `device`, by where they're linked
+    fn filesets(device: &str) -> HashMap<PathBuf, String> {
+        let filesets = Self::query("mmlsfileset", &[device]).unwrap_or_default();
+        filesets
+            .into_iter()
+            .filter_map(|fields| {
+                let path = fields.get("path").filter(|p| p.starts_with('/'))?;
+                Some((PathBuf::from(path), fields.get("filesetName")?.clone()))
+            })
+            .collect()
+    }
+
+    /// The usage and limits of the quota of `fileset`, in kilobytes
+    fn quota(device: &str, fileset: &str) -> Option<RStats> {
+        let quotas = Self::query("mmlsquota", &["-j", fileset, device])?;
+        let fields = quotas.first()?;
+        let number = |name: &str| -> Option<usize> { fields.get(name)?.parse().ok() };
+        let (kbytes, kbytes_limit) = (number("blockUsage")?, number("blockLimit")?);
+        let (files, files_limit) = (number("filesUsage")?, number("filesLimit")?);
+        Some(RStats {
+            bytes: Some(kbytes * 1024),
+            entries: Some(files),
+            ctime: None,
+            quota: Some(Quota {
+                max_bytes: kbytes_limit * 1024,
+                max_files: files_limit,
+            })
+            .filter(|q| q.max_bytes > 0 || q.max_files > 0),
+        })
+    }
+}
+
+impl RecursiveStats for GpfsStats {
+    fn name(&self) -> &'static str {
+        "GPFS fileset quotas"
+    }
+
+    fn load(&self, path: &Path, stat: &Stat, timings: &mut Timings) -> RStats {
+        // The device is what's mounted, e.g. "gpfs0"
+        let Some(mount) = mount_of(path, stat.dev) else {
+            return RStats::default();
+        };
+        let device = mount.source;
+        let filesets = timings.time_xattr(|| {
+            cached(&GPFS_FILESETS, device.clone(), COMMAND_TTL, || {
+                Self::filesets(&device)
+            })
+        });
+        let Some(fileset) = filesets.get(path) else {
+            return RStats::default();
+        };
+        let key = (device.clone(), fileset.clone());
+        let stats = timings.time_xattr(|| {
+            cached(&GPFS_QUOTAS, key, COMMAND_TTL, || {
+                Self::quota(&device, fileset)
+            })
+        });
+        stats.unwrap_or_default()
+    }
+}
+
+/// The mm commands' -Y output escapes colons and other characters like
+/// URLs do, e.g. "%3A" for ':'
+fn percent_decode(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = field.get(i + 1..i + 3);
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(byte) if bytes[i] == b'%' => {
+                out.push(byte);
+                i += 3;
+            }
+            _ => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -0,0 +1,156 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// The version of the JSON that `report`, `scan`, `diff`, and `dashboard`
+/// write, and that the control socket and the agent reply with, in the
+/// `schema_version` field of every object. It only goes up when a field is
+/// renamed, removed, or changes meaning; new fields can appear without it.
+/// Records written by older versions can still be read.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// A directory, as `report --format jsonl` and `scan --format jsonl` print it,
+/// or an entry of a listing that the control socket or the agent sends
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirRecord {
+    pub schema_version: u32,
+    pub path: PathBuf,
+    /// "file", "dir", or "symlink", for the control socket's entries, which
+    /// aren't all directories
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// How far below the directory that was walked, which is at depth 0
+    pub depth: usize,
+    pub bytes: usize,
+    pub rentries: usize,
+    pub rctime: Option<usize>,
+    pub uid: Option<u32>,
+    pub owner: Option<String>,
+}
+
+/// How a directory changed between two snapshots, as `diff --format jsonl`
+/// prints it. The sizes before are missing for new directories, and the
+/// ones after for directories that are gone.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffRecord {
+    pub schema_version: u32,
+    pub path: PathBuf,
+    pub bytes_before: Option<usize>,
+    pub bytes_after: Option<usize>,
+    pub bytes_delta: isize,
+    pub rentries_before: Option<usize>,
+    pub rentries_after: Option<usize>,
+    pub rentries_delta: isize,
+}
+
+/// A share, as `dashboard --format jsonl` prints it. The quota limits are
+/// missing without a quota, and the growth without enough history.
+#[derive(Debug, Clone, Serialize)]
+pub struct ShareRecord {
+    pub schema_version: u32,
+    pub path: PathBuf,
+    pub bytes: Option<usize>,
+    pub rentries: Option<usize>,
+    pub max_bytes: Option<usize>,
+    pub max_files: Option<usize>,
+    /// Bytes per week, on average over the last weeks of history
+    pub growth_per_week: Option<isize>,
+}
+
+/// The current directory, as the control socket's `query cwd` replies with it
+#[derive(Debug, Clone, Serialize)]
+pub struct CwdRecord {
+    pub schema_version: u32,
+    pub path: PathBuf,
+}
+
+/// The listing of the current directory, as shown, as the control socket's
+/// `export json` replies with it. Its entries are at depth 1.
+#[derive(Debug, Clone, Serialize)]
+pub struct ListingRecord {
+    pub schema_version: u32,
+    pub path: PathBuf,
+    pub bytes: usize,
+    pub rentries: usize,
+    pub entries: Vec<DirRecord>,
+}
+
+impl DirRecord {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("records are always serializable")
+    }
+
+    /// Read one line of JSONL written by any version of cephdu so far
+    pub fn parse(line: &str) -> Result<DirRecord, String> {
+        let value: Value = serde_json::from_str(line).map_err(|e| e.to_string())?;
+        let Value::Object(mut record) = value else {
+            return Err("not a JSON object".to_string());
+        };
+        let version = match record.get("schema_version") {
+            // Before there were versions
+            None => 0,
+            Some(version) => version
+                .as_u64()
+                .and_then(|v| u32::try_from(v).ok())
+                .ok_or_else(|| format!("bad schema_version {}", version))?,
+        };
+        if version > SCHEMA_VERSION {
+            return Err(format!(
+                "schema version {} is from a newer cephdu; this one reads up to {}",
+                version, SCHEMA_VERSION
+            ));
+        }
+        upgrade(&mut record, version);
+        serde_json::from_value(Value::Object(record)).map_err(|e| e.to_string())
+    }
+}
+
+impl DiffRecord {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("records are always serializable")
+    }
+}
+
+impl ShareRecord {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("records are always serializable")
+    }
+}
+
+impl CwdRecord {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("records are always serializable")
+    }
+}
+
+impl ListingRecord {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("records are always serializable")
+    }
+}
+
+/// Bring a record written as schema version `from` up to SCHEMA_VERSION,
+/// one version at a time
+fn upgrade(record: &mut Map<String, Value>, from: u32) {
+    for version in from..SCHEMA_VERSION {
+        match version {
+            // The same fields, without schema_version
+            0 => {}
+            _ => unreachable!("every older version has an upgrade step"),
+        }
+    }
+    record.insert("schema_version".to_string(), SCHEMA_VERSION.into());
+}
+
+/// Read a JSONL file of directories, e.g. saved from `report --format jsonl`
+pub fn read_records(path: &Path) -> Result<Vec<DirRecord>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{:?}: {}", path, e))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            DirRecord::parse(line).map_err(|e| format!("{:?}, line {}: {}", path, i + 1, e))
+        })
+        .collect()
+}
@@ -0,0 +1,312 @@
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use rhai::{AST, Array, Dynamic, Engine, EvalAltResult, FnPtr, Map};
+
+use crate::app::{DirEntry, EntryKind};
+use crate::command;
+use crate::jobs::Progress;
+use crate::keymap::ConfigKey;
+
+/// A function of a plugin script, with the script it's in
+#[derive(Debug, Clone)]
+pub struct ScriptFn {
+    f: FnPtr,
+    ast: Arc<AST>,
+}
+
+/// What a script added when it was loaded
+#[derive(Debug, Default)]
+pub struct Loaded {
+    /// Keys, with the names of the actions and what they call
+    pub actions: Vec<(ConfigKey, String, ScriptFn)>,
+    /// Column names, with what's called for each entry
+    pub columns: Vec<(String, ScriptFn)>,
+}
+
+/// What the script functions see while a script runs on this thread
+struct Context {
+    /// What's added while a script is being loaded, and only then
+    loaded: Option<Loaded>,
+    listing: Array,
+    selected: Dynamic,
+    dir: PathBuf,
+    deadline: Instant,
+    /// The job it's run for, which can be cancelled
+    progress: Option<Arc<Progress>>,
+    /// What the script printed
+    printed: String,
+}
+
+thread_local! {
+    static CONTEXT: RefCell<Option<Context>> = const { RefCell::new(None) };
+}
+
+/// The engine that all plugin scripts run in. Scripts can call:
+///
+/// - `listing()`: the entries of the directory being listed, as maps with
+///   `name`, `path`, `kind` ("dir", "file", or "symlink"), `size`, `files`,
+///   `ctime`, `uid`, and `gid`, the numbers being () where they aren't known
+/// - `selected()`: the highlighted entry, or () if none is, e.g. in a column
+/// - `run(["program", "arg", ...])`: run a command in the directory, and
+///   return what it printed, or throw why it failed
+/// - `add_column("name", f)`: show `f(entry)` next to each entry for which
+///   it doesn't return ()
+/// - `add_action("key", "name", f)`: call `f(entry)` on the highlighted
+///   entry when the key is pressed, showing what it printed and returned
+fn engine() -> &'static Engine {
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+    ENGINE.get_or_init(|| {
+        let mut engine = Engine::new();
+        engine.register_fn("listing", || {
+            with_context(|context| Ok(context.listing.clone()))
+        });
+        engine.register_fn("selected", || {
+            with_context(|context| Ok(context.selected.clone()))
+        });
+        engine.register_fn("run", run);
+        engine.register_fn("add_column", |name: &str, f: FnPtr| add_column(name, f));
+        engine.register_fn("add_column", |name: &str, f: &str| {
+            add_column(name, FnPtr::new(f)?)
+        });
+        engine.register_fn("add_action", |key: &str, name: &str, f: FnPtr| {
+            add_action(key, name, f)
+        });
+        engine.register_fn("add_action", |key: &str, name: &str, f: &str| {
+            add_action(key, name, FnPtr::new(f)?)
+        });
+        engine.on_print(|text| {
+            let _ = with_context(|context| {
+                context.printed.push_str(text);
+                context.printed.push('\n');
+                Ok(())
+            });
+        });
+        engine.on_debug(|_, _, _| {});
+        // Scripts that take too long, e.g. with an endless loop, or whose job
+        // was cancelled, are stopped
+        engine.on_progress(|_| {
+            let stop = with_context(|context| {
+                Ok(Instant::now() > context.deadline
+                    || context
+                        .progress
+                        .as_ref()
+                        .is_some_and(|progress| progress.cancelled().load(Ordering::Relaxed)))
+            });
+            stop.unwrap_or(false).then(|| "stopped".into())
+        });
+        engine
+    })
+}
+
+fn with_context<T>(
+    f: impl FnOnce(&mut Context) -> Result<T, Box<EvalAltResult>>,
+) -> Result<T, Box<EvalAltResult>> {
+    CONTEXT.with_borrow_mut(|context| match context {
+        Some(context) => f(context),
+        None => Err("not running in cephdu".into()),
+    })
+}
+
+/// Run `f` with `context` for the script functions on this thread
+fn with<T>(context: Context, f: impl FnOnce() -> T) -> (T, Context) {
+    CONTEXT.with_borrow_mut(|c| *c = Some(context));
+    let result = f();
+    let context = CONTEXT.with_borrow_mut(Option::take);
+    (result, context.expect("the context was set above"))
+}
+
+fn run(command: Array) -> Result<String, Box<EvalAltResult>> {
+    let args = command
+        .into_iter()
+        .map(|arg| arg.into_string())
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(|kind| format!("run() takes strings, not {}", kind))?;
+    let (program, args) = args.split_first().ok_or("run() needs a command")?;
+    let (dir, timeout, progress) = with_context(|context| {
+        if context.loaded.is_some() {
+            return Err("run() only works in actions and columns".into());
+        }
+        let timeout = context.deadline.saturating_duration_since(Instant::now());
+        Ok((context.dir.clone(), timeout, context.progress.clone()))
+    })?;
+    let mut cmd = Command::new(program);
+    cmd.args(args).env("CEPHDU_DIR", &dir);
+    // Not in the demo's made-up tree
+    if dir.is_dir() {
+        cmd.current_dir(&dir);
+    }
+    let not_cancelled = AtomicBool::new(false);
+    let cancel = progress
+        .as_deref()
+        .map_or(&not_cancelled, Progress::cancelled);
+    let output = command::output_unless(cmd, timeout, cancel)?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim_end()
+        .to_string())
+}
+
+fn add_column(name: &str, f: FnPtr) -> Result<(), Box<EvalAltResult>> {
+    with_context(|context| {
+        let loaded = context
+            .loaded
+            .as_mut()
+            .ok_or("add_column() only works while the script is loaded")?;
+        loaded
+            .columns
+            .push((name.to_string(), ScriptFn::pending(f)));
+        Ok(())
+    })
+}
+
+fn add_action(key: &str, name: &str, f: FnPtr) -> Result<(), Box<EvalAltResult>> {
+    let key = ConfigKey::try_from(key.to_string())?;
+    with_context(|context| {
+        let loaded = context
+            .loaded
+            .as_mut()
+            .ok_or("add_action() only works while the script is loaded")?;
+        loaded
+            .actions
+            .push((key, name.to_string(), ScriptFn::pending(f)));
+        Ok(())
+    })
+}
+
+impl ScriptFn {
+    /// A function whose script is still being loaded
+    fn pending(f: FnPtr) -> ScriptFn {
+        ScriptFn {
+            f,
+            ast: Arc::new(AST::empty()),
+        }
+    }
+}
+
+/// Compile and run the script at `path`, which adds its actions and columns.
+/// Running it can't run commands, and takes at most `timeout`.
+pub fn load(path: &Path, timeout: Duration) -> Result<Loaded, String> {
+    let error = |e: Box<EvalAltResult>| format!("Error in plugin {:?}: {}", path, e);
+    let ast = Arc::new(engine().compile_file(path.to_path_buf()).map_err(error)?);
+    let context = Context {
+        loaded: Some(Loaded::default()),
+        listing: Array::new(),
+        selected: Dynamic::UNIT,
+        dir: PathBuf::new(),
+        deadline: Instant::now() + timeout,
+        progress: None,
+        printed: String::new(),
+    };
+    let (result, context) = with(context, || engine().run_ast(&ast));
+    result.map_err(error)?;
+    let mut loaded = context.loaded.unwrap_or_default();
+    let functions = loaded
+        .actions
+        .iter_mut()
+        .map(|(_, _, f)| f)
+        .chain(loaded.columns.iter_mut().map(|(_, f)| f));
+    for f in functions {
+        f.ast = ast.clone();
+    }
+    Ok(loaded)
+}
+
+/// Call `f` with `args`, for what's in `dir`, until `timeout` or until the
+/// job of `progress` is cancelled. Returns what it printed, and what it
+/// returned.
+pub fn call(
+    f: &ScriptFn,
+    args: Vec<Dynamic>,
+    dir: &Path,
+    listing: Array,
+    selected: Dynamic,
+    timeout: Duration,
+    progress: Arc<Progress>,
+) -> (String, Result<Dynamic, String>) {
+    let context = Context {
+        loaded: None,
+        listing,
+        selected,
+        dir: dir.to_path_buf(),
+        deadline: Instant::now() + timeout,
+        progress: Some(progress),
+        printed: String::new(),
+    };
+    let (result, context) = with(context, || f.f.call::<Dynamic>(engine(), &f.ast, args));
+    (context.printed, result.map_err(|e| e.to_string()))
+}
+
+/// Call the column function `f` for each entry of `listing`, in `dir`,
+/// within `timeout` in all. Returns lines of "NAME<tab>VALUE", like the
+/// commands of columns print, for the entries it returned something for.
+pub fn column(
+    f: &ScriptFn,
+    dir: &Path,
+    listing: Array,
+    timeout: Duration,
+) -> Result<String, String> {
+    let context = Context {
+        loaded: None,
+        listing: listing.clone(),
+        selected: Dynamic::UNIT,
+        dir: dir.to_path_buf(),
+        deadline: Instant::now() + timeout,
+        progress: None,
+        printed: String::new(),
+    };
+    let (result, _) = with(context, || {
+        let mut lines = String::new();
+        for entry in listing {
+            let name = entry
+                .read_lock::<Map>()
+                .and_then(|entry| entry.get("name").map(|name| name.to_string()))
+                .unwrap_or_default();
+            let value = f.f.call::<Dynamic>(engine(), &f.ast, (entry,))?;
+            if value.is_unit() {
+                continue;
+            }
+            // One line each, for the names and the values
+            let value = value.to_string().replace(['\t', '\n'], " ");
+            lines.push_str(&format!("{}\t{}\n", name.replace(['\t', '\n'], " "), value));
+        }
+        Ok::<_, Box<EvalAltResult>>(lines)
+    });
+    result.map_err(|e| e.to_string())
+}
+
+/// `entry` of `dir` as a script sees it
+pub fn entry_map(dir: &Path, entry: &DirEntry) -> Dynamic {
+    let name = entry.name.trim_end_matches('/');
+    let number = |n: Option<usize>| n.map_or(Dynamic::UNIT, |n| Dynamic::from(n as i64));
+    let id = |id: Option<u32>| id.map_or(Dynamic::UNIT, |id| Dynamic::from(id as i64));
+    let kind = match entry.kind {
+        EntryKind::Dir => "dir",
+        EntryKind::File => "file",
+        EntryKind::Symlink => "symlink",
+    };
+    let mut map = Map::new();
+    map.insert("name".into(), name.into());
+    map.insert(
+        "path".into(),
+        dir.join(name).to_string_lossy().to_string().into(),
+    );
+    map.insert("kind".into(), kind.into());
+    map.insert("size".into(), number(entry.size));
+    map.insert("files".into(), number(entry.rentries));
+    map.insert("ctime".into(), number(entry.ctime));
+    map.insert("uid".into(), id(entry.uid));
+    map.insert("gid".into(), id(entry.gid));
+    Dynamic::from_map(map)
+}
+
+/// The entries of `dir`, but "..", as a script sees them
+pub fn listing<'a>(dir: &Path, entries: impl Iterator<Item = &'a DirEntry>) -> Array {
+    entries
+        .filter(|entry| entry.name != "..")
+        .map(|entry| entry_map(dir, entry))
+        .collect()
+}
@@ -0,0 +1,309 @@
+use std::collections::HashSet;
+use std::fs::{OpenOptions, Permissions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::app::EntryKind;
+use crate::config::{SearchConfig, cache_dir};
+use crate::glob;
+use crate::jobs::Progress;
+use crate::loader::interrupted_error;
+use crate::provider;
+
+/// A search stops once it has found this many
+pub const MAX_MATCHES: usize = 10_000;
+/// How often a walk passes on what it has found so far
+const BATCH_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Something a search found, by its path under the root
+#[derive(Debug, Clone)]
+pub struct Match {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+}
+
+/// What a search's thread sends: matches as it finds them, and then how it ended
+enum Update {
+    Found(Vec<Match>),
+    /// With the time of the index it read, if it read one
+    Done(Result<Option<SystemTime>, String>),
+}
+
+/// A search for names matching a glob under a directory, in the background.
+/// It walks the tree, or reads the tree's index, if there's a recent one.
+pub struct Search {
+    pub root: PathBuf,
+    pub pattern: String,
+    pub job: usize,
+    pub matches: Vec<Match>,
+    /// How it ended, once it has: with the time of the index it read, if any
+    pub done: Option<Result<Option<SystemTime>, String>>,
+    rx: Receiver<Update>,
+}
+
+impl Search {
+    /// Look under `root` for names matching `pattern`, or containing it if
+    /// it has no wildcards, as the job `job`, without going into what
+    /// matches `exclude`
+    pub fn start(
+        root: &Path,
+        pattern: &str,
+        exclude: &[String],
+        config: &SearchConfig,
+        persist: bool,
+        job: usize,
+        progress: Arc<Progress>,
+    ) -> Search {
+        let glob = if pattern.contains(['*', '?', '[']) {
+            pattern.to_string()
+        } else {
+            format!("*{}*", pattern)
+        };
+        let (tx, rx) = mpsc::channel();
+        let thread_root = root.to_path_buf();
+        let exclude = exclude.to_vec();
+        let config = config.clone();
+        provider::spawn(move || {
+            let result = run(
+                &thread_root,
+                &glob,
+                &exclude,
+                &config,
+                persist,
+                &progress,
+                &tx,
+            );
+            let _ = tx.send(Update::Done(result));
+        });
+        Search {
+            root: root.to_path_buf(),
+            pattern: pattern.to_string(),
+            job,
+            matches: Vec::new(),
+            done: None,
+            rx,
+        }
+    }
+
+    /// Take in what the search has found since the last call. Returns true
+    /// if there was anything.
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+        loop {
+            match self.rx.try_recv() {
+                Ok(Update::Found(matches)) => self.matches.extend(matches),
+                Ok(Update::Done(result)) => self.done = Some(result),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    if self.done.is_none() {
+                        self.done = Some(Err("the search thread died".to_string()));
+                    }
+                    break;
+                }
+            }
+            changed = true;
+        }
+        changed
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.done.is_none()
+    }
+}
+
+fn run(
+    root: &Path,
+    glob: &str,
+    exclude: &[String],
+    config: &SearchConfig,
+    persist: bool,
+    progress: &Progress,
+    tx: &Sender<Update>,
+) -> Result<Option<SystemTime>, String> {
+    let max_age = Duration::from_secs(config.index_max_age_secs);
+    if let Some((index_root, time)) = find_index(root)
+        && time.elapsed().is_ok_and(|age| age < max_age)
+    {
+        search_index(&index_root, root, glob, exclude, tx).map_err(|e| e.to_string())?;
+        return Ok(Some(time));
+    }
+    let index = (config.index && persist).then(Vec::new);
+    walk(root, glob, exclude, progress, tx, index).map_err(|e| e.to_string())?;
+    Ok(None)
+}
+
+/// Walk `root`, sending what matches `glob` in batches, and writing every
+/// path to the index if given somewhere to collect them. What matches
+/// `exclude` is neither searched nor indexed.
+fn walk(
+    root: &Path,
+    glob: &str,
+    exclude: &[String],
+    progress: &Progress,
+    tx: &Sender<Update>,
+    mut index: Option<Vec<(PathBuf, EntryKind)>>,
+) -> std::io::Result<()> {
+    let fs = provider::get();
+    let root_stat = fs.lstat(root)?;
+    let dev = root_stat.dev;
+    // Directories reached again, e.g. through a bind mount, aren't searched again
+    let mut visited = HashSet::from([(root_stat.dev, root_stat.ino)]);
+    let mut batch = Vec::new();
+    let mut sent_at = Instant::now();
+    let mut found = 0;
+    let mut stack = vec![PathBuf::new()];
+    progress.add_total(1);
+
+    while let Some(dir) = stack.pop() {
+        if progress.is_cancelled() {
+            return Err(interrupted_error());
+        }
+        progress.add_done(1);
+        let Ok(read_dir) = fs.read_dir(&root.join(&dir)) else {
+            continue;
+        };
+        for entry in read_dir.flatten() {
+            let Some(name) = entry.path.file_name() else {
+                continue;
+            };
+            if glob::matches_any(exclude, &name.to_string_lossy()) {
+                continue;
+            }
+            let path = dir.join(name);
+            progress.add_items(1);
+            let kind = entry.kind;
+            // Only the directories on the same filesystem
+            if kind == EntryKind::Dir
+                && let Ok(stat) = fs.lstat(&entry.path)
+                && stat.dev == dev
+                && visited.insert((stat.dev, stat.ino))
+            {
+                progress.add_total(1);
+                stack.push(path.clone());
+            }
+            if found < MAX_MATCHES && glob::matches(glob, &name.to_string_lossy()) {
+                batch.push(Match {
+                    path: path.clone(),
+                    kind,
+                });
+                found += 1;
+            }
+            if let Some(index) = &mut index {
+                index.push((path, kind));
+            }
+        }
+        if !batch.is_empty() && sent_at.elapsed() >= BATCH_INTERVAL {
+            let _ = tx.send(Update::Found(std::mem::take(&mut batch)));
+            sent_at = Instant::now();
+        }
+        // With no index to finish, there's no point going on
+        if found >= MAX_MATCHES && index.is_none() {
+            break;
+        }
+    }
+    if !batch.is_empty() {
+        let _ = tx.send(Update::Found(batch));
+    }
+    if let Some(index) = index {
+        // The search worked even if the index couldn't be saved
+        let _ = write_index(root, &index);
+    }
+    Ok(())
+}
+
+/// Where the index of `root` is kept
+fn index_path(root: &Path) -> Option<PathBuf> {
+    let name = root
+        .to_string_lossy()
+        .replace('%', "%25")
+        .replace('/', "%2F");
+    Some(cache_dir()?.join("index").join(name))
+}
+
+/// The index of `root`, or of the nearest directory above it that has one,
+/// with the directory and when the index was made
+fn find_index(root: &Path) -> Option<(PathBuf, SystemTime)> {
+    root.ancestors().find_map(|dir| {
+        let modified = std::fs::metadata(index_path(dir)?).ok()?.modified().ok()?;
+        Some((dir.to_path_buf(), modified))
+    })
+}
+
+/// One path per line, under `root`, after a 'd' for directories or an 'f'
+/// for everything else, and a tab
+fn write_index(root: &Path, index: &[(PathBuf, EntryKind)]) -> std::io::Result<()> {
+    let path = index_path(root).ok_or_else(|| std::io::Error::other("no home directory"))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // Written aside and moved into place, so that a search never reads half of it
+    let partial = path.with_extension("partial");
+    // It lists everything under `root`, which isn't for other users to read
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&partial)?;
+    // The mode is only given to new files, and one may be left over
+    file.set_permissions(Permissions::from_mode(0o600))?;
+    let mut out = BufWriter::new(file);
+    for (path, kind) in index {
+        let kind = if *kind == EntryKind::Dir { 'd' } else { 'f' };
+        writeln!(out, "{}\t{}", kind, path.display())?;
+    }
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    std::fs::rename(partial, path)
+}
+
+/// Send what in the index of `index_root` is under `root` and matches `glob`,
+/// leaving out what matches `exclude` as `walk()` would, since the index may
+/// have been made with other exclusions
+fn search_index(
+    index_root: &Path,
+    root: &Path,
+    glob: &str,
+    exclude: &[String],
+    tx: &Sender<Update>,
+) -> std::io::Result<()> {
+    let path = index_path(index_root).ok_or_else(|| std::io::Error::other("no home directory"))?;
+    let prefix = root.strip_prefix(index_root).unwrap_or(Path::new(""));
+    let mut matches = Vec::new();
+    for line in BufReader::new(std::fs::File::open(path)?).lines() {
+        let line = line?;
+        let Some((kind, path)) = line.split_once('\t') else {
+            continue;
+        };
+        let Ok(path) = Path::new(path).strip_prefix(prefix) else {
+            continue;
+        };
+        let Some(name) = path.file_name() else {
+            continue;
+        };
+        let excluded = path
+            .components()
+            .any(|c| glob::matches_any(exclude, &c.as_os_str().to_string_lossy()));
+        if excluded {
+            continue;
+        }
+        if glob::matches(glob, &name.to_string_lossy()) {
+            matches.push(Match {
+                path: path.to_path_buf(),
+                kind: if kind == "d" {
+                    EntryKind::Dir
+                } else {
+                    EntryKind::File
+                },
+            });
+            if matches.len() >= MAX_MATCHES {
+                break;
+            }
+        }
+    }
+    let _ = tx.send(Update::Found(matches));
+    Ok(())
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::command;
+use crate::config::SubvolumeConfig;
+use crate::provider;
+use crate::ui::size_str;
+
+/// How long to wait for the ceph command, which hangs if the monitors can't be reached
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// The group of subvolumes made without one
+const DEFAULT_GROUP: &str = "_nogroup";
+
+/// A subvolume made by the mgr's volumes module, which keeps it in
+/// `volumes/<group>/<name>/`, next to a `.meta` file, with the data in a
+/// directory below named by a UUID
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subvolume {
+    pub group: String,
+    pub name: String,
+}
+
+impl Subvolume {
+    /// The subvolume that `path` is, or is the data directory of
+    pub fn of(path: &Path) -> Option<Subvolume> {
+        [Some(path), path.parent()]
+            .into_iter()
+            .flatten()
+            .find_map(|dir| {
+                let mut components = dir.iter().rev();
+                let name = components.next()?.to_str()?;
+                let group = components.next()?.to_str()?;
+                let is_subvolume = components.next()? == "volumes"
+                    && provider::get().lstat(&dir.join(".meta")).is_ok();
+                is_subvolume.then(|| Subvolume {
+                    group: group.to_string(),
+                    name: name.to_string(),
+                })
+            })
+    }
+}
+
+impl fmt::Display for Subvolume {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.group.as_str() {
+            DEFAULT_GROUP => write!(f, "{}", self.name),
+            group => write!(f, "{} (group {})", self.name, group),
+        }
+    }
+}
+
+/// What `ceph fs subvolume info` says, as labels and values for the details popup
+pub type Info = Vec<(&'static str, String)>;
+
+/// Looks up subvolumes with the ceph command in the background, since it
+/// can take a while. Results are kept for the session.
+pub struct SubvolumeLookups {
+    pending: Vec<(PathBuf, Receiver<Result<Info, String>>)>,
+    results: HashMap<PathBuf, Result<Info, String>>,
+}
+
+impl SubvolumeLookups {
+    pub fn new() -> Self {
+        SubvolumeLookups {
+            pending: Vec::new(),
+            results: HashMap::new(),
+        }
+    }
+
+    /// Start looking up the subvolume at `path`, unless we already are.
+    /// `volume` is the CephFS it's on, if that's known.
+    pub fn request(
+        &mut self,
+        path: &Path,
+        subvolume: Subvolume,
+        volume: Option<&str>,
+        config: &SubvolumeConfig,
+    ) {
+        if self.pending.iter().any(|(p, _)| p == path) {
+            return;
+        }
+        let Some(volume) = config.volume.as_deref().or(volume) else {
+            let error = "don't know which CephFS it's on; set subvolumes.volume".to_string();
+            self.results.insert(path.to_path_buf(), Err(error));
+            return;
+        };
+        let (tx, rx) = mpsc::channel();
+        let command = config.command.clone();
+        let volume = volume.to_string();
+        provider::spawn(move || {
+            let _ = tx.send(look_up(&command, &volume, &subvolume));
+        });
+        self.pending.push((path.to_path_buf(), rx));
+    }
+
+    /// Collect the lookups that have finished. Returns their paths.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        let mut done = Vec::new();
+        self.pending.retain(|(path, rx)| match rx.try_recv() {
+            Err(TryRecvError::Empty) => true,
+            Ok(result) => {
+                self.results.insert(path.clone(), result);
+                done.push(path.clone());
+                false
+            }
+            Err(TryRecvError::Disconnected) => false,
+        });
+        done
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&Result<Info, String>> {
+        self.results.get(path)
+    }
+
+    /// Forget the results. Lookups in flight will still finish.
+    pub fn clear(&mut self) {
+        self.results.clear();
+    }
+}
+
+/// Run `<command> fs subvolume info`, giving up after `TIMEOUT`
+fn look_up(command: &[String], volume: &str, subvolume: &Subvolume) -> Result<Info, String> {
+    let (program, options) = command.split_first().ok_or("no command")?;
+    let mut cmd = Command::new(program);
+    cmd.args(options)
+        .args(["fs", "subvolume", "info", volume, &subvolume.name]);
+    if subvolume.group != DEFAULT_GROUP {
+        cmd.args(["--group_name", &subvolume.group]);
+    }
+    cmd.args(["--format", "json"]);
+    let output = command::output(cmd, TIMEOUT)?;
+    let info: Value = serde_json::from_slice(&output.stdout)
+        .map_err(|e| format!("couldn't parse what {} said: {}", program, e))?;
+    Ok(parse(&info))
+}
+
+/// The interesting parts of the JSON `ceph fs subvolume info` prints
+fn parse(info: &Value) -> Info {
+    let text = |key: &str| match &info[key] {
+        Value::String(s) => Some(s.clone()),
+        Value::Null => None,
+        value => Some(value.to_string()),
+    };
+    let mut lines = Vec::new();
+    if let Some(quota) = text("bytes_quota") {
+        let quota = match quota.parse::<usize>() {
+            Ok(bytes) => size_str(Some(bytes), false),
+            // "infinite"
+            Err(_) => "none".to_string(),
+        };
+        lines.push(("  quota:", quota));
+    }
+    if let Some(used) = info["bytes_used"].as_u64() {
+        let mut used = size_str(Some(used as usize), false);
+        if let Some(percent) = text("bytes_pcent").filter(|p| p.parse::<f64>().is_ok()) {
+            used.push_str(&format!(" ({}% of quota)", percent));
+        }
+        lines.push(("  used:", used));
+    }
+    if let (Some(uid), Some(gid)) = (text("uid"), text("gid")) {
+        lines.push(("  owner:", format!("{}:{}", uid, gid)));
+    }
+    if let Some(created) = text("created_at") {
+        lines.push(("  created:", created));
+    }
+    if let Some(pool) = text("data_pool") {
+        let pool = match text("pool_namespace").filter(|ns| !ns.is_empty()) {
+            Some(namespace) => format!("{} (namespace {})", pool, namespace),
+            None => pool,
+        };
+        lines.push(("  pool:", pool));
+    }
+    if let Some(state) = text("state") {
+        lines.push(("  state:", state));
+    }
+    lines
+}
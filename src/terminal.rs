@@ -0,0 +1,328 @@
+use std::io::{IsTerminal, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use chrono::Local;
+use clap::ValueEnum;
+use crossterm::cursor::{MoveTo, Show};
+use crossterm::style::{
+    Attribute, Print, ResetColor, SetAttribute, SetBackgroundColor, SetForegroundColor,
+};
+use crossterm::terminal::SetTitle;
+use crossterm::{execute, queue};
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::style::Modifier;
+use ratatui::{TerminalOptions, Viewport};
+use signal_hook::consts::signal::{SIGHUP, SIGQUIT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+// XTWINOPS: save and restore the window title on the terminal's stack
+const PUSH_TITLE: &str = "\x1b[22;0t";
+const POP_TITLE: &str = "\x1b[23;0t";
+
+/// A description of the app state, refreshed every frame, to be written to
+/// the crash report if we go down.
+static STATE: Mutex<String> = Mutex::new(String::new());
+
+/// Whether we're drawing in the normal screen, below the prompt, rather
+/// than in the alternate screen
+static INLINE: AtomicBool = AtomicBool::new(false);
+
+/// Set on SIGHUP, which asks for the config file to be reloaded
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Make sure the terminal is restored however we exit: on a panic, or on a
+/// termination signal. If `crash_report` is given, a report is written there too.
+/// SIGHUP doesn't exit, but asks for the config to be reloaded, like for daemons;
+/// if the terminal really did hang up, reading keys fails and we exit anyway.
+pub fn install_handlers(crash_report: Option<PathBuf>) -> std::io::Result<()> {
+    let report_path = crash_report.clone();
+    let prev_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore();
+        if let Some(path) = &report_path {
+            write_report(path, &format!("{}", info));
+        }
+        prev_hook(info);
+    }));
+
+    let mut signals = Signals::new([SIGTERM, SIGHUP, SIGQUIT])?;
+    std::thread::spawn(move || {
+        for signal in signals.forever() {
+            if signal == SIGHUP {
+                RELOAD_REQUESTED.store(true, Ordering::Relaxed);
+                continue;
+            }
+            restore();
+            if let Some(path) = &crash_report {
+                write_report(path, &format!("received signal {}", signal));
+            }
+            std::process::exit(128 + signal);
+        }
+    });
+
+    Ok(())
+}
+
+/// Initialize the terminal, saving the window title so that we can put it back later.
+/// With `inline`, only take that many lines below the prompt, like `fzf --height`.
+pub fn init(inline: Option<u16>) -> ratatui::DefaultTerminal {
+    let terminal = match inline {
+        Some(height) => {
+            INLINE.store(true, Ordering::Relaxed);
+            ratatui::init_with_options(TerminalOptions {
+                viewport: Viewport::Inline(height),
+            })
+        }
+        None => ratatui::init(),
+    };
+    let _ = execute!(std::io::stdout(), Print(PUSH_TITLE));
+    terminal
+}
+
+/// Give the terminal back. Inline, the last screen stays, and the shell
+/// prompt comes back below it.
+pub fn restore() {
+    if INLINE.load(Ordering::Relaxed) {
+        let _ = crossterm::terminal::disable_raw_mode();
+        let _ = execute!(std::io::stdout(), Show, Print("\r\n"));
+    } else {
+        ratatui::restore();
+    }
+    let _ = execute!(std::io::stdout(), Print(POP_TITLE));
+}
+
+pub fn set_title(path: &Path) {
+    let _ = execute!(
+        std::io::stdout(),
+        SetTitle(format!("cephdu: {}", path.to_string_lossy()))
+    );
+}
+
+/// Get the user's attention: ring the bell, and ask for a desktop
+/// notification with OSC 9, which terminals that don't know it ignore
+pub fn notify(text: &str) {
+    let text: String = text.chars().filter(|c| !c.is_control()).collect();
+    let _ = execute!(std::io::stdout(), Print(format!("\x07\x1b]9;{}\x07", text)));
+}
+
+/// Put `text` on the clipboard with OSC 52, which works over SSH in the
+/// terminals that allow it
+pub fn copy(text: &str) {
+    let _ = execute!(
+        std::io::stdout(),
+        Print(format!("\x1b]52;c;{}\x07", base64(text.as_bytes())))
+    );
+}
+
+/// Standard base64, with padding, as OSC 52 wants
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HyperlinkMode {
+    /// Use hyperlinks if the terminal looks like it supports them
+    Auto,
+    Always,
+    Never,
+}
+
+impl HyperlinkMode {
+    pub fn enabled(self) -> bool {
+        match self {
+            HyperlinkMode::Always => true,
+            HyperlinkMode::Never => false,
+            HyperlinkMode::Auto => supports_hyperlinks(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ColorMode {
+    /// Use colors if stdout is a terminal and $NO_COLOR isn't set
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => {
+                std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+            }
+        }
+    }
+}
+
+/// The screen with its colors as escape codes, one line per row, e.g. to
+/// print a frame of the TUI into a MOTD banner
+pub fn screen_ansi(buf: &Buffer) -> std::io::Result<String> {
+    let mut out: Vec<u8> = Vec::new();
+    for y in buf.area.top()..buf.area.bottom() {
+        let mut last = None;
+        for x in buf.area.left()..buf.area.right() {
+            let cell = &buf[(x, y)];
+            let style = (cell.fg, cell.bg, cell.modifier);
+            if last != Some(style) {
+                queue!(
+                    out,
+                    SetAttribute(Attribute::Reset),
+                    SetForegroundColor(cell.fg.into()),
+                    SetBackgroundColor(cell.bg.into()),
+                )?;
+                for (modifier, attribute) in [
+                    (Modifier::BOLD, Attribute::Bold),
+                    (Modifier::DIM, Attribute::Dim),
+                    (Modifier::ITALIC, Attribute::Italic),
+                    (Modifier::UNDERLINED, Attribute::Underlined),
+                    (Modifier::REVERSED, Attribute::Reverse),
+                ] {
+                    if cell.modifier.contains(modifier) {
+                        queue!(out, SetAttribute(attribute))?;
+                    }
+                }
+                last = Some(style);
+            }
+            queue!(out, Print(cell.symbol()))?;
+        }
+        queue!(out, SetAttribute(Attribute::Reset), Print("\n"))?;
+    }
+    String::from_utf8(out).map_err(std::io::Error::other)
+}
+
+/// A guess based on the environment variables set by terminals known to support OSC 8
+fn supports_hyperlinks() -> bool {
+    let var = |name: &str| std::env::var(name).unwrap_or_default();
+
+    if var("TERM") == "dumb" {
+        return false;
+    }
+    if ["iTerm.app", "WezTerm", "vscode", "ghostty"].contains(&var("TERM_PROGRAM").as_str()) {
+        return true;
+    }
+    if var("VTE_VERSION").parse::<u32>().unwrap_or(0) >= 5000 {
+        return true;
+    }
+    ["KITTY_WINDOW_ID", "WT_SESSION", "KONSOLE_VERSION"]
+        .iter()
+        .any(|v| std::env::var_os(v).is_some())
+        || ["kitty", "foot", "alacritty"]
+            .iter()
+            .any(|t| var("TERM").contains(t))
+}
+
+/// A region of the screen that should link to a path
+pub struct Hyperlink {
+    pub area: Rect,
+    pub url: String,
+}
+
+impl Hyperlink {
+    pub fn file(area: Rect, hostname: &str, path: &Path) -> Hyperlink {
+        Hyperlink {
+            area,
+            url: format!("file://{}{}", hostname, percent_encode(path)),
+        }
+    }
+}
+
+fn percent_encode(path: &Path) -> String {
+    let mut url = String::new();
+    for &b in path.as_os_str().as_bytes() {
+        if b.is_ascii_alphanumeric() || b"/-._~".contains(&b) {
+            url.push(b as char);
+        } else {
+            url.push_str(&format!("%{:02X}", b));
+        }
+    }
+    url
+}
+
+/// Rewrite the given regions of the screen wrapped in OSC 8 hyperlinks.
+/// We write them directly rather than through ratatui, because ratatui would
+/// count the escape sequences towards the width of the text.
+pub fn write_hyperlinks(buf: &Buffer, links: &[Hyperlink]) -> std::io::Result<()> {
+    let mut stdout = std::io::stdout();
+    for link in links {
+        let area = link.area.intersection(buf.area);
+        if area.is_empty() {
+            continue;
+        }
+        let first = &buf[(area.x, area.y)];
+        let text: String = (area.x..area.right())
+            .map(|x| buf[(x, area.y)].symbol())
+            .collect();
+
+        queue!(
+            stdout,
+            MoveTo(area.x, area.y),
+            SetForegroundColor(first.fg.into()),
+            SetBackgroundColor(first.bg.into()),
+        )?;
+        if first.modifier.contains(Modifier::BOLD) {
+            queue!(stdout, SetAttribute(Attribute::Bold))?;
+        }
+        queue!(
+            stdout,
+            Print(format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", link.url, text)),
+            SetAttribute(Attribute::Reset),
+            ResetColor,
+        )?;
+    }
+    stdout.flush()
+}
+
+/// Whether a reload was asked for since the last call
+pub fn take_reload_request() -> bool {
+    RELOAD_REQUESTED.swap(false, Ordering::Relaxed)
+}
+
+pub fn record_state(state: String) {
+    if let Ok(mut s) = STATE.lock() {
+        *s = state;
+    }
+}
+
+fn write_report(path: &Path, reason: &str) {
+    // Don't block on the lock: the panic may have happened while it was held
+    let state = STATE
+        .try_lock()
+        .map(|s| s.clone())
+        .unwrap_or_else(|_| "[unavailable]".to_string());
+
+    let report = format!(
+        "cephdu v{} crash report\ntime: {}\nreason: {}\n\n{}",
+        env!("CARGO_PKG_VERSION"),
+        Local::now().to_rfc3339(),
+        reason,
+        state
+    );
+
+    let res = std::fs::File::create(path).and_then(|mut f| f.write_all(report.as_bytes()));
+    match res {
+        Ok(()) => eprintln!("Wrote crash report to {:?}", path),
+        Err(e) => eprintln!("Error writing crash report to {:?}: {}", path, e),
+    }
+}
@@ -0,0 +1,65 @@
+use std::cell::RefCell;
+use std::io;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+use crate::provider;
+
+type Call = Box<dyn FnOnce() + Send>;
+
+thread_local! {
+    /// The thread that this thread's calls run on, unless it got stuck
+    static WORKER: RefCell<Option<Sender<Call>>> = const { RefCell::new(None) };
+}
+
+/// Run `f` on a worker thread, and stop waiting for it after `timeout`, for
+/// calls like stat(2) that a hung MDS or a blocklisted client can block
+/// forever, and that can't be interrupted. A worker that's stuck is left
+/// behind, and the next call gets a new one. Without a timeout, `f` just
+/// runs on this thread.
+pub fn call<T: Send + 'static>(
+    timeout: Option<Duration>,
+    f: impl FnOnce() -> T + Send + 'static,
+) -> io::Result<T> {
+    let Some(timeout) = timeout else {
+        return Ok(f());
+    };
+    let (tx, rx) = mpsc::channel();
+    // The worker outlives the call, so it takes on the caller's provider each time
+    let provider = provider::get();
+    let call: Call = Box::new(move || {
+        provider::set_for_thread(provider);
+        let _ = tx.send(f());
+    });
+    WORKER.with_borrow_mut(|worker| {
+        let sender = worker.get_or_insert_with(spawn);
+        // A worker that has gone away is replaced
+        if let Err(mpsc::SendError(call)) = sender.send(call) {
+            let sender = spawn();
+            let _ = sender.send(call);
+            *worker = Some(sender);
+        }
+    });
+    match rx.recv_timeout(timeout) {
+        Ok(value) => Ok(value),
+        Err(RecvTimeoutError::Timeout) => {
+            WORKER.with_borrow_mut(|worker| *worker = None);
+            Err(timed_out_error())
+        }
+        Err(RecvTimeoutError::Disconnected) => Err(io::Error::other("the worker thread died")),
+    }
+}
+
+fn spawn() -> Sender<Call> {
+    let (tx, rx) = mpsc::channel::<Call>();
+    std::thread::spawn(move || {
+        for call in rx {
+            call();
+        }
+    });
+    tx
+}
+
+pub fn timed_out_error() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "Timed out")
+}
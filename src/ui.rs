@@ -1,28 +1,40 @@
 use ratatui::{
     Frame,
     buffer::Buffer,
-    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    layout::{Alignment, Constraint, Direction, Layout, Margin, Rect},
     style::{
         Color, Modifier, Style, Stylize,
-        palette::tailwind::{RED, SLATE, YELLOW},
+        palette::tailwind::{AMBER, EMERALD, INDIGO, RED, SKY, SLATE, YELLOW},
     },
     symbols::{self, border},
     text::{Line, Span, Text},
     widgets::{
-        Block, Borders, Clear, HighlightSpacing, List, ListItem, Paragraph, Scrollbar,
+        Block, Borders, Clear, HighlightSpacing, List, ListItem, ListState, Paragraph, Scrollbar,
         ScrollbarOrientation, StatefulWidget, Widget, Wrap,
     },
 };
 
 use chrono::{DateTime, Datelike, Local};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
 use crate::app::App;
+use crate::app::Change;
 use crate::app::DirEntry;
 use crate::app::EntryKind;
+use crate::app::KindCounts;
 use crate::app::ListingStats;
 use crate::app::Message;
 use crate::app::MessageKind;
+use crate::config::{ThemeConfig, Units};
+use crate::fs::{interned_count, rss};
+use crate::history::{TREND_WEEKS, Trend};
+use crate::keymap::Action;
+use crate::mounts::{CephFs, Mount};
 use crate::popup::Popup;
+use crate::provider::Space;
+use crate::terminal::Hyperlink;
+use crate::userquota::UserQuota;
 
 const SELECTED_BG_COLOR: Color = SLATE.c700;
 const SELECTED_STYLE: Style = Style::new()
@@ -33,7 +45,16 @@ const HEADER_BG_COLOR: Color = SLATE.c800;
 const DIR_TEXT_COLOR: Color = SLATE.c200;
 const NONDIR_TEXT_COLOR: Color = SLATE.c200;
 const LIST_BG_COLOR: Color = SLATE.c950;
-const GAUGE_COLOR: Color = SLATE.c200;
+const CHANGED_BG_COLOR: Color = EMERALD.c950;
+const GREW_STYLE: Style = Style::new().fg(AMBER.c300);
+const SHRANK_STYLE: Style = Style::new().fg(EMERALD.c300);
+const MARKED_BG_COLOR: Color = INDIGO.c900;
+const MOUNT_STYLE: Style = Style::new().fg(SLATE.c400);
+const SPARSE_STYLE: Style = Style::new().fg(SKY.c300);
+const TIMED_OUT_STYLE: Style = Style::new().fg(RED.c400);
+const RISKY_MODE_STYLE: Style = Style::new().fg(RED.c400).add_modifier(Modifier::BOLD);
+const SELECTED_MARKED_BG_COLOR: Color = INDIGO.c700;
+pub const GAUGE_COLOR: Color = SLATE.c200;
 
 const ERROR_MESSAGE_STYLE: Style = Style::new().fg(RED.c50).bg(RED.c800);
 const WARNING_MESSAGE_STYLE: Style = Style::new().fg(YELLOW.c950).bg(YELLOW.c300);
@@ -44,20 +65,83 @@ const POPUP_BG_COLOR: Color = SLATE.c950;
 pub const POPUP_TEXT_HEIGHT: usize = 10;
 
 const GAUGE_WIDTH: usize = 20;
+/// Narrower gauges are dropped, leaving room for the names
+const MIN_GAUGE_WIDTH: usize = 8;
+/// How much of the names to keep before the gauges start shrinking
+const MIN_NAME_WIDTH: usize = 12;
+/// The columns of a row that aren't gauges or the name: the "> " highlight
+/// symbol, the size, the file count, and their separators
+const ROW_FIXED_WIDTH: usize = 2 + 10 + 12 + 1;
+/// How wide the size and the file count are, unless they're exact
+const SIZE_WIDTH: usize = 8;
+const RENTRIES_WIDTH: usize = 7;
+/// Below this, there's no room for even one row of the listing
+const MIN_WIDTH: u16 = 32;
+const MIN_HEIGHT: u16 = 5;
+/// Below this many lines, the key hints are dropped to make room for the listing
+const HINTS_MIN_HEIGHT: u16 = 12;
 // This should be constant: 'Jan  1  2000' or 'Dec 31 12:34'
 const CTIME_FMT_WIDTH: usize = 12;
 
 impl App {
     fn render_header(&self, area: Rect, buf: &mut Buffer) {
-        Line::from(format!("cephdu v{} ", env!("CARGO_PKG_VERSION")).bold())
-            .centered()
-            .bg(TEXT_FG_COLOR)
-            .fg(HEADER_BG_COLOR)
-            .render(area, buf);
+        let width = area.width as usize;
+        let kinds = Line::from(format!(
+            " {} ",
+            kinds_str(self.dir_listing.stats.kinds, self.dir_listing.has_more())
+        ));
+        // On a narrow terminal, the kinds win over the version, which wins
+        // over the CephFS name
+        let version = Line::from(format!("cephdu v{} ", env!("CARGO_PKG_VERSION")).bold());
+        let version_width = version.width();
+        let (version_start, version_end) = if (width + version_width) / 2 + kinds.width() <= width {
+            version
+                .centered()
+                .bg(TEXT_FG_COLOR)
+                .fg(HEADER_BG_COLOR)
+                .render(area, buf);
+            (
+                width.saturating_sub(version_width) / 2,
+                (width + version_width) / 2,
+            )
+        } else {
+            let start = width.saturating_sub(kinds.width());
+            (start, start)
+        };
+        if let Some(cephfs) = self.dir_listing.cephfs()
+            && let Some(line) = [true, false]
+                .into_iter()
+                .map(|fsid| Line::from(format!(" {} ", cephfs_str(cephfs, fsid))))
+                .find(|line| line.width() <= version_start)
+        {
+            line.fg(HEADER_BG_COLOR).render(area, buf);
+        } else if let Some(quota) = self.dir_listing.user_quota
+            && let Some(line) = [true, false]
+                .into_iter()
+                .map(|long| Line::from(format!(" {} ", user_quota_str(quota, long))))
+                .find(|line| line.width() <= version_start)
+        {
+            let color = self.theme.quota_color(quota.fraction_used());
+            line.fg(color.unwrap_or(HEADER_BG_COLOR)).render(area, buf);
+        }
+        // How full the filesystem is goes between the version and the kinds,
+        // with less detail if it doesn't fit
+        let room = width.saturating_sub(version_end + kinds.width());
+        if let Some(space) = self.dir_listing.space
+            && let Some(line) = space_line(space, room, &self.theme)
+        {
+            let space_area = Rect {
+                x: area.x + version_end as u16,
+                width: room as u16,
+                ..area
+            };
+            line.right_aligned().render(space_area, buf);
+        }
+        kinds.right_aligned().fg(HEADER_BG_COLOR).render(area, buf);
     }
 
-    fn render_list(&mut self, area: Rect, buf: &mut Buffer) {
-        let title = Line::from(format!(
+    fn render_list(&mut self, area: Rect, buf: &mut Buffer) -> Vec<Hyperlink> {
+        let mut title = Line::from(format!(
             " {} ━━ {}, {} files ",
             self.cwd.to_str().unwrap_or("[invalid UTF-8]"),
             size_str(Some(self.dir_listing.stats.total_size), false),
@@ -65,73 +149,433 @@ impl App {
         ))
         .fg(TEXT_FG_COLOR)
         .bold();
+        if let Some(rctime) = self.dir_listing.last_activity() {
+            title.push_span(Span::raw(format!("━━ last activity: {} ", age_str(rctime))));
+        }
+        if self.show_cost && self.cost.by_pool() {
+            self.dir_listing.check_pools(0, 0);
+        }
+        if self.show_cost
+            && let Some(cost) = self.dir_listing.total_cost(&self.cost)
+        {
+            title.push_span(Span::raw(format!(
+                "━━ ~{}/mo ",
+                cost_str(cost, &self.cost.currency)
+            )));
+        }
+        if self.pan > 0 {
+            title.push_span(Span::raw(format!("━━ panned {} columns ", self.pan)));
+        }
+        if let Some((count, size)) = self.dir_listing.hidden_sums() {
+            title.push_span(Span::raw(format!(
+                "(+{} hidden, {}) ",
+                count_str(count),
+                size_str(Some(size), false)
+            )));
+        }
+        if self.dir_listing.stats_may_be_stale() {
+            title.push_span(Span::styled(" stats may be stale ", WARNING_MESSAGE_STYLE));
+        }
+        let risky = self
+            .dir_listing
+            .iter_entries()
+            .filter(|e| e.permission_warning().is_some())
+            .count();
+        if self.show_permissions && risky > 0 {
+            title.push_span(Span::styled(
+                format!(" {} with risky permissions ", risky),
+                ERROR_MESSAGE_STYLE,
+            ));
+        }
+        if let Some(marked) = self.marked_summary() {
+            title.push_span(Span::styled(
+                format!(" {} ", marked),
+                Style::new().fg(TEXT_FG_COLOR).bg(SELECTED_MARKED_BG_COLOR),
+            ));
+        }
 
-        let helptitle = Line::from(" Press ? for help ").fg(TEXT_FG_COLOR).bold();
+        let mut helptitle = Line::from(" Press ? for help ").fg(TEXT_FG_COLOR).bold();
+        let running = self.jobs.running();
+        if running > 0 {
+            let jobs = match running {
+                1 => " 1 job running (J) ━━".to_string(),
+                n => format!(" {} jobs running (J) ━━", n),
+            };
+            helptitle.spans.insert(0, Span::raw(jobs));
+        }
 
-        let block = Block::bordered()
+        let mut block = Block::bordered()
             .title(title.left_aligned())
             .title_bottom(helptitle.right_aligned())
             .border_set(border::THICK);
+        if self.show_unaccounted
+            && let Some((size, rentries)) = self.dir_listing.unaccounted()
+            && (size, rentries) != (0, 0)
+        {
+            let unaccounted = Line::from(format!(
+                " unaccounted: {}, {} files ",
+                signed_str(size, |s| size_str(Some(s), false)),
+                signed_str(rentries, |r| rentries_str(Some(r), false)),
+            ))
+            .fg(TEXT_FG_COLOR);
+            block = block.title_bottom(unaccounted.left_aligned());
+        }
+        let alerts = self.alert_count();
+        if alerts > 0 {
+            let alerts = Line::from(format!(
+                " {} over the alert limits ",
+                if alerts == 1 {
+                    "1 entry".to_string()
+                } else {
+                    format!("{} entries", count_str(alerts))
+                }
+            ))
+            .style(ERROR_MESSAGE_STYLE);
+            block = block.title_bottom(alerts.centered());
+        }
+        let inner = block.inner(area);
+
+        // Only build the rows that are on screen, which matters for huge listings
+        let height = inner.height as usize;
+        let offset = self.dir_listing.scroll_into_view(height, self.scroll_off);
+        self.dir_listing.viewport_height = height;
+        if self.show_permissions {
+            self.dir_listing.check_acls(offset, height);
+        }
+        if self.show_cost && self.cost.by_pool() {
+            self.dir_listing.check_pools(offset, height);
+        }
+        let visible: Vec<&DirEntry> = self
+            .dir_listing
+            .iter_entries()
+            .skip(offset)
+            .take(height)
+            .collect();
 
-        let (user_width, group_width) = if self.show_owner {
-            (
-                self.dir_listing
-                    .iter_entries()
-                    .filter_map(|e| e.user.as_ref())
-                    .map(|s| s.len())
-                    .max()
-                    .unwrap_or(0),
-                self.dir_listing
-                    .iter_entries()
-                    .filter_map(|e| e.group.as_ref())
-                    .map(|s| s.len())
-                    .max()
-                    .unwrap_or(0),
-            )
-        } else {
-            (0, 0)
-        };
+        let (mut user_width, mut group_width) = self.dir_listing.owner_widths;
+        for entry in &visible {
+            if self.show_owner {
+                let user = entry.user(self.numeric_ids);
+                user_width = user_width.max(user.map(|s| s.len()).unwrap_or(0));
+            }
+            if self.show_group {
+                let group = entry.group(self.numeric_ids);
+                group_width = group_width.max(group.map(|s| s.len()).unwrap_or(0));
+            }
+        }
+
+        // The site's plugin columns are as wide as what they say on screen
+        let plugin_values: Vec<Vec<Option<&str>>> = visible
+            .iter()
+            .map(|entry| self.plugin_values(entry))
+            .collect();
+        let mut plugin_widths = vec![0; self.plugins.columns.len()];
+        for values in &plugin_values {
+            for (width, value) in plugin_widths.iter_mut().zip(values) {
+                *width = (*width).max(value.map_or(0, |v| v.chars().count()));
+            }
+        }
 
         let ctime_width = if self.show_ctime { CTIME_FMT_WIDTH } else { 0 };
+        // Only show growth if there's history for some of the subdirectories
+        let show_trend = !self.dir_listing.trends.is_empty();
 
         // Iterate through all elements in the `items` and stylize them.
         let selected = self.dir_listing.selected();
         // Get the current year so that we know how to format a time string
         let current_year = Local::now().year() as isize;
-        let items: Vec<ListItem> = self
-            .dir_listing
-            .iter_entries()
+        // Exact sizes and counts need wider columns than rounded ones
+        let stats = &self.dir_listing.stats;
+        let number_widths = (
+            size_str(Some(stats.max_size), true).len().max(SIZE_WIDTH),
+            rentries_str(Some(stats.max_rentries), true)
+                .len()
+                .max(RENTRIES_WIDTH),
+        );
+        let extra_width = number_widths.0 + number_widths.1 - SIZE_WIDTH - RENTRIES_WIDTH;
+        let gauge_width = gauge_width((inner.width as usize).saturating_sub(extra_width));
+        let rows: Vec<(Line, usize, usize)> = visible
+            .iter()
             .enumerate()
             .map(|(i, entry)| {
-                entry
-                    .to_listitem(
-                        GAUGE_WIDTH,
-                        &self.dir_listing.stats,
-                        user_width,
-                        group_width,
-                        ctime_width,
-                        current_year,
-                        selected.map(|s| s == i).unwrap_or(false),
-                        self.show_owner,
-                        self.show_ctime,
-                    )
-                    .fg(TEXT_FG_COLOR)
-                    .bg(if selected.map(|s| s == i).unwrap_or(false) {
-                        SELECTED_BG_COLOR
-                    } else {
-                        LIST_BG_COLOR
-                    })
+                entry.to_line(
+                    gauge_width,
+                    number_widths,
+                    &self.theme,
+                    &self.dir_listing.stats,
+                    user_width,
+                    group_width,
+                    ctime_width,
+                    current_year,
+                    selected == Some(offset + i),
+                    self.show_owner,
+                    self.show_group,
+                    self.numeric_ids,
+                    self.show_permissions,
+                    self.show_ctime,
+                    self.show_average,
+                    self.show_cost.then(|| {
+                        self.dir_listing
+                            .cost(entry, &self.cost)
+                            .map(|cost| cost_str(cost, &self.cost.currency))
+                    }),
+                    show_trend.then(|| self.dir_listing.trends.get(&entry.name)),
+                    self.dir_listing.change(entry),
+                    self.dir_listing.since_visit(entry),
+                    self.dir_listing.mounted_on(entry),
+                    plugin_values[i]
+                        .iter()
+                        .copied()
+                        .zip(plugin_widths.iter().copied()),
+                )
+            })
+            .collect();
+        // Account for the "> " highlight symbol
+        let available = (inner.width as usize).saturating_sub(2);
+        let widest = rows
+            .iter()
+            .map(|(line, _, _)| line.width())
+            .max()
+            .unwrap_or(0);
+        self.pan_limit = widest.saturating_sub(available);
+        self.pan = self.pan.min(self.pan_limit);
+        let (lines, trailing): (Vec<Line>, Vec<usize>) = rows
+            .into_iter()
+            .map(|(line, pinned, trailing)| (pan_line(line, pinned, self.pan), trailing))
+            .unzip();
+        // The name comes right before the `trailing` spans; remember where it starts
+        let name_cols: Vec<u16> = lines
+            .iter()
+            .zip(trailing)
+            .map(|(line, trailing)| {
+                let end = line.spans.len().saturating_sub(trailing + 1);
+                let before: usize = line.spans[..end].iter().map(|s| s.width()).sum();
+                before as u16 + 1
+            })
+            .collect();
+        let items: Vec<ListItem> = lines
+            .into_iter()
+            .enumerate()
+            .map(|(i, line)| {
+                ListItem::new(line).fg(TEXT_FG_COLOR).bg(
+                    match (
+                        selected == Some(offset + i),
+                        self.dir_listing.is_marked(visible[i]),
+                    ) {
+                        (true, true) => SELECTED_MARKED_BG_COLOR,
+                        (true, false) => SELECTED_BG_COLOR,
+                        (false, true) => MARKED_BG_COLOR,
+                        _ if self.is_alert(visible[i]) => self.theme.alert.0,
+                        _ if self.dir_listing.is_changed(visible[i]) => CHANGED_BG_COLOR,
+                        _ => LIST_BG_COLOR,
+                    },
+                )
             })
             .collect();
 
-        // Create a List from all list items and highlight the currently selected one
+        let hyperlinks: Vec<Hyperlink> = match &self.hyperlinks {
+            Some(hostname) => {
+                // Account for the "> " highlight symbol
+                let name_x = inner.x + 2;
+                visible
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, entry)| entry.name != "..")
+                    .map(|(i, entry)| {
+                        let x = name_x + name_cols[i];
+                        let width = (Span::raw(entry.name.as_str()).width() as u16)
+                            .min(inner.right().saturating_sub(x));
+                        let area = Rect::new(x, inner.y + i as u16, width, 1);
+                        Hyperlink::file(area, hostname, &self.cwd.join(&entry.name))
+                    })
+                    .collect()
+            }
+            None => vec![],
+        };
+
+        // Create a List from the visible items and highlight the currently selected one
         let list = List::new(items)
             .block(block)
             .highlight_symbol("> ")
             .highlight_spacing(HighlightSpacing::Always)
             .bg(LIST_BG_COLOR);
 
-        StatefulWidget::render(list, area, buf, self.dir_listing.state_mut());
+        let mut state = ListState::default().with_selected(selected.map(|s| s - offset));
+        StatefulWidget::render(list, area, buf, &mut state);
+
+        self.dir_listing.owner_widths = (user_width, group_width);
+
+        hyperlinks
+    }
+
+    fn render_debug(&self, area: Rect, buf: &mut Buffer) {
+        let report = self.dir_listing.load_report;
+        let timings = report.timings;
+        let mean_str = |mean: Option<Duration>| mean.map(duration_str).unwrap_or("-".to_string());
+
+        let lines = [
+            format!(
+                "strategy: {} ({} threads){}",
+                report.strategy.name(),
+                report.strategy.threads(),
+                if report.lazy { ", lazy" } else { "" }
+            ),
+            format!("next:     {}", self.loader.strategy().name()),
+            format!(
+                "entries:  {} in {}",
+                report.entries,
+                duration_str(report.elapsed)
+            ),
+            format!(
+                "stat:     {} calls, mean {}",
+                timings.stat_calls,
+                mean_str(timings.mean_stat())
+            ),
+            format!(
+                "getxattr: {} calls, mean {}",
+                timings.xattr_calls,
+                mean_str(timings.mean_xattr())
+            ),
+            format!(
+                "memory:   {} listing, {} rss",
+                size_str(Some(self.dir_listing.memory_usage()), false),
+                size_str(rss(), false)
+            ),
+            format!(
+                "names:    {} interned ({} B/entry)",
+                interned_count(),
+                std::mem::size_of::<DirEntry>()
+            ),
+            format!(
+                "prefetch: {} cached{}",
+                self.prefetcher.len(),
+                if self.prefetcher.in_flight().is_some() {
+                    ", 1 in flight"
+                } else {
+                    ""
+                }
+            ),
+            format!("du:       {} in flight", self.deep_sizer.in_flight()),
+        ];
+
+        let width = lines.iter().map(|l| l.len()).max().unwrap_or(0) as u16 + 4;
+        let height = lines.len() as u16 + 2;
+        let [_, area] = Layout::horizontal([Constraint::Fill(1), Constraint::Length(width)])
+            .areas(area.inner(Margin::new(1, 1)));
+        let [_, area] =
+            Layout::vertical([Constraint::Fill(1), Constraint::Length(height)]).areas(area);
+
+        let block = Block::bordered()
+            .title(" Debug ")
+            .border_set(border::THICK)
+            .fg(POPUP_FG_COLOR)
+            .bg(POPUP_BG_COLOR);
+
+        Clear.render(area, buf);
+        Paragraph::new(Text::from(lines.join("\n")))
+            .block(block)
+            .render(area, buf);
+    }
+
+    /// The keys worth knowing about right now, for the hint bar
+    fn hints(&self) -> Vec<(String, &'static str)> {
+        if let Some(popup) = &self.popup {
+            let hints: &[(&str, &'static str)] = if popup.search.is_some() {
+                &[
+                    ("Type", "Search"),
+                    ("Enter", "Run"),
+                    ("Esc", "Clear/Close"),
+                    ("Up/Down", "Scroll"),
+                ]
+            } else {
+                &[("Esc", "Close"), ("Up/Down", "Scroll")]
+            };
+            return hints.iter().map(|&(k, l)| (k.to_string(), l)).collect();
+        }
+
+        if self.pending_mark.is_some() {
+            return vec![("a-z".to_string(), "Mark"), ("Esc".to_string(), "Cancel")];
+        }
+        if self.jump.is_some() {
+            return vec![
+                ("Type".to_string(), "Jump to name"),
+                ("Esc".to_string(), "Cancel"),
+            ];
+        }
+
+        let mut actions = vec![
+            (Action::Help, "Help"),
+            (Action::Open, "Open"),
+            (Action::Parent, "Up"),
+            (Action::SortSize, "Size"),
+            (Action::SortName, "Name"),
+            (Action::SortCount, "Files"),
+            (Action::Details, "Info"),
+            (Action::Refresh, "Refresh"),
+        ];
+        if self.dir_listing.has_more() {
+            actions.push((Action::LoadMore, "More"));
+        }
+        if self.pan > 0 {
+            actions.push((Action::PanLeft, "Pan back"));
+        } else if self.pan_limit > 0 {
+            actions.push((Action::PanRight, "Pan"));
+        }
+        if self.dir_listing.marked_count() > 0 {
+            actions.push((Action::UnmarkAll, "Unmark"));
+        } else {
+            actions.push((Action::ToggleMarked, "Mark"));
+        }
+        if !self.dir_listing.is_ceph() {
+            actions.push((Action::DeepSize, "du"));
+        }
+        actions.push((Action::Quit, "Quit"));
+        actions
+            .into_iter()
+            .filter_map(|(action, label)| Some((self.keymap.first_key_for(action)?, label)))
+            .collect()
+    }
+
+    /// What the marked entries add up to, e.g. "marked: 37 items, 4.2 TB, 1.3 M files"
+    fn marked_summary(&self) -> Option<String> {
+        let count = self.dir_listing.marked_count();
+        if count == 0 {
+            return None;
+        }
+        let (size, rentries) = self.dir_listing.marked_sums();
+        let local = format!(
+            "{}, {} files",
+            size_str(Some(size), false),
+            rentries_str(Some(rentries), false)
+        );
+        Some(format!(
+            "marked: {} {}, {}",
+            count,
+            if count == 1 { "item" } else { "items" },
+            with_elsewhere(local, rentries, &self.dir_listing.marked_sums_elsewhere())
+        ))
+    }
+
+    fn render_hints(&self, area: Rect, buf: &mut Buffer) {
+        if area.height == 0 {
+            return;
+        }
+        let mut spans = vec![];
+        for (key, label) in self.hints() {
+            spans.push(Span::styled(
+                key,
+                Style::new()
+                    .fg(TEXT_FG_COLOR)
+                    .bg(LIST_BG_COLOR)
+                    .add_modifier(Modifier::BOLD),
+            ));
+            spans.push(Span::styled(
+                format!(" {} ", label),
+                Style::new().fg(HEADER_BG_COLOR).bg(TEXT_FG_COLOR),
+            ));
+            spans.push(Span::raw(" "));
+        }
+        Line::from(spans).bg(LIST_BG_COLOR).render(area, buf);
     }
 
     fn render_message(&self, message: &Option<Message>, area: Rect, buf: &mut Buffer) {
@@ -151,6 +595,8 @@ impl App {
 }
 
 fn render_popup(popup: &mut Popup, areas: [Rect; 2], buf: &mut Buffer) {
+    popup.set_visible_height(areas[0].height.saturating_sub(2) as usize);
+
     let top_border_set = symbols::border::Set {
         // Connect the top block with the bottom block
         bottom_left: symbols::line::THICK.vertical_right,
@@ -205,9 +651,11 @@ fn safe_div(a: usize, b: usize) -> f64 {
 
 impl DirEntry {
     #[allow(clippy::too_many_arguments)]
-    fn to_listitem(
+    fn to_line<'a>(
         &self,
         gauge_width: usize,
+        (size_width, rentries_width): (usize, usize),
+        theme: &ThemeConfig,
         listing_stats: &ListingStats,
         user_width: usize,
         group_width: usize,
@@ -215,25 +663,45 @@ impl DirEntry {
         current_year: isize,
         selected: bool,
         show_owner: bool,
+        show_group: bool,
+        numeric_ids: bool,
+        show_permissions: bool,
         show_ctime: bool,
-    ) -> ListItem<'static> {
+        show_average: bool,
+        cost: Option<Option<String>>,
+        trend: Option<Option<&Trend>>,
+        change: Option<Change>,
+        since_visit: Option<Change>,
+        mounted: Option<&Mount>,
+        plugins: impl Iterator<Item = (Option<&'a str>, usize)>,
+    ) -> (Line<'static>, usize, usize) {
         // The borrow checker complains that self.dir_listing remains borrowed
-        // immutably unless we insist on the static lifetime of the ListItem.
+        // immutably unless we insist on the static lifetime of the Line.
         // I'm pretty sure this a borrow checker limitation, rather than a real bug.
 
         let size_gauge_fraction = safe_div(self.size.unwrap_or(0), listing_stats.max_size);
-        let size_gauge_percent = self.size.map(|s| safe_div(s, listing_stats.total_size));
+        // A mount point's size isn't part of the total, which is of another filesystem
+        let size_gauge_percent = self
+            .size
+            .filter(|_| mounted.is_none())
+            .map(|s| safe_div(s, listing_stats.total_size));
 
         let rentries_gauge_fraction =
             safe_div(self.rentries.unwrap_or(0), listing_stats.max_rentries);
         let rentries_gauge_percent = self
             .rentries
+            .filter(|_| mounted.is_none())
             .map(|r| safe_div(r, listing_stats.total_rentries));
 
         let text_color = match self.kind {
             EntryKind::Dir => DIR_TEXT_COLOR,
             _ => NONDIR_TEXT_COLOR,
         };
+        // Directories about to hit their quota stand out
+        let text_color = self
+            .quota_used()
+            .and_then(|used| theme.quota_color(used))
+            .unwrap_or(text_color);
 
         let mut spans: Vec<Span> = vec![];
 
@@ -246,60 +714,124 @@ impl DirEntry {
         };
 
         spans.push(style_selected(Span::styled(
-            format!("{:>8} ┃", size_str(self.size, true)),
+            format!("{:>size_width$} ┃", size_str(self.size, true)),
             text_color,
         )));
 
-        spans.extend(gauge(
-            size_gauge_fraction,
-            size_gauge_percent,
-            gauge_width,
-            selected,
-        ));
+        // A gauge_width of 0 means the gauges are left out, on narrow terminals
+        let gauges = gauge_width > 0;
+        if gauges {
+            spans.extend(gauge(
+                size_gauge_fraction,
+                size_gauge_percent,
+                gauge_width,
+                theme.gauge_color(size_gauge_percent),
+                selected,
+            ));
+        }
 
         spans.push(style_selected(Span::styled(
-            format!("┃  {:>7} ┃", rentries_str(self.rentries, true),),
+            format!(
+                "{}  {:>rentries_width$} ┃",
+                if gauges { "┃" } else { "" },
+                rentries_str(self.rentries, true),
+            ),
             text_color,
         )));
 
-        spans.extend(gauge(
-            rentries_gauge_fraction,
-            rentries_gauge_percent,
-            gauge_width,
-            selected,
-        ));
+        if gauges {
+            spans.extend(gauge(
+                rentries_gauge_fraction,
+                rentries_gauge_percent,
+                gauge_width,
+                theme.gauge_color(rentries_gauge_percent),
+                selected,
+            ));
+            spans.push(style_selected(Span::styled("┃", text_color)));
+        }
+        // The sizes and gauges stay put when panning
+        let pinned = spans.len();
+
+        // None if the column is hidden, Some(None) if there's no trend for this entry
+        if let Some(trend) = trend {
+            let (sparkline, per_week) = match trend {
+                Some(trend) => (
+                    trend.sparkline.as_str(),
+                    format!(
+                        "{}{}/wk",
+                        if trend.per_week > 0 { "+" } else { "" },
+                        signed_str(trend.per_week, |s| size_str(Some(s), false))
+                    ),
+                ),
+                None => ("", String::new()),
+            };
+            spans.push(style_selected(Span::styled(
+                format!(
+                    " {:spark$} {:>12} ┃",
+                    sparkline,
+                    per_week,
+                    spark = TREND_WEEKS
+                ),
+                text_color,
+            )));
+        }
+
+        if show_average {
+            let average = match self.average_size() {
+                Some(average) => format!("{}/file", size_str(Some(average), true)),
+                None => String::new(),
+            };
+            spans.push(style_selected(Span::styled(
+                format!(" {:>awidth$}", average, awidth = size_width + 5),
+                text_color,
+            )));
+        }
 
-        spans.push(style_selected(Span::styled("┃", text_color)));
+        // None if the column is hidden, Some(None) if there's no rate for this entry
+        if let Some(cost) = cost {
+            spans.push(style_selected(Span::styled(
+                format!(" {:>10}", cost.map(|c| c + "/mo").unwrap_or_default()),
+                text_color,
+            )));
+        }
 
-        if show_owner {
-            if let Some(user) = &self.user {
-                spans.push(style_selected(Span::styled(
-                    format!(" {:>uwidth$}", user, uwidth = user_width),
-                    text_color,
-                )));
-            }
-            if let Some(group) = &self.group {
-                spans.push(style_selected(Span::styled(
-                    format!(":{:gwidth$}", group, gwidth = group_width),
-                    text_color,
-                )));
-            }
+        for (value, width) in plugins.filter(|(_, width)| *width > 0) {
+            spans.push(style_selected(Span::styled(
+                format!(" {:>width$}", value.unwrap_or_default(), width = width),
+                text_color,
+            )));
         }
 
-        if show_ctime && let Some(ctime_seconds) = self.ctime {
-            let ctime: DateTime<Local> =
-                DateTime::from_timestamp_secs(ctime_seconds.try_into().unwrap_or(0))
-                    .unwrap()
-                    .into();
-            let fmt = if (ctime.year() as isize) == current_year {
-                "%b %e %H:%M"
-            } else {
-                "%b %e  %Y"
-            };
+        if show_owner && let Some(user) = self.user(numeric_ids) {
+            spans.push(style_selected(Span::styled(
+                format!(" {:>uwidth$}", user, uwidth = user_width),
+                text_color,
+            )));
+        }
+        if show_group && let Some(group) = self.group(numeric_ids) {
+            // Like ls -l, user:group reads as one column when both are shown
+            let separator = if show_owner { ':' } else { ' ' };
+            spans.push(style_selected(Span::styled(
+                format!("{}{:gwidth$}", separator, group, gwidth = group_width),
+                text_color,
+            )));
+        }
+
+        if show_permissions {
+            let mode = self.mode_str().unwrap_or_default();
+            let span = Span::styled(format!(" {:11}", mode), text_color);
+            spans.push(match self.permission_warning() {
+                // Stand out even on the selected row
+                Some(_) => span.style(RISKY_MODE_STYLE),
+                None => style_selected(span),
+            });
+        }
+
+        if show_ctime && let Some(ctime) = self.ctime {
             spans.push(style_selected(Span::styled(
                 format!(
                     " {:cwidth$}",
-                    ctime.format(fmt).to_string(),
+                    ctime_str(ctime, current_year),
                     cwidth = ctime_width
                 ),
                 text_color,
@@ -310,15 +842,83 @@ impl DirEntry {
             format!(" {}", self.name),
             text_color,
         )));
+        let mut trailing = 0;
+        // Sparse files' sizes say little about the space they take
+        if self.allocated.is_some() {
+            spans.push(Span::styled("  s", SPARSE_STYLE));
+            trailing += 1;
+        }
+        if self.timed_out {
+            spans.push(Span::styled("  timed out", TIMED_OUT_STYLE));
+            trailing += 1;
+        }
+        if let Some(mount) = mounted {
+            spans.push(Span::styled(
+                format!("  ⇢ {}", mount_str(mount)),
+                MOUNT_STYLE,
+            ));
+            trailing += 1;
+        }
+        // What changed in a refresh, or else since the last visit
+        let (change, since) = match change {
+            Some(change) => (Some(change), ""),
+            None => (since_visit, " since last visit"),
+        };
+        if let Some((text, grew)) = change.and_then(change_str) {
+            let (arrow, style) = match change {
+                Some(Change::Added) => ("", GREW_STYLE),
+                _ if grew => ("▲ ", GREW_STYLE),
+                _ => ("▼ ", SHRANK_STYLE),
+            };
+            spans.push(Span::styled(format!("  {}{}{}", arrow, text, since), style));
+            trailing += 1;
+        }
 
-        let line = Line::from(spans);
-        ListItem::new(line)
+        (Line::from(spans), pinned, trailing)
     }
 }
 
+/// How wide the gauges can be in a listing `width` wide, shrinking them
+/// before the names, and 0 if there's no room for them at all
+fn gauge_width(width: usize) -> usize {
+    let room = width.saturating_sub(ROW_FIXED_WIDTH + MIN_NAME_WIDTH) / 2;
+    match room.min(GAUGE_WIDTH) {
+        width if width < MIN_GAUGE_WIDTH => 0,
+        width => width,
+    }
+}
+
+/// Cut `pan` columns off `line`, after its first `pinned` spans
+fn pan_line(line: Line<'static>, pinned: usize, pan: usize) -> Line<'static> {
+    let mut spans = line.spans;
+    let rest = spans.split_off(pinned.min(spans.len()));
+    let mut skip = pan;
+    for span in rest {
+        let width = span.width();
+        if skip >= width {
+            skip -= width;
+            continue;
+        }
+        if skip > 0 {
+            let content: String = span.content.chars().skip(skip).collect();
+            spans.push(Span::styled(content, span.style));
+            skip = 0;
+        } else {
+            spans.push(span);
+        }
+    }
+    Line::from(spans)
+}
+
 /// Draw a unicode gauge bar with a given percentage and width.
 /// The percentage will be written as a number in the middle of the gauge.
-fn gauge(fraction: f64, percent: Option<f64>, width: usize, selected: bool) -> Vec<Span<'static>> {
+fn gauge(
+    fraction: f64,
+    percent: Option<f64>,
+    width: usize,
+    color: Color,
+    selected: bool,
+) -> Vec<Span<'static>> {
     let text_start = width / 2 - 3;
 
     let count = |filled: f64, width: usize| -> (usize, usize) {
@@ -347,7 +947,7 @@ fn gauge(fraction: f64, percent: Option<f64>, width: usize, selected: bool) -> V
                 eighths[remainder],
                 " ".repeat(width - whole - (remainder > 0) as usize)
             ),
-            Style::default().fg(GAUGE_COLOR).bg(bg_color),
+            Style::default().fg(color).bg(bg_color),
         )
     };
 
@@ -365,13 +965,13 @@ fn gauge(fraction: f64, percent: Option<f64>, width: usize, selected: bool) -> V
         if split_char > 0 {
             spans.push(Span::styled(
                 percent_text[..split_char.min(text_width)].to_string(),
-                Style::default().bg(GAUGE_COLOR).fg(bg_color),
+                Style::default().bg(color).fg(bg_color),
             ));
         }
         if split_char < text_width {
             spans.push(Span::styled(
                 percent_text[split_char..].to_string(),
-                Style::default().fg(GAUGE_COLOR).bg(bg_color),
+                Style::default().fg(color).bg(bg_color),
             ));
         }
 
@@ -388,17 +988,41 @@ fn gauge(fraction: f64, percent: Option<f64>, width: usize, selected: bool) -> V
     spans
 }
 
-fn size_str(size: Option<usize>, align: bool) -> String {
+/// Whether sizes and counts are shown in full, e.g. "4,213,002,117,632 B"
+/// rather than "4.2 TB", to see whether a cleanup actually freed anything
+static EXACT_SIZES: AtomicBool = AtomicBool::new(false);
+
+pub fn set_exact_sizes(exact: bool) {
+    EXACT_SIZES.store(exact, Ordering::Relaxed);
+}
+
+pub fn exact_sizes() -> bool {
+    EXACT_SIZES.load(Ordering::Relaxed)
+}
+
+/// Whether rounded sizes are in powers of 1024, like "3.8 TiB", rather than 1000
+static BINARY_UNITS: AtomicBool = AtomicBool::new(false);
+
+pub fn set_units(units: Units) {
+    BINARY_UNITS.store(units == Units::Binary, Ordering::Relaxed);
+}
+
+pub fn size_str(size: Option<usize>, align: bool) -> String {
     if size.is_none() {
         return "".to_string();
     }
     let size = size.unwrap();
-    let units = [" B", "KB", "MB", "GB", "TB", "PB", "EB", "ZB", "YB"];
-    let base: usize = 1000;
-    let i = if size > 0 {
-        size.ilog10() / base.ilog10()
+    if exact_sizes() {
+        return format!("{} B", count_str(size));
+    }
+    let (units, base, i) = if BINARY_UNITS.load(Ordering::Relaxed) {
+        let units = [
+            "  B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB", "ZiB", "YiB",
+        ];
+        (units, 1024usize, size.checked_ilog2().unwrap_or(0) / 10)
     } else {
-        0
+        let units = [" B", "KB", "MB", "GB", "TB", "PB", "EB", "ZB", "YB"];
+        (units, 1000usize, size.checked_ilog10().unwrap_or(0) / 3)
     };
     let size = size as f64 / base.pow(i) as f64;
     if i == 0 {
@@ -413,11 +1037,14 @@ fn size_str(size: Option<usize>, align: bool) -> String {
     }
 }
 
-fn rentries_str(rentries: Option<usize>, align: bool) -> String {
+pub fn rentries_str(rentries: Option<usize>, align: bool) -> String {
     if rentries.is_none() {
         return "".to_string();
     }
     let rentries = rentries.unwrap();
+    if exact_sizes() {
+        return count_str(rentries);
+    }
     let units = ["", "K", "M", "G", "T", "P", "E", "Z", "Y"];
     let base: usize = 1000;
     let i = if rentries > 0 {
@@ -433,7 +1060,145 @@ fn rentries_str(rentries: Option<usize>, align: bool) -> String {
     }
 }
 
+impl DirEntry {
+    /// Permissions like `ls -l` shows them, with a `+` if there's an ACL
+    pub fn mode_str(&self) -> Option<String> {
+        let mut mode = mode_str(self.kind, self.mode?);
+        if self.acl == Some(true) {
+            mode.push('+');
+        }
+        Some(mode)
+    }
+}
+
+/// An xattr value for display: text if it's printable, otherwise hex, and
+/// cut off after `max_chars`, saying how long it was
+pub fn xattr_value_str(value: &[u8], max_chars: usize) -> String {
+    let text = std::str::from_utf8(value)
+        .ok()
+        .map(|s| s.trim_end_matches('\0'))
+        .filter(|s| !s.chars().any(char::is_control));
+    let (shown, truncated) = match text {
+        Some(text) => (
+            text.chars().take(max_chars).collect::<String>(),
+            text.chars().count() > max_chars,
+        ),
+        None => {
+            let bytes = max_chars.saturating_sub(2) / 2;
+            let hex: String = value
+                .iter()
+                .take(bytes)
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            (format!("0x{}", hex), value.len() > bytes)
+        }
+    };
+    if truncated {
+        format!("{}… ({} bytes)", shown, value.len())
+    } else {
+        shown
+    }
+}
+
+/// Permissions like `ls -l` shows them, e.g. "drwxr-sr-x"
+pub fn mode_str(kind: EntryKind, mode: u32) -> String {
+    let mut s = String::with_capacity(10);
+    s.push(match kind {
+        EntryKind::Dir => 'd',
+        EntryKind::Symlink => 'l',
+        EntryKind::File => '-',
+    });
+    // Each of user, group, and other, with the special bit that replaces its x
+    for (shift, special, set_char) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+        let bits = (mode >> shift) & 0o7;
+        s.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        s.push(match (bits & 0o1 != 0, mode & special != 0) {
+            (true, true) => set_char,
+            (false, true) => set_char.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    s
+}
+
+/// Format a possibly negative number with a formatter for unsigned ones
+/// How an entry changed in a refresh, e.g. "+1.2 GB, +30 files", and
+/// whether it grew. None if nothing that's shown changed.
+fn change_str(change: Change) -> Option<(String, bool)> {
+    let Change::Resized { size, rentries } = change else {
+        return Some(("new".to_string(), true));
+    };
+    let plus = |n: isize| if n > 0 { "+" } else { "" };
+    let mut parts = vec![];
+    if size != 0 {
+        let size_text = signed_str(size, |s| size_str(Some(s), false));
+        parts.push(format!("{}{}", plus(size), size_text));
+    }
+    if rentries != 0 {
+        let rentries_text = signed_str(rentries, count_str);
+        parts.push(format!("{}{} files", plus(rentries), rentries_text));
+    }
+    let grew = if size != 0 { size > 0 } else { rentries > 0 };
+    (!parts.is_empty()).then(|| (parts.join(", "), grew))
+}
+
+pub fn signed_str(n: isize, format: impl Fn(usize) -> String) -> String {
+    if n < 0 {
+        format!("-{}", format(n.unsigned_abs()))
+    } else {
+        format(n as usize)
+    }
+}
+
+fn duration_str(d: Duration) -> String {
+    if d >= Duration::from_secs(1) {
+        format!("{:.2} s", d.as_secs_f64())
+    } else if d >= Duration::from_millis(1) {
+        format!("{:.1} ms", d.as_secs_f64() * 1e3)
+    } else {
+        format!("{} µs", d.as_micros())
+    }
+}
+
+/// A time like `ls -l` shows it: the time of day this year, otherwise the year
+pub fn ctime_str(ctime: usize, current_year: isize) -> String {
+    let ctime: DateTime<Local> = DateTime::from_timestamp_secs(ctime.try_into().unwrap_or(0))
+        .unwrap()
+        .into();
+    let fmt = if (ctime.year() as isize) == current_year {
+        "%b %e %H:%M"
+    } else {
+        "%b %e  %Y"
+    };
+    ctime.format(fmt).to_string()
+}
+
+/// How long ago `time` (in seconds since the epoch) was, roughly, e.g. "3 days ago"
+pub fn age_str(time: usize) -> String {
+    let secs = Local::now().timestamp() - time as i64;
+    if secs < 60 {
+        return "just now".to_string();
+    }
+    let (n, unit) = [
+        (365 * 24 * 3600, "year"),
+        (30 * 24 * 3600, "month"),
+        (7 * 24 * 3600, "week"),
+        (24 * 3600, "day"),
+        (3600, "hour"),
+        (60, "minute"),
+    ]
+    .into_iter()
+    .find_map(|(unit_secs, unit)| (secs >= unit_secs).then(|| (secs / unit_secs, unit)))
+    .unwrap_or((0, "minute"));
+    format!("{} {}{} ago", n, unit, if n == 1 { "" } else { "s" })
+}
+
+/// Where a popup goes: centered, and no bigger than `r`
 fn popup_rects(xsize: u16, ysize: u16, r: Rect) -> [Rect; 2] {
+    let xsize = xsize.min(r.width);
+    let ysize = ysize.min(r.height.saturating_sub(2));
     // Cut the x axis
     let xrect = Layout::default()
         .direction(Direction::Horizontal)
@@ -458,18 +1223,38 @@ fn popup_rects(xsize: u16, ysize: u16, r: Rect) -> [Rect; 2] {
     [yrects[1], yrects[2]]
 }
 
-pub fn ui(frame: &mut Frame, app: &mut App) {
-    let [header_area, message_area, main_area] = Layout::vertical([
+/// Draw the app, returning the regions of the screen that should be hyperlinked.
+pub fn ui(frame: &mut Frame, app: &mut App) -> Vec<Hyperlink> {
+    if app.plain {
+        ui_plain(frame, app);
+        return vec![];
+    }
+
+    let area = frame.area();
+    if area.width < MIN_WIDTH || area.height < MIN_HEIGHT {
+        render_too_small(area, frame.buffer_mut());
+        return vec![];
+    }
+
+    let show_hints = app.show_hints && area.height >= HINTS_MIN_HEIGHT;
+    let [header_area, message_area, main_area, hint_area] = Layout::vertical([
         Constraint::Length(1),
         Constraint::Length(1),
         Constraint::Fill(1),
+        Constraint::Length(show_hints as u16),
     ])
-    .areas(frame.area());
+    .areas(area);
 
     app.render_header(header_area, frame.buffer_mut());
-    app.render_list(main_area, frame.buffer_mut());
+    let mut links = app.render_list(main_area, frame.buffer_mut());
+    if app.show_debug {
+        app.render_debug(main_area, frame.buffer_mut());
+        // Links underneath the overlay would bleed through it
+        links.clear();
+    }
 
     app.render_message(&app.message, message_area, frame.buffer_mut());
+    app.render_hints(hint_area, frame.buffer_mut());
 
     if let Some(popup) = &mut app.popup {
         let popup_areas = popup_rects(
@@ -478,5 +1263,471 @@ pub fn ui(frame: &mut Frame, app: &mut App) {
             frame.area(),
         );
         render_popup(popup, popup_areas, frame.buffer_mut());
+        links.clear();
+    }
+
+    links
+}
+
+/// Instead of a garbled listing, say how much room is needed; the app is
+/// drawn again as soon as the terminal is resized
+fn render_too_small(area: Rect, buf: &mut Buffer) {
+    let text = Text::from(vec![
+        Line::from("Terminal too small").bold(),
+        Line::from(format!("{}×{}", area.width, area.height)),
+        Line::from(format!("needs {}×{}", MIN_WIDTH, MIN_HEIGHT)),
+    ])
+    .centered()
+    .fg(TEXT_FG_COLOR)
+    .bg(LIST_BG_COLOR);
+    let [_, middle, _] = Layout::vertical([
+        Constraint::Fill(1),
+        Constraint::Length(text.height() as u16),
+        Constraint::Fill(1),
+    ])
+    .areas(area);
+    Block::new().bg(LIST_BG_COLOR).render(area, buf);
+    text.render(middle, buf);
+}
+
+/// Draw the app as plain lines of text, for screen readers: the selected
+/// entry on the top line (where the cursor is left), then the message, the
+/// directory, and one line per entry. Popups replace the listing.
+fn ui_plain(frame: &mut Frame, app: &mut App) {
+    let [status_area, message_area, title_area, main_area, hint_area] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(1),
+        Constraint::Length(1),
+        Constraint::Fill(1),
+        Constraint::Length(app.show_hints as u16),
+    ])
+    .areas(frame.area());
+    let buf = frame.buffer_mut();
+
+    let hints: Vec<String> = app
+        .hints()
+        .into_iter()
+        .map(|(key, label)| format!("{} {}", key, label.to_lowercase()))
+        .collect();
+    Line::from(format!("Keys: {}", hints.join(", "))).render(hint_area, buf);
+
+    let height = main_area.height as usize;
+    let offset = app.dir_listing.scroll_into_view(height, app.scroll_off);
+    app.dir_listing.viewport_height = height;
+    if app.show_permissions {
+        app.dir_listing.check_acls(offset, height);
+    }
+    if app.show_cost && app.cost.by_pool() {
+        app.dir_listing.check_pools(offset, height);
+    }
+    let selected = app.dir_listing.selected();
+    let stats = &app.dir_listing.stats;
+    let trends = &app.dir_listing.trends;
+    let describe = |entry: &DirEntry| {
+        entry.describe(
+            stats,
+            app.show_owner,
+            app.show_group,
+            app.numeric_ids,
+            app.show_permissions,
+            app.show_ctime,
+            app.show_average,
+            app.dir_listing
+                .cost(entry, &app.cost)
+                .filter(|_| app.show_cost)
+                .map(|cost| cost_str(cost, &app.cost.currency)),
+            trends.get(&entry.name),
+            app.dir_listing.change(entry),
+            app.dir_listing.since_visit(entry),
+            app.plugins
+                .columns
+                .iter()
+                .zip(app.plugin_values(entry))
+                .filter_map(|(column, value)| Some((column.name.as_str(), value?))),
+        )
+    };
+
+    let status = match selected {
+        Some(i) => format!("Selected: {}", describe(app.dir_listing.get(i))),
+        None => "Nothing selected".to_string(),
+    };
+    Line::from(status).render(status_area, buf);
+    if let Some(message) = &app.message {
+        Line::from(message.text.as_str()).render(message_area, buf);
+    }
+
+    let mut title = format!(
+        "Directory {}: size {}, {} files.",
+        app.cwd.to_string_lossy(),
+        size_str(Some(stats.total_size), false),
+        count_str(stats.total_rentries)
+    );
+    if let Some(rctime) = app.dir_listing.last_activity() {
+        title.push_str(&format!(" Last activity {}.", age_str(rctime)));
+    }
+    if app.show_cost
+        && let Some(cost) = app.dir_listing.total_cost(&app.cost)
+    {
+        title.push_str(&format!(
+            " Costs about {} a month.",
+            cost_str(cost, &app.cost.currency)
+        ));
+    }
+    if let Some((count, size)) = app.dir_listing.hidden_sums() {
+        title.push_str(&format!(
+            " Hidden: {} {}, {}.",
+            count_str(count),
+            if count == 1 { "entry" } else { "entries" },
+            size_str(Some(size), false)
+        ));
+    }
+    if app.dir_listing.stats_may_be_stale() {
+        title.push_str(" Stats may be stale.");
+    }
+    let marked = app.dir_listing.marked_count();
+    if marked > 0 {
+        let (size, rentries) = app.dir_listing.marked_sums();
+        let local = format!(
+            "{}, {} files",
+            size_str(Some(size), false),
+            count_str(rentries)
+        );
+        title.push_str(&format!(
+            " Marked: {} {}, {}.",
+            count_str(marked),
+            if marked == 1 { "item" } else { "items" },
+            with_elsewhere(local, rentries, &app.dir_listing.marked_sums_elsewhere())
+        ));
+    }
+    if let Some(cephfs) = app.dir_listing.cephfs() {
+        title.push_str(&format!(" On {}.", cephfs_str(cephfs, true)));
+    }
+    if let Some(space) = app.dir_listing.space.filter(|space| space.total > 0) {
+        title.push_str(&format!(
+            " Filesystem {:.0}% full, {} free of {}.",
+            used_fraction(space) * 100.0,
+            size_str(Some(space.avail), false),
+            size_str(Some(space.total), false)
+        ));
+    }
+    if let Some(quota) = app.dir_listing.user_quota {
+        title.push_str(&format!(" Your {}.", user_quota_str(quota, true)));
+    }
+    let alerts = app.alert_count();
+    if alerts > 0 {
+        title.push_str(&format!(
+            " {} {} over the alert limits.",
+            count_str(alerts),
+            if alerts == 1 { "entry" } else { "entries" }
+        ));
+    }
+    title.push_str(&format!(
+        " Contains {}.",
+        kinds_str(stats.kinds, app.dir_listing.has_more())
+    ));
+    match app.jobs.running() {
+        0 => {}
+        1 => title.push_str(" 1 job running."),
+        n => title.push_str(&format!(" {} jobs running.", n)),
+    }
+    title.push_str(" Press ? for help.");
+    Line::from(title).render(title_area, buf);
+
+    if let Some(popup) = &app.popup {
+        let mut lines = vec![Line::from(popup.title.clone())];
+        lines.extend(popup.text.lines().map(|l| Line::from(l.trim().to_string())));
+        lines.push(Line::from(popup.bottom_title.clone()));
+        Paragraph::new(lines)
+            .scroll((popup.scroll() as u16, 0))
+            .render(main_area, buf);
+    } else {
+        let lines: Vec<Line> = app
+            .dir_listing
+            .iter_entries()
+            .enumerate()
+            .skip(offset)
+            .take(height)
+            .map(|(i, entry)| {
+                let marker = if selected == Some(i) { '>' } else { ' ' };
+                let marked = if app.dir_listing.is_marked(entry) {
+                    '*'
+                } else {
+                    ' '
+                };
+                Line::from(format!("{}{}{}", marker, marked, describe(entry)))
+            })
+            .collect();
+        Paragraph::new(lines).render(main_area, buf);
+    }
+
+    frame.set_cursor_position((status_area.x, status_area.y));
+}
+
+impl DirEntry {
+    /// The entry in words, e.g. "data/, directory, size 12.3 GB, 45% of the total, 4,500 files"
+    #[allow(clippy::too_many_arguments)]
+    fn describe<'a>(
+        &self,
+        listing_stats: &ListingStats,
+        show_owner: bool,
+        show_group: bool,
+        numeric_ids: bool,
+        show_permissions: bool,
+        show_ctime: bool,
+        show_average: bool,
+        cost: Option<String>,
+        trend: Option<&Trend>,
+        change: Option<Change>,
+        since_visit: Option<Change>,
+        plugins: impl Iterator<Item = (&'a str, &'a str)>,
+    ) -> String {
+        if self.name == ".." {
+            return "parent directory".to_string();
+        }
+        let mut parts = vec![
+            self.name.to_string(),
+            match self.kind {
+                EntryKind::Dir => "directory",
+                EntryKind::File => "file",
+                EntryKind::Symlink => "symlink",
+            }
+            .to_string(),
+        ];
+        if !self.loaded {
+            parts.push("loading".to_string());
+            return parts.join(", ");
+        }
+        if self.timed_out {
+            parts.push("timed out".to_string());
+            return parts.join(", ");
+        }
+        if let Some(size) = self.size {
+            parts.push(format!("size {}", size_str(Some(size), false)));
+            if listing_stats.total_size > 0 {
+                let percent = 100.0 * safe_div(size, listing_stats.total_size);
+                parts.push(format!("{:.0}% of the total", percent));
+            }
+        }
+        if let Some(rentries) = self.rentries {
+            parts.push(format!("{} files", count_str(rentries)));
+        }
+        if let Some(allocated) = self.allocated {
+            parts.push(format!(
+                "sparse, {} on disk",
+                size_str(Some(allocated), false)
+            ));
+        }
+        if show_average && let Some(average) = self.average_size() {
+            parts.push(format!("{} per file", size_str(Some(average), false)));
+        }
+        if let Some(cost) = cost {
+            parts.push(format!("costs {} a month", cost));
+        }
+        if let Some(trend) = trend {
+            let change = signed_str(trend.per_week, |s| size_str(Some(s), false));
+            parts.push(format!("changing {} per week", change));
+        }
+        if let Some((text, _)) = change.and_then(change_str) {
+            parts.push(match change {
+                Some(Change::Added) => "new since the last refresh".to_string(),
+                _ => format!("changed by {} since the last refresh", text),
+            });
+        } else if let Some((text, _)) = since_visit.and_then(change_str) {
+            parts.push(match since_visit {
+                Some(Change::Added) => "new since the last visit".to_string(),
+                _ => format!("changed by {} since the last visit", text),
+            });
+        }
+        for (name, value) in plugins {
+            parts.push(format!("{} {}", name, value));
+        }
+        if show_owner && let Some(user) = self.user(numeric_ids) {
+            parts.push(format!("owner {}", user));
+        }
+        if show_group && let Some(group) = self.group(numeric_ids) {
+            parts.push(format!("group {}", group));
+        }
+        if show_permissions && let Some(mode) = self.mode_str() {
+            parts.push(format!("permissions {}", mode));
+            if let Some(warning) = self.permission_warning() {
+                parts.push(format!("warning: {}", warning));
+            }
+        }
+        if show_ctime
+            && let Some(ctime) = self.ctime
+            && let Some(ctime) = DateTime::from_timestamp_secs(ctime as i64)
+        {
+            let ctime = ctime.with_timezone(&Local);
+            parts.push(format!("changed {}", ctime.format("%Y-%m-%d %H:%M")));
+        }
+        parts.join(", ")
+    }
+}
+
+/// The fraction of a filesystem that's used, like df(1) says: out of what's
+/// used and what unprivileged users can still use
+fn used_fraction(space: Space) -> f64 {
+    let used = space.total.saturating_sub(space.free);
+    used as f64 / (used + space.avail).max(1) as f64
+}
+
+/// How full the filesystem is, e.g. a gauge then "used of 1.2 PB, 300 TB
+/// free", in at most `width` columns. None if not even the gauge fits.
+fn space_line(space: Space, width: usize, theme: &ThemeConfig) -> Option<Line<'static>> {
+    const SPACE_GAUGE_WIDTH: usize = 12;
+    if space.total == 0 {
+        return None;
+    }
+    let total = size_str(Some(space.total), false);
+    let free = size_str(Some(space.avail), false);
+    let text = [
+        format!(" used of {}, {} free ", total, free),
+        format!(" of {} ", total),
+        " ".to_string(),
+    ]
+    .into_iter()
+    .find(|text| 1 + SPACE_GAUGE_WIDTH + text.chars().count() <= width)?;
+
+    let fraction = used_fraction(space);
+    let mut spans = vec![Span::raw(" ")];
+    spans.extend(gauge(
+        fraction,
+        Some(fraction),
+        SPACE_GAUGE_WIDTH,
+        theme.gauge_color(Some(fraction)),
+        false,
+    ));
+    spans.push(Span::styled(text, Style::default().fg(HEADER_BG_COLOR)));
+    Some(Line::from(spans))
+}
+
+/// An amount of money, e.g. "$12.34", or "$1,234" without the cents once
+/// they don't matter
+pub fn cost_str(amount: f64, currency: &str) -> String {
+    if amount < 100.0 {
+        format!("{}{:.2}", currency, amount)
+    } else {
+        format!("{}{}", currency, count_str(amount.round() as usize))
+    }
+}
+
+/// How much of their quota the user has used, e.g. "quota: 12.4 GB of 20.0 GB
+/// (62%), 1.2 K of 10.0 K files", or just "quota: 62% of 20.0 GB"
+fn user_quota_str(quota: UserQuota, long: bool) -> String {
+    let percent = |used: usize, max: usize| (used as f64 / max as f64 * 100.0).round();
+    let mut parts = Vec::new();
+    if quota.quota.max_bytes > 0 {
+        let max = size_str(Some(quota.quota.max_bytes), false);
+        parts.push(if long {
+            format!("{} of {}", size_str(Some(quota.bytes), false), max)
+        } else {
+            format!(
+                "{}% of {}",
+                percent(quota.bytes, quota.quota.max_bytes),
+                max
+            )
+        });
+    }
+    if quota.quota.max_files > 0 && (long || parts.is_empty()) {
+        let max = rentries_str(Some(quota.quota.max_files), false);
+        parts.push(if long {
+            format!(
+                "{} of {} files",
+                rentries_str(Some(quota.files), false),
+                max
+            )
+        } else {
+            format!(
+                "{}% of {} files",
+                percent(quota.files, quota.quota.max_files),
+                max
+            )
+        });
+    }
+    if long {
+        let used = (quota.fraction_used() * 100.0).round();
+        format!("quota: {} ({}%)", parts.join(", "), used)
+    } else {
+        format!("quota: {}", parts.join(", "))
+    }
+}
+
+/// Which CephFS, e.g. "cephfs: hpc_data (fsid 3f1c2a9b…)", so that it's
+/// clear which cluster is which
+fn cephfs_str(cephfs: &CephFs, with_fsid: bool) -> String {
+    let mut s = match &cephfs.name {
+        Some(name) => format!("cephfs: {}", name),
+        None => "cephfs".to_string(),
+    };
+    if with_fsid && let Some(fsid) = &cephfs.fsid {
+        let short: String = fsid.chars().take(8).collect();
+        let more = if short.len() < fsid.len() { "…" } else { "" };
+        s.push_str(&format!(" (fsid {}{})", short, more));
+    }
+    s
+}
+
+/// What's mounted, e.g. "nfs4 from fileserver:/archive" or "cephfs: hpc_data (fsid 3f1c2a9b…)"
+fn mount_str(mount: &Mount) -> String {
+    match &mount.cephfs {
+        Some(cephfs) => cephfs_str(cephfs, true),
+        None => format!("{} from {}", mount.fs_type, mount.source),
+    }
+}
+
+/// `local`, the sums of what's on this filesystem, followed by those on
+/// other filesystems, kept apart, e.g. "4.2 TB, 1.3 M files + 1.2 TB, 300
+/// files on nfs4 from fileserver:/archive". `local` is left out if it's of
+/// nothing, i.e. `local_rentries` is 0.
+fn with_elsewhere(local: String, local_rentries: usize, sums: &[(&Mount, usize, usize)]) -> String {
+    let elsewhere = sums.iter().map(|(mount, size, rentries)| {
+        format!(
+            "{}, {} files on {}",
+            size_str(Some(*size), false),
+            count_str(*rentries),
+            mount_str(mount)
+        )
+    });
+    let local = (local_rentries > 0 || sums.is_empty()).then_some(local);
+    local
+        .into_iter()
+        .chain(elsewhere)
+        .collect::<Vec<_>>()
+        .join(" + ")
+}
+
+/// What a directory holds, e.g. "123 dirs, 4,567 files, 12 symlinks". Only
+/// counts what's been listed so far if `partial`.
+fn kinds_str(kinds: KindCounts, partial: bool) -> String {
+    let plural = |n: usize, one: &str, many: &str| {
+        format!("{} {}", count_str(n), if n == 1 { one } else { many })
+    };
+    let mut s = format!(
+        "{}, {}",
+        plural(kinds.dirs, "dir", "dirs"),
+        plural(kinds.files, "file", "files")
+    );
+    if kinds.symlinks > 0 {
+        s.push_str(&format!(
+            ", {}",
+            plural(kinds.symlinks, "symlink", "symlinks")
+        ));
+    }
+    if partial {
+        s.push_str(" so far");
+    }
+    s
+}
+
+/// A count with thousands separators, e.g. "4,500", which reads better than "4.5 K"
+pub fn count_str(n: usize) -> String {
+    let digits = n.to_string();
+    let mut out = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(c);
     }
+    out
 }
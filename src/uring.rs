@@ -0,0 +1,350 @@
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// How many calls are handed to the kernel at a time
+const RING_ENTRIES: u32 = 256;
+/// What a value is read into; longer ones come back with ERANGE
+const XATTR_BUF_SIZE: usize = 64;
+
+/// From linux/io_uring.h. GETXATTR needs Linux 5.19; older kernels fail it
+/// with EINVAL.
+const IORING_OP_STATX: u8 = 21;
+const IORING_OP_GETXATTR: u8 = 44;
+const IORING_ENTER_GETEVENTS: u32 = 1;
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_CQ_RING: libc::off_t = 0x8000000;
+const IORING_OFF_SQES: libc::off_t = 0x10000000;
+
+/// Whether listings batch their calls through io_uring, from the config
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Whether the kernel lets us set up a ring, which seccomp filters can forbid
+static AVAILABLE: OnceLock<bool> = OnceLock::new();
+
+thread_local! {
+    /// Each thread's ring, set up on first use
+    static RING: RefCell<Option<Ring>> = const { RefCell::new(None) };
+}
+
+pub fn configure(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether io_uring is enabled, and works here
+pub fn available() -> bool {
+    ENABLED.load(Ordering::Relaxed) && *AVAILABLE.get_or_init(|| Ring::new(1).is_ok())
+}
+
+/// lstat(2) the `(dirfd, path)`s, like fstatat(2) with AT_SYMLINK_NOFOLLOW,
+/// all at once. An empty path is the dirfd itself, as with AT_EMPTY_PATH.
+/// None if io_uring isn't available, for the caller to make the calls itself.
+pub fn statx(requests: Vec<(RawFd, CString)>) -> Option<Vec<io::Result<libc::statx>>> {
+    let mut buffers: Vec<MaybeUninit<libc::statx>> = Vec::with_capacity(requests.len());
+    buffers.resize_with(requests.len(), MaybeUninit::uninit);
+    let sqes: Vec<Sqe> = requests
+        .iter()
+        .zip(buffers.iter_mut())
+        .map(|((dirfd, path), buf)| {
+            let mut flags = libc::AT_SYMLINK_NOFOLLOW | libc::AT_STATX_SYNC_AS_STAT;
+            if path.is_empty() {
+                flags |= libc::AT_EMPTY_PATH;
+            }
+            Sqe {
+                opcode: IORING_OP_STATX,
+                fd: *dirfd,
+                addr: path.as_ptr() as u64,
+                off: buf.as_mut_ptr() as u64,
+                len: libc::STATX_BASIC_STATS,
+                op_flags: flags as u32,
+                ..Sqe::default()
+            }
+        })
+        .collect();
+    let (results, (_, buffers)) = run(&sqes, (requests, buffers))?;
+    Some(
+        results
+            .into_iter()
+            .zip(buffers)
+            .map(|(res, buf)| match res {
+                0 => Ok(unsafe { buf.assume_init() }),
+                res => Err(io::Error::from_raw_os_error(-res)),
+            })
+            .collect(),
+    )
+}
+
+/// getxattr(2) the `(path, name)`s all at once. Values that don't fit in a
+/// small buffer fail with ERANGE. None if io_uring isn't available.
+pub fn getxattr(requests: Vec<(CString, &'static CStr)>) -> Option<Vec<io::Result<Vec<u8>>>> {
+    let mut values = vec![[0u8; XATTR_BUF_SIZE]; requests.len()];
+    let sqes: Vec<Sqe> = requests
+        .iter()
+        .zip(values.iter_mut())
+        .map(|((path, name), value)| Sqe {
+            opcode: IORING_OP_GETXATTR,
+            addr: name.as_ptr() as u64,
+            off: value.as_mut_ptr() as u64,
+            len: XATTR_BUF_SIZE as u32,
+            addr3: path.as_ptr() as u64,
+            ..Sqe::default()
+        })
+        .collect();
+    let (results, (_, values)) = run(&sqes, (requests, values))?;
+    Some(
+        results
+            .into_iter()
+            .zip(values)
+            .map(|(res, value)| match res {
+                res if res >= 0 => Ok(value[..res as usize].to_vec()),
+                res => Err(io::Error::from_raw_os_error(-res)),
+            })
+            .collect(),
+    )
+}
+
+/// Submit `sqes` on this thread's ring and wait for all of them, returning
+/// their results in order. What they point to is in `keep`, which is given
+/// back once they're done: if the ring fails partway, both are leaked
+/// rather than freed under the kernel.
+fn run<K>(sqes: &[Sqe], keep: K) -> Option<(Vec<i32>, K)> {
+    if !available() {
+        return None;
+    }
+    RING.with_borrow_mut(|ring| {
+        if ring.is_none() {
+            *ring = Ring::new(RING_ENTRIES).ok();
+        }
+        match ring.as_mut()?.run(sqes) {
+            Ok(results) => Some((results, keep)),
+            Err(_) => {
+                std::mem::forget(keep);
+                std::mem::forget(ring.take());
+                None
+            }
+        }
+    })
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+/// A submission queue entry, with the unions named for how we use them
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    /// Where statx(2) writes, or the value buffer of getxattr(2)
+    off: u64,
+    /// The path of statx(2), or the name of getxattr(2)
+    addr: u64,
+    len: u32,
+    op_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    /// The path of getxattr(2)
+    addr3: u64,
+    pad: u64,
+}
+
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+struct Mmap {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mmap {
+    fn new(fd: &OwnedFd, len: usize, offset: libc::off_t) -> io::Result<Mmap> {
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd.as_raw_fd(),
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mmap {
+            ptr: ptr.cast(),
+            len,
+        })
+    }
+
+    /// What's at `offset`, which the kernel says is a `T`
+    fn at<T>(&self, offset: u32) -> *mut T {
+        debug_assert!(offset as usize + size_of::<T>() <= self.len);
+        unsafe { self.ptr.add(offset as usize).cast() }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr.cast(), self.len) };
+    }
+}
+
+/// An io_uring instance: the submission and completion queues it shares
+/// with the kernel, which runs the calls on its own workers, side by side
+struct Ring {
+    fd: OwnedFd,
+    params: Params,
+    sq: Mmap,
+    cq: Mmap,
+    sqes: Mmap,
+}
+
+impl Ring {
+    fn new(entries: u32) -> io::Result<Ring> {
+        let mut params = Params::default();
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_setup,
+                entries,
+                &mut params as *mut Params,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * size_of::<u32>();
+        let cq_len = params.cq_off.cqes as usize + params.cq_entries as usize * size_of::<Cqe>();
+        let sqes_len = params.sq_entries as usize * size_of::<Sqe>();
+        Ok(Ring {
+            sq: Mmap::new(&fd, sq_len, IORING_OFF_SQ_RING)?,
+            cq: Mmap::new(&fd, cq_len, IORING_OFF_CQ_RING)?,
+            sqes: Mmap::new(&fd, sqes_len, IORING_OFF_SQES)?,
+            fd,
+            params,
+        })
+    }
+
+    /// Submit `sqes`, as many at a time as fit, and wait for them
+    fn run(&mut self, sqes: &[Sqe]) -> io::Result<Vec<i32>> {
+        let mut results = vec![0; sqes.len()];
+        let sq_off = &self.params.sq_off;
+        let cq_off = &self.params.cq_off;
+        let sq_tail: &AtomicU32 = unsafe { &*self.sq.at(sq_off.tail) };
+        let sq_mask = unsafe { *self.sq.at::<u32>(sq_off.ring_mask) };
+        let sq_array: *mut u32 = self.sq.at(sq_off.array);
+        let cq_head: &AtomicU32 = unsafe { &*self.cq.at(cq_off.head) };
+        let cq_tail: &AtomicU32 = unsafe { &*self.cq.at(cq_off.tail) };
+        let cq_mask = unsafe { *self.cq.at::<u32>(cq_off.ring_mask) };
+        let cqes: *const Cqe = self.cq.at(cq_off.cqes);
+        let sqe_slots: *mut Sqe = self.sqes.at(0);
+
+        for (n, chunk) in sqes.chunks(self.params.sq_entries as usize).enumerate() {
+            let base = n * self.params.sq_entries as usize;
+            // Only this thread adds to the queue
+            let mut tail = sq_tail.load(Ordering::Relaxed);
+            for (i, sqe) in chunk.iter().enumerate() {
+                let slot = tail & sq_mask;
+                let sqe = Sqe {
+                    user_data: (base + i) as u64,
+                    ..*sqe
+                };
+                unsafe {
+                    sqe_slots.add(slot as usize).write(sqe);
+                    sq_array.add(slot as usize).write(slot);
+                }
+                tail = tail.wrapping_add(1);
+            }
+            sq_tail.store(tail, Ordering::Release);
+
+            let mut unsubmitted = chunk.len();
+            let mut pending = chunk.len();
+            while pending > 0 {
+                let submitted = unsafe {
+                    libc::syscall(
+                        libc::SYS_io_uring_enter,
+                        self.fd.as_raw_fd(),
+                        unsubmitted as u32,
+                        pending as u32,
+                        IORING_ENTER_GETEVENTS,
+                        std::ptr::null::<libc::sigset_t>(),
+                        0usize,
+                    )
+                };
+                if submitted < 0 {
+                    let e = io::Error::last_os_error();
+                    match e.raw_os_error() {
+                        Some(libc::EINTR | libc::EAGAIN | libc::EBUSY) => continue,
+                        _ => return Err(e),
+                    }
+                }
+                unsubmitted -= (submitted as usize).min(unsubmitted);
+
+                let mut head = cq_head.load(Ordering::Relaxed);
+                let end = cq_tail.load(Ordering::Acquire);
+                while head != end {
+                    let cqe = unsafe { &*cqes.add((head & cq_mask) as usize) };
+                    if let Some(result) = results.get_mut(cqe.user_data as usize) {
+                        *result = cqe.res;
+                    }
+                    pending -= 1;
+                    head = head.wrapping_add(1);
+                }
+                cq_head.store(head, Ordering::Release);
+            }
+        }
+        Ok(results)
+    }
+}
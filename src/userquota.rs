@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::command;
+use crate::fs::Quota;
+use crate::mounts::Mount;
+
+/// quotactl(2)'s quota types, from linux/quota.h
+const USRQUOTA: libc::c_int = 0;
+/// Which fields of a dqblk were filled in
+const QIF_BLIMITS: u32 = 1;
+const QIF_SPACE: u32 = 2;
+const QIF_ILIMITS: u32 = 4;
+const QIF_INODES: u32 = 8;
+/// The unit of the block limits, unlike the usage, which is in bytes
+const QIF_DQBLKSIZE: usize = 1024;
+
+/// How long a filesystem's answer is kept, since listings are made often
+const TTL: Duration = Duration::from_secs(60);
+/// How long to wait for the quota command, which asks the NFS server
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How much of their quota on a filesystem the current user uses
+#[derive(Debug, Clone, Copy)]
+pub struct UserQuota {
+    pub bytes: usize,
+    pub files: usize,
+    pub quota: Quota,
+}
+
+impl UserQuota {
+    pub fn fraction_used(&self) -> f64 {
+        self.quota.fraction_used(self.bytes, self.files)
+    }
+}
+
+/// What each filesystem said, and when, by mount point
+type Cache = HashMap<PathBuf, (Option<UserQuota>, Instant)>;
+
+static CACHE: LazyLock<Mutex<Cache>> = LazyLock::new(Default::default);
+
+/// The current user's quota on the filesystem of `mount`, if they have one.
+/// CephFS has none; its quotas are on directories.
+pub fn of_current_user(mount: &Mount) -> Option<UserQuota> {
+    if mount.cephfs.is_some() {
+        return None;
+    }
+    if let Some((quota, time)) = CACHE.lock().expect("not poisoned").get(&mount.mount_point)
+        && time.elapsed() < TTL
+    {
+        return *quota;
+    }
+    let quota = if mount.fs_type.starts_with("nfs") {
+        from_quota_command(mount)
+    } else {
+        from_quotactl(mount)
+    };
+    CACHE
+        .lock()
+        .expect("not poisoned")
+        .insert(mount.mount_point.clone(), (quota, Instant::now()));
+    quota
+}
+
+/// The limit that matters: the soft one, which turns hard after the grace
+/// period, or the hard one if there's no soft one
+fn limit(soft: usize, hard: usize) -> usize {
+    if soft > 0 { soft } else { hard }
+}
+
+/// Ask the kernel, for filesystems on block devices, like XFS and ext4
+fn from_quotactl(mount: &Mount) -> Option<UserQuota> {
+    if !mount.source.starts_with('/') {
+        return None;
+    }
+    let device = CString::new(mount.source.as_str()).ok()?;
+    let mut dqblk: libc::dqblk = unsafe { std::mem::zeroed() };
+    let res = unsafe {
+        libc::quotactl(
+            libc::QCMD(libc::Q_GETQUOTA, USRQUOTA),
+            device.as_ptr(),
+            libc::getuid() as libc::c_int,
+            &mut dqblk as *mut libc::dqblk as *mut libc::c_char,
+        )
+    };
+    let wanted = QIF_BLIMITS | QIF_SPACE | QIF_ILIMITS | QIF_INODES;
+    if res != 0 || dqblk.dqb_valid & wanted != wanted {
+        return None;
+    }
+    let quota = Quota {
+        max_bytes: limit(dqblk.dqb_bsoftlimit as usize, dqblk.dqb_bhardlimit as usize)
+            * QIF_DQBLKSIZE,
+        max_files: limit(dqblk.dqb_isoftlimit as usize, dqblk.dqb_ihardlimit as usize),
+    };
+    (quota.max_bytes > 0 || quota.max_files > 0).then_some(UserQuota {
+        bytes: dqblk.dqb_curspace as usize,
+        files: dqblk.dqb_curinodes as usize,
+        quota,
+    })
+}
+
+/// Ask the NFS server's rquotad through quota(1), since quotactl doesn't
+/// work over NFS. With -w and -p, its lines are e.g.
+/// "fileserver:/home  12345*  100000  120000  0  234  0  0  0", with the
+/// sizes in kilobytes and a '*' on what's over its soft limit.
+fn from_quota_command(mount: &Mount) -> Option<UserQuota> {
+    let mut cmd = Command::new("quota");
+    cmd.args(["-w", "-p", "-f"]).arg(&mount.mount_point);
+    let output = command::output(cmd, TIMEOUT).ok()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    let line = text
+        .lines()
+        .find(|line| line.split_whitespace().next() == Some(mount.source.as_str()))?;
+    let numbers: Vec<usize> = line
+        .split_whitespace()
+        .skip(1)
+        .map(|field| field.trim_end_matches('*').parse().ok())
+        .collect::<Option<_>>()?;
+    let [kbytes, soft, hard, _, files, files_soft, files_hard, _] = numbers[..] else {
+        return None;
+    };
+    let quota = Quota {
+        max_bytes: limit(soft, hard) * 1024,
+        max_files: limit(files_soft, files_hard),
+    };
+    (quota.max_bytes > 0 || quota.max_files > 0).then_some(UserQuota {
+        bytes: kbytes * 1024,
+        files,
+        quota,
+    })
+}
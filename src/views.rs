@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::app::{SortField, SortMode};
+use crate::config::{state_dir, write_state_file};
+
+/// How a listing is shown: the sort and the optional columns. A view can be
+/// saved for a directory, and is used there and below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct View {
+    pub sort: SortField,
+    pub reversed: bool,
+    pub owner: bool,
+    pub group: bool,
+    pub numeric_ids: bool,
+    pub permissions: bool,
+    pub ctime: bool,
+    pub average: bool,
+    pub cost: bool,
+    pub hide_dotfiles: bool,
+}
+
+impl Default for View {
+    fn default() -> Self {
+        View::new(SortField::Size.initial_mode())
+    }
+}
+
+impl View {
+    /// Sorted by `sort_mode`, with no optional columns
+    pub fn new(sort_mode: SortMode) -> Self {
+        View {
+            sort: *sort_mode.field(),
+            reversed: sort_mode.is_reversed(),
+            owner: false,
+            group: false,
+            numeric_ids: false,
+            permissions: false,
+            ctime: false,
+            average: false,
+            cost: false,
+            hide_dotfiles: false,
+        }
+    }
+
+    pub fn sort_mode(&self) -> SortMode {
+        if self.reversed {
+            SortMode::Reversed(self.sort)
+        } else {
+            SortMode::Normal(self.sort)
+        }
+    }
+}
+
+/// Where saved views are kept: a TOML table per directory
+fn path() -> Option<PathBuf> {
+    Some(state_dir()?.join("views.toml"))
+}
+
+/// The views saved by earlier sessions. A missing or unreadable file just
+/// means there aren't any.
+pub fn load() -> BTreeMap<PathBuf, View> {
+    let Some(text) = path().and_then(|path| std::fs::read_to_string(path).ok()) else {
+        return BTreeMap::new();
+    };
+    toml::from_str::<BTreeMap<PathBuf, View>>(&text).unwrap_or_default()
+}
+
+/// Save `view` for `dir`, or forget what was saved with None, keeping the
+/// other saved views, which may be from another session that's still running.
+pub fn save(dir: &Path, view: Option<View>) -> io::Result<()> {
+    let Some(path) = path() else {
+        return Ok(());
+    };
+    if dir.to_str().is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "can't save a view for a path that isn't UTF-8",
+        ));
+    }
+    let mut views = load();
+    match view {
+        Some(view) => views.insert(dir.to_path_buf(), view),
+        None => views.remove(dir),
+    };
+    let text = toml::to_string(&views).map_err(io::Error::other)?;
+    write_state_file(&path, text.as_bytes())
+}
@@ -0,0 +1,95 @@
+use std::ffi::CString;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::Duration;
+
+/// Wait for this long without events before refreshing, so that a burst of
+/// changes (e.g. an `rm -r` in progress) doesn't refresh on every file
+pub const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+/// ...but never wait longer than this while changes keep coming
+pub const WATCH_MAX_DELAY: Duration = Duration::from_secs(3);
+
+const WATCH_MASK: u32 = libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MODIFY
+    | libc::IN_ATTRIB
+    | libc::IN_CLOSE_WRITE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO;
+
+const EVENT_HEADER_SIZE: usize = std::mem::size_of::<libc::inotify_event>();
+
+/// Watches a single directory with inotify for entries being created,
+/// deleted, or resized.
+/// Note that inotify only sees changes made through this kernel: on CephFS,
+/// changes made from other clients, and changes deeper in the tree, are not seen.
+pub struct Watcher {
+    fd: OwnedFd,
+    wd: Option<i32>,
+}
+
+impl Watcher {
+    pub fn new() -> std::io::Result<Watcher> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Watcher {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            wd: None,
+        })
+    }
+
+    /// Watch `path` instead of whatever we were watching before.
+    /// With `None`, stop watching.
+    pub fn watch(&mut self, path: Option<&Path>) -> std::io::Result<()> {
+        if let Some(wd) = self.wd.take() {
+            unsafe { libc::inotify_rm_watch(self.fd.as_raw_fd(), wd) };
+        }
+        let Some(path) = path else {
+            return Ok(());
+        };
+
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        let wd =
+            unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), c_path.as_ptr(), WATCH_MASK) };
+        if wd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        self.wd = Some(wd);
+        Ok(())
+    }
+
+    /// Drain the queued events. Returns true if any of them were for the
+    /// directory we're watching now.
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                )
+            };
+            if n <= 0 {
+                // EAGAIN: nothing more to read
+                return changed;
+            }
+
+            let mut offset = 0;
+            while offset + EVENT_HEADER_SIZE <= n as usize {
+                let event: libc::inotify_event =
+                    unsafe { std::ptr::read_unaligned(buf.as_ptr().add(offset) as *const _) };
+                if event.mask & libc::IN_Q_OVERFLOW != 0
+                    || (Some(event.wd) == self.wd && event.mask & libc::IN_IGNORED == 0)
+                {
+                    changed = true;
+                }
+                offset += EVENT_HEADER_SIZE + event.len as usize;
+            }
+        }
+    }
+}